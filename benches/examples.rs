@@ -0,0 +1,121 @@
+// Times running each of the example programs, in the manner of Criterion:
+// after warming up, the time per run is measured over a number of samples,
+// and compared with the times saved by the last run of the benchmark. Run
+// with cargo bench --bench examples.
+
+use rbasic::evaluator::Interpreter;
+use rbasic::host::HostEvent;
+use rbasic::lexer;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+const WARM_UP: Duration = Duration::from_millis(500);
+const SAMPLES: usize = 50;
+const SAMPLE_TIME: Duration = Duration::from_millis(20);
+
+// What the examples are given each time they use INPUT
+const INPUT: &str = "12";
+
+fn load(path: &Path) -> Interpreter {
+    let text = fs::read_to_string(path).unwrap();
+    let code_lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| lexer::tokenize_line(line).unwrap())
+        .collect();
+    Interpreter::new(code_lines).unwrap()
+}
+
+// Runs the program until it finishes or asks for a second line of input,
+// which stops the examples that loop forever
+fn run(interpreter: &mut Interpreter) {
+    let mut input_given = false;
+    interpreter.start();
+
+    loop {
+        match interpreter.resume() {
+            HostEvent::Output(_) => {}
+            HostEvent::NeedsInput { .. } if !input_given => {
+                interpreter.provide_input(INPUT);
+                input_given = true;
+            }
+            HostEvent::NeedsInput { .. } | HostEvent::Finished(_) => return,
+            HostEvent::Error(e) => panic!("{}", e),
+        }
+    }
+}
+
+// The time per run of each sample, fastest first
+fn measure(interpreter: &mut Interpreter) -> Vec<Duration> {
+    let started = Instant::now();
+    let mut runs = 0;
+    while started.elapsed() < WARM_UP {
+        run(interpreter);
+        runs += 1;
+    }
+    let runs_per_sample =
+        ((SAMPLE_TIME.as_secs_f64() * runs as f64 / WARM_UP.as_secs_f64()) as u32).max(1);
+
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let started = Instant::now();
+            for _ in 0..runs_per_sample {
+                run(interpreter);
+            }
+            started.elapsed() / runs_per_sample
+        })
+        .collect();
+    samples.sort();
+    samples
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("target")
+        .join("rbasic-bench")
+        .join(format!("{}.txt", name))
+}
+
+fn main() {
+    let mut paths: Vec<PathBuf> =
+        fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("examples"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some("bas".as_ref()))
+            .collect();
+    paths.sort();
+
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let samples = measure(&mut load(&path));
+        let median = samples[SAMPLES / 2];
+
+        println!(
+            "{:<12} time: [{:?} {:?} {:?}]",
+            name,
+            samples[0],
+            median,
+            samples[SAMPLES - 1]
+        );
+
+        // Compare with the median saved last time, then save this one
+        let baseline = baseline_path(&name);
+        if let Some(previous) = fs::read_to_string(&baseline)
+            .ok()
+            .and_then(|text| text.trim().parse::<f64>().ok())
+        {
+            println!(
+                "{:<12} change: {:+.1}% (was {:?})",
+                "",
+                (median.as_secs_f64() / previous - 1.0) * 100.0,
+                Duration::from_secs_f64(previous)
+            );
+        }
+        fs::create_dir_all(baseline.parent().unwrap()).unwrap();
+        fs::write(&baseline, median.as_secs_f64().to_string()).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+// Compares running a program with the interpreter and with the VM. Run with
+// cargo bench.
+
+use rbasic::compiler;
+use rbasic::evaluator::Interpreter;
+use rbasic::lexer;
+use rbasic::vm::Vm;
+
+use std::time::Duration;
+use std::time::Instant;
+
+const PROGRAM: &[&str] = &[
+    "10 DEF FNSQ(X) = X * X",
+    "20 DIM A(100)",
+    "30 LET T = 0",
+    "40 FOR I = 1 TO 2000",
+    "50 FOR J = 0 TO 100",
+    "60 LET A(J) = A(J) + FNSQ(J) - I",
+    "70 IF A(J) > 1000000 THEN 90",
+    "80 LET T = T + 1",
+    "90 NEXT J",
+    "100 NEXT I",
+];
+
+const RUNS: u32 = 5;
+
+fn program() -> Vec<lexer::LineOfCode> {
+    PROGRAM
+        .iter()
+        .map(|line| lexer::tokenize_line(line).unwrap())
+        .collect()
+}
+
+// The fastest of several runs
+fn time<F: FnMut()>(mut run: F) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            run();
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let mut interpreter = Interpreter::new(program()).unwrap();
+    let interpreted = time(|| {
+        interpreter.run().unwrap();
+    });
+
+    let mut vm = Vm::new(compiler::compile(program()).unwrap());
+    let compiled = time(|| {
+        vm.run().unwrap();
+    });
+
+    assert_eq!(vm.variables(), interpreter.variables());
+    println!("interpreter: {:?}", interpreted);
+    println!("vm:          {:?}", compiled);
+    println!(
+        "speedup:     {:.1}x",
+        interpreted.as_secs_f64() / compiled.as_secs_f64()
+    );
+}
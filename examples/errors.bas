@@ -0,0 +1,17 @@
+10  REM divides pairs of numbers, trapping the errors that come up
+20  ON ERROR GOTO 200
+30  DEF FNQ(X, Y) = X / Y
+40  READ A, B
+50  PRINT FNQ(A, B)
+60  GOTO 40
+70  DATA 10, 2, 7, 0, 9, 3
+100 REM out of DATA ends up here
+110 PRINT "done"
+120 ERROR 200
+130 END
+200 PRINT "error"
+201 PRINT ERR
+202 PRINT ERL
+210 IF ERR = 4 THEN 230
+220 RESUME NEXT
+230 RESUME 100
@@ -0,0 +1,19 @@
+10  REM totals the scores in scores.txt, which is opened from the
+20  REM directory rbasic runs in
+30  ON ERROR GOTO 200
+40  OPEN "examples/scores.txt" FOR INPUT AS #1
+50  LET T = 0
+60  WHILE !EOF(1)
+70  INPUT #1, N$, S
+80  PRINT N$
+85  PRINT S
+90  LET T = T + S
+100 WEND
+110 CLOSE #1
+120 PRINT T
+130 OPEN "examples/missing.txt" FOR INPUT AS #1
+140 END
+200 PRINT "error"
+201 PRINT ERR
+202 PRINT ERL
+210 RESUME NEXT
@@ -0,0 +1,16 @@
+10  REM factorial.bas without the GOTO
+20  PRINT " factorial of:"
+30  INPUT A
+40  LET B = 1
+50  WHILE A > 1
+60  LET B = B * A
+70  LET A = A - 1
+80  WEND
+90  PRINT B
+100 REM count up, leaving the loop early at 3
+110 DO
+120 PRINT A
+130 LET A = A + 1
+140 IF A < 3 THEN 160
+150 EXIT DO
+160 LOOP UNTIL A > 10
@@ -0,0 +1,95 @@
+use crate::error;
+use crate::value;
+
+// The largest subscript of each dimension of an array used without DIM
+pub const DEFAULT_BOUND: usize = 10;
+
+// An array created by DIM, or on first use. Subscripts start from 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicArray {
+    // The largest subscript of each dimension
+    bounds: Vec<usize>,
+    values: Vec<value::RBasicValue>,
+}
+
+impl BasicArray {
+    // Elements start out as empty strings for names ending in $ and as 0
+    // otherwise
+    pub fn new(name: &str, bounds: Vec<usize>) -> BasicArray {
+        let initial = if name.ends_with('$') {
+            value::RBasicValue::String("".into())
+        } else {
+            value::RBasicValue::Number(0)
+        };
+
+        BasicArray {
+            values: vec![initial; element_count(&bounds)],
+            bounds,
+        }
+    }
+
+    // An array holding the given values in order, with the last subscript
+    // changing fastest, as values returns them
+    pub fn with_values(bounds: Vec<usize>, values: Vec<value::RBasicValue>) -> Option<BasicArray> {
+        if values.len() != element_count(&bounds) {
+            return None;
+        }
+        Some(BasicArray { bounds, values })
+    }
+
+    pub fn bounds(&self) -> &[usize] {
+        &self.bounds
+    }
+
+    pub fn values(&self) -> &[value::RBasicValue] {
+        &self.values
+    }
+
+    pub fn get(&self, subscripts: &[i32]) -> Result<&value::RBasicValue, error::RBasicError> {
+        let offset = self.offset(subscripts)?;
+        Ok(&self.values[offset])
+    }
+
+    // Stores the value, returning the value it replaced
+    pub fn set(
+        &mut self,
+        subscripts: &[i32],
+        value: value::RBasicValue,
+    ) -> Result<value::RBasicValue, error::RBasicError> {
+        let offset = self.offset(subscripts)?;
+        Ok(std::mem::replace(&mut self.values[offset], value))
+    }
+
+    fn offset(&self, subscripts: &[i32]) -> Result<usize, error::RBasicError> {
+        if subscripts.len() != self.bounds.len() {
+            return Err(error::RBasicError::new(
+                error::SUBSCRIPT_OUT_OF_RANGE,
+                format!(
+                    "Array has {} dimension(s) but was given {} subscript(s)",
+                    self.bounds.len(),
+                    subscripts.len()
+                ),
+            ));
+        }
+
+        let mut offset = 0;
+        for (&subscript, &bound) in subscripts.iter().zip(&self.bounds) {
+            if subscript < 0 || subscript as usize > bound {
+                return Err(error::RBasicError::new(
+                    error::SUBSCRIPT_OUT_OF_RANGE,
+                    format!("Subscript {} is out of range 0 to {}", subscript, bound),
+                ));
+            }
+            offset = offset * (bound + 1) + subscript as usize;
+        }
+
+        Ok(offset)
+    }
+}
+
+// The number of elements in an array with the given bounds
+pub fn element_count(bounds: &[usize]) -> usize {
+    bounds
+        .iter()
+        .fold(1, |count: usize, bound| count.saturating_mul(bound + 1))
+}
@@ -0,0 +1,957 @@
+use crate::evaluator;
+use crate::lexer;
+use crate::token;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    // Something that is likely to go wrong, or not do what was meant
+    Warning,
+    // Something that stops the program from loading or running correctly
+    Error,
+}
+
+// A problem found in a program without running it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line_number: lexer::LineNumber,
+    // Position in the line, counted from 0 as the lexer does
+    pub pos: u32,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+// Shown as line number:column, with columns counted from 1
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line_number.0,
+            self.pos + 1,
+            severity,
+            self.message
+        )
+    }
+}
+
+// Checks the text of a program, including that each of its lines can be
+// tokenized
+pub fn check_source(source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut code_lines = Vec::new();
+
+    for line in source.lines() {
+        match lexer::tokenize_line(line) {
+            Ok(code_line) => code_lines.push(code_line),
+            Err(e) => {
+                let line_number = line
+                    .split_whitespace()
+                    .next()
+                    .and_then(|number| u32::from_str(number).ok())
+                    .unwrap_or(0);
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line_number: lexer::LineNumber(line_number),
+                    pos: 0,
+                    message: e
+                        .lines()
+                        .next()
+                        .unwrap_or("")
+                        .trim_end_matches(':')
+                        .to_string(),
+                });
+            }
+        }
+    }
+
+    diagnostics.extend(check(code_lines));
+    diagnostics.sort_by(|first, second| {
+        (&first.line_number, first.pos).cmp(&(&second.line_number, second.pos))
+    });
+    diagnostics
+}
+
+// Checks a program for problems that can be found before it runs, returning
+// them in line order. Lines are ordered, and replaced when given twice, as
+// they are when the program is loaded.
+pub fn check(code_lines: Vec<lexer::LineOfCode>) -> Vec<Diagnostic> {
+    let mut lineno_to_code = BTreeMap::new();
+
+    for line in code_lines {
+        lineno_to_code.insert(line.line_number.clone(), line);
+    }
+
+    let program: Vec<lexer::LineOfCode> = lineno_to_code.into_values().collect();
+    let mut checker = Checker::new(&program);
+    checker.check_loops();
+    checker.check_reachability();
+    checker.check_subroutines();
+    checker.check_unused_data();
+    checker.check_types();
+    checker.check_assignments();
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by(|first, second| {
+        (&first.line_number, first.pos).cmp(&(&second.line_number, second.pos))
+    });
+    diagnostics
+}
+
+// The line indices each line of a program, in line number order, can go on
+// to, with GOSUBs going on to the line after them rather than into their
+// subroutine
+pub(crate) fn flow_within_subroutines(program: &[lexer::LineOfCode]) -> Vec<Vec<usize>> {
+    let mut checker = Checker::new(program);
+    checker.check_loops();
+    (0..program.len())
+        .map(|line_index| checker.successors(line_index, false))
+        .collect()
+}
+
+// A loop, with the line indices of the statements starting and ending it
+struct Loop {
+    statement: &'static str,
+    start: usize,
+    end: usize,
+}
+
+struct Checker<'a> {
+    program: &'a [lexer::LineOfCode],
+    // The lines each line refers to, with the line index of each that exists
+    references: Vec<Vec<(evaluator::LineReference, usize)>>,
+    // The line index of the statement at the other end of each WHILE, WEND,
+    // DO, LOOP and EXIT DO that is matched
+    blocks: HashMap<usize, usize>,
+    // The line indices of the FOR loops each NEXT ends
+    next_loops: HashMap<usize, Vec<usize>>,
+    loops: Vec<Loop>,
+    // Line indices that GOSUBs return to
+    return_indices: Vec<usize>,
+    reachable: Vec<bool>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(program: &'a [lexer::LineOfCode]) -> Checker<'a> {
+        let line_map: BTreeMap<_, _> = program
+            .iter()
+            .enumerate()
+            .map(|(index, line)| (&line.line_number, index))
+            .collect();
+        let mut diagnostics = Vec::new();
+        let mut references = Vec::with_capacity(program.len());
+        let mut return_indices = Vec::new();
+
+        for (line_index, line) in program.iter().enumerate() {
+            let mut line_references = Vec::new();
+            for reference in evaluator::line_references(&line.tokens) {
+                match line_map.get(&reference.target) {
+                    Some(index) => {
+                        if reference.statement.ends_with("GOSUB") {
+                            return_indices.push(line_index + 1);
+                        }
+                        line_references.push((reference, *index))
+                    }
+                    None => diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        line_number: line.line_number.clone(),
+                        pos: reference.pos,
+                        message: format!(
+                            "{} refers to line {}, which doesn't exist",
+                            reference.statement, reference.target.0
+                        ),
+                    }),
+                }
+            }
+            references.push(line_references);
+        }
+
+        return_indices.sort_unstable();
+        return_indices.dedup();
+
+        Checker {
+            program,
+            references,
+            blocks: HashMap::new(),
+            next_loops: HashMap::new(),
+            loops: Vec::new(),
+            return_indices,
+            reachable: vec![false; program.len()],
+            diagnostics,
+        }
+    }
+
+    fn report(&mut self, severity: Severity, line_index: usize, pos: u32, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line_number: self.program[line_index].line_number.clone(),
+            pos,
+            message,
+        });
+    }
+
+    fn statement(&self, line_index: usize) -> Option<&'a token::Token> {
+        self.program[line_index]
+            .tokens
+            .first()
+            .map(|first| &first.1)
+    }
+
+    fn statement_pos(&self, line_index: usize) -> u32 {
+        self.program[line_index]
+            .tokens
+            .first()
+            .map_or(0, |first| first.0)
+    }
+
+    // Matches FOR with NEXT, WHILE with WEND and DO with LOOP, and reports
+    // jumps from outside of a loop into the middle of it
+    fn check_loops(&mut self) {
+        // Open WHILE and DO statements, along with the EXIT DOs of each DO
+        let mut open_blocks: Vec<(&token::Token, usize, Vec<usize>)> = Vec::new();
+
+        for line_index in 0..self.program.len() {
+            let pos = self.statement_pos(line_index);
+            let statement = match self.statement(line_index) {
+                Some(statement) => statement,
+                None => continue,
+            };
+
+            match *statement {
+                token::Token::For => match evaluator::find_next(self.program, line_index) {
+                    Some(next_index) => {
+                        self.next_loops
+                            .entry(next_index)
+                            .or_default()
+                            .push(line_index);
+                        self.loops.push(Loop {
+                            statement: "FOR",
+                            start: line_index,
+                            end: next_index,
+                        });
+                    }
+                    None => self.report(
+                        Severity::Error,
+                        line_index,
+                        pos,
+                        "FOR without NEXT".to_string(),
+                    ),
+                },
+                token::Token::While | token::Token::Do => {
+                    open_blocks.push((statement, line_index, Vec::new()))
+                }
+                // An unmatched end of a block is left for the block it is
+                // within to be matched with
+                token::Token::Wend => match open_blocks.last() {
+                    Some((token::Token::While, while_index, _)) => {
+                        let while_index = *while_index;
+                        open_blocks.pop();
+                        self.match_block("WHILE", while_index, line_index)
+                    }
+                    _ => self.report(
+                        Severity::Error,
+                        line_index,
+                        pos,
+                        "WEND without WHILE".to_string(),
+                    ),
+                },
+                token::Token::Loop => match open_blocks.last() {
+                    Some((token::Token::Do, _, _)) => {
+                        let (_, do_index, exits) = open_blocks.pop().unwrap();
+                        self.match_block("DO", do_index, line_index);
+                        for exit_index in exits {
+                            self.blocks.insert(exit_index, line_index);
+                        }
+                    }
+                    _ => self.report(
+                        Severity::Error,
+                        line_index,
+                        pos,
+                        "LOOP without DO".to_string(),
+                    ),
+                },
+                token::Token::Exit => match open_blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|(block, _, _)| **block == token::Token::Do)
+                {
+                    Some((_, _, exits)) => exits.push(line_index),
+                    None => self.report(
+                        Severity::Error,
+                        line_index,
+                        pos,
+                        "EXIT DO outside of DO loop".to_string(),
+                    ),
+                },
+                _ => {}
+            }
+        }
+
+        for (block, line_index, _) in open_blocks {
+            let message = match *block {
+                token::Token::While => "WHILE without WEND",
+                _ => "DO without LOOP",
+            };
+            let pos = self.statement_pos(line_index);
+            self.report(Severity::Error, line_index, pos, message.to_string());
+        }
+
+        let lines_with_next: BTreeSet<usize> = self.next_loops.keys().copied().collect();
+        for line_index in 0..self.program.len() {
+            if self.statement(line_index) == Some(&token::Token::Next)
+                && !lines_with_next.contains(&line_index)
+            {
+                let pos = self.statement_pos(line_index);
+                self.report(
+                    Severity::Error,
+                    line_index,
+                    pos,
+                    "NEXT without FOR".to_string(),
+                );
+            }
+        }
+
+        let mut jumps = Vec::new();
+        for (line_index, references) in self.references.iter().enumerate() {
+            for (reference, target_index) in references {
+                if !is_jump(reference) {
+                    continue;
+                }
+                for a_loop in &self.loops {
+                    let inside = |index: usize| a_loop.start < index && index <= a_loop.end;
+                    let outside = line_index < a_loop.start || line_index > a_loop.end;
+                    if inside(*target_index) && outside {
+                        jumps.push((
+                            line_index,
+                            reference.pos,
+                            format!(
+                                "{} jumps into the middle of the {} loop at line {}",
+                                reference.statement,
+                                a_loop.statement,
+                                self.program[a_loop.start].line_number.0
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+        for (line_index, pos, message) in jumps {
+            self.report(Severity::Warning, line_index, pos, message);
+        }
+    }
+
+    fn match_block(&mut self, statement: &'static str, start: usize, end: usize) {
+        self.blocks.insert(start, end);
+        self.blocks.insert(end, start);
+        self.loops.push(Loop {
+            statement,
+            start,
+            end,
+        });
+    }
+
+    // The line indices that can run after the given line. GOSUBs go both
+    // to their subroutine and, once it returns, to the line after them,
+    // unless follow_gosubs is false when they only go to the line after and
+    // RETURN goes nowhere.
+    fn successors(&self, line_index: usize, follow_gosubs: bool) -> Vec<usize> {
+        let tokens = &self.program[line_index].tokens;
+        let next = line_index + 1;
+        let targets = self.references[line_index]
+            .iter()
+            .filter(|(reference, _)| reference.statement != "RESTORE")
+            .map(|(_, target_index)| *target_index);
+        let has_condition = matches!(
+            tokens.get(1).map(|second| &second.1),
+            Some(token::Token::While) | Some(token::Token::Until)
+        );
+        let block_end = self.blocks.get(&line_index).copied();
+
+        let successors: Vec<usize> = match self.statement(line_index) {
+            None => vec![next],
+            Some(token::Token::Goto) => targets.collect(),
+            Some(token::Token::Gosub) if !follow_gosubs => vec![next],
+            Some(token::Token::On)
+                if !follow_gosubs
+                    && self.references[line_index]
+                        .iter()
+                        .any(|(reference, _)| reference.statement == "ON GOSUB") =>
+            {
+                vec![next]
+            }
+            Some(token::Token::Gosub) | Some(token::Token::On) | Some(token::Token::If) => {
+                targets.chain(Some(next)).collect()
+            }
+            Some(token::Token::End) | Some(token::Token::Error) => Vec::new(),
+            Some(token::Token::Return) if follow_gosubs => self.return_indices.clone(),
+            Some(token::Token::Return) => Vec::new(),
+            // RESUME and RESUME NEXT go back to wherever the error was
+            Some(token::Token::Resume) => targets.collect(),
+            Some(token::Token::For) => {
+                let mut successors = vec![next];
+                let next_index = self
+                    .next_loops
+                    .iter()
+                    .find(|(_, for_indices)| for_indices.contains(&line_index))
+                    .map(|(next_index, _)| *next_index);
+                if let Some(next_index) = next_index {
+                    successors.push(next_index + 1);
+                }
+                successors
+            }
+            Some(token::Token::Next) => {
+                let mut successors = vec![next];
+                if let Some(for_indices) = self.next_loops.get(&line_index) {
+                    successors.extend(for_indices.iter().map(|for_index| for_index + 1));
+                }
+                successors
+            }
+            // Unmatched ends of blocks, which have been reported, are
+            // passed over
+            Some(token::Token::Wend) | Some(token::Token::Loop) | Some(token::Token::Exit)
+                if block_end.is_none() =>
+            {
+                vec![next]
+            }
+            Some(token::Token::While) => block_end
+                .map(|end| end + 1)
+                .into_iter()
+                .chain(Some(next))
+                .collect(),
+            Some(token::Token::Wend) => block_end.into_iter().collect(),
+            Some(token::Token::Do) if has_condition => block_end
+                .map(|end| end + 1)
+                .into_iter()
+                .chain(Some(next))
+                .collect(),
+            Some(token::Token::Loop) if has_condition => {
+                block_end.into_iter().chain(Some(next)).collect()
+            }
+            Some(token::Token::Loop) => block_end.into_iter().collect(),
+            Some(token::Token::Exit) => block_end.map(|end| end + 1).into_iter().collect(),
+            Some(_) => vec![next],
+        };
+
+        successors
+            .into_iter()
+            .filter(|successor| *successor < self.program.len())
+            .collect()
+    }
+
+    // Reports the lines that can't be reached from the start of the program,
+    // once for each run of them
+    fn check_reachability(&mut self) {
+        if self.program.is_empty() {
+            return;
+        }
+
+        let mut pending = vec![0];
+        while let Some(line_index) = pending.pop() {
+            if self.reachable[line_index] {
+                continue;
+            }
+            self.reachable[line_index] = true;
+            pending.extend(self.successors(line_index, true));
+        }
+
+        // The first and last line index of each run of unreachable lines.
+        // Comments and DATA neither count as unreachable nor end a run.
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        let mut in_run = false;
+        for line_index in 0..self.program.len() {
+            if self.reachable[line_index] {
+                in_run = false;
+                continue;
+            }
+            match self.statement(line_index) {
+                None | Some(token::Token::Rem) | Some(token::Token::Data) => {}
+                Some(_) if in_run => runs.last_mut().unwrap().1 = line_index,
+                Some(_) => {
+                    runs.push((line_index, line_index));
+                    in_run = true;
+                }
+            }
+        }
+
+        for (first, last) in runs {
+            let message = if first == last {
+                format!(
+                    "line {} is never reached",
+                    self.program[first].line_number.0
+                )
+            } else {
+                format!(
+                    "lines {} to {} are never reached",
+                    self.program[first].line_number.0, self.program[last].line_number.0
+                )
+            };
+            let pos = self.statement_pos(first);
+            self.report(Severity::Warning, first, pos, message);
+        }
+    }
+
+    // Reports GOSUBs to subroutines from which no RETURN can be reached
+    fn check_subroutines(&mut self) {
+        let mut returns: HashMap<usize, bool> = HashMap::new();
+        let mut never_return = Vec::new();
+
+        for (line_index, references) in self.references.iter().enumerate() {
+            for (reference, target_index) in references {
+                if !reference.statement.ends_with("GOSUB") {
+                    continue;
+                }
+                let does_return = *returns
+                    .entry(*target_index)
+                    .or_insert_with(|| self.reaches_return(*target_index));
+                if !does_return {
+                    never_return.push((
+                        line_index,
+                        reference.pos,
+                        format!(
+                            "the subroutine at line {} never RETURNs",
+                            reference.target.0
+                        ),
+                    ));
+                }
+            }
+        }
+
+        for (line_index, pos, message) in never_return {
+            self.report(Severity::Warning, line_index, pos, message);
+        }
+    }
+
+    fn reaches_return(&self, start: usize) -> bool {
+        let mut visited = vec![false; self.program.len()];
+        let mut pending = vec![start];
+
+        while let Some(line_index) = pending.pop() {
+            if visited[line_index] {
+                continue;
+            }
+            visited[line_index] = true;
+            if self.statement(line_index) == Some(&token::Token::Return) {
+                return true;
+            }
+            pending.extend(self.successors(line_index, false));
+        }
+
+        false
+    }
+
+    // Reports DATA statements in a program that never uses READ
+    fn check_unused_data(&mut self) {
+        let has_read = (0..self.program.len())
+            .any(|line_index| self.statement(line_index) == Some(&token::Token::Read));
+        if has_read {
+            return;
+        }
+
+        for line_index in 0..self.program.len() {
+            if self.statement(line_index) == Some(&token::Token::Data) {
+                let pos = self.statement_pos(line_index);
+                self.report(
+                    Severity::Warning,
+                    line_index,
+                    pos,
+                    "DATA is never READ".to_string(),
+                );
+            }
+        }
+    }
+
+    // Reports values whose types can be told from literals and from the $
+    // ending string variable names, and which don't suit how they are used
+    fn check_types(&mut self) {
+        let mut mismatches = Vec::new();
+
+        for (line_index, line) in self.program.iter().enumerate() {
+            let tokens = &line.tokens;
+            let assignment = match tokens.first().map(|first| &first.1) {
+                None | Some(token::Token::Rem) | Some(token::Token::Data) => continue,
+                Some(token::Token::Let)
+                | Some(token::Token::For)
+                | Some(token::Token::Lset)
+                | Some(token::Token::Rset)
+                | Some(token::Token::Def) => top_level_position(tokens, &token::Token::Equals),
+                Some(_) => None,
+            };
+
+            match assignment {
+                Some(equals) => {
+                    if tokens[0].1 != token::Token::Def {
+                        check_expressions(&tokens[1..equals], &mut mismatches, line_index);
+                    }
+                    let value_types =
+                        check_expressions(&tokens[equals + 1..], &mut mismatches, line_index);
+
+                    // A scalar assignment, as in LET A$ = expression
+                    if equals != 2 || tokens[0].1 == token::Token::Def {
+                        continue;
+                    }
+                    if let (
+                        Some(lexer::TokenAndPos(pos, token::Token::Variable(name, _))),
+                        Some((_, value_type)),
+                    ) = (tokens.get(1), value_types.first())
+                    {
+                        let described = match (variable_type(name), value_type) {
+                            (Type::String, Type::Number) => "a number is assigned to string",
+                            (Type::Unknown, Type::String) => "a string is assigned to numeric",
+                            _ => continue,
+                        };
+                        mismatches.push((
+                            line_index,
+                            *pos,
+                            format!("type mismatch: {} variable {}", described, name),
+                        ));
+                    }
+                }
+                None => {
+                    check_expressions(&tokens[1..], &mut mismatches, line_index);
+                }
+            }
+        }
+
+        for (line_index, pos, message) in mismatches {
+            self.report(Severity::Warning, line_index, pos, message);
+        }
+    }
+
+    // Reports variables read where no path from the start of the program can
+    // have assigned them
+    fn check_assignments(&mut self) {
+        let line_count = self.program.len();
+        let uses: Vec<_> = self
+            .program
+            .iter()
+            .map(|line| variable_uses(&line.tokens))
+            .collect();
+        let successors: Vec<Vec<usize>> = (0..line_count)
+            .map(|line_index| self.successors(line_index, true))
+            .collect();
+        let handlers: Vec<usize> = self
+            .references
+            .iter()
+            .flatten()
+            .filter(|(reference, _)| reference.statement == "ON ERROR GOTO")
+            .map(|(_, target_index)| *target_index)
+            .collect();
+
+        // The variables that may have been assigned when each line starts
+        let mut assigned: Vec<BTreeSet<&str>> = vec![BTreeSet::new(); line_count];
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+            // An error handler can be reached from any line, and RESUME can
+            // go back to any line
+            let mut assigned_anywhere: BTreeSet<&str> = BTreeSet::new();
+            let mut assigned_before_resume: BTreeSet<&str> = BTreeSet::new();
+
+            for line_index in 0..line_count {
+                if !self.reachable[line_index] {
+                    continue;
+                }
+                let mut after = assigned[line_index].clone();
+                after.extend(uses[line_index].0.iter().copied());
+
+                for successor in &successors[line_index] {
+                    changed |= extend(&mut assigned[*successor], &after);
+                }
+                if self.statement(line_index) == Some(&token::Token::Resume)
+                    && successors[line_index].is_empty()
+                {
+                    assigned_before_resume.extend(after.iter().copied());
+                }
+                if !handlers.is_empty() {
+                    assigned_anywhere.extend(after);
+                }
+            }
+
+            for handler in &handlers {
+                changed |= extend(&mut assigned[*handler], &assigned_anywhere);
+            }
+            if !assigned_before_resume.is_empty() {
+                for line_assigned in assigned.iter_mut() {
+                    changed |= extend(line_assigned, &assigned_before_resume);
+                }
+            }
+        }
+
+        let mut unassigned = Vec::new();
+        for (line_index, (_, reads)) in uses.iter().enumerate() {
+            if !self.reachable[line_index] {
+                continue;
+            }
+            let mut reported = BTreeSet::new();
+            for (pos, name) in reads {
+                if !assigned[line_index].contains(name) && reported.insert(*name) {
+                    unassigned.push((
+                        line_index,
+                        *pos,
+                        format!("{} is read before anything is assigned to it", name),
+                    ));
+                }
+            }
+        }
+
+        for (line_index, pos, message) in unassigned {
+            self.report(Severity::Warning, line_index, pos, message);
+        }
+    }
+}
+
+fn extend<'a>(set: &mut BTreeSet<&'a str>, other: &BTreeSet<&'a str>) -> bool {
+    let before = set.len();
+    set.extend(other.iter().copied());
+    set.len() != before
+}
+
+// Whether a reference transfers control to the line, rather than naming a
+// line for RESTORE, an error handler or RESUME
+fn is_jump(reference: &evaluator::LineReference) -> bool {
+    matches!(
+        reference.statement,
+        "GOTO" | "GOSUB" | "IF" | "ON GOTO" | "ON GOSUB"
+    )
+}
+
+// Finds the index of the first token outside of parentheses equal to the
+// given one
+pub(crate) fn top_level_position(tokens: &[lexer::TokenAndPos], wanted: &token::Token) -> Option<usize> {
+    let mut depth = 0;
+
+    for (index, lexer::TokenAndPos(_, token)) in tokens.iter().enumerate() {
+        match *token {
+            token::Token::LParen => depth += 1,
+            token::Token::RParen => depth -= 1,
+            ref token if depth == 0 && token == wanted => return Some(index),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// The variables a line assigns, and those it reads along with where. Names
+// followed by ( are arrays or functions, which aren't checked, and the
+// parameters and body of DEF FN are left until the function is called.
+pub(crate) fn variable_uses(tokens: &[lexer::TokenAndPos]) -> (Vec<&str>, Vec<(u32, &str)>) {
+    let is_scalar = |index: usize| {
+        matches!(tokens[index].1, token::Token::Variable(_, _))
+            && tokens.get(index + 1).map(|next| &next.1) != Some(&token::Token::LParen)
+    };
+    let name = |index: usize| match tokens[index].1 {
+        token::Token::Variable(ref name, _) => &**name,
+        _ => unreachable!(),
+    };
+
+    let targets: Vec<usize> = match tokens.first().map(|first| &first.1) {
+        None
+        | Some(token::Token::Rem)
+        | Some(token::Token::Data)
+        | Some(token::Token::Def)
+        | Some(token::Token::Next) => return (Vec::new(), Vec::new()),
+        Some(token::Token::Let)
+        | Some(token::Token::For)
+        | Some(token::Token::Lset)
+        | Some(token::Token::Rset) => {
+            if tokens.len() > 2 && is_scalar(1) && tokens[2].1 == token::Token::Equals {
+                vec![1]
+            } else {
+                Vec::new()
+            }
+        }
+        Some(token::Token::Input) | Some(token::Token::Read) | Some(token::Token::Line) => {
+            // Past the file number of INPUT # and LINE INPUT #
+            let start = match top_level_position(tokens, &token::Token::Hash) {
+                Some(_) => top_level_position(tokens, &token::Token::Comma)
+                    .map_or(tokens.len(), |comma| comma + 1),
+                None => 1,
+            };
+            let mut depth = 0;
+            let mut targets = Vec::new();
+            for (index, lexer::TokenAndPos(_, token)) in tokens.iter().enumerate().skip(start) {
+                match *token {
+                    token::Token::LParen => depth += 1,
+                    token::Token::RParen => depth -= 1,
+                    _ if depth == 0 && is_scalar(index) => targets.push(index),
+                    _ => {}
+                }
+            }
+            targets
+        }
+        Some(token::Token::Field) => (1..tokens.len())
+            .filter(|index| tokens[index - 1].1 == token::Token::As && is_scalar(*index))
+            .collect(),
+        Some(_) => Vec::new(),
+    };
+
+    let reads = (0..tokens.len())
+        .filter(|index| is_scalar(*index) && !targets.contains(index))
+        .map(|index| (tokens[index].0, name(index)))
+        .filter(|(_, name)| *name != "ERR" && *name != "ERL")
+        .collect();
+
+    (targets.into_iter().map(name).collect(), reads)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Number,
+    String,
+    Bool,
+    // A variable without a $ may hold a string as well as a number, as INPUT
+    // stores a string in it
+    Unknown,
+}
+
+impl Type {
+    fn description(self) -> &'static str {
+        match self {
+            Type::Number => "a number",
+            Type::String => "a string",
+            Type::Bool => "a Boolean value",
+            Type::Unknown => "a value",
+        }
+    }
+}
+
+fn variable_type(name: &str) -> Type {
+    if name.ends_with('$') {
+        Type::String
+    } else {
+        Type::Unknown
+    }
+}
+
+fn function_type(name: &str) -> Type {
+    match name {
+        "CVD" | "CVI" | "CVS" | "EOF" | "INT" | "LOF" | "RAND" | "RND" => Type::Number,
+        name => variable_type(name),
+    }
+}
+
+// Works out the type of each expression among the tokens, with the position
+// each starts at, and collects the type mismatches within them. Tokens that
+// can't start an expression, such as keywords, are passed over.
+fn check_expressions(
+    tokens: &[lexer::TokenAndPos],
+    mismatches: &mut Vec<(usize, u32, String)>,
+    line_index: usize,
+) -> Vec<(u32, Type)> {
+    let mut types = Vec::new();
+    let mut start = 0;
+
+    while start < tokens.len() {
+        let mut token_iter = tokens[start..].iter().peekable();
+        let parsed = evaluator::parse_expression(&mut token_iter);
+        let used = tokens.len() - start - token_iter.len();
+        let pos = tokens[start].0;
+
+        if let Ok(output_queue) = parsed {
+            if !output_queue.is_empty() {
+                let mut messages = Vec::new();
+                types.push((pos, expression_type(output_queue, &mut messages)));
+                mismatches.extend(
+                    messages
+                        .into_iter()
+                        .map(|message| (line_index, pos, message)),
+                );
+            }
+        }
+
+        start += used.max(1);
+    }
+
+    types
+}
+
+// Works out the type of an expression in postfix order, as given by
+// parse_expression, adding a message for each operator given values it can't
+// work on
+fn expression_type(output_queue: VecDeque<token::Token>, messages: &mut Vec<String>) -> Type {
+    let mut stack: Vec<Type> = Vec::new();
+
+    for token in output_queue {
+        let result = match token {
+            token::Token::Number(_) | token::Token::Float(_) => Type::Number,
+            token::Token::BString(_) => Type::String,
+            token::Token::Variable(ref name, _) => variable_type(name),
+            token::Token::FunctionCall(ref name, arg_count) => {
+                let remaining = stack.len().saturating_sub(arg_count);
+                stack.truncate(remaining);
+                function_type(name)
+            }
+            ref operator if operator.is_unary_operator() => {
+                let operand = stack.pop().unwrap_or(Type::Unknown);
+                let (wanted, result) = match *operator {
+                    token::Token::UMinus => (Type::Number, Type::Number),
+                    _ => (Type::Bool, Type::Bool),
+                };
+                if operand != wanted && operand != Type::Unknown {
+                    messages.push(format!(
+                        "type mismatch: {} can't be given {}",
+                        operator.keyword_text().unwrap_or(""),
+                        operand.description()
+                    ));
+                }
+                result
+            }
+            ref operator if operator.is_binary_operator() => {
+                let second = stack.pop().unwrap_or(Type::Unknown);
+                let first = stack.pop().unwrap_or(Type::Unknown);
+                let (mismatched, result) = binary_type(operator, first, second);
+                if mismatched {
+                    messages.push(format!(
+                        "type mismatch: {} can't be given {} and {}",
+                        operator.keyword_text().unwrap_or(""),
+                        first.description(),
+                        second.description()
+                    ));
+                }
+                result
+            }
+            _ => Type::Unknown,
+        };
+        stack.push(result);
+    }
+
+    stack.pop().unwrap_or(Type::Unknown)
+}
+
+// Whether a binary operator is given operands it can't work on, along with
+// the type of its result
+fn binary_type(operator: &token::Token, first: Type, second: Type) -> (bool, Type) {
+    let known = first != Type::Unknown && second != Type::Unknown;
+
+    if operator.is_comparison_operator() {
+        return (known && first != second, Type::Bool);
+    }
+
+    match (operator, first, second) {
+        (_, Type::Number, Type::Number) => (false, Type::Number),
+        (token::Token::Plus, Type::String, Type::String) => (false, Type::String),
+        (_, Type::Bool, _) | (_, _, Type::Bool) => (true, Type::Unknown),
+        (token::Token::Plus, _, _) => (known, Type::Unknown),
+        (_, Type::String, Type::String) => (true, Type::Unknown),
+        _ => (known, Type::Unknown),
+    }
+}
@@ -0,0 +1,1023 @@
+use crate::error;
+use crate::evaluator;
+use crate::lexer;
+use crate::token;
+use crate::value;
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::slice::Iter;
+
+// An instruction for the VM. Expressions leave their value on the VM's stack
+// for the statement using it to take.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    // Starts the statement described by the given entry of statements
+    Statement(usize),
+    Constant(usize),
+    Load(usize),
+    // An argument of the user function being called
+    LoadParameter(usize),
+    Negate,
+    Not,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessThanEqual,
+    GreaterThanEqual,
+    // Calls the given entry of callees with the given number of arguments
+    Call(usize, usize),
+    // Ends a user function, giving the value on top of the stack
+    ReturnValue,
+    Pop,
+    Store(usize),
+    // Turns the given number of values on top of the stack into the
+    // subscripts of the array element a statement sets
+    Subscripts(usize),
+    StoreElement(usize, usize),
+    Dim(usize, usize),
+    ReadVariable(usize),
+    ReadElement(usize, usize),
+    // Moves READ to the given DATA item
+    Restore(usize),
+    Print,
+    // Reads a line into the given variable, after the given entry of prompts
+    Input(usize, usize),
+    Jump(usize),
+    // Takes a Boolean value and jumps if it is the same as when, ending the
+    // program with the given entry of errors for any other value
+    Branch {
+        when: bool,
+        target: usize,
+        error: usize,
+    },
+    Gosub(usize),
+    Return,
+    On(usize),
+    // Starts a loop with the start, end and step values on the stack, going
+    // to exit instead when it wouldn't run at all
+    For {
+        variable: usize,
+        exit: usize,
+    },
+    Next(usize),
+    // Gives the function in the given slot the given entry of bodies
+    Define(usize, usize),
+    Randomize,
+    SeedFromTime,
+    Error,
+    // Sets where errors are trapped, or turns trapping off with None
+    OnError(Option<usize>),
+    Resume(Resume),
+    // The values of ERR and ERL
+    ErrorCode,
+    ErrorLine,
+    // Ends the program with the given entry of errors as it is
+    Raise(usize),
+    // Ends the program with the given entry of errors, reported as coming
+    // from the current statement
+    Fail(usize),
+    Stop,
+    End,
+    // Reached by running past the last line, which is an error in an error
+    // handler
+    EndOfProgram,
+}
+
+// Where RESUME carries on from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    // The start of the line with the error
+    Error,
+    // The line after it
+    Next,
+    Line(usize),
+    // RESUME followed by something else, which takes the error as RESUME
+    // does before raising the given entry of errors
+    Invalid(usize),
+}
+
+// How errors in a statement are reported
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub line_number: lexer::LineNumber,
+    // The index of the line it is on
+    pub line: usize,
+    // The start of messages about the statement itself, giving its position
+    pub at: String,
+    // The start of messages about errors within it
+    pub prefix: String,
+}
+
+// What a function call in an expression may turn out to be, which depends on
+// the arrays and functions there are when it is made
+#[derive(Debug, Clone)]
+pub struct Callee {
+    pub name: String,
+    pub array: usize,
+    // The function slot for names starting with FN
+    pub function: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OnTargets {
+    pub is_gosub: bool,
+    pub targets: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionBody {
+    pub start: usize,
+    pub parameters: usize,
+}
+
+// A program compiled for the VM, with jumps going straight to the
+// instruction they continue from and variables, arrays and functions
+// numbered by the slot they are kept in
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub code: Vec<Op>,
+    // Where the code for each line starts, along with the end of the program
+    pub line_starts: Vec<usize>,
+    pub statements: Vec<Statement>,
+    pub constants: Vec<value::RBasicValue>,
+    // The names of the variables, arrays and functions in each slot
+    pub variables: Vec<String>,
+    pub arrays: Vec<String>,
+    pub functions: Vec<String>,
+    pub bodies: Vec<FunctionBody>,
+    pub callees: Vec<Callee>,
+    pub on_targets: Vec<OnTargets>,
+    // The loop variables of each NEXT, with None for the innermost loop
+    pub next_variables: Vec<Vec<Option<usize>>>,
+    pub prompts: Vec<String>,
+    pub errors: Vec<error::RBasicError>,
+    pub data: Vec<value::RBasicValue>,
+}
+
+// Compiles a program for the VM. The program is checked when it is loaded
+// just as the interpreter checks it, and any other mistake is compiled into
+// an error raised when the statement with it runs, so that the VM behaves
+// exactly as the interpreter does. Statements working with files, and TRON
+// and TROFF, can't be compiled, and programs using them are refused with
+// ADVANCED_FEATURE.
+pub fn compile(code_lines: Vec<lexer::LineOfCode>) -> Result<Program, error::RBasicError> {
+    let loaded = evaluator::load_program(code_lines)?;
+    let mut compiler = Compiler {
+        loaded: &loaded,
+        program: Program {
+            code: Vec::new(),
+            line_starts: Vec::new(),
+            statements: Vec::new(),
+            constants: Vec::new(),
+            variables: Vec::new(),
+            arrays: Vec::new(),
+            functions: Vec::new(),
+            bodies: Vec::new(),
+            callees: Vec::new(),
+            on_targets: Vec::new(),
+            next_variables: Vec::new(),
+            prompts: Vec::new(),
+            errors: Vec::new(),
+            data: loaded.data.iter().map(|(_, item)| item.clone()).collect(),
+        },
+        variable_slots: HashMap::new(),
+        array_slots: HashMap::new(),
+        function_slots: HashMap::new(),
+        line_jumps: Vec::new(),
+        error_jumps: Vec::new(),
+        functions: Vec::new(),
+    };
+
+    for line_index in 0..loaded.lines.len() {
+        let start = compiler.program.code.len();
+        compiler.program.line_starts.push(start);
+        compiler.line(line_index)?;
+    }
+    let end = compiler.program.code.len();
+    compiler.program.line_starts.push(end);
+    compiler.emit(Op::EndOfProgram);
+
+    Ok(compiler.finish())
+}
+
+struct Compiler<'a> {
+    loaded: &'a evaluator::LoadedProgram,
+    program: Program,
+    variable_slots: HashMap<String, usize>,
+    array_slots: HashMap<String, usize>,
+    function_slots: HashMap<String, usize>,
+    // Jumps to a line, by the index of the jump and of the line, which are
+    // filled in once every line has been compiled
+    line_jumps: Vec<(usize, usize)>,
+    // Jumps that go to an error instead of a line, as for a FOR without NEXT
+    error_jumps: Vec<(usize, usize)>,
+    // User function bodies, by the index in bodies they are given
+    functions: Vec<(usize, evaluator::UserFunction)>,
+}
+
+impl<'a> Compiler<'a> {
+    // Starts the code for a statement, which errors are reported against
+    fn statement(&mut self, line_index: usize, at: String, prefix: String) {
+        self.program.statements.push(Statement {
+            line_number: self.loaded.lines[line_index].line_number.clone(),
+            line: line_index,
+            at,
+            prefix,
+        });
+        self.emit(Op::Statement(self.program.statements.len() - 1));
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.program.code.push(op);
+        self.program.code.len() - 1
+    }
+
+    fn slot(slots: &mut HashMap<String, usize>, names: &mut Vec<String>, name: &str) -> usize {
+        if let Some(slot) = slots.get(name) {
+            return *slot;
+        }
+        names.push(name.to_string());
+        slots.insert(name.to_string(), names.len() - 1);
+        names.len() - 1
+    }
+
+    fn variable(&mut self, name: &str) -> usize {
+        Compiler::slot(&mut self.variable_slots, &mut self.program.variables, name)
+    }
+
+    fn array(&mut self, name: &str) -> usize {
+        Compiler::slot(&mut self.array_slots, &mut self.program.arrays, name)
+    }
+
+    fn function(&mut self, name: &str) -> usize {
+        Compiler::slot(&mut self.function_slots, &mut self.program.functions, name)
+    }
+
+    fn constant(&mut self, value: value::RBasicValue) {
+        self.program.constants.push(value);
+        let index = self.program.constants.len() - 1;
+        self.emit(Op::Constant(index));
+    }
+
+    fn error(&mut self, code: i32, message: String) -> usize {
+        self.program
+            .errors
+            .push(error::RBasicError::new(code, message));
+        self.program.errors.len() - 1
+    }
+
+    fn raise(&mut self, code: i32, message: String) {
+        let error = self.error(code, message);
+        self.emit(Op::Raise(error));
+    }
+
+    fn fail(&mut self, code: i32, message: &str) {
+        let error = self.error(code, message.to_string());
+        self.emit(Op::Fail(error));
+    }
+
+    fn fail_with(&mut self, e: error::RBasicError) {
+        self.program.errors.push(e);
+        let error = self.program.errors.len() - 1;
+        self.emit(Op::Fail(error));
+    }
+
+    // Emits an instruction jumping to the line with the given index
+    fn jump_to_line(&mut self, op: Op, line_index: usize) {
+        let index = self.emit(op);
+        self.line_jumps.push((index, line_index));
+    }
+
+    // Emits an instruction jumping to an error raised when the jump is taken
+    fn jump_to_error(&mut self, op: Op, code: i32, message: String) {
+        let index = self.emit(op);
+        let error = self.error(code, message);
+        self.error_jumps.push((index, error));
+    }
+
+    fn line(&mut self, line_index: usize) -> Result<(), error::RBasicError> {
+        let loaded = self.loaded;
+        let line = &loaded.lines[line_index];
+        let line_number = &line.line_number;
+        let tokens = &line.tokens;
+        let mut token_iter = tokens.iter().peekable();
+
+        let (pos, token) = match token_iter.next() {
+            Some(lexer::TokenAndPos(pos, token)) => (*pos, token),
+            None => {
+                // Empty lines still count towards the statement limit
+                self.statement(line_index, String::new(), String::new());
+                return Ok(());
+            }
+        };
+        let at = format!("At {:?}, {} ", line_number, pos);
+
+        let unsupported = match *token {
+            token::Token::Print | token::Token::Input => {
+                token_iter.peek().map(|next| &next.1) == Some(&token::Token::Hash)
+            }
+            token::Token::Tron
+            | token::Token::Troff
+            | token::Token::Open
+            | token::Token::Close
+            | token::Token::Write
+            | token::Token::Field
+            | token::Token::Lset
+            | token::Token::Rset
+            | token::Token::Get
+            | token::Token::Put
+            | token::Token::Line => true,
+            _ => false,
+        };
+        if unsupported {
+            return Err(error::RBasicError::new(
+                error::ADVANCED_FEATURE,
+                format!(
+                    "{}{} can't be compiled",
+                    at,
+                    token.keyword_text().unwrap_or("statement")
+                ),
+            ));
+        }
+
+        let prefix = match *token {
+            token::Token::Randomize => format!("{}error in RANDOMIZE expression: ", at),
+            token::Token::Error => format!("{}error in ERROR expression: ", at),
+            token::Token::On => format!("{}error in ON expression: ", at),
+            token::Token::Let
+                if tokens.get(2).map(|next| &next.1) != Some(&token::Token::LParen) =>
+            {
+                format!("{}error in LET expression: ", at)
+            }
+            token::Token::Print => format!(
+                "At {:?}. {} PRINT must be followed by valid expression: ",
+                line_number, pos
+            ),
+            token::Token::If => format!("{}error in IF expression: ", at),
+            token::Token::While => format!("{}error in WHILE expression: ", at),
+            token::Token::Do => format!("{}error in DO condition: ", at),
+            token::Token::Loop => format!("{}error in LOOP condition: ", at),
+            _ => at.clone(),
+        };
+        self.statement(line_index, at.clone(), prefix.clone());
+
+        match *token {
+            token::Token::Rem | token::Token::Data => {}
+
+            token::Token::Read => self.read(&mut token_iter),
+
+            token::Token::Restore => match evaluator::restore_data(
+                &mut token_iter,
+                loaded.targets[line_index].first().copied(),
+                &loaded.data,
+            ) {
+                Ok(data_index) => {
+                    self.emit(Op::Restore(data_index));
+                }
+                Err(e) => self.fail_with(e),
+            },
+
+            token::Token::End => {
+                self.emit(Op::End);
+            }
+
+            token::Token::Stop => {
+                self.emit(Op::Stop);
+            }
+
+            token::Token::Goto => self.goto(&mut token_iter, line_index, pos, false),
+
+            token::Token::Gosub => self.goto(&mut token_iter, line_index, pos, true),
+
+            token::Token::Return => {
+                self.emit(Op::Return);
+            }
+
+            token::Token::Randomize => {
+                if token_iter.peek().is_none() {
+                    self.emit(Op::SeedFromTime);
+                } else {
+                    self.expression(&mut token_iter, &[]);
+                    self.emit(Op::Randomize);
+                }
+            }
+
+            token::Token::Error => {
+                self.expression(&mut token_iter, &[]);
+                self.emit(Op::Error);
+            }
+
+            token::Token::On if tokens.get(1).map(|next| &next.1) == Some(&token::Token::Error) => {
+                let target = evaluator::parse_on_error_target(&mut token_iter)
+                    .expect("ON ERROR statements are validated when the program is loaded");
+                if target.0 == 0 {
+                    self.emit(Op::OnError(None));
+                } else {
+                    self.jump_to_line(Op::OnError(Some(0)), loaded.targets[line_index][0]);
+                }
+            }
+
+            token::Token::Resume => {
+                let resume = match token_iter.next() {
+                    None | Some(&lexer::TokenAndPos(_, token::Token::Number(0))) => Resume::Error,
+                    Some(&lexer::TokenAndPos(_, token::Token::Next)) => Resume::Next,
+                    Some(&lexer::TokenAndPos(_, token::Token::Number(_))) => {
+                        let op = Op::Resume(Resume::Line(0));
+                        self.jump_to_line(op, loaded.targets[line_index][0]);
+                        return Ok(());
+                    }
+                    Some(&lexer::TokenAndPos(pos, _)) => Resume::Invalid(self.error(
+                        error::SYNTAX_ERROR,
+                        format!(
+                            "At {:?}, {} RESUME must be followed by NEXT or a line number",
+                            line_number, pos
+                        ),
+                    )),
+                };
+                self.emit(Op::Resume(resume));
+            }
+
+            token::Token::On => {
+                self.expression(&mut token_iter, &[]);
+                let (is_gosub, targets) = evaluator::parse_on_targets(&mut token_iter)
+                    .expect("ON statements are validated when the program is loaded");
+                // Line indices for now, until every line has been compiled
+                let targets = loaded.targets[line_index][..targets.len()].to_vec();
+                self.program
+                    .on_targets
+                    .push(OnTargets { is_gosub, targets });
+                self.emit(Op::On(self.program.on_targets.len() - 1));
+            }
+
+            token::Token::Let
+                if tokens.get(2).map(|next| &next.1) == Some(&token::Token::LParen) =>
+            {
+                let name = match token_iter.next() {
+                    Some(lexer::TokenAndPos(_, token::Token::Variable(name, _))) => name,
+                    _ => {
+                        self.fail(error::SYNTAX_ERROR, "invalid syntax for LET.");
+                        return Ok(());
+                    }
+                };
+                let array = self.array(name);
+                let count = match self.subscripts(&mut token_iter) {
+                    Some(count) => count,
+                    None => return Ok(()),
+                };
+                match token_iter.next() {
+                    Some(lexer::TokenAndPos(_, token::Token::Equals)) => {}
+                    _ => {
+                        self.fail(error::SYNTAX_ERROR, "invalid syntax for LET.");
+                        return Ok(());
+                    }
+                }
+                self.expression(&mut token_iter, &[]);
+                self.emit(Op::StoreElement(array, count));
+            }
+
+            token::Token::Let => {
+                let (variable, equals) = (token_iter.next(), token_iter.next());
+                self.expression(&mut token_iter, &[]);
+                match (variable, equals) {
+                    (
+                        Some(lexer::TokenAndPos(_, token::Token::Variable(variable, _))),
+                        Some(lexer::TokenAndPos(_, token::Token::Equals)),
+                    ) => {
+                        let slot = self.variable(variable);
+                        self.emit(Op::Store(slot));
+                    }
+                    _ => {
+                        self.emit(Op::Pop);
+                        self.raise(
+                            error::SYNTAX_ERROR,
+                            format!("{}invalid syntax for LET.", at),
+                        );
+                    }
+                }
+            }
+
+            token::Token::Print => {
+                self.expression(&mut token_iter, &[]);
+                match token_iter.peek() {
+                    Some(lexer::TokenAndPos(extra_pos, _)) => {
+                        self.emit(Op::Pop);
+                        let e = evaluator::print_takes_one_expression(line_number, *extra_pos);
+                        self.raise(e.code, e.message);
+                    }
+                    None => {
+                        self.emit(Op::Print);
+                    }
+                }
+            }
+
+            token::Token::Dim => self.dim(&mut token_iter),
+
+            token::Token::Input => {
+                let prompt = evaluator::parse_input_prompt(&mut token_iter);
+                match token_iter.next() {
+                    Some(lexer::TokenAndPos(_, token::Token::Variable(variable, _))) => {
+                        let slot = self.variable(variable);
+                        self.program.prompts.push(prompt);
+                        self.emit(Op::Input(slot, self.program.prompts.len() - 1));
+                    }
+                    _ => self.raise(
+                        error::SYNTAX_ERROR,
+                        format!(
+                            "At {:?}, {} INPUT must be followed by a variable name",
+                            line_number,
+                            pos + 5
+                        ),
+                    ),
+                }
+            }
+
+            token::Token::If => {
+                self.expression(&mut token_iter, &[]);
+                let syntax = format!("At {:?}, {}, invalid syntax for IF.", line_number, pos);
+                match (token_iter.next(), token_iter.next()) {
+                    (
+                        Some(lexer::TokenAndPos(_, token::Token::Then)),
+                        Some(lexer::TokenAndPos(_, token::Token::Number(_))),
+                    ) => {
+                        let error = self.error(error::SYNTAX_ERROR, syntax);
+                        let branch = Op::Branch {
+                            when: true,
+                            target: 0,
+                            error,
+                        };
+                        self.jump_to_line(branch, loaded.targets[line_index][0]);
+                    }
+                    _ => {
+                        self.emit(Op::Pop);
+                        self.raise(error::SYNTAX_ERROR, syntax);
+                    }
+                }
+            }
+
+            token::Token::For => self.for_loop(&mut token_iter, line_index, &at),
+
+            token::Token::Next => match evaluator::parse_next(&mut token_iter) {
+                Ok(variables) => {
+                    let slots = variables
+                        .iter()
+                        .map(|variable| variable.map(|name| self.variable(name)))
+                        .collect();
+                    self.program.next_variables.push(slots);
+                    self.emit(Op::Next(self.program.next_variables.len() - 1));
+                }
+                Err(e) => self.fail_with(e),
+            },
+
+            token::Token::While => {
+                self.expression(&mut token_iter, &[]);
+                let error = self.error(
+                    error::TYPE_MISMATCH,
+                    format!("{}WHILE condition must be a Boolean value", at),
+                );
+                let branch = Op::Branch {
+                    when: false,
+                    target: 0,
+                    error,
+                };
+                self.jump_to_line(branch, self.loaded.blocks[&line_index] + 1);
+            }
+
+            token::Token::Wend => {
+                self.jump_to_line(Op::Jump(0), self.loaded.blocks[&line_index]);
+            }
+
+            token::Token::Do | token::Token::Loop => {
+                let until = match token_iter.next() {
+                    None => {
+                        if *token == token::Token::Loop {
+                            self.jump_to_line(Op::Jump(0), self.loaded.blocks[&line_index]);
+                        }
+                        return Ok(());
+                    }
+                    Some(lexer::TokenAndPos(_, token::Token::While)) => false,
+                    Some(lexer::TokenAndPos(_, token::Token::Until)) => true,
+                    Some(_) => {
+                        self.fail(error::SYNTAX_ERROR, "expected WHILE or UNTIL");
+                        return Ok(());
+                    }
+                };
+
+                self.expression(&mut token_iter, &[]);
+                let error = self.error(
+                    error::TYPE_MISMATCH,
+                    format!("{}condition must be a Boolean value", prefix),
+                );
+                // DO leaves the loop once the condition says to stop, and LOOP
+                // goes back to the DO while it says to keep going
+                if *token == token::Token::Do {
+                    let branch = Op::Branch {
+                        when: until,
+                        target: 0,
+                        error,
+                    };
+                    self.jump_to_line(branch, self.loaded.blocks[&line_index] + 1);
+                } else {
+                    let branch = Op::Branch {
+                        when: !until,
+                        target: 0,
+                        error,
+                    };
+                    self.jump_to_line(branch, self.loaded.blocks[&line_index]);
+                }
+            }
+
+            token::Token::Exit => {
+                self.jump_to_line(Op::Jump(0), self.loaded.blocks[&line_index] + 1);
+            }
+
+            token::Token::Def => match evaluator::parse_function_definition(&mut token_iter) {
+                Ok((name, function)) => {
+                    let slot = self.function(&name);
+                    self.program.bodies.push(FunctionBody {
+                        start: 0,
+                        parameters: function.parameters.len(),
+                    });
+                    let body = self.program.bodies.len() - 1;
+                    self.functions.push((body, function));
+                    self.emit(Op::Define(slot, body));
+                }
+                Err(e) => self.raise(
+                    error::SYNTAX_ERROR,
+                    format!("{}invalid syntax for DEF: {}", at, e),
+                ),
+            },
+
+            _ => self.raise(error::SYNTAX_ERROR, format!("{}invalid syntax", at)),
+        }
+
+        Ok(())
+    }
+
+    fn goto(
+        &mut self,
+        token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+        line_index: usize,
+        pos: u32,
+        is_gosub: bool,
+    ) {
+        let loaded = self.loaded;
+        let line_number = &loaded.lines[line_index].line_number;
+        let (keyword, op) = if is_gosub {
+            ("GOSUB", Op::Gosub(0))
+        } else {
+            ("GOTO", Op::Jump(0))
+        };
+
+        match token_iter.next() {
+            Some(&lexer::TokenAndPos(_, token::Token::Number(_))) => {
+                self.jump_to_line(op, loaded.targets[line_index][0])
+            }
+            Some(&lexer::TokenAndPos(pos, _)) => self.raise(
+                error::SYNTAX_ERROR,
+                format!(
+                    "At {:?}, {} {} must be followed by valid line number",
+                    line_number, pos, keyword
+                ),
+            ),
+            None => self.raise(
+                error::SYNTAX_ERROR,
+                format!(
+                    "At {:?}, {} {} must be followed by a line number",
+                    line_number,
+                    // The position past the keyword
+                    pos + keyword.len() as u32,
+                    keyword
+                ),
+            ),
+        }
+    }
+
+    fn read(&mut self, token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>) {
+        loop {
+            let name = match token_iter.next() {
+                Some(lexer::TokenAndPos(_, token::Token::Variable(name, _))) => name,
+                _ => return self.fail(error::SYNTAX_ERROR, "READ must be followed by variables"),
+            };
+
+            match token_iter.peek() {
+                Some(lexer::TokenAndPos(_, token::Token::LParen)) => {
+                    let array = self.array(name);
+                    match self.subscripts(token_iter) {
+                        Some(count) => self.emit(Op::ReadElement(array, count)),
+                        None => return,
+                    };
+                }
+                _ => {
+                    let slot = self.variable(name);
+                    self.emit(Op::ReadVariable(slot));
+                }
+            }
+
+            match token_iter.next() {
+                None => return,
+                Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+                _ => {
+                    return self.fail(error::SYNTAX_ERROR, "variables must be separated by commas")
+                }
+            }
+        }
+    }
+
+    fn dim(&mut self, token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>) {
+        loop {
+            let name = match token_iter.next() {
+                Some(lexer::TokenAndPos(_, token::Token::Variable(name, _))) => name,
+                _ => {
+                    return self.fail(
+                        error::SYNTAX_ERROR,
+                        "DIM must be followed by array names and sizes",
+                    )
+                }
+            };
+
+            let array = self.array(name);
+            match self.subscripts(token_iter) {
+                Some(count) => self.emit(Op::Dim(array, count)),
+                None => return,
+            };
+
+            match token_iter.next() {
+                None => return,
+                Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+                _ => return self.fail(error::SYNTAX_ERROR, "arrays must be separated by commas"),
+            }
+        }
+    }
+
+    fn for_loop(
+        &mut self,
+        token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+        line_index: usize,
+        at: &str,
+    ) {
+        let variable = match (token_iter.next(), token_iter.next()) {
+            (
+                Some(lexer::TokenAndPos(_, token::Token::Variable(variable, _))),
+                Some(lexer::TokenAndPos(_, token::Token::Equals)),
+            ) => self.variable(variable),
+            _ => {
+                return self.fail(
+                    error::SYNTAX_ERROR,
+                    "FOR must be followed by a variable and =",
+                )
+            }
+        };
+
+        self.expression(token_iter, &[]);
+
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::To)) => {}
+            _ => {
+                return self.fail(
+                    error::SYNTAX_ERROR,
+                    "FOR starting value must be followed by TO",
+                )
+            }
+        }
+
+        self.expression(token_iter, &[]);
+
+        match token_iter.next() {
+            None => self.constant(value::RBasicValue::Number(1)),
+            Some(lexer::TokenAndPos(_, token::Token::Step)) => self.expression(token_iter, &[]),
+            _ => {
+                return self.fail(
+                    error::SYNTAX_ERROR,
+                    "FOR end value must be followed by STEP or nothing",
+                )
+            }
+        }
+
+        // A loop that wouldn't run at all continues after its NEXT
+        let op = Op::For { variable, exit: 0 };
+        match evaluator::find_next(&self.loaded.lines, line_index) {
+            Some(next_index) => self.jump_to_line(op, next_index + 1),
+            None => self.jump_to_error(
+                op,
+                error::FOR_WITHOUT_NEXT,
+                format!("{}FOR without NEXT", at),
+            ),
+        }
+    }
+
+    // Compiles a parenthesized list of subscripts the way parse_subscripts
+    // reads it, returning how many there are, or None if the code compiled
+    // ends with an error
+    fn subscripts(
+        &mut self,
+        token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    ) -> Option<usize> {
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::LParen)) => {}
+            _ => {
+                self.fail(error::SYNTAX_ERROR, "expected ( and subscripts");
+                return None;
+            }
+        }
+
+        let mut count = 0;
+        let mut subscript_tokens: Vec<lexer::TokenAndPos> = Vec::new();
+        let mut depth = 0;
+
+        loop {
+            let next = match token_iter.next() {
+                Some(next) => next,
+                None => {
+                    self.fail(error::SYNTAX_ERROR, "missing ) after subscripts");
+                    return None;
+                }
+            };
+
+            match next.1 {
+                token::Token::Comma | token::Token::RParen if depth == 0 => {
+                    if subscript_tokens.is_empty() {
+                        self.fail(error::SYNTAX_ERROR, "missing subscript");
+                        return None;
+                    }
+                    let mut subscript_iter = subscript_tokens.iter().peekable();
+                    self.expression(&mut subscript_iter, &[]);
+                    if subscript_iter.peek().is_some() {
+                        self.fail(error::SYNTAX_ERROR, "invalid subscript");
+                        return None;
+                    }
+                    subscript_tokens.clear();
+                    count += 1;
+
+                    if next.1 == token::Token::RParen {
+                        self.emit(Op::Subscripts(count));
+                        return Some(count);
+                    }
+                }
+                token::Token::LParen => {
+                    depth += 1;
+                    subscript_tokens.push(next.clone());
+                }
+                token::Token::RParen => {
+                    depth -= 1;
+                    subscript_tokens.push(next.clone());
+                }
+                _ => subscript_tokens.push(next.clone()),
+            }
+        }
+    }
+
+    // Compiles an expression, with the values it works with tracked as
+    // parse_and_eval_expression does so that badly formed expressions fail
+    // the same way
+    fn expression(
+        &mut self,
+        token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+        parameters: &[String],
+    ) {
+        let output_queue = match evaluator::parse_expression(token_iter) {
+            Ok(output_queue) => output_queue,
+            Err(_) => return self.fail(error::SYNTAX_ERROR, "Invalid expression!"),
+        };
+        let mut depth = 0;
+
+        for token in output_queue {
+            match token {
+                token::Token::Number(number) => {
+                    self.constant(value::RBasicValue::Number(number));
+                    depth += 1;
+                }
+                token::Token::Float(float) => {
+                    self.constant(value::RBasicValue::Float(float));
+                    depth += 1;
+                }
+                token::Token::BString(bstring) => {
+                    self.constant(value::RBasicValue::String(bstring));
+                    depth += 1;
+                }
+                token::Token::Variable(ref name, _) => {
+                    if &**name == "ERR" {
+                        self.emit(Op::ErrorCode);
+                    } else if &**name == "ERL" {
+                        self.emit(Op::ErrorLine);
+                    } else if let Some(index) = parameters.iter().position(|p| **p == **name) {
+                        self.emit(Op::LoadParameter(index));
+                    } else {
+                        let slot = self.variable(name);
+                        self.emit(Op::Load(slot));
+                    }
+                    depth += 1;
+                }
+                ref unary_token if unary_token.is_unary_operator() => {
+                    if depth == 0 {
+                        let message = format!("Operator {:?} requires an operand!", unary_token);
+                        return self.fail(error::MISSING_OPERAND, &message);
+                    }
+                    self.emit(match *unary_token {
+                        token::Token::UMinus => Op::Negate,
+                        _ => Op::Not,
+                    });
+                }
+                ref comparison_token if comparison_token.is_comparison_operator() => {
+                    if depth < 2 {
+                        let message = format!(
+                            "Comparison operator {:?} requires two operands",
+                            comparison_token
+                        );
+                        return self.fail(error::MISSING_OPERAND, &message);
+                    }
+                    self.emit(match *comparison_token {
+                        token::Token::Equals => Op::Equal,
+                        token::Token::NotEqual => Op::NotEqual,
+                        token::Token::LessThan => Op::LessThan,
+                        token::Token::GreaterThan => Op::GreaterThan,
+                        token::Token::LessThanEqual => Op::LessThanEqual,
+                        _ => Op::GreaterThanEqual,
+                    });
+                    depth -= 1;
+                }
+                ref binary_op_token if binary_op_token.is_binary_operator() => {
+                    // As when interpreted, a binary operator without two
+                    // operands is left out
+                    if depth >= 2 {
+                        self.emit(match *binary_op_token {
+                            token::Token::Plus => Op::Add,
+                            token::Token::Minus => Op::Subtract,
+                            token::Token::Multiply => Op::Multiply,
+                            _ => Op::Divide,
+                        });
+                        depth -= 1;
+                    }
+                }
+                token::Token::FunctionCall(ref name, arg_count) => {
+                    if depth < arg_count {
+                        let message =
+                            format!("Function {} requires {} argument(s)", name, arg_count);
+                        return self.fail(error::MISSING_OPERAND, &message);
+                    }
+                    let array = self.array(name);
+                    let function = if name.starts_with("FN") {
+                        Some(self.function(name))
+                    } else {
+                        None
+                    };
+                    self.program.callees.push(Callee {
+                        name: name.to_string(),
+                        array,
+                        function,
+                    });
+                    self.emit(Op::Call(self.program.callees.len() - 1, arg_count));
+                    depth = depth - arg_count + 1;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        if depth != 1 {
+            self.fail(error::SYNTAX_ERROR, "Invalid expression!");
+        }
+    }
+
+    // Adds the errors some jumps go to and the user function bodies after
+    // the program, and fills in where every jump goes
+    fn finish(mut self) -> Program {
+        for (index, error) in std::mem::take(&mut self.error_jumps) {
+            let target = self.emit(Op::Raise(error));
+            set_target(&mut self.program.code[index], target);
+        }
+
+        for (body, function) in std::mem::take(&mut self.functions) {
+            self.program.bodies[body].start = self.program.code.len();
+            self.expression(&mut function.body.iter().peekable(), &function.parameters);
+            self.emit(Op::ReturnValue);
+        }
+
+        for (index, line_index) in std::mem::take(&mut self.line_jumps) {
+            let target = self.program.line_starts[line_index];
+            set_target(&mut self.program.code[index], target);
+        }
+
+        for on_targets in &mut self.program.on_targets {
+            for target in &mut on_targets.targets {
+                *target = self.program.line_starts[*target];
+            }
+        }
+
+        self.program
+    }
+}
+
+fn set_target(op: &mut Op, new_target: usize) {
+    match op {
+        Op::Jump(target) | Op::Gosub(target) => *target = new_target,
+        Op::Branch { target, .. } => *target = new_target,
+        Op::For { exit, .. } => *exit = new_target,
+        Op::OnError(Some(target)) | Op::Resume(Resume::Line(target)) => *target = new_target,
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,186 @@
+use crate::lexer;
+use crate::token;
+
+use std::collections::BTreeMap;
+use std::io;
+
+// How often an IF jumped to its line and how often it fell through
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// Counts the lines a program runs and the way each IF went, for --coverage
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    lines: BTreeMap<lexer::LineNumber, u64>,
+    branches: BTreeMap<lexer::LineNumber, BranchCounts>,
+}
+
+// A line of the source file that can be run, with its number in the file,
+// counting from 1
+struct SourceLine<'a> {
+    file_line: usize,
+    text: &'a str,
+    line_number: lexer::LineNumber,
+    is_if: bool,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    pub fn record_line(&mut self, line_number: &lexer::LineNumber) {
+        *self.lines.entry(line_number.clone()).or_default() += 1;
+    }
+
+    pub fn record_branch(&mut self, line_number: &lexer::LineNumber, taken: bool) {
+        let branch = self.branches.entry(line_number.clone()).or_default();
+        if taken {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+
+    // How often the line ran
+    pub fn line(&self, line_number: &lexer::LineNumber) -> u64 {
+        self.lines.get(line_number).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, line_number: &lexer::LineNumber) -> Option<&BranchCounts> {
+        self.branches.get(line_number)
+    }
+
+    // Adds the counts of another run of the same program
+    pub fn merge(&mut self, other: &Coverage) {
+        for (line_number, count) in &other.lines {
+            *self.lines.entry(line_number.clone()).or_default() += count;
+        }
+        for (line_number, counts) in &other.branches {
+            let branch = self.branches.entry(line_number.clone()).or_default();
+            branch.taken += counts.taken;
+            branch.not_taken += counts.not_taken;
+        }
+    }
+
+    // Writes the counts as an lcov tracefile for the program read from path,
+    // numbering the lines as they are in the file so that coverage viewers
+    // can show them beside it. Each IF is a pair of branches, jumping and
+    // falling through. Comments, DATA and blank lines aren't counted.
+    pub fn write_lcov<S: AsRef<str>>(
+        &self,
+        path: &str,
+        source_lines: &[S],
+        output: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        let lines = runnable_lines(source_lines);
+        let mut lines_hit = 0;
+        let mut branches_found = 0;
+        let mut branches_hit = 0;
+
+        writeln!(output, "TN:")?;
+        writeln!(output, "SF:{}", path)?;
+        for line in &lines {
+            let count = self.line(&line.line_number);
+            if count > 0 {
+                lines_hit += 1;
+            }
+            writeln!(output, "DA:{},{}", line.file_line, count)?;
+
+            if line.is_if {
+                branches_found += 2;
+                let counts = self.branch(&line.line_number);
+                for (branch, taken) in [
+                    counts.map(|counts| counts.taken),
+                    counts.map(|counts| counts.not_taken),
+                ]
+                .iter()
+                .enumerate()
+                {
+                    match taken {
+                        Some(taken) => {
+                            if *taken > 0 {
+                                branches_hit += 1;
+                            }
+                            writeln!(output, "BRDA:{},0,{},{}", line.file_line, branch, taken)?
+                        }
+                        // An IF that never ran
+                        None => writeln!(output, "BRDA:{},0,{},-", line.file_line, branch)?,
+                    }
+                }
+            }
+        }
+        writeln!(output, "BRF:{}", branches_found)?;
+        writeln!(output, "BRH:{}", branches_hit)?;
+        writeln!(output, "LF:{}", lines.len())?;
+        writeln!(output, "LH:{}", lines_hit)?;
+        writeln!(output, "end_of_record")
+    }
+
+    // Lists the program with how often each line ran before it, ##### for
+    // lines that never ran and - for those that aren't counted, and how each
+    // IF went after it, as in:
+    //          3: 30 IF X > 1 THEN 50   [taken 1, not taken 2]
+    //      #####: 40 PRINT "NEVER"
+    pub fn annotate<S: AsRef<str>>(&self, source_lines: &[S]) -> String {
+        let lines: BTreeMap<usize, SourceLine> = runnable_lines(source_lines)
+            .into_iter()
+            .map(|line| (line.file_line, line))
+            .collect();
+        let mut listing = String::new();
+
+        for (index, text) in source_lines.iter().enumerate() {
+            let text = text.as_ref();
+            let line = match lines.get(&(index + 1)) {
+                Some(line) => line,
+                None => {
+                    listing.push_str(format!("{:>9}: {}", "-", text).trim_end());
+                    listing.push('\n');
+                    continue;
+                }
+            };
+
+            let count = self.line(&line.line_number);
+            let count = if count == 0 {
+                "#####".to_string()
+            } else {
+                count.to_string()
+            };
+            listing.push_str(&format!("{:>9}: {}", count, line.text));
+            if line.is_if {
+                let counts = self.branch(&line.line_number).cloned().unwrap_or_default();
+                listing.push_str(&format!(
+                    "   [taken {}, not taken {}]",
+                    counts.taken, counts.not_taken
+                ));
+            }
+            listing.push('\n');
+        }
+
+        listing
+    }
+}
+
+fn runnable_lines<S: AsRef<str>>(source_lines: &[S]) -> Vec<SourceLine<'_>> {
+    source_lines
+        .iter()
+        .enumerate()
+        .filter_map(|(index, text)| {
+            let text = text.as_ref();
+            let code_line = lexer::tokenize_line(text).ok()?;
+            let statement = code_line.tokens.first().map(|first| &first.1);
+            match statement {
+                None | Some(token::Token::Rem) | Some(token::Token::Data) => None,
+                _ => Some(SourceLine {
+                    file_line: index + 1,
+                    text,
+                    is_if: statement == Some(&token::Token::If),
+                    line_number: code_line.line_number,
+                }),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,298 @@
+use crate::evaluator;
+use crate::lexer;
+use crate::value;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+const HELP: &str = "Commands:
+  break N      stop before running line N (b)
+  delete N     remove the breakpoint on line N (d)
+  step         run the next line (s)
+  continue     run until a breakpoint, a watched variable changes or the end (c)
+  print VAR    show a variable (p)
+  set VAR = X  change a variable to a number, \"string\", TRUE or FALSE
+  watch VAR    stop when a variable changes (w)
+  unwatch VAR  stop watching a variable
+  stack        show the active GOSUBs and FOR loops (bt)
+  list         show the next line to run (l)
+  restart      start the program again from the beginning
+  quit         leave the debugger (q)";
+
+// Runs a program under the control of commands, for finding out where it
+// goes wrong
+pub struct Debugger {
+    interpreter: evaluator::Interpreter,
+    // The text of each line, to show where the program is
+    source: BTreeMap<lexer::LineNumber, String>,
+    breakpoints: BTreeSet<lexer::LineNumber>,
+    // Watched variables, with their values when last checked
+    watches: Vec<(String, Option<value::RBasicValue>)>,
+    finished: bool,
+}
+
+impl Debugger {
+    pub fn new(mut interpreter: evaluator::Interpreter, source_lines: &[&str]) -> Debugger {
+        let source = source_lines
+            .iter()
+            .filter_map(|line| {
+                lexer::tokenize_line(line)
+                    .ok()
+                    .map(|code| (code.line_number, line.trim().to_string()))
+            })
+            .collect();
+        interpreter.start();
+
+        Debugger {
+            interpreter,
+            source,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            finished: false,
+        }
+    }
+
+    // Reads commands until quit or the end of input
+    pub fn run(&mut self) -> Result<(), io::Error> {
+        println!("{}", self.where_am_i());
+
+        loop {
+            print!("(debug) ");
+            io::stdout().flush()?;
+
+            let mut input = String::new();
+
+            if io::stdin().read_line(&mut input)? == 0 {
+                break;
+            }
+
+            match input.trim() {
+                "quit" | "q" => break,
+                "" => {}
+                command => println!("{}", self.command(command)),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Carries out a command, returning what to show for it
+    pub fn command(&mut self, input: &str) -> String {
+        let (command, argument) = match input.find(char::is_whitespace) {
+            Some(end) => (&input[..end], input[end..].trim()),
+            None => (input, ""),
+        };
+
+        match (command, argument) {
+            ("help" | "h", _) => HELP.to_string(),
+            ("break" | "b", line) => match self.line_number(line) {
+                Ok(line_number) => {
+                    let message = format!("Breakpoint at line {}", line_number.0);
+                    self.breakpoints.insert(line_number);
+                    message
+                }
+                Err(e) => e,
+            },
+            ("delete" | "d", line) => match self.line_number(line) {
+                Ok(ref line_number) if self.breakpoints.remove(line_number) => {
+                    format!("Deleted breakpoint at line {}", line_number.0)
+                }
+                Ok(line_number) => format!("No breakpoint at line {}", line_number.0),
+                Err(e) => e,
+            },
+            ("step" | "s", "") => self.resume(true),
+            ("continue" | "c", "") => self.resume(false),
+            ("print" | "p", name) if !name.is_empty() => match self.interpreter.variable(name) {
+                Some(value) => format!("{} = {}", name, describe(&value)),
+                None => format!("{} is not set", name),
+            },
+            ("set", assignment) => match assignment.find('=') {
+                Some(equals) => {
+                    let name = assignment[..equals].trim().to_string();
+                    match parse_value(assignment[equals + 1..].trim()) {
+                        Some(value) if !name.is_empty() => {
+                            let message = format!("{} = {}", name, describe(&value));
+                            match self.interpreter.set_variable(&name, value) {
+                                Ok(()) => {
+                                    self.update_watches();
+                                    message
+                                }
+                                Err(e) => e.message,
+                            }
+                        }
+                        _ => "set needs a variable, = and a value".to_string(),
+                    }
+                }
+                None => "set needs a variable, = and a value".to_string(),
+            },
+            ("watch" | "w", name) if !name.is_empty() => {
+                let name = name.to_string();
+                let value = self.interpreter.variable(&name);
+                let message = format!("Watching {}", name);
+                self.watches.retain(|(watched, _)| *watched != name);
+                self.watches.push((name, value));
+                message
+            }
+            ("unwatch", name) if !name.is_empty() => {
+                let count = self.watches.len();
+                self.watches.retain(|(watched, _)| *watched != name);
+                if self.watches.len() < count {
+                    format!("Stopped watching {}", name)
+                } else {
+                    format!("{} is not being watched", name)
+                }
+            }
+            ("stack" | "bt", "") => self.stack(),
+            ("list" | "l", "") => self.where_am_i(),
+            ("restart", "") => {
+                self.interpreter.start();
+                self.finished = false;
+                self.update_watches();
+                self.where_am_i()
+            }
+            _ => format!("Unknown command: {} (try help)", input),
+        }
+    }
+
+    // Runs one line, or keeps running until there's a reason to stop
+    fn resume(&mut self, single_step: bool) -> String {
+        if self.finished {
+            return "The program has finished, use restart to run it again".to_string();
+        }
+
+        loop {
+            match self.interpreter.step_line() {
+                Ok(None) => {}
+                Ok(Some(evaluator::ExecutionStatus::Ended)) => {
+                    self.finished = true;
+                    return "Program ended".to_string();
+                }
+                Ok(Some(status)) => return format!("{}\n{}", status, self.where_am_i()),
+                Err(e) => {
+                    self.finished = true;
+                    return format!("Execution failed: {}", e);
+                }
+            }
+
+            let changes = self.update_watches();
+            if !changes.is_empty() {
+                return format!("{}\n{}", changes.join("\n"), self.where_am_i());
+            }
+
+            match self.interpreter.current_line() {
+                Some(ref line_number) if self.breakpoints.contains(line_number) => {
+                    return format!(
+                        "Breakpoint at line {}\n{}",
+                        line_number.0,
+                        self.where_am_i()
+                    );
+                }
+                _ if single_step => return self.where_am_i(),
+                _ => {}
+            }
+        }
+    }
+
+    // Records the current values of the watched variables, returning a
+    // description of each one that changed
+    fn update_watches(&mut self) -> Vec<String> {
+        let interpreter = &self.interpreter;
+        let mut changes = Vec::new();
+
+        for (name, last_value) in self.watches.iter_mut() {
+            let value = interpreter.variable(name);
+
+            if value != *last_value {
+                changes.push(format!(
+                    "{} changed from {} to {}",
+                    name,
+                    last_value.as_ref().map_or("unset".to_string(), describe),
+                    value.as_ref().map_or("unset".to_string(), describe)
+                ));
+                *last_value = value;
+            }
+        }
+
+        changes
+    }
+
+    fn stack(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+
+        for line_number in self.interpreter.gosub_stack().iter().rev() {
+            lines.push(format!("GOSUB at line {}", line_number.0));
+        }
+
+        for for_loop in self.interpreter.for_stack().iter().rev() {
+            let current = self
+                .interpreter
+                .variable(&for_loop.variable)
+                .map_or("unset".to_string(), |value| describe(&value));
+            lines.push(format!(
+                "FOR {} TO {} STEP {} at line {}, with {} = {}",
+                for_loop.variable,
+                for_loop.end,
+                for_loop.step,
+                for_loop.line_number.0,
+                for_loop.variable,
+                current
+            ));
+        }
+
+        if lines.is_empty() {
+            "No active GOSUBs or FOR loops".to_string()
+        } else {
+            lines.join("\n")
+        }
+    }
+
+    fn where_am_i(&self) -> String {
+        match self.interpreter.current_line() {
+            Some(ref line_number) => format!(
+                "Next: {}",
+                self.source
+                    .get(line_number)
+                    .cloned()
+                    .unwrap_or_else(|| line_number.0.to_string())
+            ),
+            None => "At the end of the program".to_string(),
+        }
+    }
+
+    fn line_number(&self, line: &str) -> Result<lexer::LineNumber, String> {
+        match u32::from_str(line) {
+            Ok(number) if self.source.contains_key(&lexer::LineNumber(number)) => {
+                Ok(lexer::LineNumber(number))
+            }
+            Ok(number) => Err(format!("There is no line {}", number)),
+            Err(_) => Err("Expected a line number".to_string()),
+        }
+    }
+}
+
+// Shows strings in quotes so that they stand out from numbers
+fn describe(value: &value::RBasicValue) -> String {
+    match *value {
+        value::RBasicValue::String(ref string) => format!("\"{}\"", string),
+        ref value => value.to_string(),
+    }
+}
+
+fn parse_value(text: &str) -> Option<value::RBasicValue> {
+    if let Ok(number) = i32::from_str(text) {
+        Some(value::RBasicValue::Number(number))
+    } else if let Ok(float) = f64::from_str(text) {
+        Some(value::RBasicValue::Float(float))
+    } else if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        Some(value::RBasicValue::String(text[1..text.len() - 1].into()))
+    } else {
+        match text.to_uppercase().as_str() {
+            "TRUE" => Some(value::RBasicValue::Bool(true)),
+            "FALSE" => Some(value::RBasicValue::Bool(false)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+
+// Error codes, numbered as in GWBASIC so that programs trapping errors with
+// ON ERROR GOTO can test ERR the way they always have. Errors specific to
+// rbasic are numbered from 100.
+pub const NEXT_WITHOUT_FOR: i32 = 1;
+pub const SYNTAX_ERROR: i32 = 2;
+pub const RETURN_WITHOUT_GOSUB: i32 = 3;
+pub const OUT_OF_DATA: i32 = 4;
+pub const ILLEGAL_FUNCTION_CALL: i32 = 5;
+pub const OVERFLOW: i32 = 6;
+pub const OUT_OF_MEMORY: i32 = 7;
+pub const UNDEFINED_LINE_NUMBER: i32 = 8;
+pub const SUBSCRIPT_OUT_OF_RANGE: i32 = 9;
+pub const DUPLICATE_DEFINITION: i32 = 10;
+pub const DIVISION_BY_ZERO: i32 = 11;
+pub const TYPE_MISMATCH: i32 = 13;
+pub const CANT_CONTINUE: i32 = 17;
+pub const UNDEFINED_USER_FUNCTION: i32 = 18;
+pub const NO_RESUME: i32 = 19;
+pub const RESUME_WITHOUT_ERROR: i32 = 20;
+pub const MISSING_OPERAND: i32 = 22;
+pub const FOR_WITHOUT_NEXT: i32 = 26;
+pub const WHILE_WITHOUT_WEND: i32 = 29;
+pub const WEND_WITHOUT_WHILE: i32 = 30;
+pub const FIELD_OVERFLOW: i32 = 50;
+pub const INTERNAL_ERROR: i32 = 51;
+pub const BAD_FILE_NUMBER: i32 = 52;
+pub const FILE_NOT_FOUND: i32 = 53;
+pub const BAD_FILE_MODE: i32 = 54;
+pub const FILE_ALREADY_OPEN: i32 = 55;
+pub const DEVICE_IO_ERROR: i32 = 57;
+pub const INPUT_PAST_END: i32 = 62;
+pub const BAD_RECORD_NUMBER: i32 = 63;
+pub const BAD_FILE_NAME: i32 = 64;
+pub const PERMISSION_DENIED: i32 = 70;
+pub const ADVANCED_FEATURE: i32 = 73;
+pub const UNDEFINED_VARIABLE: i32 = 100;
+pub const STATEMENT_LIMIT: i32 = 101;
+pub const TIME_LIMIT: i32 = 102;
+pub const STRING_LIMIT: i32 = 103;
+pub const ARRAY_LIMIT: i32 = 104;
+pub const CALL_DEPTH_LIMIT: i32 = 105;
+pub const BAD_SNAPSHOT: i32 = 106;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RBasicError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl RBasicError {
+    pub fn new(code: i32, message: String) -> RBasicError {
+        RBasicError { code, message }
+    }
+}
+
+impl fmt::Display for RBasicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// The standard description of an error code, as used for errors raised by
+// the ERROR statement
+pub fn message_for_code(code: i32) -> &'static str {
+    match code {
+        NEXT_WITHOUT_FOR => "NEXT without FOR",
+        SYNTAX_ERROR => "Syntax error",
+        RETURN_WITHOUT_GOSUB => "RETURN without GOSUB",
+        OUT_OF_DATA => "Out of DATA",
+        ILLEGAL_FUNCTION_CALL => "Illegal function call",
+        OVERFLOW => "Overflow",
+        OUT_OF_MEMORY => "Out of memory",
+        UNDEFINED_LINE_NUMBER => "Undefined line number",
+        SUBSCRIPT_OUT_OF_RANGE => "Subscript out of range",
+        DUPLICATE_DEFINITION => "Duplicate Definition",
+        DIVISION_BY_ZERO => "Division by zero",
+        TYPE_MISMATCH => "Type mismatch",
+        CANT_CONTINUE => "Can't continue",
+        UNDEFINED_USER_FUNCTION => "Undefined user function",
+        NO_RESUME => "No RESUME",
+        RESUME_WITHOUT_ERROR => "RESUME without error",
+        MISSING_OPERAND => "Missing operand",
+        FOR_WITHOUT_NEXT => "FOR without NEXT",
+        WHILE_WITHOUT_WEND => "WHILE without WEND",
+        WEND_WITHOUT_WHILE => "WEND without WHILE",
+        FIELD_OVERFLOW => "FIELD overflow",
+        INTERNAL_ERROR => "Internal error",
+        BAD_FILE_NUMBER => "Bad file number",
+        FILE_NOT_FOUND => "File not found",
+        BAD_FILE_MODE => "Bad file mode",
+        FILE_ALREADY_OPEN => "File already open",
+        DEVICE_IO_ERROR => "Device I/O Error",
+        INPUT_PAST_END => "Input past end",
+        BAD_RECORD_NUMBER => "Bad record number",
+        BAD_FILE_NAME => "Bad file name",
+        PERMISSION_DENIED => "Permission Denied",
+        ADVANCED_FEATURE => "Advanced feature",
+        UNDEFINED_VARIABLE => "Undefined variable",
+        STATEMENT_LIMIT => "Statement limit exceeded",
+        TIME_LIMIT => "Time limit exceeded",
+        STRING_LIMIT => "String space limit exceeded",
+        ARRAY_LIMIT => "Array size limit exceeded",
+        CALL_DEPTH_LIMIT => "Call depth limit exceeded",
+        BAD_SNAPSHOT => "Bad snapshot",
+        _ => "Unprintable error",
+    }
+}
+
+// Errors from exceeding the limits set for a program, which ON ERROR GOTO
+// can't trap
+pub fn is_limit_error(code: i32) -> bool {
+    matches!(
+        code,
+        STATEMENT_LIMIT | TIME_LIMIT | STRING_LIMIT | ARRAY_LIMIT | CALL_DEPTH_LIMIT
+    )
+}
@@ -1,238 +1,3175 @@
+use crate::array;
+use crate::coverage;
+use crate::error;
+use crate::files;
+use crate::host;
 use crate::lexer;
+use crate::limits;
+use crate::native;
+use crate::packing;
+use crate::profiler;
+use crate::random;
+use crate::snapshot;
 use crate::token;
+use crate::trace;
 use crate::value;
+use crate::variables;
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
 use std::io;
+use std::io::Write;
 use std::iter::Peekable;
+use std::rc::Rc;
 use std::slice::Iter;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+
+// How deeply calls of user-defined functions may be nested, even without a
+// call depth limit. Each call is evaluated by a nested Rust call, so a
+// function calling itself forever would otherwise overflow the stack and
+// abort, rather than fail as an error the program can trap.
+pub const MAX_FUNCTION_DEPTH: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct UserFunction {
+    pub parameters: Vec<String>,
+    // The slot of each parameter
+    pub slots: Vec<usize>,
+    pub body: Vec<lexer::TokenAndPos>,
+}
 
 #[derive(Debug)]
 struct RBasicContext {
-    variables: HashMap<String, value::RBasicValue>,
+    variables: variables::Variables,
+    // Shared so that calling a function doesn't copy its body
+    functions: HashMap<String, Rc<UserFunction>>,
+    // Parameter bindings, by slot, of the user functions currently being
+    // called
+    scopes: Vec<Vec<(usize, value::RBasicValue)>>,
+    // Values of ERR and ERL for the last error trapped by ON ERROR GOTO
+    error_code: i32,
+    error_line: u32,
+    rng: random::Rng,
+    // Files opened with OPEN, by file number
+    files: HashMap<i32, files::BasicFile>,
+    arrays: HashMap<String, array::BasicArray>,
+    // Functions registered by the program embedding the interpreter
+    natives: HashMap<String, native::NativeFunction>,
+    limits: limits::Limits,
+    // What the program is using, to check against the limits
+    string_bytes: usize,
+    array_elements: usize,
+}
+
+impl RBasicContext {
+    fn new(
+        seed: u64,
+        limits: limits::Limits,
+        natives: HashMap<String, native::NativeFunction>,
+        variables: variables::Variables,
+    ) -> RBasicContext {
+        RBasicContext {
+            variables,
+            functions: HashMap::new(),
+            scopes: Vec::new(),
+            error_code: 0,
+            error_line: 0,
+            rng: random::Rng::new(seed),
+            files: HashMap::new(),
+            arrays: HashMap::new(),
+            natives,
+            limits,
+            string_bytes: 0,
+            array_elements: 0,
+        }
+    }
+
+    fn set_variable(
+        &mut self,
+        slot: usize,
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        let old_length = string_length(self.variables.get(slot)?);
+        self.use_string_bytes(old_length, &value)?;
+        self.variables.set(slot, value)
+    }
+
+    // Sets a variable the program may not name, such as a field variable
+    // or one set by the host
+    fn set_named_variable(
+        &mut self,
+        name: &str,
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        let slot = self.variables.slot(name);
+        self.set_variable(slot, value)
+    }
+
+    // Keeps count of the bytes used by strings as a value is replaced
+    fn use_string_bytes(
+        &mut self,
+        old_length: usize,
+        new_value: &value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        let string_bytes = self.string_bytes - old_length + string_length(Some(new_value));
+        self.limits.check_string_bytes(string_bytes)?;
+        self.string_bytes = string_bytes;
+        Ok(())
+    }
+
+    fn dim_array(&mut self, name: &str, bounds: Vec<usize>) -> Result<(), error::RBasicError> {
+        if self.arrays.contains_key(name) {
+            return Err(error::RBasicError::new(
+                error::DUPLICATE_DEFINITION,
+                format!("Array {} is already dimensioned", name),
+            ));
+        }
+
+        let array_elements = self
+            .array_elements
+            .saturating_add(array::element_count(&bounds));
+        self.limits.check_array_elements(array_elements)?;
+
+        self.arrays
+            .insert(name.to_string(), array::BasicArray::new(name, bounds));
+        self.array_elements = array_elements;
+        Ok(())
+    }
+
+    // As in GWBASIC, an array used without DIM is created with subscripts up
+    // to 10 in each dimension
+    fn array(
+        &mut self,
+        name: &str,
+        dimensions: usize,
+    ) -> Result<&mut array::BasicArray, error::RBasicError> {
+        if !self.arrays.contains_key(name) {
+            self.dim_array(name, vec![array::DEFAULT_BOUND; dimensions])?;
+        }
+        Ok(self.arrays.get_mut(name).unwrap())
+    }
+
+    fn get_array_element(
+        &mut self,
+        name: &str,
+        subscripts: &[i32],
+    ) -> Result<value::RBasicValue, error::RBasicError> {
+        self.array(name, subscripts.len())?.get(subscripts).cloned()
+    }
+
+    fn set_array_element(
+        &mut self,
+        name: &str,
+        subscripts: &[i32],
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        let old_value = self.array(name, subscripts.len())?.get(subscripts)?.clone();
+        self.use_string_bytes(string_length(Some(&old_value)), &value)?;
+        self.array(name, subscripts.len())?.set(subscripts, value)?;
+        Ok(())
+    }
+
+    fn file(&mut self, number: i32) -> Result<&mut files::BasicFile, error::RBasicError> {
+        open_file_number(&mut self.files, number)
+    }
+
+    fn close_all_files(&mut self) -> Result<(), error::RBasicError> {
+        for (_, file) in self.files.drain() {
+            file.close()?;
+        }
+        Ok(())
+    }
+
+    // Adds the host's preset variables and arrays, which are held to the
+    // limits only by what the program adds to them
+    fn preset(
+        &mut self,
+        variables: &BTreeMap<String, value::RBasicValue>,
+        arrays: &BTreeMap<String, array::BasicArray>,
+    ) {
+        for (name, value) in variables {
+            self.string_bytes += string_length(Some(value));
+            self.variables.set_named(name, value.clone());
+        }
+
+        for (name, array) in arrays {
+            self.string_bytes += array
+                .values()
+                .iter()
+                .map(|value| string_length(Some(value)))
+                .sum::<usize>();
+            self.array_elements += array.values().len();
+            self.arrays.insert(name.clone(), array.clone());
+        }
+    }
+
+    fn get_variable(&self, slot: usize) -> Result<Option<value::RBasicValue>, error::RBasicError> {
+        let parameter = self
+            .scopes
+            .last()
+            .and_then(|scope| scope.iter().find(|(parameter, _)| *parameter == slot));
+        match (slot, parameter) {
+            (variables::ERR, _) => Ok(Some(value::RBasicValue::Number(self.error_code))),
+            (variables::ERL, _) => Ok(Some(value::RBasicValue::Number(self.error_line as i32))),
+            (_, Some((_, value))) => Ok(Some(value.clone())),
+            (_, None) => Ok(self.variables.get(slot)?.cloned()),
+        }
+    }
+
+    fn get_named_variable(&self, name: &str) -> Option<value::RBasicValue> {
+        // find only gives slots the variables have, so reading them can't fail
+        self.variables
+            .find(name)
+            .and_then(|slot| self.get_variable(slot).ok().flatten())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionStatus {
+    // Ran off the end of the program or reached END
+    Ended,
+    // Halted by STOP on the given line, and can be resumed with CONT
+    Stopped(lexer::LineNumber),
+}
+
+impl fmt::Display for ExecutionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExecutionStatus::Ended => write!(f, "Completed Successfully"),
+            ExecutionStatus::Stopped(ref line_number) => write!(f, "BREAK IN {}", line_number.0),
+        }
+    }
+}
+
+// An active FOR loop
+#[derive(Debug, Clone)]
+pub struct ForLoop {
+    pub variable: String,
+    slot: usize,
+    pub end: value::RBasicValue,
+    pub step: value::RBasicValue,
+    // The line of the FOR statement
+    pub line_number: lexer::LineNumber,
+    // Line index to continue from when NEXT loops again
+    body_index: usize,
+}
+
+impl ForLoop {
+    // Whether the loop variable has gone past the end of the loop, which
+    // depends on which way the loop is counting
+    fn finished(&self, value: &value::RBasicValue) -> Result<bool, error::RBasicError> {
+        loop_finished(value, &self.end, &self.step)
+    }
+}
+
+// Whether a FOR loop variable has gone past the end of its loop, which
+// depends on which way the loop is counting
+pub fn loop_finished(
+    value: &value::RBasicValue,
+    end: &value::RBasicValue,
+    step: &value::RBasicValue,
+) -> Result<bool, error::RBasicError> {
+    if step.lt(&value::RBasicValue::Number(0))? {
+        value.lt(end)
+    } else {
+        value.gt(end)
+    }
+}
+
+// A program with its lines in line number order, along with what is worked
+// out from it when it is loaded
+pub struct LoadedProgram {
+    pub lines: Vec<lexer::LineOfCode>,
+    // The line index of each line number each line refers to, in the order
+    // they appear in it
+    pub targets: Vec<Vec<usize>>,
+    // The line index of the statement at the other end of each WHILE, WEND,
+    // DO, LOOP and EXIT DO
+    pub blocks: HashMap<usize, usize>,
+    pub data: Vec<(usize, value::RBasicValue)>,
+    // The slot given to each variable the program names
+    pub variables: variables::Variables,
+}
+
+// Orders the lines of a program, replacing any line given twice with the
+// later one, and checks what can be checked before it runs
+pub fn load_program(
+    code_lines: Vec<lexer::LineOfCode>,
+) -> Result<LoadedProgram, error::RBasicError> {
+    let mut lineno_to_code = BTreeMap::new();
+
+    for line in code_lines {
+        lineno_to_code.insert(line.line_number.clone(), line);
+    }
+
+    let mut lines: Vec<lexer::LineOfCode> = lineno_to_code.into_values().collect();
+    let variables = assign_slots(&mut lines);
+    let line_map: BTreeMap<_, _> = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| (line.line_number.clone(), index))
+        .collect();
+    let blocks = resolve_blocks(&lines)?;
+    validate_on_statements(&lines)?;
+    let targets = resolve_targets(&lines, &line_map)?;
+    let data = collect_data(&lines)?;
+
+    Ok(LoadedProgram {
+        lines,
+        targets,
+        blocks,
+        data,
+        variables,
+    })
+}
+
+// Gives each variable named in the program a slot, and each reference to it
+// that slot
+fn assign_slots(program: &mut [lexer::LineOfCode]) -> variables::Variables {
+    let mut variables = variables::Variables::new();
+    for line in program {
+        assign_token_slots(&mut line.tokens, &mut variables);
+    }
+    variables
+}
+
+fn assign_token_slots(tokens: &mut [lexer::TokenAndPos], variables: &mut variables::Variables) {
+    for lexer::TokenAndPos(_, token) in tokens {
+        if let token::Token::Variable(ref name, ref mut slot) = *token {
+            *slot = variables.slot(name);
+        }
+    }
+}
+
+pub struct Interpreter {
+    // The program's lines in line number order
+    program: Vec<lexer::LineOfCode>,
+    // The line index of each line number each line refers to, so that jumps
+    // don't need to look anything up
+    targets: Vec<Vec<usize>>,
+    blocks: HashMap<usize, usize>,
+    // The slot of every variable the program names, which each run starts
+    // from
+    variables: variables::Variables,
+    context: RBasicContext,
+    // Index into program of the next line to execute
+    line_index: usize,
+    // Line indices to continue from when each active GOSUB returns
+    gosub_stack: Vec<usize>,
+    for_stack: Vec<ForLoop>,
+    // Line index of the handler set by ON ERROR GOTO
+    error_handler: Option<usize>,
+    // The error being handled along with the line index it happened on,
+    // until the handler uses RESUME
+    trapped_error: Option<(usize, error::RBasicError)>,
+    can_continue: bool,
+    // Seed for the random number generator each time the program is run
+    seed: u64,
+    tracer: trace::Tracer,
+    // Whether lines are being traced, as turned on by TRON
+    tracing: bool,
+    // Whether tracing is on when the program starts
+    trace_at_start: bool,
+    limits: limits::Limits,
+    // Statements executed and when the program started, for the limits
+    statements: u64,
+    started: Instant,
+    // The items of every DATA statement in order, with the line index of
+    // each, and the index of the next item READ will take
+    data: Vec<(usize, value::RBasicValue)>,
+    data_index: usize,
+    // Variables and arrays the host sets before each run
+    preset_variables: BTreeMap<String, value::RBasicValue>,
+    preset_arrays: BTreeMap<String, array::BasicArray>,
+    // Where PRINT and INPUT go once the host drives the program with
+    // resume, instead of the terminal
+    host: Option<host::HostConsole>,
+    // Counts and times the lines run, when profiling
+    profiler: Option<profiler::Profiler>,
+    // Counts the lines run and the way each IF went, when measuring coverage
+    coverage: Option<coverage::Coverage>,
+}
+
+impl Interpreter {
+    pub fn new(code_lines: Vec<lexer::LineOfCode>) -> Result<Interpreter, error::RBasicError> {
+        let LoadedProgram {
+            lines: program,
+            targets,
+            blocks,
+            data,
+            variables,
+        } = load_program(code_lines)?;
+
+        Ok(Interpreter {
+            program,
+            targets,
+            blocks,
+            context: RBasicContext::new(
+                random::DEFAULT_SEED,
+                limits::Limits::default(),
+                HashMap::new(),
+                variables.cleared(),
+            ),
+            variables,
+            line_index: 0,
+            gosub_stack: Vec::new(),
+            for_stack: Vec::new(),
+            error_handler: None,
+            trapped_error: None,
+            can_continue: false,
+            seed: random::DEFAULT_SEED,
+            tracer: trace::Tracer::to_stderr(),
+            tracing: false,
+            trace_at_start: false,
+            limits: limits::Limits::default(),
+            statements: 0,
+            started: Instant::now(),
+            data,
+            data_index: 0,
+            preset_variables: BTreeMap::new(),
+            preset_arrays: BTreeMap::new(),
+            host: None,
+            profiler: None,
+            coverage: None,
+        })
+    }
+
+    // Runs the program from the beginning with no variables set
+    pub fn run(&mut self) -> Result<ExecutionStatus, error::RBasicError> {
+        self.start();
+        self.execute()
+    }
+
+    // Gets ready to run the program from the beginning with no variables
+    // set, without running any of it
+    pub fn start(&mut self) {
+        let natives = self.context.natives.clone();
+        self.context = RBasicContext::new(
+            self.seed,
+            self.limits.clone(),
+            natives,
+            self.variables.cleared(),
+        );
+        self.context
+            .preset(&self.preset_variables, &self.preset_arrays);
+        self.line_index = 0;
+        self.gosub_stack.clear();
+        self.for_stack.clear();
+        self.error_handler = None;
+        self.trapped_error = None;
+        self.can_continue = false;
+        self.tracing = self.trace_at_start;
+        self.statements = 0;
+        self.started = Instant::now();
+        self.data_index = 0;
+        if self.host.is_some() {
+            self.host = Some(host::HostConsole::new());
+        }
+        if self.profiler.is_some() {
+            self.profiler = Some(profiler::Profiler::new());
+        }
+        if self.coverage.is_some() {
+            self.coverage = Some(coverage::Coverage::new());
+        }
+    }
+
+    // Runs the program until the host has something to do: take output,
+    // give input, or handle the program finishing. Once this is used, PRINT
+    // and INPUT go through the host rather than the terminal. Call start
+    // first to run from the beginning, and resume again after each event;
+    // resuming after a STOP carries on as CONT does. After an error the
+    // program can't carry on, as with CONT, until it is started again.
+    pub fn resume(&mut self) -> host::HostEvent {
+        self.host.get_or_insert_with(host::HostConsole::new);
+
+        loop {
+            let host = self.host.as_mut().unwrap();
+            if let Some(event) = host.next_event() {
+                return event;
+            }
+
+            if host.failed() {
+                return host::HostEvent::Error(error::RBasicError::new(
+                    error::CANT_CONTINUE,
+                    "Can't continue".to_string(),
+                ));
+            }
+
+            if !host.has_input() {
+                if let Some(prompt) = self.awaited_input() {
+                    return host::HostEvent::NeedsInput { prompt };
+                }
+            }
+
+            let event = match self.step_line() {
+                Ok(None) => continue,
+                Ok(Some(status)) => host::HostEvent::Finished(status),
+                Err(e) => host::HostEvent::Error(e),
+            };
+            self.host.as_mut().unwrap().finish(event);
+        }
+    }
+
+    // Gives the line for the INPUT statement that resume is waiting on
+    pub fn provide_input(&mut self, line: &str) {
+        self.host
+            .get_or_insert_with(host::HostConsole::new)
+            .provide_input(line);
+    }
+
+    // The prompt of the next line when it is an INPUT from the keyboard
+    fn awaited_input(&self) -> Option<String> {
+        let line = self.program.get(self.line_index)?;
+        let mut token_iter = line.tokens.iter().peekable();
+
+        match (token_iter.next(), token_iter.peek()) {
+            (
+                Some(lexer::TokenAndPos(_, token::Token::Input)),
+                Some(lexer::TokenAndPos(_, token::Token::Hash)),
+            ) => None,
+            (Some(lexer::TokenAndPos(_, token::Token::Input)), _) => {
+                Some(parse_input_prompt(&mut token_iter))
+            }
+            _ => None,
+        }
+    }
+
+    // Makes a Rust function callable from BASIC by name, like the built-in
+    // functions. Each argument is converted to its declared type before the
+    // function is called, and a wrong number or type of arguments is an
+    // error in the program. Built-in functions and arrays with the same name
+    // take precedence.
+    pub fn register_function<F>(
+        &mut self,
+        name: &str,
+        argument_types: &[native::ArgumentType],
+        function: F,
+    ) -> Result<(), error::RBasicError>
+    where
+        F: Fn(&[value::RBasicValue]) -> Result<value::RBasicValue, error::RBasicError> + 'static,
+    {
+        check_name(name, "function")?;
+        if name.starts_with("FN") {
+            return Err(error::RBasicError::new(
+                error::SYNTAX_ERROR,
+                format!("{} can't be used as a function name", name),
+            ));
+        }
+
+        self.context.natives.insert(
+            name.to_string(),
+            native::NativeFunction::new(argument_types, function),
+        );
+        Ok(())
+    }
+
+    // Sets the limits each run of the program is held to
+    pub fn set_limits(&mut self, limits: limits::Limits) {
+        self.context.limits = limits.clone();
+        self.limits = limits;
+    }
+
+    // Fixes the seed the random number generator starts from when the program
+    // runs, so that programs using RND give the same results every time
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Sets where traced lines are logged, which is stderr unless changed
+    pub fn set_tracer(&mut self, tracer: trace::Tracer) {
+        self.tracer = tracer;
+    }
+
+    // Traces the program from its first line, as if it started with TRON
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace_at_start = trace;
+    }
+
+    // Turns tracing on or off from the next line, as TRON and TROFF do, such
+    // as for carrying on a program restored from a snapshot
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
+    // Counts how often each line runs and the time it takes, from the next
+    // time the program starts
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiler = if profiling {
+            Some(profiler::Profiler::new())
+        } else {
+            None
+        };
+    }
+
+    // What has been counted since the program started, when profiling
+    pub fn profiler(&self) -> Option<&profiler::Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Counts the lines run and the way each IF goes, from the next time the
+    // program starts
+    pub fn set_coverage(&mut self, coverage: bool) {
+        self.coverage = if coverage {
+            Some(coverage::Coverage::new())
+        } else {
+            None
+        };
+    }
+
+    // What has been counted since the program started, when measuring
+    // coverage
+    pub fn coverage(&self) -> Option<&coverage::Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Resumes the program from the line after the STOP that halted it
+    pub fn cont(&mut self) -> Result<ExecutionStatus, error::RBasicError> {
+        if !self.can_continue {
+            return Err(error::RBasicError::new(
+                error::CANT_CONTINUE,
+                "Can't continue".to_string(),
+            ));
+        }
+        self.execute()
+    }
+
+    fn execute(&mut self) -> Result<ExecutionStatus, error::RBasicError> {
+        self.can_continue = false;
+
+        loop {
+            if let Some(status) = self.step_line()? {
+                return Ok(status);
+            }
+        }
+    }
+
+    // Executes the next line of the program, returning the status once the
+    // program has ended or stopped
+    pub fn step_line(&mut self) -> Result<Option<ExecutionStatus>, error::RBasicError> {
+        if self.line_index < self.program.len() {
+            self.check_limits()?;
+
+            let before = if self.tracing {
+                self.tracer
+                    .line(&self.program[self.line_index].line_number)?;
+                if self.tracer.shows_variables() {
+                    Some(self.context.variables.clone())
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+
+            let profiled = self.profiler.as_ref().map(|_| {
+                (
+                    self.program[self.line_index].line_number.clone(),
+                    self.gosub_stack.len(),
+                    Instant::now(),
+                )
+            });
+
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record_line(&self.program[self.line_index].line_number);
+            }
+
+            let result = self.step();
+
+            if let Some((line_number, depth, started)) = profiled {
+                let profiler = self.profiler.as_mut().unwrap();
+                profiler.record_line(&line_number, started.elapsed());
+                if self.gosub_stack.len() > depth {
+                    profiler.call(&self.program[self.line_index].line_number);
+                } else if self.gosub_stack.len() < depth {
+                    profiler.return_to(self.gosub_stack.len());
+                }
+            }
+
+            if let Some(ref before) = before {
+                self.tracer.changes(before, &self.context.variables)?;
+            }
+
+            match result {
+                Ok(Some(status)) => return Ok(Some(status)),
+                Ok(None) => {}
+                Err(e) => self.trap_error(e)?,
+            }
+        }
+
+        // If we're at the end of the program then we stop
+        if self.line_index < self.program.len() {
+            return Ok(None);
+        }
+
+        if let Some((error_index, _)) = self.trapped_error.take() {
+            return Err(error::RBasicError::new(
+                error::NO_RESUME,
+                format!(
+                    "At {:?}, error handler ended without RESUME",
+                    self.program[error_index].line_number
+                ),
+            ));
+        }
+
+        // As in GWBASIC, files are closed when the program ends
+        self.context.close_all_files()?;
+
+        Ok(Some(ExecutionStatus::Ended))
+    }
+
+    fn check_limits(&mut self) -> Result<(), error::RBasicError> {
+        self.statements += 1;
+        self.limits.check_line(
+            &self.program[self.line_index].line_number,
+            self.statements,
+            self.started,
+        )
+    }
+
+    // The lines of the program in order, as they were tokenized
+    pub fn program(&self) -> &[lexer::LineOfCode] {
+        &self.program
+    }
+
+    // The line that will be executed next, if the program hasn't ended
+    pub fn current_line(&self) -> Option<lexer::LineNumber> {
+        self.program
+            .get(self.line_index)
+            .map(|line| line.line_number.clone())
+    }
+
+    pub fn variable(&self, name: &str) -> Option<value::RBasicValue> {
+        self.context.get_named_variable(name)
+    }
+
+    // The variables the program has set, by name
+    pub fn variables(&self) -> BTreeMap<&str, &value::RBasicValue> {
+        self.context.variables.iter().collect()
+    }
+
+    pub fn array(&self, name: &str) -> Option<&array::BasicArray> {
+        self.context.arrays.get(name)
+    }
+
+    // The arrays the program has created, by name
+    pub fn arrays(&self) -> BTreeMap<&str, &array::BasicArray> {
+        self.context
+            .arrays
+            .iter()
+            .map(|(name, array)| (name.as_str(), array))
+            .collect()
+    }
+
+    // Sets a variable to a value before each run of the program, as inputs
+    // for it. Preset strings count towards the string space limit, but
+    // aren't checked against it.
+    pub fn preset_variable(
+        &mut self,
+        name: &str,
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        check_name(name, "variable")?;
+        self.preset_variables.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    // Creates an array before each run of the program, as if by DIM, with
+    // the contents given
+    pub fn preset_array(
+        &mut self,
+        name: &str,
+        array: array::BasicArray,
+    ) -> Result<(), error::RBasicError> {
+        check_name(name, "array")?;
+        self.preset_arrays.insert(name.to_string(), array);
+        Ok(())
+    }
+
+    pub fn set_variable(
+        &mut self,
+        name: &str,
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        self.context.set_named_variable(name, value)
+    }
+
+    // Saves everything needed to carry on running the program later, in the
+    // versioned format of the snapshot module. The host's own settings, such
+    // as limits, tracers and native functions, aren't saved.
+    pub fn snapshot(&mut self) -> Result<String, error::RBasicError> {
+        let mut writer = snapshot::Writer::new();
+
+        writer.field("lines", self.program.len());
+        for line in &self.program {
+            writer.field("line", line.line_number.0);
+            writer.tokens("tokens", &line.tokens);
+        }
+
+        writer.field("seed", self.seed);
+        writer.field("line_index", self.line_index);
+        writer.field("statements", self.statements);
+        writer.field("elapsed", self.started.elapsed().as_secs_f64());
+        writer.field("tracing", self.tracing);
+        writer.field("data_index", self.data_index);
+        writer.optional("error_handler", self.error_handler);
+        writer.optional(
+            "trapped_error",
+            self.trapped_error.as_ref().map(|(index, _)| index),
+        );
+        if let Some((_, ref e)) = self.trapped_error {
+            writer.field("code", e.code);
+            writer.string("message", &e.message);
+        }
+
+        writer.field("gosubs", self.gosub_stack.len());
+        for index in &self.gosub_stack {
+            writer.field("gosub", index);
+        }
+
+        writer.field("for_loops", self.for_stack.len());
+        for for_loop in &self.for_stack {
+            writer.field("variable", &for_loop.variable);
+            writer.value("end", &for_loop.end);
+            writer.value("step", &for_loop.step);
+            writer.field("line", for_loop.line_number.0);
+            writer.field("body", for_loop.body_index);
+        }
+
+        let context = &mut self.context;
+        let (rng_state, rng_last) = context.rng.state();
+        writer.field("err", context.error_code);
+        writer.field("erl", context.error_line);
+        writer.field("rng", rng_state);
+        writer.field("rng_last", format!("{:?}", rng_last));
+
+        let variables: BTreeMap<_, _> = context.variables.iter().collect();
+        writer.field("variables", variables.len());
+        for (name, value) in variables {
+            writer.field("name", name);
+            writer.value("value", value);
+        }
+
+        let arrays: BTreeMap<_, _> = context.arrays.iter().collect();
+        writer.field("arrays", arrays.len());
+        for (name, array) in arrays {
+            writer.field("name", name);
+            let bounds: Vec<String> = array.bounds().iter().map(usize::to_string).collect();
+            writer.field("bounds", bounds.join(" "));
+            for value in array.values() {
+                writer.value("value", value);
+            }
+        }
+
+        let functions: BTreeMap<_, _> = context.functions.iter().collect();
+        writer.field("functions", functions.len());
+        for (name, function) in functions {
+            writer.field("name", name);
+            writer.field("parameters", function.parameters.join(" "));
+            writer.tokens("body", &function.body);
+        }
+
+        let mut numbers: Vec<i32> = context.files.keys().cloned().collect();
+        numbers.sort_unstable();
+        writer.field("files", numbers.len());
+        for number in numbers {
+            let state = context.file(number)?.state()?;
+            writer.field("number", number);
+            writer.string("path", &state.path);
+            writer.field("mode", &state.mode);
+            writer.field("position", state.position);
+            writer.field("past_end", state.past_end);
+            writer.bytes("buffer", &state.buffer);
+            writer.field("fields", state.fields.len());
+            for (name, offset, width) in &state.fields {
+                writer.field("field", format!("{} {} {}", name, offset, width));
+            }
+        }
+
+        Ok(writer.finish())
+    }
+
+    // Loads a program saved by snapshot, ready to carry on from where it was
+    // with cont, resume or step_line. Open files are opened again.
+    pub fn from_snapshot(text: &str) -> Result<Interpreter, error::RBasicError> {
+        let mut reader = snapshot::Reader::new(text)?;
+
+        let mut code_lines = Vec::new();
+        for _ in 0..reader.field::<usize>("lines")? {
+            code_lines.push(lexer::LineOfCode {
+                line_number: lexer::LineNumber(reader.field("line")?),
+                tokens: reader.tokens("tokens")?,
+            });
+        }
+        let mut interpreter = Interpreter::new(code_lines)?;
+        let program_length = interpreter.program.len();
+        // Indices may point one past the end, as they do once the program
+        // has finished
+        let index_in = |index: usize, length: usize| -> Result<usize, error::RBasicError> {
+            if index <= length {
+                Ok(index)
+            } else {
+                Err(bad_snapshot(&format!("index {} is past the end", index)))
+            }
+        };
+
+        interpreter.seed = reader.field("seed")?;
+        interpreter.line_index = index_in(reader.field("line_index")?, program_length)?;
+        interpreter.statements = reader.field("statements")?;
+        let elapsed = Duration::from_secs_f64(reader.field::<f64>("elapsed")?.max(0.0));
+        interpreter.started = Instant::now()
+            .checked_sub(elapsed)
+            .unwrap_or_else(Instant::now);
+        interpreter.can_continue = interpreter.line_index < program_length;
+        interpreter.tracing = reader.field("tracing")?;
+        interpreter.data_index = index_in(reader.field("data_index")?, interpreter.data.len())?;
+        interpreter.error_handler = match reader.optional::<usize>("error_handler")? {
+            Some(index) => Some(index_in(index + 1, program_length)? - 1),
+            None => None,
+        };
+        interpreter.trapped_error = match reader.optional::<usize>("trapped_error")? {
+            Some(index) => Some((
+                index_in(index + 1, program_length)? - 1,
+                error::RBasicError::new(reader.field("code")?, reader.string("message")?),
+            )),
+            None => None,
+        };
+
+        for _ in 0..reader.field::<usize>("gosubs")? {
+            // Each GOSUB returns to the line after one in the program
+            match index_in(reader.field("gosub")?, program_length)? {
+                0 => return Err(bad_snapshot("GOSUB returns to the first line")),
+                index => interpreter.gosub_stack.push(index),
+            }
+        }
+
+        for _ in 0..reader.field::<usize>("for_loops")? {
+            let variable: String = reader.field("variable")?;
+            interpreter.for_stack.push(ForLoop {
+                slot: interpreter.context.variables.slot(&variable),
+                variable,
+                end: reader.value("end")?,
+                step: reader.value("step")?,
+                line_number: lexer::LineNumber(reader.field("line")?),
+                body_index: index_in(reader.field("body")?, program_length)?,
+            });
+        }
+
+        let context = &mut interpreter.context;
+        context.error_code = reader.field("err")?;
+        context.error_line = reader.field("erl")?;
+        context.rng = random::Rng::from_state(reader.field("rng")?, reader.field("rng_last")?);
+
+        let mut variables = BTreeMap::new();
+        for _ in 0..reader.field::<usize>("variables")? {
+            variables.insert(reader.field("name")?, reader.value("value")?);
+        }
+
+        let mut arrays = BTreeMap::new();
+        for _ in 0..reader.field::<usize>("arrays")? {
+            let name: String = reader.field("name")?;
+            let bounds = reader
+                .field::<String>("bounds")?
+                .split_whitespace()
+                .map(usize::from_str)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| bad_snapshot(&format!("invalid bounds for array {}", name)))?;
+            let mut values = Vec::new();
+            for _ in 0..array::element_count(&bounds) {
+                values.push(reader.value("value")?);
+            }
+            let array = array::BasicArray::with_values(bounds, values)
+                .ok_or_else(|| bad_snapshot(&format!("invalid values for array {}", name)))?;
+            arrays.insert(name, array);
+        }
+        context.preset(&variables, &arrays);
+
+        for _ in 0..reader.field::<usize>("functions")? {
+            let name = reader.field("name")?;
+            let parameters: Vec<String> = reader
+                .field::<String>("parameters")?
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+            let slots = parameters
+                .iter()
+                .map(|parameter| context.variables.slot(parameter))
+                .collect();
+            let mut body = reader.tokens("body")?;
+            assign_token_slots(&mut body, &mut context.variables);
+            context.functions.insert(
+                name,
+                Rc::new(UserFunction {
+                    parameters,
+                    slots,
+                    body,
+                }),
+            );
+        }
+
+        for _ in 0..reader.field::<usize>("files")? {
+            let number = reader.field("number")?;
+            let mut state = files::FileState {
+                path: reader.string("path")?,
+                mode: reader.field("mode")?,
+                position: reader.field("position")?,
+                past_end: reader.field("past_end")?,
+                buffer: reader.bytes("buffer")?,
+                fields: Vec::new(),
+            };
+            for _ in 0..reader.field::<usize>("fields")? {
+                let field: String = reader.field("field")?;
+                let parts: Vec<&str> = field.split(' ').collect();
+                match parts[..] {
+                    [name, offset, width] => {
+                        match (usize::from_str(offset), usize::from_str(width)) {
+                            (Ok(offset), Ok(width)) => {
+                                state.fields.push((name.to_string(), offset, width))
+                            }
+                            _ => return Err(bad_snapshot(&format!("invalid field {}", field))),
+                        }
+                    }
+                    _ => return Err(bad_snapshot(&format!("invalid field {}", field))),
+                }
+            }
+            context.files.insert(number, files::reopen(&state)?);
+        }
+
+        reader.finish()?;
+        Ok(interpreter)
+    }
+
+    pub fn save_snapshot(&mut self, path: &str) -> Result<(), error::RBasicError> {
+        let text = self.snapshot()?;
+        fs::write(path, text).map_err(|e| {
+            error::RBasicError::new(
+                error::DEVICE_IO_ERROR,
+                format!("Saving snapshot to {} failed: {}", path, e),
+            )
+        })
+    }
+
+    pub fn load_snapshot(path: &str) -> Result<Interpreter, error::RBasicError> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            error::RBasicError::new(
+                error::FILE_NOT_FOUND,
+                format!("Loading snapshot from {} failed: {}", path, e),
+            )
+        })?;
+        Interpreter::from_snapshot(&text)
+    }
+
+    // The lines of the active GOSUBs, innermost last
+    pub fn gosub_stack(&self) -> Vec<lexer::LineNumber> {
+        self.gosub_stack
+            .iter()
+            .map(|index| self.program[index - 1].line_number.clone())
+            .collect()
+    }
+
+    // The active FOR loops, innermost last
+    pub fn for_stack(&self) -> &[ForLoop] {
+        &self.for_stack
+    }
+
+    // Jumps to the ON ERROR GOTO handler if there is one, unless an error is
+    // already being handled, in which case the new error ends the program
+    fn trap_error(&mut self, e: error::RBasicError) -> Result<(), error::RBasicError> {
+        match self.error_handler {
+            Some(handler_index)
+                if self.trapped_error.is_none() && !error::is_limit_error(e.code) =>
+            {
+                self.context.error_code = e.code;
+                self.context.error_line = self.program[self.line_index].line_number.0;
+                self.trapped_error = Some((self.line_index, e));
+                self.line_index = handler_index;
+                Ok(())
+            }
+            _ => Err(e),
+        }
+    }
+
+    // Executes the current line, then moves on to the following line unless
+    // the line jumped elsewhere
+    fn step(&mut self) -> Result<Option<ExecutionStatus>, error::RBasicError> {
+        let line = &self.program[self.line_index];
+        let line_number = &line.line_number;
+        let tokens = &line.tokens;
+        let mut token_iter = tokens.iter().peekable();
+        // TODO: Feels hacky
+        let mut line_has_goto = false;
+
+        // println!("Looking at line: {:?}", line_number);
+        if !tokens.is_empty() {
+            let lexer::TokenAndPos(pos, ref token) = *token_iter.next().unwrap();
+
+            match *token {
+                token::Token::Rem => {
+                    // Skip the rest of the line so do nothing
+                }
+
+                token::Token::Data => {
+                    // The items were collected when the program was loaded
+                }
+
+                token::Token::Read => {
+                    // Expected Next:
+                    // Variable [LParen EXPRESSION {Comma EXPRESSION} RParen]
+                    //     {Comma Variable [LParen EXPRESSION {Comma EXPRESSION} RParen]}
+                    read_data(
+                        &mut token_iter,
+                        &mut self.context,
+                        &self.data,
+                        &mut self.data_index,
+                    )
+                    .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Restore => {
+                    // Expected Next:
+                    // [Number]
+                    // Where Number is a Line Number
+                    self.data_index = restore_data(
+                        &mut token_iter,
+                        self.targets[self.line_index].first().copied(),
+                        &self.data,
+                    )
+                    .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::End => {
+                    line_has_goto = true;
+                    self.line_index = self.program.len();
+                    self.trapped_error = None;
+                }
+
+                token::Token::Tron => self.tracing = true,
+
+                token::Token::Troff => self.tracing = false,
+
+                token::Token::Stop => {
+                    // Remember where to pick up again if CONT is used
+                    self.line_index += 1;
+                    self.can_continue = true;
+                    return Ok(Some(ExecutionStatus::Stopped(line_number.clone())));
+                }
+
+                token::Token::Goto => {
+                    line_has_goto = true;
+                    match token_iter.next() {
+                        Some(&lexer::TokenAndPos(_, token::Token::Number(_))) => {
+                            // Where the target was resolved when the program
+                            // was loaded
+                            self.line_index = self.targets[self.line_index][0];
+                        }
+                        Some(&lexer::TokenAndPos(pos, _)) => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!(
+                                    "At {:?}, {} GOTO must be followed by valid line \
+                                                number",
+                                    line_number, pos
+                                ),
+                            ));
+                        }
+                        None => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!(
+                                    "At {:?}, {} GOTO must be followed by a line \
+                                                number",
+                                    line_number,
+                                    // Adding 4 to give the position past GOTO
+                                    pos + 4
+                                ),
+                            ));
+                        }
+                    }
+                }
+
+                token::Token::Gosub => {
+                    line_has_goto = true;
+                    let gosub_pos = pos;
+                    match token_iter.next() {
+                        Some(&lexer::TokenAndPos(_, token::Token::Number(_))) => {
+                            self.context
+                                .limits
+                                .check_call_depth(self.gosub_stack.len())
+                                .map_err(|e| error_at(line_number, gosub_pos, e))?;
+                            self.gosub_stack.push(self.line_index + 1);
+                            self.line_index = self.targets[self.line_index][0];
+                        }
+                        Some(&lexer::TokenAndPos(pos, _)) => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!(
+                                    "At {:?}, {} GOSUB must be followed by valid line \
+                                                number",
+                                    line_number, pos
+                                ),
+                            ));
+                        }
+                        None => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!(
+                                    "At {:?}, {} GOSUB must be followed by a line \
+                                                number",
+                                    line_number,
+                                    // Adding 5 to give the position past GOSUB
+                                    pos + 5
+                                ),
+                            ));
+                        }
+                    }
+                }
+
+                token::Token::Return => {
+                    line_has_goto = true;
+                    match self.gosub_stack.pop() {
+                        Some(index) => self.line_index = index,
+                        None => {
+                            return Err(error::RBasicError::new(
+                                error::RETURN_WITHOUT_GOSUB,
+                                format!("At {:?}, {} RETURN without GOSUB", line_number, pos),
+                            ))
+                        }
+                    }
+                }
+
+                token::Token::Randomize => {
+                    // Expected Next:
+                    // [EXPRESSION]
+                    if token_iter.peek().is_none() {
+                        self.context.rng.seed_from_time();
+                    } else {
+                        match parse_and_eval_expression(&mut token_iter, &mut self.context) {
+                            Ok(ref seed) if seed.as_float().is_some() => {
+                                self.context.rng.seed(seed.as_float().unwrap().to_bits())
+                            }
+                            Ok(_) => {
+                                return Err(error::RBasicError::new(
+                                    error::TYPE_MISMATCH,
+                                    format!(
+                                        "At {:?}, {} RANDOMIZE seed must be a number",
+                                        line_number, pos
+                                    ),
+                                ))
+                            }
+                            Err(e) => {
+                                return Err(error::RBasicError::new(
+                                    e.code,
+                                    format!(
+                                        "At {:?}, {} error in RANDOMIZE expression: {}",
+                                        line_number, pos, e
+                                    ),
+                                ))
+                            }
+                        }
+                    }
+                }
+
+                token::Token::On
+                    if tokens.get(1).map(|next| &next.1) == Some(&token::Token::Error) =>
+                {
+                    // Expected Next:
+                    // Error Goto Number
+                    // Where the target was checked when the program was loaded
+                    let target = parse_on_error_target(&mut token_iter)
+                        .expect("ON ERROR statements are validated when the program is loaded");
+
+                    if target.0 == 0 {
+                        self.error_handler = None;
+
+                        // Turning off trapping within the handler ends the
+                        // program with the error being handled
+                        if let Some((_, e)) = self.trapped_error.take() {
+                            return Err(e);
+                        }
+                    } else {
+                        self.error_handler = Some(self.targets[self.line_index][0]);
+                    }
+                }
+
+                token::Token::Resume => {
+                    // Expected Next:
+                    // [Next | Number]
+                    let error_index = match self.trapped_error.take() {
+                        Some((error_index, _)) => error_index,
+                        None => {
+                            return Err(error::RBasicError::new(
+                                error::RESUME_WITHOUT_ERROR,
+                                format!("At {:?}, {} RESUME without error", line_number, pos),
+                            ))
+                        }
+                    };
+
+                    line_has_goto = true;
+                    match token_iter.next() {
+                        None | Some(&lexer::TokenAndPos(_, token::Token::Number(0))) => {
+                            self.line_index = error_index
+                        }
+                        Some(&lexer::TokenAndPos(_, token::Token::Next)) => {
+                            self.line_index = error_index + 1
+                        }
+                        Some(&lexer::TokenAndPos(_, token::Token::Number(_))) => {
+                            self.line_index = self.targets[self.line_index][0]
+                        }
+                        Some(&lexer::TokenAndPos(pos, _)) => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!(
+                                    "At {:?}, {} RESUME must be followed by NEXT or a \
+                                     line number",
+                                    line_number, pos
+                                ),
+                            ))
+                        }
+                    }
+                }
+
+                token::Token::Error => {
+                    // Expected Next:
+                    // EXPRESSION
+                    // Where the expression is an error code from 1 to 255
+                    match parse_and_eval_expression(&mut token_iter, &mut self.context) {
+                        Ok(value::RBasicValue::Number(code)) if (1..=255).contains(&code) => {
+                            return Err(error::RBasicError::new(
+                                code,
+                                format!(
+                                    "At {:?}, {} {}",
+                                    line_number,
+                                    pos,
+                                    error::message_for_code(code)
+                                ),
+                            ))
+                        }
+                        Ok(_) => {
+                            return Err(error::RBasicError::new(
+                                error::ILLEGAL_FUNCTION_CALL,
+                                format!(
+                                    "At {:?}, {} ERROR must be given a code from 1 to 255",
+                                    line_number, pos
+                                ),
+                            ))
+                        }
+                        Err(e) => {
+                            return Err(error::RBasicError::new(
+                                e.code,
+                                format!(
+                                    "At {:?}, {} error in ERROR expression: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                    }
+                }
+
+                token::Token::On => {
+                    // Expected Next:
+                    // EXPRESSION (Goto | Gosub) Number {Comma Number}
+                    // Where the targets were checked when the program was loaded
+                    let selector =
+                        match parse_and_eval_expression(&mut token_iter, &mut self.context) {
+                            Ok(value::RBasicValue::Number(number)) => number,
+                            Ok(_) => {
+                                return Err(error::RBasicError::new(
+                                    error::TYPE_MISMATCH,
+                                    format!(
+                                        "At {:?}, {} ON expression must be a number",
+                                        line_number, pos
+                                    ),
+                                ))
+                            }
+                            Err(e) => {
+                                return Err(error::RBasicError::new(
+                                    e.code,
+                                    format!(
+                                        "At {:?}, {} error in ON expression: {}",
+                                        line_number, pos, e
+                                    ),
+                                ))
+                            }
+                        };
+                    let (is_gosub, targets) = parse_on_targets(&mut token_iter)
+                        .expect("ON statements are validated when the program is loaded");
+
+                    // As in Applesoft, values past the end of the target list
+                    // fall through to the next line
+                    if !(0..=255).contains(&selector) {
+                        return Err(error::RBasicError::new(
+                            error::ILLEGAL_FUNCTION_CALL,
+                            format!(
+                                "At {:?}, {} illegal quantity {} for ON",
+                                line_number, pos, selector
+                            ),
+                        ));
+                    }
+                    if selector >= 1 && selector as usize <= targets.len() {
+                        line_has_goto = true;
+                        if is_gosub {
+                            self.context
+                                .limits
+                                .check_call_depth(self.gosub_stack.len())
+                                .map_err(|e| error_at(line_number, pos, e))?;
+                            self.gosub_stack.push(self.line_index + 1);
+                        }
+                        self.line_index = self.targets[self.line_index][selector as usize - 1];
+                    }
+                }
+
+                token::Token::Let
+                    if tokens.get(2).map(|next| &next.1) == Some(&token::Token::LParen) =>
+                {
+                    // Expected Next:
+                    // Variable LParen EXPRESSION {Comma EXPRESSION} RParen Equals EXPRESSION
+                    assign_array_element(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Let => {
+                    // Expected Next:
+                    // Variable Equals EXPRESSION
+                    match (
+                        token_iter.next(),
+                        token_iter.next(),
+                        parse_and_eval_expression(&mut token_iter, &mut self.context),
+                    ) {
+                        (
+                            Some(&lexer::TokenAndPos(_, token::Token::Variable(_, slot))),
+                            Some(&lexer::TokenAndPos(_, token::Token::Equals)),
+                            Ok(ref value),
+                        ) => {
+                            self.context
+                                .set_variable(slot, value.clone())
+                                .map_err(|e| error_at(line_number, pos, e))?;
+                        }
+                        (_, _, Err(e)) => {
+                            return Err(error::RBasicError::new(
+                                e.code,
+                                format!(
+                                    "At {:?}, {} error in LET expression: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                        _ => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!("At {:?}, {} invalid syntax for LET.", line_number, pos),
+                            ));
+                        }
+                    }
+                }
+
+                token::Token::Print
+                    if token_iter.peek().map(|next| &next.1) == Some(&token::Token::Hash) =>
+                {
+                    // Expected Next:
+                    // Hash EXPRESSION Comma EXPRESSION
+                    print_to_file(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Print => {
+                    // Expected Next:
+                    // EXPRESSION
+                    let value = match parse_and_eval_expression(&mut token_iter, &mut self.context)
+                    {
+                        Ok(value) => value,
+                        Err(e) => {
+                            return Err(error::RBasicError::new(
+                                e.code,
+                                format!(
+                                    "At {:?}. {} PRINT must be followed by valid \
+                                                expression: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                    };
+                    // Separating items with commas isn't supported, so
+                    // anything after the expression is an error rather than
+                    // being left out
+                    if let Some(lexer::TokenAndPos(extra_pos, _)) = token_iter.peek() {
+                        return Err(print_takes_one_expression(line_number, *extra_pos));
+                    }
+                    match self.host {
+                        Some(ref mut host) => host.print(format!("{}\n", value)),
+                        None => println!("{}", value),
+                    }
+                }
+
+                token::Token::Write => {
+                    // Expected Next:
+                    // Hash EXPRESSION Comma EXPRESSION {Comma EXPRESSION}
+                    write_to_file(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Open => {
+                    // Expected Next:
+                    // EXPRESSION For (Input | Output | Append) As [Hash] EXPRESSION
+                    open_file(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Close => {
+                    // Expected Next:
+                    // [[Hash] EXPRESSION {Comma [Hash] EXPRESSION}]
+                    close_files(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Input
+                    if token_iter.peek().map(|next| &next.1) == Some(&token::Token::Hash) =>
+                {
+                    // Expected Next:
+                    // Hash EXPRESSION Comma Variable {Comma Variable}
+                    input_from_file(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Dim => {
+                    // Expected Next:
+                    // Variable LParen EXPRESSION {Comma EXPRESSION} RParen
+                    //     {Comma Variable LParen EXPRESSION {Comma EXPRESSION} RParen}
+                    dim_arrays(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Field => {
+                    // Expected Next:
+                    // [Hash] EXPRESSION Comma EXPRESSION As Variable
+                    //     {Comma EXPRESSION As Variable}
+                    define_fields(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Lset | token::Token::Rset => {
+                    // Expected Next:
+                    // Variable Equals EXPRESSION
+                    justify_variable(
+                        &mut token_iter,
+                        &mut self.context,
+                        *token == token::Token::Rset,
+                    )
+                    .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Get => {
+                    // Expected Next:
+                    // [Hash] EXPRESSION [Comma EXPRESSION]
+                    get_record(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Put => {
+                    // Expected Next:
+                    // [Hash] EXPRESSION [Comma EXPRESSION]
+                    put_record(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Line => {
+                    // Expected Next:
+                    // Input Hash EXPRESSION Comma Variable
+                    line_input_from_file(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+                }
+
+                token::Token::Input => {
+                    // Expected Next:
+                    // [BString Comma] Variable
+                    let prompt = parse_input_prompt(&mut token_iter);
+                    match token_iter.next() {
+                        Some(&lexer::TokenAndPos(_, token::Token::Variable(_, slot))) => {
+                            let input = match self.host {
+                                Some(ref mut host) => host.take_input().ok_or_else(|| {
+                                    error::RBasicError::new(
+                                        error::INPUT_PAST_END,
+                                        format!(
+                                            "At {:?}, {} INPUT has not been given a line",
+                                            line_number, pos
+                                        ),
+                                    )
+                                })?,
+                                None => read_console_line(&prompt).map_err(|e| {
+                                    error::RBasicError::new(
+                                        error::DEVICE_IO_ERROR,
+                                        format!(
+                                            "At {:?}, {} INPUT failed to read a line: {}",
+                                            line_number, pos, e
+                                        ),
+                                    )
+                                })?,
+                            };
+                            let value = value::RBasicValue::from(input);
+
+                            // Store the string now, can coerce to number later if needed
+                            // Can overwrite an existing value
+                            self.context
+                                .set_variable(slot, value)
+                                .map_err(|e| error_at(line_number, pos, e))?;
+                        }
+
+                        _ => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!(
+                                    "At {:?}, {} INPUT must be followed by a \
+                                                variable name",
+                                    line_number,
+                                    // Adding 5 to put position past INPUT
+                                    pos + 5
+                                ),
+                            ));
+                        }
+                    }
+                }
+
+                token::Token::If => {
+                    // Expected Next:
+                    // EXPRESSION Then Number
+                    // Where Number is a Line Number
+                    match (
+                        parse_and_eval_expression(&mut token_iter, &mut self.context),
+                        token_iter.next(),
+                        token_iter.next(),
+                    ) {
+                        (
+                            Ok(value::RBasicValue::Bool(ref value)),
+                            Some(&lexer::TokenAndPos(_, token::Token::Then)),
+                            Some(&lexer::TokenAndPos(_, token::Token::Number(_))),
+                        ) => {
+                            if let Some(coverage) = self.coverage.as_mut() {
+                                coverage.record_branch(line_number, *value);
+                            }
+                            if *value {
+                                line_has_goto = true;
+                                self.line_index = self.targets[self.line_index][0];
+                            }
+                        }
+                        (Err(e), _, _) => {
+                            return Err(error::RBasicError::new(
+                                e.code,
+                                format!(
+                                    "At {:?}, {} error in IF expression: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                        _ => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!("At {:?}, {}, invalid syntax for IF.", line_number, pos),
+                            ));
+                        }
+                    }
+                }
+
+                token::Token::For => {
+                    // Expected Next:
+                    // Variable Equals EXPRESSION To EXPRESSION [Step EXPRESSION]
+                    let (for_loop, start) = parse_for(&mut token_iter, &mut self.context)
+                        .map_err(|e| error_at(line_number, pos, e))?;
+
+                    // A FOR using the variable of an active loop replaces
+                    // that loop and any inside of it
+                    if let Some(index) = self
+                        .for_stack
+                        .iter()
+                        .position(|active| active.variable == for_loop.variable)
+                    {
+                        self.for_stack.truncate(index);
+                    }
+
+                    if for_loop
+                        .finished(&start)
+                        .map_err(|e| error_at(line_number, pos, e))?
+                    {
+                        // As in GWBASIC, a loop that wouldn't run at all is
+                        // skipped past its NEXT
+                        match find_next(&self.program, self.line_index) {
+                            Some(next_index) => {
+                                line_has_goto = true;
+                                self.line_index = next_index + 1;
+                            }
+                            None => {
+                                return Err(error::RBasicError::new(
+                                    error::FOR_WITHOUT_NEXT,
+                                    format!("At {:?}, {} FOR without NEXT", line_number, pos),
+                                ))
+                            }
+                        }
+                    } else {
+                        self.for_stack.push(ForLoop {
+                            line_number: line_number.clone(),
+                            body_index: self.line_index + 1,
+                            ..for_loop
+                        });
+                    }
+                }
+
+                token::Token::Next => {
+                    // Expected Next:
+                    // [Variable {Comma Variable}]
+                    let variables =
+                        parse_next(&mut token_iter).map_err(|e| error_at(line_number, pos, e))?;
+
+                    for variable in variables {
+                        // NEXT without a variable applies to the innermost loop
+                        let index = match variable {
+                            Some(name) => self
+                                .for_stack
+                                .iter()
+                                .rposition(|active| active.variable == *name),
+                            None => self.for_stack.len().checked_sub(1),
+                        };
+                        let index = match index {
+                            Some(index) => index,
+                            None => {
+                                return Err(error::RBasicError::new(
+                                    error::NEXT_WITHOUT_FOR,
+                                    format!("At {:?}, {} NEXT without FOR", line_number, pos),
+                                ))
+                            }
+                        };
+
+                        // Loops inside this one are left unfinished
+                        self.for_stack.truncate(index + 1);
+                        let for_loop = &self.for_stack[index];
+                        let value = self
+                            .context
+                            .variables
+                            .get(for_loop.slot)
+                            .map_err(|e| error_at(line_number, pos, e))?
+                            .cloned()
+                            .unwrap_or(value::RBasicValue::Number(0));
+                        let value = (value + for_loop.step.clone())
+                            .and_then(|value| {
+                                for_loop.finished(&value).map(|finished| (value, finished))
+                            })
+                            .map_err(|e| error_at(line_number, pos, e))?;
+                        self.context
+                            .set_variable(for_loop.slot, value.0)
+                            .map_err(|e| error_at(line_number, pos, e))?;
+
+                        if value.1 {
+                            self.for_stack.pop();
+                        } else {
+                            line_has_goto = true;
+                            self.line_index = for_loop.body_index;
+                            break;
+                        }
+                    }
+                }
+
+                token::Token::While => {
+                    // Expected Next:
+                    // EXPRESSION
+                    match parse_and_eval_expression(&mut token_iter, &mut self.context) {
+                        Ok(value::RBasicValue::Bool(true)) => {}
+                        Ok(value::RBasicValue::Bool(false)) => {
+                            line_has_goto = true;
+                            self.line_index = self.blocks[&self.line_index] + 1;
+                        }
+                        Ok(_) => {
+                            return Err(error::RBasicError::new(
+                                error::TYPE_MISMATCH,
+                                format!(
+                                    "At {:?}, {} WHILE condition must be a Boolean value",
+                                    line_number, pos
+                                ),
+                            ))
+                        }
+                        Err(e) => {
+                            return Err(error::RBasicError::new(
+                                e.code,
+                                format!(
+                                    "At {:?}, {} error in WHILE expression: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                    }
+                }
+
+                token::Token::Wend => {
+                    line_has_goto = true;
+                    self.line_index = self.blocks[&self.line_index];
+                }
+
+                token::Token::Do => {
+                    // Expected Next:
+                    // [(While | Until) EXPRESSION]
+                    match eval_loop_condition(&mut token_iter, &mut self.context) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            line_has_goto = true;
+                            self.line_index = self.blocks[&self.line_index] + 1;
+                        }
+                        Err(e) => {
+                            return Err(error::RBasicError::new(
+                                e.code,
+                                format!(
+                                    "At {:?}, {} error in DO condition: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                    }
+                }
+
+                token::Token::Loop => {
+                    // Expected Next:
+                    // [(While | Until) EXPRESSION]
+                    match eval_loop_condition(&mut token_iter, &mut self.context) {
+                        Ok(true) => {
+                            line_has_goto = true;
+                            self.line_index = self.blocks[&self.line_index];
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            return Err(error::RBasicError::new(
+                                e.code,
+                                format!(
+                                    "At {:?}, {} error in LOOP condition: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                    }
+                }
+
+                token::Token::Exit => {
+                    // Only EXIT DO is supported, which is checked when the
+                    // blocks are resolved
+                    line_has_goto = true;
+                    self.line_index = self.blocks[&self.line_index] + 1;
+                }
+
+                token::Token::Def => {
+                    // Expected Next:
+                    // Variable LParen [Variable {Comma Variable}] RParen Equals EXPRESSION
+                    // Where the Variable naming the function starts with FN
+                    match parse_function_definition(&mut token_iter) {
+                        Ok((name, function)) => {
+                            self.context.functions.insert(name, Rc::new(function));
+                        }
+                        Err(e) => {
+                            return Err(error::RBasicError::new(
+                                error::SYNTAX_ERROR,
+                                format!(
+                                    "At {:?}, {} invalid syntax for DEF: {}",
+                                    line_number, pos, e
+                                ),
+                            ))
+                        }
+                    }
+                }
+
+                _ => {
+                    return Err(error::RBasicError::new(
+                        error::SYNTAX_ERROR,
+                        format!("At {:?}, {} invalid syntax", line_number, pos),
+                    ));
+                }
+            }
+        }
+
+        // At end of execution, show context:
+        // println!("Current context: {:?}", self.context);
+
+        if !line_has_goto {
+            self.line_index += 1;
+        }
+
+        Ok(None)
+    }
+}
+
+pub fn evaluate(code_lines: Vec<lexer::LineOfCode>) -> Result<String, String> {
+    let mut interpreter = Interpreter::new(code_lines).map_err(|e| e.message)?;
+
+    interpreter
+        .run()
+        .map(|status| status.to_string())
+        .map_err(|e| e.message)
+}
+
+// Matches every WHILE with its WEND and every DO with its LOOP before the
+// program runs, so that unmatched blocks are reported as syntax errors up
+// front. The returned map goes both ways between the line indices of each
+// pair, and from the line index of each EXIT DO to its LOOP.
+fn resolve_blocks(
+    program: &[lexer::LineOfCode],
+) -> Result<HashMap<usize, usize>, error::RBasicError> {
+    let mut blocks: HashMap<usize, usize> = HashMap::new();
+    // Open WHILE and DO statements, along with the EXIT DOs of each DO
+    let mut open_blocks: Vec<(&token::Token, usize, Vec<usize>)> = Vec::new();
+
+    for (line_index, line) in program.iter().enumerate() {
+        let line_number = &line.line_number;
+        let (pos, statement, next) = match line.tokens.as_slice() {
+            [lexer::TokenAndPos(pos, statement), rest @ ..] => {
+                (pos, statement, rest.first().map(|next| &next.1))
+            }
+            [] => continue,
+        };
+
+        match *statement {
+            token::Token::While | token::Token::Do => {
+                open_blocks.push((statement, line_index, Vec::new()));
+            }
+            token::Token::Wend => match open_blocks.pop() {
+                Some((token::Token::While, while_index, _)) => {
+                    blocks.insert(while_index, line_index);
+                    blocks.insert(line_index, while_index);
+                }
+                _ => {
+                    return Err(error::RBasicError::new(
+                        error::WEND_WITHOUT_WHILE,
+                        format!("At {:?}, {} WEND without WHILE", line_number, pos),
+                    ))
+                }
+            },
+            token::Token::Loop => match open_blocks.pop() {
+                Some((token::Token::Do, do_index, exits)) => {
+                    blocks.insert(do_index, line_index);
+                    blocks.insert(line_index, do_index);
+                    for exit_index in exits {
+                        blocks.insert(exit_index, line_index);
+                    }
+                }
+                _ => {
+                    return Err(error::RBasicError::new(
+                        error::SYNTAX_ERROR,
+                        format!("At {:?}, {} LOOP without DO", line_number, pos),
+                    ))
+                }
+            },
+            token::Token::Exit => {
+                if next != Some(&token::Token::Do) {
+                    return Err(error::RBasicError::new(
+                        error::SYNTAX_ERROR,
+                        format!("At {:?}, {} EXIT must be followed by DO", line_number, pos),
+                    ));
+                }
+
+                match open_blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|(block, _, _)| **block == token::Token::Do)
+                {
+                    Some((_, _, exits)) => exits.push(line_index),
+                    None => {
+                        return Err(error::RBasicError::new(
+                            error::SYNTAX_ERROR,
+                            format!("At {:?}, {} EXIT DO outside of DO loop", line_number, pos),
+                        ))
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match open_blocks.pop() {
+        Some((block, line_index, _)) => {
+            let line_number = &program[line_index].line_number;
+            let pos = program[line_index].tokens[0].0;
+            match *block {
+                token::Token::While => Err(error::RBasicError::new(
+                    error::WHILE_WITHOUT_WEND,
+                    format!("At {:?}, {} WHILE without WEND", line_number, pos),
+                )),
+                _ => Err(error::RBasicError::new(
+                    error::SYNTAX_ERROR,
+                    format!("At {:?}, {} DO without LOOP", line_number, pos),
+                )),
+            }
+        }
+        None => Ok(blocks),
+    }
+}
+
+// Checks the syntax of every ON statement, so that its targets can be
+// resolved when the program is loaded
+fn validate_on_statements(program: &[lexer::LineOfCode]) -> Result<(), error::RBasicError> {
+    for line in program {
+        let line_number = &line.line_number;
+        let mut token_iter = line.tokens.iter().peekable();
+
+        let pos = match token_iter.next() {
+            Some(&lexer::TokenAndPos(pos, token::Token::On)) => pos,
+            _ => continue,
+        };
+
+        if let Some(lexer::TokenAndPos(_, token::Token::Error)) = token_iter.peek() {
+            match parse_on_error_target(&mut token_iter) {
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(error::RBasicError::new(
+                        error::SYNTAX_ERROR,
+                        format!(
+                            "At {:?}, {} invalid syntax for ON ERROR: {}",
+                            line_number, pos, e
+                        ),
+                    ))
+                }
+            }
+            continue;
+        }
+
+        match parse_expression(&mut token_iter) {
+            Ok(ref output_queue) if !output_queue.is_empty() => {}
+            Ok(_) => {
+                return Err(error::RBasicError::new(
+                    error::SYNTAX_ERROR,
+                    format!(
+                        "At {:?}, {} ON must be followed by an expression",
+                        line_number, pos
+                    ),
+                ))
+            }
+            Err(e) => {
+                return Err(error::RBasicError::new(
+                    error::SYNTAX_ERROR,
+                    format!(
+                        "At {:?}, {} error in ON expression: {}",
+                        line_number, pos, e
+                    ),
+                ))
+            }
+        }
+
+        if let Err(e) = parse_on_targets(&mut token_iter) {
+            return Err(error::RBasicError::new(
+                error::SYNTAX_ERROR,
+                format!("At {:?}, {} invalid syntax for ON: {}", line_number, pos, e),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// A line number one statement refers to
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineReference {
+    // Position of the line number in its line
+    pub pos: u32,
+    pub target: lexer::LineNumber,
+    // The statement making the reference, such as GOSUB or ON GOTO
+    pub statement: &'static str,
+}
+
+// Finds the line numbers a line refers to, in the order they appear in it
+pub fn line_references(tokens: &[lexer::TokenAndPos]) -> Vec<LineReference> {
+    let reference = |token: Option<&lexer::TokenAndPos>, statement| match token {
+        Some(&lexer::TokenAndPos(pos, token::Token::Number(number))) => Some(LineReference {
+            pos,
+            target: lexer::LineNumber(number as u32),
+            statement,
+        }),
+        _ => None,
+    };
+    let mut token_iter = tokens.iter().peekable();
+
+    let references = match token_iter.next().map(|token| &token.1) {
+        Some(token::Token::Goto) => reference(token_iter.next(), "GOTO"),
+        Some(token::Token::Gosub) => reference(token_iter.next(), "GOSUB"),
+        Some(token::Token::Restore) => reference(token_iter.next(), "RESTORE"),
+        // RESUME 0 goes back to the statement with the error
+        Some(token::Token::Resume) => {
+            reference(token_iter.next(), "RESUME").filter(|reference| reference.target.0 != 0)
+        }
+        Some(token::Token::If) => match (
+            parse_expression(&mut token_iter),
+            token_iter.next().map(|token| &token.1),
+        ) {
+            (Ok(_), Some(token::Token::Then)) => reference(token_iter.next(), "IF"),
+            _ => None,
+        },
+        // ON ERROR GOTO 0 turns error trapping off
+        Some(token::Token::On)
+            if tokens.get(1).map(|token| &token.1) == Some(&token::Token::Error) =>
+        {
+            reference(tokens.get(3), "ON ERROR GOTO").filter(|reference| reference.target.0 != 0)
+        }
+        Some(token::Token::On) => {
+            let statement = match (
+                parse_expression(&mut token_iter),
+                token_iter.next().map(|token| &token.1),
+            ) {
+                (Ok(_), Some(token::Token::Goto)) => "ON GOTO",
+                (Ok(_), Some(token::Token::Gosub)) => "ON GOSUB",
+                _ => return Vec::new(),
+            };
+            return token_iter
+                .filter_map(|token| reference(Some(token), statement))
+                .collect();
+        }
+        _ => None,
+    };
+
+    references.into_iter().collect()
+}
+
+// Finds the line index of every line number the program refers to, reporting
+// every reference to a missing line at once rather than leaving them to be
+// found when, or if, the statement making them runs
+fn resolve_targets(
+    program: &[lexer::LineOfCode],
+    line_map: &BTreeMap<lexer::LineNumber, usize>,
+) -> Result<Vec<Vec<usize>>, error::RBasicError> {
+    let mut invalid = Vec::new();
+    let mut targets = Vec::with_capacity(program.len());
+
+    for line in program {
+        let mut line_targets = Vec::new();
+        for reference in line_references(&line.tokens) {
+            match line_map.get(&reference.target) {
+                Some(index) => line_targets.push(*index),
+                None => invalid.push(format!(
+                    "At {:?}, {} invalid target line {} for {}",
+                    line.line_number, reference.pos, reference.target.0, reference.statement
+                )),
+            }
+        }
+        targets.push(line_targets);
+    }
+
+    if invalid.is_empty() {
+        Ok(targets)
+    } else {
+        Err(error::RBasicError::new(
+            error::UNDEFINED_LINE_NUMBER,
+            invalid.join("\n"),
+        ))
+    }
+}
+
+// Parses the (Goto | Gosub) Number {Comma Number} part of an ON statement,
+// returning whether it is a GOSUB along with the target line numbers
+pub fn parse_on_targets(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+) -> Result<(bool, Vec<lexer::LineNumber>), String> {
+    let is_gosub = match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Goto)) => false,
+        Some(lexer::TokenAndPos(_, token::Token::Gosub)) => true,
+        _ => return Err("expected GOTO or GOSUB".to_string()),
+    };
+    let mut targets: Vec<lexer::LineNumber> = Vec::new();
+
+    loop {
+        match token_iter.next() {
+            Some(&lexer::TokenAndPos(_, token::Token::Number(number))) if number >= 0 => {
+                targets.push(lexer::LineNumber(number as u32))
+            }
+            _ => return Err("expected a line number".to_string()),
+        }
+
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            None => break,
+            _ => return Err("expected a comma between line numbers".to_string()),
+        }
+    }
+
+    Ok((is_gosub, targets))
+}
+
+// Gathers the items of every DATA statement, so that READ can take them in
+// order wherever it is in the program. Items are numbers, quoted strings or
+// single words, which are read as strings.
+fn collect_data(
+    program: &[lexer::LineOfCode],
+) -> Result<Vec<(usize, value::RBasicValue)>, error::RBasicError> {
+    let mut data = Vec::new();
+
+    for (index, line) in program.iter().enumerate() {
+        let mut token_iter = line.tokens.iter();
+
+        let pos = match token_iter.next() {
+            Some(&lexer::TokenAndPos(pos, token::Token::Data)) => pos,
+            _ => continue,
+        };
+
+        loop {
+            let item = match token_iter.next() {
+                Some(lexer::TokenAndPos(_, token::Token::UMinus)) => match token_iter.next() {
+                    Some(lexer::TokenAndPos(_, token::Token::Number(number))) => {
+                        Some(value::RBasicValue::Number(-number))
+                    }
+                    Some(lexer::TokenAndPos(_, token::Token::Float(float))) => {
+                        Some(value::RBasicValue::Float(-float))
+                    }
+                    _ => None,
+                },
+                Some(lexer::TokenAndPos(_, token::Token::Number(number))) => {
+                    Some(value::RBasicValue::Number(*number))
+                }
+                Some(lexer::TokenAndPos(_, token::Token::Float(float))) => {
+                    Some(value::RBasicValue::Float(*float))
+                }
+                Some(lexer::TokenAndPos(_, token::Token::BString(string)))
+                | Some(lexer::TokenAndPos(_, token::Token::Variable(string, _))) => {
+                    Some(value::RBasicValue::String(string.clone()))
+                }
+                _ => None,
+            };
+
+            match (item, token_iter.next()) {
+                (Some(item), None) => {
+                    data.push((index, item));
+                    break;
+                }
+                (Some(item), Some(lexer::TokenAndPos(_, token::Token::Comma))) => {
+                    data.push((index, item))
+                }
+                _ => {
+                    return Err(error::RBasicError::new(
+                        error::SYNTAX_ERROR,
+                        format!(
+                            "At {:?}, {} DATA must be followed by constants separated by \
+                                        commas",
+                            line.line_number, pos
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+// The value a DATA item gives a variable or array element. As in GWBASIC,
+// names ending in $ take numbers as strings, but other names can't take
+// strings.
+pub fn data_item(
+    item: &value::RBasicValue,
+    name: &str,
+) -> Result<value::RBasicValue, error::RBasicError> {
+    match item {
+        _ if name.ends_with('$') => Ok(value::RBasicValue::from(item.to_string())),
+        value::RBasicValue::String(_) => Err(error::RBasicError::new(
+            error::TYPE_MISMATCH,
+            format!("Type mismatch reading {} into {}", item, name),
+        )),
+        _ => Ok(item.clone()),
+    }
+}
+
+// Reads the next DATA items into each variable or array element given
+fn read_data(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+    data: &[(usize, value::RBasicValue)],
+    data_index: &mut usize,
+) -> Result<(), error::RBasicError> {
+    loop {
+        let (name, slot) = match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Variable(name, slot))) => (name, *slot),
+            _ => return Err(syntax_error("READ must be followed by variables")),
+        };
+        let subscripts = match token_iter.peek() {
+            Some(lexer::TokenAndPos(_, token::Token::LParen)) => {
+                Some(parse_subscripts(token_iter, context)?)
+            }
+            _ => None,
+        };
+
+        let item = match data.get(*data_index) {
+            Some((_, item)) => item,
+            None => {
+                return Err(error::RBasicError::new(
+                    error::OUT_OF_DATA,
+                    "Out of DATA".to_string(),
+                ))
+            }
+        };
+        // An item that doesn't fit the variable is still used up, as on the VM
+        *data_index += 1;
+        let item = data_item(item, name)?;
+
+        match subscripts {
+            Some(subscripts) => context.set_array_element(name, &subscripts, item)?,
+            None => context.set_variable(slot, item)?,
+        }
+
+        match token_iter.next() {
+            None => return Ok(()),
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            _ => return Err(syntax_error("variables must be separated by commas")),
+        }
+    }
+}
+
+// Finds the DATA item READ takes after a RESTORE, which is the first one at or
+// after the line the RESTORE names, or the first one in the program. The line
+// index of the named line is given as resolved when the program was loaded.
+pub fn restore_data(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    target: Option<usize>,
+    data: &[(usize, value::RBasicValue)],
+) -> Result<usize, error::RBasicError> {
+    let line_index = match (token_iter.next(), token_iter.next()) {
+        (None, _) => 0,
+        (Some(lexer::TokenAndPos(_, token::Token::Number(_))), None) => {
+            target.expect("RESTORE targets are resolved when the program is loaded")
+        }
+        _ => {
+            return Err(syntax_error(
+                "RESTORE may only be followed by a line number",
+            ))
+        }
+    };
+
+    Ok(data
+        .iter()
+        .position(|(index, _)| *index >= line_index)
+        .unwrap_or(data.len()))
+}
+
+// Parses the Error Goto Number part of an ON ERROR GOTO statement, where a
+// target of 0 turns error trapping off
+pub fn parse_on_error_target(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+) -> Result<lexer::LineNumber, String> {
+    match (
+        token_iter.next(),
+        token_iter.next(),
+        token_iter.next(),
+        token_iter.next(),
+    ) {
+        (
+            Some(lexer::TokenAndPos(_, token::Token::Error)),
+            Some(lexer::TokenAndPos(_, token::Token::Goto)),
+            Some(&lexer::TokenAndPos(_, token::Token::Number(number))),
+            None,
+        ) => Ok(lexer::LineNumber(number as u32)),
+        _ => Err("expected ERROR GOTO and a line number".to_string()),
+    }
+}
+
+// Evaluates the optional WHILE or UNTIL condition of a DO or LOOP statement,
+// returning whether the loop should keep running
+fn eval_loop_condition(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<bool, error::RBasicError> {
+    let until = match token_iter.next() {
+        None => return Ok(true),
+        Some(lexer::TokenAndPos(_, token::Token::While)) => false,
+        Some(lexer::TokenAndPos(_, token::Token::Until)) => true,
+        Some(_) => {
+            return Err(error::RBasicError::new(
+                error::SYNTAX_ERROR,
+                "expected WHILE or UNTIL".to_string(),
+            ))
+        }
+    };
+
+    match parse_and_eval_expression(token_iter, context) {
+        Ok(value::RBasicValue::Bool(value)) => Ok(value != until),
+        Ok(_) => Err(error::RBasicError::new(
+            error::TYPE_MISMATCH,
+            "condition must be a Boolean value".to_string(),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+// Parses the prompt an INPUT statement may start with. As in GWBASIC, the
+// prompt is "? " when none is given.
+pub fn parse_input_prompt(token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>) -> String {
+    if let Some(lexer::TokenAndPos(_, token::Token::BString(prompt))) = token_iter.peek() {
+        token_iter.next();
+        if let Some(lexer::TokenAndPos(_, token::Token::Comma)) = token_iter.peek() {
+            token_iter.next();
+        }
+        return prompt.to_string();
+    }
+    "? ".to_string()
+}
+
+pub fn read_console_line(prompt: &str) -> Result<String, io::Error> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
+}
+
+// Checks that a name given by the host can be used in a program
+fn check_name(name: &str, kind: &str) -> Result<(), error::RBasicError> {
+    if !lexer::is_valid_identifier(name) || token::Token::token_for_string(name).is_some() {
+        return Err(error::RBasicError::new(
+            error::SYNTAX_ERROR,
+            format!("{} can't be used as a {} name", name, kind),
+        ));
+    }
+    Ok(())
+}
+
+fn bad_snapshot(message: &str) -> error::RBasicError {
+    error::RBasicError::new(error::BAD_SNAPSHOT, format!("Bad snapshot: {}", message))
+}
+
+// The bytes a value uses for the string space limit
+pub fn string_length(value: Option<&value::RBasicValue>) -> usize {
+    match value {
+        Some(value::RBasicValue::String(string)) => string.len(),
+        _ => 0,
+    }
+}
+
+// Adds the position of the statement to an error from one of the helpers
+// below
+fn error_at(
+    line_number: &lexer::LineNumber,
+    pos: u32,
+    e: error::RBasicError,
+) -> error::RBasicError {
+    error::RBasicError::new(e.code, format!("At {:?}, {} {}", line_number, pos, e))
+}
+
+fn syntax_error(message: &str) -> error::RBasicError {
+    error::RBasicError::new(error::SYNTAX_ERROR, message.to_string())
+}
+
+fn open_file_number(
+    files: &mut HashMap<i32, files::BasicFile>,
+    number: i32,
+) -> Result<&mut files::BasicFile, error::RBasicError> {
+    match files.get_mut(&number) {
+        Some(file) => Ok(file),
+        None => Err(error::RBasicError::new(
+            error::BAD_FILE_NUMBER,
+            format!("Bad file number {}", number),
+        )),
+    }
+}
+
+// Parses a file number, with or without its leading #
+fn parse_file_number(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<i32, error::RBasicError> {
+    if token_iter.peek().map(|next| &next.1) == Some(&token::Token::Hash) {
+        token_iter.next();
+    }
+
+    match parse_and_eval_expression(token_iter, context)? {
+        value::RBasicValue::Number(number) if (1..=255).contains(&number) => Ok(number),
+        value::RBasicValue::Number(number) => Err(error::RBasicError::new(
+            error::BAD_FILE_NUMBER,
+            format!("Bad file number {}", number),
+        )),
+        _ => Err(error::RBasicError::new(
+            error::TYPE_MISMATCH,
+            "file number must be a number".to_string(),
+        )),
+    }
+}
+
+// Parses the file number and comma that start PRINT #, WRITE #, INPUT # and
+// LINE INPUT #
+fn parse_file_prefix(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<i32, error::RBasicError> {
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Hash)) => {}
+        _ => return Err(syntax_error("expected # and a file number")),
+    }
+
+    let number = parse_file_number(token_iter, context)?;
+
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Comma)) => Ok(number),
+        _ => Err(syntax_error("file number must be followed by a comma")),
+    }
+}
+
+fn open_file(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let path = match parse_and_eval_expression(token_iter, context)? {
+        value::RBasicValue::String(path) => path,
+        _ => {
+            return Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "OPEN file name must be a string".to_string(),
+            ))
+        }
+    };
+
+    let mode = match token_iter.peek().map(|next| &next.1) {
+        Some(token::Token::For) => {
+            token_iter.next();
+            match token_iter.next().map(|next| &next.1) {
+                Some(token::Token::Input) => files::FileMode::Input,
+                Some(token::Token::Output) => files::FileMode::Output,
+                Some(token::Token::Append) => files::FileMode::Append,
+                Some(token::Token::Random) => files::FileMode::Random(files::DEFAULT_RECORD_LENGTH),
+                _ => {
+                    return Err(syntax_error(
+                        "OPEN mode must be INPUT, OUTPUT, APPEND or RANDOM",
+                    ))
+                }
+            }
+        }
+        // Without FOR, the file is opened for random access
+        _ => files::FileMode::Random(files::DEFAULT_RECORD_LENGTH),
+    };
+
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::As)) => {}
+        _ => {
+            return Err(syntax_error(
+                "OPEN must be followed by a file name, FOR mode and AS file number",
+            ))
+        }
+    }
+
+    let number = parse_file_number(token_iter, context)?;
+
+    let mode = match token_iter.next() {
+        None => mode,
+        Some(lexer::TokenAndPos(_, token::Token::Len)) => {
+            match token_iter.next() {
+                Some(lexer::TokenAndPos(_, token::Token::Equals)) => {}
+                _ => {
+                    return Err(syntax_error(
+                        "LEN must be followed by = and a record length",
+                    ))
+                }
+            }
+
+            let record_length = match parse_and_eval_expression(token_iter, context)? {
+                value::RBasicValue::Number(length) => length.max(0) as usize,
+                _ => {
+                    return Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        "record length must be a number".to_string(),
+                    ))
+                }
+            };
+
+            // The record length only matters for random access files
+            match mode {
+                files::FileMode::Random(_) => files::FileMode::Random(record_length),
+                mode => mode,
+            }
+        }
+        _ => {
+            return Err(syntax_error(
+                "OPEN file number must be followed by LEN or nothing",
+            ))
+        }
+    };
+
+    if context.files.contains_key(&number) {
+        return Err(error::RBasicError::new(
+            error::FILE_ALREADY_OPEN,
+            format!("File {} is already open", number),
+        ));
+    }
+
+    let file = files::open(&path, mode)?;
+    context.files.insert(number, file);
+    Ok(())
+}
+
+fn close_files(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    // CLOSE on its own closes every open file
+    if token_iter.peek().is_none() {
+        return context.close_all_files();
+    }
+
+    loop {
+        let number = parse_file_number(token_iter, context)?;
+
+        // Closing a file that isn't open does nothing
+        if let Some(file) = context.files.remove(&number) {
+            file.close()?;
+        }
+
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            None => return Ok(()),
+            _ => return Err(syntax_error("file numbers must be separated by commas")),
+        }
+    }
+}
+
+fn print_to_file(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let number = parse_file_prefix(token_iter, context)?;
+    let value = parse_and_eval_expression(token_iter, context)?;
+
+    context.file(number)?.write_line(value.to_string().as_str())
+}
+
+// Writes values separated by commas, with strings in quotes, so INPUT # can
+// read them back
+fn write_to_file(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let number = parse_file_prefix(token_iter, context)?;
+    let mut items: Vec<String> = Vec::new();
+
+    loop {
+        match parse_and_eval_expression(token_iter, context)? {
+            value::RBasicValue::String(value) => items.push(format!("\"{}\"", value)),
+            value => items.push(value.to_string()),
+        }
+
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            None => break,
+            _ => return Err(syntax_error("values must be separated by commas")),
+        }
+    }
+
+    context.file(number)?.write_line(items.join(",").as_str())
+}
+
+fn input_from_file(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let number = parse_file_prefix(token_iter, context)?;
+
+    loop {
+        let slot = match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Variable(_, slot))) => *slot,
+            _ => return Err(syntax_error("INPUT # must be followed by variable names")),
+        };
+
+        // Unquoted items that look like numbers are read as numbers, so
+        // that what WRITE # wrote comes back with the same type
+        let value = match context.file(number)?.read_item()? {
+            (item, true) => value::RBasicValue::from(item),
+            (item, false) => match (i32::from_str(&item), f64::from_str(&item)) {
+                (Ok(number), _) => value::RBasicValue::Number(number),
+                (_, Ok(float)) => value::RBasicValue::Float(float),
+                _ => value::RBasicValue::from(item),
+            },
+        };
+        context.set_variable(slot, value)?;
+
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            None => return Ok(()),
+            _ => return Err(syntax_error("variable names must be separated by commas")),
+        }
+    }
+}
+
+fn line_input_from_file(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Input)) => {}
+        _ => return Err(syntax_error("LINE must be followed by INPUT")),
+    }
+
+    let number = parse_file_prefix(token_iter, context)?;
+
+    match (token_iter.next(), token_iter.next()) {
+        (Some(lexer::TokenAndPos(_, token::Token::Variable(_, slot))), None) => {
+            let line = context.file(number)?.read_line()?;
+            context.set_variable(*slot, value::RBasicValue::from(line))?;
+            Ok(())
+        }
+        _ => Err(syntax_error(
+            "LINE INPUT # must be followed by a single variable name",
+        )),
+    }
+}
+
+// Parses the file number and optional record number of GET and PUT
+fn parse_record_target(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(i32, Option<u64>), error::RBasicError> {
+    let number = parse_file_number(token_iter, context)?;
+
+    match token_iter.next() {
+        None => Ok((number, None)),
+        Some(lexer::TokenAndPos(_, token::Token::Comma)) => {
+            match parse_and_eval_expression(token_iter, context)? {
+                value::RBasicValue::Number(record) => Ok((number, Some(record.max(0) as u64))),
+                _ => Err(error::RBasicError::new(
+                    error::TYPE_MISMATCH,
+                    "record number must be a number".to_string(),
+                )),
+            }
+        }
+        _ => Err(syntax_error(
+            "file number must be followed by a comma and record number",
+        )),
+    }
 }
 
-impl RBasicContext {
-    fn new() -> RBasicContext {
-        RBasicContext {
-            variables: HashMap::new(),
+fn get_record(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let (number, record) = parse_record_target(token_iter, context)?;
+    let file = context.file(number)?;
+    file.get(record)?;
+    set_field_variables(file.field_values(), context)
+}
+
+fn put_record(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let (number, record) = parse_record_target(token_iter, context)?;
+    context.file(number)?.put(record)
+}
+
+fn define_fields(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let number = parse_file_number(token_iter, context)?;
+    let mut fields: Vec<(String, usize)> = Vec::new();
+
+    loop {
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            None if !fields.is_empty() => break,
+            _ => {
+                return Err(syntax_error(
+                    "FIELD must be followed by a file number and widths AS variables",
+                ))
+            }
+        }
+
+        let width = match parse_and_eval_expression(token_iter, context)? {
+            value::RBasicValue::Number(width) if (0..=255).contains(&width) => width as usize,
+            value::RBasicValue::Number(width) => {
+                return Err(error::RBasicError::new(
+                    error::ILLEGAL_FUNCTION_CALL,
+                    format!("illegal field width {}", width),
+                ))
+            }
+            _ => {
+                return Err(error::RBasicError::new(
+                    error::TYPE_MISMATCH,
+                    "field width must be a number".to_string(),
+                ))
+            }
+        };
+
+        match (token_iter.next(), token_iter.next()) {
+            (
+                Some(lexer::TokenAndPos(_, token::Token::As)),
+                Some(lexer::TokenAndPos(_, token::Token::Variable(name, _))),
+            ) => fields.push((name.to_string(), width)),
+            _ => {
+                return Err(syntax_error(
+                    "field width must be followed by AS and a variable",
+                ))
+            }
         }
     }
+
+    let file = context.file(number)?;
+    file.set_fields(&fields)?;
+    set_field_variables(file.field_values(), context)
 }
 
-pub fn evaluate(code_lines: Vec<lexer::LineOfCode>) -> Result<String, String> {
-    let mut context = RBasicContext::new();
-    let mut lineno_to_code = BTreeMap::new();
-    let mut line_map = BTreeMap::new();
+fn set_field_variables(
+    values: Vec<(String, String)>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    for (name, contents) in values {
+        context.set_named_variable(&name, value::RBasicValue::from(contents))?;
+    }
+    Ok(())
+}
+
+// Stores a string in a field variable for LSET and RSET. Other string
+// variables keep their current length.
+fn justify_variable(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+    right_justify: bool,
+) -> Result<(), error::RBasicError> {
+    let (name, slot) = match (token_iter.next(), token_iter.next()) {
+        (
+            Some(lexer::TokenAndPos(_, token::Token::Variable(name, slot))),
+            Some(lexer::TokenAndPos(_, token::Token::Equals)),
+        ) => (name, *slot),
+        _ => {
+            return Err(syntax_error(
+                "LSET and RSET must be followed by a variable, = and an expression",
+            ))
+        }
+    };
+
+    let value = match parse_and_eval_expression(token_iter, context)? {
+        value::RBasicValue::String(value) => value,
+        _ => {
+            return Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "LSET and RSET require a string value".to_string(),
+            ))
+        }
+    };
+
+    // When more than one file has the field, the lowest numbered one is used
+    let mut numbers: Vec<i32> = context.files.keys().cloned().collect();
+    numbers.sort_unstable();
+    let field_contents = numbers.iter().find_map(|number| {
+        context
+            .files
+            .get_mut(number)
+            .and_then(|file| file.set_field(name, &value, right_justify))
+    });
+
+    let contents = match (field_contents, context.variables.get(slot)?) {
+        (Some(contents), _) => contents,
+        (None, Some(value::RBasicValue::String(current))) => {
+            files::justify(&value, current.chars().count(), right_justify)
+        }
+        _ => {
+            return Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                format!("{} is not a field or string variable", name),
+            ))
+        }
+    };
+
+    context.set_variable(slot, value::RBasicValue::from(contents))
+}
 
-    for (index, line) in code_lines.iter().enumerate() {
-        line_map.insert(&line.line_number, index);
-        lineno_to_code.insert(&line.line_number, &line.tokens);
+// Parses a FOR statement, setting the loop variable to its starting value,
+// and returns the loop along with that value
+fn parse_for(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(ForLoop, value::RBasicValue), error::RBasicError> {
+    let variable = match (token_iter.next(), token_iter.next()) {
+        (
+            Some(lexer::TokenAndPos(_, token::Token::Variable(variable, slot))),
+            Some(lexer::TokenAndPos(_, token::Token::Equals)),
+        ) => (variable, *slot),
+        _ => return Err(syntax_error("FOR must be followed by a variable and =")),
+    };
+
+    let start = parse_and_eval_expression(token_iter, context)?;
+
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::To)) => {}
+        _ => return Err(syntax_error("FOR starting value must be followed by TO")),
     }
 
-    let line_numbers: Vec<_> = line_map.keys().clone().collect();
-    let num_lines = line_numbers.len();
-    let mut line_index = 0;
-    // TODO: Feels hacky
-    let mut line_has_goto = false;
+    let end = parse_and_eval_expression(token_iter, context)?;
 
-    loop {
+    let step = match token_iter.next() {
+        None => value::RBasicValue::Number(1),
+        Some(lexer::TokenAndPos(_, token::Token::Step)) => {
+            parse_and_eval_expression(token_iter, context)?
+        }
+        _ => {
+            return Err(syntax_error(
+                "FOR end value must be followed by STEP or nothing",
+            ))
+        }
+    };
 
-        // If we're at the end of the program then we stop
-        if line_index == num_lines {
-               break;
+    for value in &[&start, &end, &step] {
+        if !matches!(
+            value,
+            value::RBasicValue::Number(_) | value::RBasicValue::Float(_)
+        ) {
+            return Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "FOR values must be numbers".to_string(),
+            ));
         }
+    }
 
-        let line_number = line_numbers[line_index];
-        let tokens = &lineno_to_code[line_number];
-        let mut token_iter = tokens.iter().peekable();
+    context.set_variable(variable.1, start.clone())?;
 
-        // println!("Looking at line: {:?}", line_number);
-        if !tokens.is_empty() {
-            let lexer::TokenAndPos(pos, ref token) = *token_iter.next().unwrap();
-            // Set default value
-            line_has_goto = false;
+    Ok((
+        ForLoop {
+            variable: variable.0.to_string(),
+            slot: variable.1,
+            end,
+            step,
+            line_number: lexer::LineNumber(0),
+            body_index: 0,
+        },
+        start,
+    ))
+}
 
-            match *token {
-                token::Token::Rem => {
-                    // Skip the rest of the line so do nothing
-                }
+// Parses the variables of a NEXT statement, with None standing for the
+// innermost loop when there are none
+pub fn parse_next<'a>(
+    token_iter: &mut Peekable<Iter<'a, lexer::TokenAndPos>>,
+) -> Result<Vec<Option<&'a str>>, error::RBasicError> {
+    let mut variables = Vec::new();
 
-                token::Token::Goto => {
-                    line_has_goto = true;
-                    match token_iter.next() {
-                        Some(&lexer::TokenAndPos(pos, token::Token::Number(number))) => {
-                            let n = lexer::LineNumber(number as u32);
-                            match line_map.get(&n) {
-                                Some(index) => line_index = *index,
-                                _ => {
-                                    return Err(format!(
-                                        "At {:?}, {} invalid target line for GOTO",
-                                        line_number, pos
-                                    ))
-                                }
-                            }
-                        }
-                        Some(&lexer::TokenAndPos(pos, _)) => {
-                            return Err(format!(
-                                "At {:?}, {} GOTO must be followed by valid line \
-                                                number",
-                                line_number, pos
-                            ));
-                        }
-                        None => {
-                            return Err(format!(
-                                "At {:?}, {} GOTO must be followed by a line \
-                                                number",
-                                line_number,
-                                // Adding 4 to give the position past GOTO
-                                pos + 4
-                            ));
-                        }
-                    }
-                }
+    loop {
+        match token_iter.next() {
+            None if variables.is_empty() => return Ok(vec![None]),
+            Some(lexer::TokenAndPos(_, token::Token::Variable(variable, _))) => {
+                variables.push(Some(&**variable))
+            }
+            _ => return Err(syntax_error("NEXT must be followed by variable names")),
+        }
 
-                token::Token::Let => {
-                    // Expected Next:
-                    // Variable Equals EXPRESSION
-                    match (
-                        token_iter.next(),
-                        token_iter.next(),
-                        parse_and_eval_expression(&mut token_iter, &context),
-                    ) {
-                        (
-                            Some(&lexer::TokenAndPos(_, token::Token::Variable(ref variable))),
-                            Some(&lexer::TokenAndPos(_, token::Token::Equals)),
-                            Ok(ref value),
-                        ) => {
-                            context
-                                .variables
-                                .insert(variable.clone().to_string(), value.clone());
-                        }
-                        (_, _, Err(e)) => {
-                            return Err(format!(
-                                "At {:?}, {} error in LET expression: {}",
-                                line_number, pos, e
-                            ))
-                        }
-                        _ => {
-                            return Err(format!(
-                                "At {:?}, {} invalid syntax for LET.",
-                                line_number, pos
-                            ));
-                        }
-                    }
-                }
+        match token_iter.next() {
+            None => return Ok(variables),
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            _ => return Err(syntax_error("variable names must be separated by commas")),
+        }
+    }
+}
 
-                token::Token::Print => {
-                    // Expected Next:
-                    // EXPRESSION
-                    match parse_and_eval_expression(&mut token_iter, &context) {
-                        Ok(value::RBasicValue::String(value)) => println!("{}", value),
-                        Ok(value::RBasicValue::Number(value)) => println!("{}", value),
-                        Ok(value::RBasicValue::Bool(value)) => println!("{}", value),
-                        Err(_) => {
-                            return Err(format!(
-                                "At {:?}. {} PRINT must be followed by valid \
-                                                expression",
-                                line_number, pos
-                            ))
-                        }
-                    }
+// Finds the NEXT ending the FOR loop on the given line, skipping over any
+// loops nested inside it
+pub fn find_next(program: &[lexer::LineOfCode], for_index: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for (line_index, line) in program.iter().enumerate().skip(for_index + 1) {
+        match line.tokens.first().map(|first| &first.1) {
+            Some(token::Token::For) => depth += 1,
+            Some(token::Token::Next) => {
+                // NEXT J, I ends two loops at once
+                let ends = line
+                    .tokens
+                    .iter()
+                    .filter(|next| matches!(next.1, token::Token::Variable(_, _)))
+                    .count()
+                    .max(1);
+
+                if ends > depth {
+                    return Some(line_index);
                 }
+                depth -= ends;
+            }
+            _ => {}
+        }
+    }
 
-                token::Token::Input => {
-                    match token_iter.next() {
-                        Some(&lexer::TokenAndPos(_, token::Token::Variable(ref variable))) => {
-                            let mut input = String::new();
+    None
+}
 
-                            io::stdin()
-                                .read_line(&mut input)
-                                .expect("failed to read line");
-                            input = input.trim().to_string();
-                            let value = value::RBasicValue::String(input);
+// Parses a parenthesized list of subscripts, as in A(I, J + 1)
+fn parse_subscripts(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<Vec<i32>, error::RBasicError> {
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::LParen)) => {}
+        _ => return Err(syntax_error("expected ( and subscripts")),
+    }
 
-                            // Store the string now, can coerce to number later if needed
-                            // Can overwrite an existing value
-                            context
-                                .variables
-                                .entry(variable.clone().to_string())
-                                .or_insert(value);
-                        }
+    let mut values: Vec<value::RBasicValue> = Vec::new();
+    let mut subscript_tokens: Vec<lexer::TokenAndPos> = Vec::new();
+    let mut depth = 0;
 
-                        _ => {
-                            return Err(format!(
-                                "At {:?}, {} INPUT must be followed by a \
-                                                variable name",
-                                line_number,
-                                // Adding 5 to put position past INPUT
-                                pos + 5
-                            ));
-                        }
-                    }
-                }
+    loop {
+        let next = match token_iter.next() {
+            Some(next) => next,
+            None => return Err(syntax_error("missing ) after subscripts")),
+        };
 
-                token::Token::If => {
-                    // Expected Next:
-                    // EXPRESSION Then Number
-                    // Where Number is a Line Number
-                    match (
-                        parse_and_eval_expression(&mut token_iter, &context),
-                        token_iter.next(),
-                        token_iter.next(),
-                    ) {
-                        (
-                            Ok(value::RBasicValue::Bool(ref value)),
-                            Some(&lexer::TokenAndPos(_, token::Token::Then)),
-                            Some(&lexer::TokenAndPos(_, token::Token::Number(ref number))),
-                        ) => {
-                            if *value {
-                                line_has_goto = true;
-                                let n = lexer::LineNumber(*number as u32);
-                                match line_map.get(&n) {
-                                    Some(index) => line_index = *index,
-                                    _ => {
-                                        return Err(format!(
-                                            "At {:?}, {} invalid target line for \
-                                                            IF",
-                                            line_number, pos
-                                        ))
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            return Err(format!(
-                                "At {:?}, {}, invalid syntax for IF.",
-                                line_number, pos
-                            ));
-                        }
-                    }
+        match next.1 {
+            token::Token::Comma | token::Token::RParen if depth == 0 => {
+                let mut subscript_iter = subscript_tokens.iter().peekable();
+                if subscript_tokens.is_empty() {
+                    return Err(syntax_error("missing subscript"));
                 }
+                values.push(parse_and_eval_expression(&mut subscript_iter, context)?);
+                if subscript_iter.peek().is_some() {
+                    return Err(syntax_error("invalid subscript"));
+                }
+                subscript_tokens.clear();
 
-                _ => {
-                    return Err(format!("At {:?}, {} invalid syntax", line_number, pos));
+                if next.1 == token::Token::RParen {
+                    return subscripts(&values);
                 }
             }
+            token::Token::LParen => {
+                depth += 1;
+                subscript_tokens.push(next.clone());
+            }
+            token::Token::RParen => {
+                depth -= 1;
+                subscript_tokens.push(next.clone());
+            }
+            _ => subscript_tokens.push(next.clone()),
         }
+    }
+}
 
-        // At end of execution, show context:
-        // println!("Current context: {:?}", context);
+pub fn subscripts(values: &[value::RBasicValue]) -> Result<Vec<i32>, error::RBasicError> {
+    values
+        .iter()
+        .map(|value| match *value {
+            value::RBasicValue::Number(number) => Ok(number),
+            value::RBasicValue::Float(float) => Ok(float.round() as i32),
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "subscripts must be numbers".to_string(),
+            )),
+        })
+        .collect()
+}
 
-        if !line_has_goto {
-            line_index += 1;
-            if line_index == num_lines {
-                break;
+fn dim_arrays(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    loop {
+        let name = match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Variable(name, _))) => name,
+            _ => {
+                return Err(syntax_error(
+                    "DIM must be followed by array names and sizes",
+                ))
+            }
+        };
+
+        let mut bounds: Vec<usize> = Vec::new();
+        for bound in parse_subscripts(token_iter, context)? {
+            if bound < 0 {
+                return Err(error::RBasicError::new(
+                    error::ILLEGAL_FUNCTION_CALL,
+                    format!("illegal array size {} for {}", bound, name),
+                ));
+            }
+            bounds.push(bound as usize);
+        }
+        context.dim_array(name, bounds)?;
+
+        match token_iter.next() {
+            None => return Ok(()),
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            _ => return Err(syntax_error("arrays must be separated by commas")),
+        }
+    }
+}
+
+fn assign_array_element(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+    context: &mut RBasicContext,
+) -> Result<(), error::RBasicError> {
+    let name = match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Variable(name, _))) => name,
+        _ => return Err(syntax_error("invalid syntax for LET.")),
+    };
+    let subscripts = parse_subscripts(token_iter, context)?;
+
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Equals)) => {}
+        _ => return Err(syntax_error("invalid syntax for LET.")),
+    }
+
+    let value = parse_and_eval_expression(token_iter, context)?;
+    context.set_array_element(name, &subscripts, value)
+}
+
+pub fn parse_function_definition(
+    token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
+) -> Result<(String, UserFunction), String> {
+    let name = match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Variable(name, _))) if name.starts_with("FN") => {
+            name.to_string()
+        }
+        _ => return Err("function name must start with FN".to_string()),
+    };
+
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::LParen)) => {}
+        _ => return Err(format!("{} must be followed by a parameter list", name)),
+    }
+
+    let mut parameters: Vec<String> = Vec::new();
+    let mut slots = Vec::new();
+
+    loop {
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::RParen)) if parameters.is_empty() => break,
+            Some(lexer::TokenAndPos(_, token::Token::Variable(parameter, slot))) => {
+                if slots.contains(slot) {
+                    return Err(format!("duplicate parameter {} for {}", parameter, name));
+                }
+                parameters.push(parameter.to_string());
+                slots.push(*slot);
             }
+            _ => return Err(format!("invalid parameter list for {}", name)),
+        }
+
+        match token_iter.next() {
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {}
+            Some(lexer::TokenAndPos(_, token::Token::RParen)) => break,
+            _ => return Err(format!("invalid parameter list for {}", name)),
         }
     }
 
-    Ok("Completed Successfully".to_string())
+    match token_iter.next() {
+        Some(lexer::TokenAndPos(_, token::Token::Equals)) => {}
+        _ => return Err(format!("{} must be followed by = and an expression", name)),
+    }
+
+    let body: Vec<lexer::TokenAndPos> = token_iter.cloned().collect();
+
+    // Check the body parses now rather than when the function is first called
+    let mut body_iter = body.iter().peekable();
+    match parse_expression(&mut body_iter) {
+        Ok(ref output_queue) if !output_queue.is_empty() && body_iter.peek().is_none() => {}
+        Ok(_) => return Err(format!("invalid expression for {}", name)),
+        Err(e) => return Err(e),
+    }
+
+    Ok((
+        name,
+        UserFunction {
+            parameters,
+            slots,
+            body,
+        },
+    ))
+}
+
+pub fn print_takes_one_expression(line_number: &lexer::LineNumber, pos: u32) -> error::RBasicError {
+    error::RBasicError::new(
+        error::SYNTAX_ERROR,
+        format!(
+            "At {:?}, {} PRINT takes a single expression, with nothing after it",
+            line_number, pos
+        ),
+    )
 }
 
-fn parse_expression(
+pub fn parse_expression(
     token_iter: &mut Peekable<Iter<'_, lexer::TokenAndPos>>,
 ) -> Result<VecDeque<token::Token>, String> {
     let mut output_queue: VecDeque<token::Token> = VecDeque::new();
@@ -240,15 +3177,41 @@ fn parse_expression(
 
     loop {
         match token_iter.peek() {
-            Some(&&lexer::TokenAndPos(_, token::Token::Then)) | None => break,
+            Some(lexer::TokenAndPos(_, token::Token::Then)) | None => break,
+            // A comma outside of a function call ends the expression
+            Some(lexer::TokenAndPos(_, token::Token::Comma))
+                if !operator_stack.contains(&token::Token::LParen) =>
+            {
+                break
+            }
+            Some(lexer::TokenAndPos(_, next_token))
+                if !(next_token.is_value()
+                    || next_token.is_operator()
+                    || *next_token == token::Token::LParen
+                    || *next_token == token::Token::RParen
+                    || *next_token == token::Token::Comma) =>
+            {
+                break
+            }
             _ => {}
         }
 
         match token_iter.next() {
-            Some(&lexer::TokenAndPos(_, ref value_token)) if value_token.is_value() => {
+            Some(lexer::TokenAndPos(_, token::Token::Variable(name, _)))
+                if token_iter.peek().map(|next| &next.1) == Some(&token::Token::LParen) =>
+            {
+                token_iter.next();
+                let arg_count = match token_iter.peek() {
+                    Some(lexer::TokenAndPos(_, token::Token::RParen)) => 0,
+                    _ => 1,
+                };
+                operator_stack.push(token::Token::FunctionCall(name.clone(), arg_count));
+                operator_stack.push(token::Token::LParen);
+            }
+            Some(lexer::TokenAndPos(_, value_token)) if value_token.is_value() => {
                 output_queue.push_back(value_token.clone())
             }
-            Some(&lexer::TokenAndPos(_, ref op_token)) if op_token.is_operator() => {
+            Some(lexer::TokenAndPos(_, op_token)) if op_token.is_operator() => {
                 if !operator_stack.is_empty() {
                     let top_op = operator_stack.last().unwrap().clone();
                     if top_op.is_operator() {
@@ -267,16 +3230,38 @@ fn parse_expression(
 
                 operator_stack.push(op_token.clone());
             }
-            Some(&lexer::TokenAndPos(_, token::Token::LParen)) => {
+            Some(lexer::TokenAndPos(_, token::Token::LParen)) => {
                 operator_stack.push(token::Token::LParen);
             }
-            Some(&lexer::TokenAndPos(_, token::Token::RParen)) => loop {
-                match operator_stack.pop() {
-                    Some(token::Token::LParen) => break,
-                    Some(ref next_token) => output_queue.push_back(next_token.clone()),
-                    None => return Err("Mismatched parenthesis in expression".to_string()),
+            Some(lexer::TokenAndPos(_, token::Token::Comma)) => {
+                loop {
+                    match operator_stack.last() {
+                        Some(token::Token::LParen) => break,
+                        Some(_) => output_queue.push_back(operator_stack.pop().unwrap()),
+                        None => unreachable!(),
+                    }
                 }
-            },
+
+                // Inside plain parentheses there is no call below the (
+                let call_index = operator_stack.len().checked_sub(2);
+                match call_index.and_then(|index| operator_stack.get_mut(index)) {
+                    Some(token::Token::FunctionCall(_, ref mut arg_count)) => *arg_count += 1,
+                    _ => return Err("Unexpected comma in expression".to_string()),
+                }
+            }
+            Some(lexer::TokenAndPos(_, token::Token::RParen)) => {
+                loop {
+                    match operator_stack.pop() {
+                        Some(token::Token::LParen) => break,
+                        Some(ref next_token) => output_queue.push_back(next_token.clone()),
+                        None => return Err("Mismatched parenthesis in expression".to_string()),
+                    }
+                }
+
+                if let Some(token::Token::FunctionCall(_, _)) = operator_stack.last() {
+                    output_queue.push_back(operator_stack.pop().unwrap());
+                }
+            }
             _ => unreachable!(),
         }
     }
@@ -293,10 +3278,186 @@ fn parse_expression(
     Ok(output_queue)
 }
 
+pub fn is_builtin_function(name: &str) -> bool {
+    matches!(
+        name,
+        "CVD" | "CVI" | "CVS" | "EOF" | "INT" | "LOF" | "MKD$" | "MKI$" | "MKS$" | "RAND" | "RND"
+    )
+}
+
+// Calls the built-in function with the given name, or returns None if there
+// is no such function
+pub fn call_builtin_function(
+    name: &str,
+    args: &[value::RBasicValue],
+    rng: &mut random::Rng,
+    files: &mut HashMap<i32, files::BasicFile>,
+) -> Option<Result<value::RBasicValue, error::RBasicError>> {
+    let arity = match name {
+        "CVD" | "CVI" | "CVS" | "EOF" | "INT" | "LOF" | "MKD$" | "MKI$" | "MKS$" | "RAND"
+        | "RND" => 1,
+        _ => return None,
+    };
+
+    if args.len() != arity {
+        return Some(Err(error::RBasicError::new(
+            error::ILLEGAL_FUNCTION_CALL,
+            format!(
+                "Function {} expects {} argument(s) but was given {}",
+                name,
+                arity,
+                args.len()
+            ),
+        )));
+    }
+
+    // Unpacking record strings is the only case taking a string argument
+    if let "CVD" | "CVI" | "CVS" = name {
+        let result = match args[0] {
+            value::RBasicValue::String(ref packed) => match name {
+                "CVI" => packing::cvi(packed).map(value::RBasicValue::Number),
+                "CVS" => packing::cvs(packed).map(value::RBasicValue::Float),
+                _ => packing::cvd(packed).map(value::RBasicValue::Float),
+            },
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                format!("Function {} requires a string argument", name),
+            )),
+        };
+        return Some(result);
+    }
+
+    let number = match args[0].as_float() {
+        Some(number) => number,
+        None => {
+            return Some(Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                format!("Function {} requires a numeric argument", name),
+            )))
+        }
+    };
+
+    let result = match name {
+        "EOF" => open_file_number(files, number as i32)
+            .and_then(|file| file.eof())
+            .map(value::RBasicValue::Bool),
+        "LOF" => open_file_number(files, number as i32)
+            .and_then(|file| file.length())
+            .map(|len| value::RBasicValue::Number(len as i32)),
+        "INT" => value::whole_number(value::from_whole_float(number.floor())),
+        "MKI$" => packing::mki(number.round() as i32).map(value::RBasicValue::from),
+        "MKS$" => packing::mks(number).map(value::RBasicValue::from),
+        "MKD$" => packing::mkd(number).map(value::RBasicValue::from),
+        // As in Microsoft BASIC, a negative argument reseeds the generator
+        // and zero repeats the last number
+        "RND" => {
+            if number < 0.0 {
+                rng.seed(number.to_bits());
+            }
+
+            if number == 0.0 {
+                Ok(value::RBasicValue::Float(rng.last_float()))
+            } else {
+                Ok(value::RBasicValue::Float(rng.next_float()))
+            }
+        }
+        // A whole number from 1 up to and including the argument
+        "RAND" => {
+            let max = number.floor();
+
+            if max < 1.0 {
+                Err(error::RBasicError::new(
+                    error::ILLEGAL_FUNCTION_CALL,
+                    format!("RAND requires a maximum of at least 1, not {}", number),
+                ))
+            } else if max > i32::MAX as f64 {
+                Err(error::RBasicError::new(
+                    error::ILLEGAL_FUNCTION_CALL,
+                    format!(
+                        "RAND requires a maximum of at most {}, not {}",
+                        i32::MAX,
+                        number
+                    ),
+                ))
+            } else {
+                Ok(value::RBasicValue::Number(
+                    1 + (rng.next_float() * max) as i32,
+                ))
+            }
+        }
+        _ => unreachable!(),
+    };
+
+    Some(result)
+}
+
+fn call_function(
+    name: &str,
+    args: Vec<value::RBasicValue>,
+    context: &mut RBasicContext,
+) -> Result<value::RBasicValue, error::RBasicError> {
+    // Array elements are parsed the same way as function calls
+    if context.arrays.contains_key(name) {
+        return context.get_array_element(name, &subscripts(&args)?);
+    }
+
+    if let Some(result) = call_builtin_function(name, &args, &mut context.rng, &mut context.files) {
+        return result;
+    }
+
+    if let Some(function) = context.natives.get(name) {
+        return function.call(name, args);
+    }
+
+    if !name.starts_with("FN") {
+        return context.get_array_element(name, &subscripts(&args)?);
+    }
+
+    let function = match context.functions.get(name) {
+        Some(function) => function.clone(),
+        None => {
+            return Err(error::RBasicError::new(
+                error::UNDEFINED_USER_FUNCTION,
+                format!("Undefined user function {}", name),
+            ))
+        }
+    };
+
+    if function.parameters.len() != args.len() {
+        return Err(error::RBasicError::new(
+            error::ILLEGAL_FUNCTION_CALL,
+            format!(
+                "Function {} expects {} argument(s) but was given {}",
+                name,
+                function.parameters.len(),
+                args.len()
+            ),
+        ));
+    }
+
+    context.limits.check_call_depth(context.scopes.len())?;
+    if context.scopes.len() >= MAX_FUNCTION_DEPTH {
+        return Err(error::RBasicError::new(
+            error::OUT_OF_MEMORY,
+            format!(
+                "Out of memory calling {}: user functions nested more than {} deep",
+                name, MAX_FUNCTION_DEPTH
+            ),
+        ));
+    }
+    context
+        .scopes
+        .push(function.slots.iter().cloned().zip(args).collect());
+    let result = parse_and_eval_expression(&mut function.body.iter().peekable(), context);
+    context.scopes.pop();
+
+    result
+}
+
 fn parse_and_eval_expression<'a>(
     token_iter: &mut Peekable<Iter<'a, lexer::TokenAndPos>>,
-    context: &RBasicContext,
-) -> Result<value::RBasicValue, String> {
+    context: &mut RBasicContext,
+) -> Result<value::RBasicValue, error::RBasicError> {
     match parse_expression(token_iter) {
         Ok(mut output_queue) => {
             let mut stack: Vec<value::RBasicValue> = Vec::new();
@@ -308,18 +3469,23 @@ fn parse_and_eval_expression<'a>(
                     Some(token::Token::Number(ref number)) => {
                         stack.push(value::RBasicValue::Number(*number))
                     }
+                    Some(token::Token::Float(ref float)) => {
+                        stack.push(value::RBasicValue::Float(*float))
+                    }
                     Some(token::Token::BString(ref bstring)) => {
                         stack.push(value::RBasicValue::String(bstring.clone()))
                     }
-                    Some(token::Token::Variable(ref name)) => match context.variables.get(name) {
-                        Some(value) => stack.push(value.clone()),
-                        None => {
-                            return Err(format!(
-                                "Invalid variable reference {} in expression",
-                                name
-                            ))
+                    Some(token::Token::Variable(ref name, slot)) => {
+                        match context.get_variable(slot)? {
+                            Some(value) => stack.push(value),
+                            None => {
+                                return Err(error::RBasicError::new(
+                                    error::UNDEFINED_VARIABLE,
+                                    format!("Invalid variable reference {} in expression", name),
+                                ))
+                            }
                         }
-                    },
+                    }
                     Some(ref unary_token) if unary_token.is_unary_operator() => {
                         if !stack.is_empty() {
                             let value = stack.pop().unwrap();
@@ -334,7 +3500,10 @@ fn parse_and_eval_expression<'a>(
                                 Err(e) => return Err(e),
                             }
                         } else {
-                            return Err(format!("Operator {:?} requires an operand!", unary_token));
+                            return Err(error::RBasicError::new(
+                                error::MISSING_OPERAND,
+                                format!("Operator {:?} requires an operand!", unary_token),
+                            ));
                         }
                     }
                     Some(ref comparison_token) if comparison_token.is_comparison_operator() => {
@@ -357,9 +3526,12 @@ fn parse_and_eval_expression<'a>(
                                 Err(e) => return Err(e),
                             }
                         } else {
-                            return Err(format!(
-                                "Comparison operator {:?} requires two operands",
-                                comparison_token
+                            return Err(error::RBasicError::new(
+                                error::MISSING_OPERAND,
+                                format!(
+                                    "Comparison operator {:?} requires two operands",
+                                    comparison_token
+                                ),
                             ));
                         }
                     }
@@ -382,17 +3554,39 @@ fn parse_and_eval_expression<'a>(
                             }
                         }
                     }
+                    Some(token::Token::FunctionCall(ref name, arg_count)) => {
+                        if stack.len() >= arg_count {
+                            let args = stack.split_off(stack.len() - arg_count);
+                            match call_function(name, args, context) {
+                                Ok(value) => stack.push(value),
+                                Err(e) => return Err(e),
+                            }
+                        } else {
+                            return Err(error::RBasicError::new(
+                                error::MISSING_OPERAND,
+                                format!("Function {} requires {} argument(s)", name, arg_count),
+                            ));
+                        }
+                    }
                     None => unreachable!(),
                     _ => unreachable!(),
                 }
             }
 
             // If expression is well formed, there will only be the result on the stack
-            assert!(stack.len() == 1);
+            if stack.len() != 1 {
+                return Err(error::RBasicError::new(
+                    error::SYNTAX_ERROR,
+                    "Invalid expression!".to_string(),
+                ));
+            }
             // println!("Final expression result: {:?}", stack[0]);
             Ok(stack[0].clone())
         }
 
-        _ => Err("Invalid expression!".to_string()),
+        _ => Err(error::RBasicError::new(
+            error::SYNTAX_ERROR,
+            "Invalid expression!".to_string(),
+        )),
     }
 }
@@ -0,0 +1,523 @@
+use crate::error;
+use crate::packing;
+
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::str::FromStr;
+
+// The record length of random access files opened without LEN
+pub const DEFAULT_RECORD_LENGTH: usize = 128;
+pub const MAX_RECORD_LENGTH: usize = 32767;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileMode {
+    Input,
+    Output,
+    Append,
+    // Random access with the given record length
+    Random(usize),
+}
+
+// Modes are written as they are in OPEN, with the record length of random
+// access files, as in "RANDOM 128"
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FileMode::Input => write!(f, "INPUT"),
+            FileMode::Output => write!(f, "OUTPUT"),
+            FileMode::Append => write!(f, "APPEND"),
+            FileMode::Random(record_length) => write!(f, "RANDOM {}", record_length),
+        }
+    }
+}
+
+impl FromStr for FileMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<FileMode, String> {
+        match text {
+            "INPUT" => Ok(FileMode::Input),
+            "OUTPUT" => Ok(FileMode::Output),
+            "APPEND" => Ok(FileMode::Append),
+            _ => text
+                .strip_prefix("RANDOM ")
+                .and_then(|record_length| usize::from_str(record_length).ok())
+                .map(FileMode::Random)
+                .ok_or_else(|| format!("Unknown file mode {}", text)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum FileData {
+    // Files opened for INPUT are read in full when opened
+    Input {
+        contents: String,
+        position: usize,
+    },
+    Output(BufWriter<File>),
+    Random {
+        file: File,
+        // The record read by GET or written by PUT
+        buffer: Vec<u8>,
+        // The record used when GET or PUT don't give one
+        next_record: u64,
+        // Whether the last GET read past the end of the file
+        past_end: bool,
+    },
+}
+
+// A variable defined by FIELD as part of a random access file's record
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    offset: usize,
+    width: usize,
+}
+
+// Where an open file is up to, so that it can be saved in a snapshot and
+// opened again later to carry on from the same place
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileState {
+    pub path: String,
+    pub mode: FileMode,
+    // The byte offset of the next input, or the next record of a random
+    // access file
+    pub position: u64,
+    // Whether the last GET read past the end of a random access file
+    pub past_end: bool,
+    // The record buffer of a random access file
+    pub buffer: Vec<u8>,
+    // The name, offset and width of each field
+    pub fields: Vec<(String, usize, usize)>,
+}
+
+// A file opened with OPEN, read or written by its file number
+#[derive(Debug)]
+pub struct BasicFile {
+    pub path: String,
+    pub mode: FileMode,
+    data: FileData,
+    fields: Vec<Field>,
+}
+
+pub fn open(path: &str, mode: FileMode) -> Result<BasicFile, error::RBasicError> {
+    if path.is_empty() {
+        return Err(error::RBasicError::new(
+            error::BAD_FILE_NAME,
+            "Bad file name".to_string(),
+        ));
+    }
+
+    let data = match mode {
+        FileMode::Input => FileData::Input {
+            contents: fs::read_to_string(path).map_err(|e| io_error(path, &e))?,
+            position: 0,
+        },
+        FileMode::Output => FileData::Output(BufWriter::new(
+            File::create(path).map_err(|e| io_error(path, &e))?,
+        )),
+        FileMode::Append => FileData::Output(BufWriter::new(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map_err(|e| io_error(path, &e))?,
+        )),
+        FileMode::Random(record_length) => {
+            if !(1..=MAX_RECORD_LENGTH).contains(&record_length) {
+                return Err(error::RBasicError::new(
+                    error::ILLEGAL_FUNCTION_CALL,
+                    format!(
+                        "Record length {} for {} is out of range",
+                        record_length, path
+                    ),
+                ));
+            }
+
+            FileData::Random {
+                file: OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+                    .map_err(|e| io_error(path, &e))?,
+                buffer: vec![b' '; record_length],
+                next_record: 1,
+                past_end: false,
+            }
+        }
+    };
+
+    Ok(BasicFile {
+        path: path.to_string(),
+        mode,
+        data,
+        fields: Vec::new(),
+    })
+}
+
+// Opens a file again as it was when its state was saved. Output files are
+// appended to, so that what was written before is kept.
+pub fn reopen(state: &FileState) -> Result<BasicFile, error::RBasicError> {
+    let mut file = match state.mode {
+        FileMode::Output => open(&state.path, FileMode::Append)?,
+        ref mode => open(&state.path, mode.clone())?,
+    };
+    file.mode = state.mode.clone();
+
+    let path = &file.path;
+    match file.data {
+        FileData::Input {
+            ref contents,
+            ref mut position,
+        } => {
+            let saved = state.position as usize;
+            if saved > contents.len() || !contents.is_char_boundary(saved) {
+                return Err(bad_file_mode(path));
+            }
+            *position = saved;
+        }
+        FileData::Random {
+            ref mut buffer,
+            ref mut next_record,
+            ref mut past_end,
+            ..
+        } => {
+            if state.buffer.len() != buffer.len() {
+                return Err(bad_file_mode(path));
+            }
+            buffer.copy_from_slice(&state.buffer);
+            *next_record = state.position;
+            *past_end = state.past_end;
+        }
+        FileData::Output(_) => {}
+    }
+
+    file.fields = state
+        .fields
+        .iter()
+        .map(|(name, offset, width)| Field {
+            name: name.clone(),
+            offset: *offset,
+            width: *width,
+        })
+        .collect();
+    Ok(file)
+}
+
+impl BasicFile {
+    // Saves where the file is up to, writing out any buffered output
+    pub fn state(&mut self) -> Result<FileState, error::RBasicError> {
+        let path = &self.path;
+        let (position, past_end, buffer) = match self.data {
+            FileData::Input { position, .. } => (position as u64, false, Vec::new()),
+            FileData::Output(ref mut writer) => {
+                writer.flush().map_err(|e| io_error(path, &e))?;
+                (0, false, Vec::new())
+            }
+            FileData::Random {
+                ref buffer,
+                next_record,
+                past_end,
+                ..
+            } => (next_record, past_end, buffer.clone()),
+        };
+
+        Ok(FileState {
+            path: self.path.clone(),
+            mode: self.mode.clone(),
+            position,
+            past_end,
+            buffer,
+            fields: self
+                .fields
+                .iter()
+                .map(|field| (field.name.clone(), field.offset, field.width))
+                .collect(),
+        })
+    }
+
+    pub fn write_line(&mut self, text: &str) -> Result<(), error::RBasicError> {
+        let path = &self.path;
+        match self.data {
+            FileData::Output(ref mut writer) => {
+                writeln!(writer, "{}", text).map_err(|e| io_error(path, &e))
+            }
+            _ => Err(bad_file_mode(path)),
+        }
+    }
+
+    // Reads the next comma or line separated item, as used by INPUT #. The
+    // Boolean is true when the item was a quoted string.
+    pub fn read_item(&mut self) -> Result<(String, bool), error::RBasicError> {
+        let (contents, position) = self.input_data()?;
+        let rest = &contents[*position..];
+        let start = rest.len() - rest.trim_start().len();
+        let rest = &rest[start..];
+
+        if rest.is_empty() {
+            return Err(input_past_end(&self.path));
+        }
+
+        let (item, quoted, consumed) = if let Some(quoted_rest) = rest.strip_prefix('"') {
+            let end = quoted_rest.find('"').unwrap_or(quoted_rest.len());
+            let after = &quoted_rest[(end + 1).min(quoted_rest.len())..];
+            let skip = after.find([',', '\n']).unwrap_or(after.len());
+            (
+                quoted_rest[..end].to_string(),
+                true,
+                rest.len() - after.len() + skip,
+            )
+        } else {
+            let end = rest.find([',', '\n']).unwrap_or(rest.len());
+            (rest[..end].trim().to_string(), false, end)
+        };
+
+        // Move past the item and the separator that ended it
+        *position += start + consumed;
+        if *position < contents.len() {
+            *position += 1;
+        }
+
+        Ok((item, quoted))
+    }
+
+    // Reads the rest of the current line, as used by LINE INPUT #
+    pub fn read_line(&mut self) -> Result<String, error::RBasicError> {
+        let (contents, position) = self.input_data()?;
+        let rest = &contents[*position..];
+
+        if rest.is_empty() {
+            return Err(input_past_end(&self.path));
+        }
+
+        let end = rest.find('\n').unwrap_or(rest.len());
+        let line = rest[..end].trim_end_matches('\r').to_string();
+        *position += (end + 1).min(rest.len());
+
+        Ok(line)
+    }
+
+    pub fn eof(&self) -> Result<bool, error::RBasicError> {
+        match self.data {
+            FileData::Input {
+                ref contents,
+                position,
+            } => Ok(contents[position..].trim().is_empty()),
+            FileData::Random { past_end, .. } => Ok(past_end),
+            FileData::Output(_) => Err(bad_file_mode(&self.path)),
+        }
+    }
+
+    // The length of the file in bytes
+    pub fn length(&mut self) -> Result<u64, error::RBasicError> {
+        let path = &self.path;
+        match self.data {
+            FileData::Input { ref contents, .. } => Ok(contents.len() as u64),
+            FileData::Output(ref mut writer) => {
+                writer.flush().map_err(|e| io_error(path, &e))?;
+                writer
+                    .get_ref()
+                    .metadata()
+                    .map(|metadata| metadata.len())
+                    .map_err(|e| io_error(path, &e))
+            }
+            FileData::Random { ref file, .. } => file
+                .metadata()
+                .map(|metadata| metadata.len())
+                .map_err(|e| io_error(path, &e)),
+        }
+    }
+
+    pub fn close(self) -> Result<(), error::RBasicError> {
+        let path = &self.path;
+        match self.data {
+            FileData::Output(mut writer) => writer.flush().map_err(|e| io_error(path, &e)),
+            FileData::Input { .. } | FileData::Random { .. } => Ok(()),
+        }
+    }
+
+    // Divides the record into the named fields, as used by FIELD
+    pub fn set_fields(&mut self, fields: &[(String, usize)]) -> Result<(), error::RBasicError> {
+        let record_length = match self.mode {
+            FileMode::Random(record_length) => record_length,
+            _ => return Err(bad_file_mode(&self.path)),
+        };
+
+        let mut offset = 0;
+        for (name, width) in fields {
+            if offset + width > record_length {
+                return Err(error::RBasicError::new(
+                    error::FIELD_OVERFLOW,
+                    format!(
+                        "Fields are longer than the record length {} of {}",
+                        record_length, self.path
+                    ),
+                ));
+            }
+
+            // A later FIELD for the same variable replaces the earlier one
+            self.fields.retain(|field| field.name != *name);
+            self.fields.push(Field {
+                name: name.clone(),
+                offset,
+                width: *width,
+            });
+            offset += width;
+        }
+
+        Ok(())
+    }
+
+    // The current contents of each field, for setting the field variables
+    pub fn field_values(&self) -> Vec<(String, String)> {
+        match self.data {
+            FileData::Random { ref buffer, .. } => self
+                .fields
+                .iter()
+                .map(|field| {
+                    (
+                        field.name.clone(),
+                        packing::string_from_bytes(
+                            &buffer[field.offset..field.offset + field.width],
+                        ),
+                    )
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Stores the value in the named field, padded with spaces or cut to the
+    // width of the field, and returns the field's new contents. The value is
+    // left justified for LSET and right justified for RSET.
+    pub fn set_field(&mut self, name: &str, value: &str, right_justify: bool) -> Option<String> {
+        let field = self.fields.iter().find(|field| field.name == name)?;
+
+        match self.data {
+            FileData::Random { ref mut buffer, .. } => {
+                let contents = justify(value, field.width, right_justify);
+                buffer[field.offset..field.offset + field.width]
+                    .copy_from_slice(&packing::bytes_from_string(&contents));
+                Some(contents)
+            }
+            _ => None,
+        }
+    }
+
+    // Reads a record into the buffer, as used by GET. Records are numbered
+    // from 1, and reading past the end of the file gives a record of zeros.
+    pub fn get(&mut self, record: Option<u64>) -> Result<(), error::RBasicError> {
+        let path = &self.path;
+        match self.data {
+            FileData::Random {
+                ref mut file,
+                ref mut buffer,
+                ref mut next_record,
+                ref mut past_end,
+            } => {
+                let record = check_record(path, record.unwrap_or(*next_record))?;
+                let record_length = buffer.len() as u64;
+                let mut contents = Vec::new();
+
+                file.seek(SeekFrom::Start((record - 1) * record_length))
+                    .and_then(|_| file.take(record_length).read_to_end(&mut contents))
+                    .map_err(|e| io_error(path, &e))?;
+
+                *past_end = contents.len() < buffer.len();
+                contents.resize(buffer.len(), 0);
+                buffer.copy_from_slice(&contents);
+                *next_record = record + 1;
+                Ok(())
+            }
+            _ => Err(bad_file_mode(path)),
+        }
+    }
+
+    // Writes the buffer to a record, as used by PUT
+    pub fn put(&mut self, record: Option<u64>) -> Result<(), error::RBasicError> {
+        let path = &self.path;
+        match self.data {
+            FileData::Random {
+                ref mut file,
+                ref buffer,
+                ref mut next_record,
+                ..
+            } => {
+                let record = check_record(path, record.unwrap_or(*next_record))?;
+
+                file.seek(SeekFrom::Start((record - 1) * buffer.len() as u64))
+                    .and_then(|_| file.write_all(buffer))
+                    .map_err(|e| io_error(path, &e))?;
+
+                *next_record = record + 1;
+                Ok(())
+            }
+            _ => Err(bad_file_mode(path)),
+        }
+    }
+
+    fn input_data(&mut self) -> Result<(&String, &mut usize), error::RBasicError> {
+        match self.data {
+            FileData::Input {
+                ref contents,
+                ref mut position,
+            } => Ok((contents, position)),
+            _ => Err(bad_file_mode(&self.path)),
+        }
+    }
+}
+
+// Pads the value with spaces, or cuts it, to the given width
+pub fn justify(value: &str, width: usize, right_justify: bool) -> String {
+    let value: String = value.chars().take(width).collect();
+
+    if right_justify {
+        format!("{:>width$}", value, width = width)
+    } else {
+        format!("{:<width$}", value, width = width)
+    }
+}
+
+fn check_record(path: &str, record: u64) -> Result<u64, error::RBasicError> {
+    if record < 1 {
+        return Err(error::RBasicError::new(
+            error::BAD_RECORD_NUMBER,
+            format!("Bad record number {} for {}", record, path),
+        ));
+    }
+    Ok(record)
+}
+
+// Maps an I/O error to the BASIC error code a program trapping it would see
+fn io_error(path: &str, e: &io::Error) -> error::RBasicError {
+    let code = match e.kind() {
+        io::ErrorKind::NotFound => error::FILE_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => error::PERMISSION_DENIED,
+        io::ErrorKind::InvalidData => error::BAD_FILE_MODE,
+        _ => error::DEVICE_IO_ERROR,
+    };
+
+    error::RBasicError::new(
+        code,
+        format!("{} for {}: {}", error::message_for_code(code), path, e),
+    )
+}
+
+fn bad_file_mode(path: &str) -> error::RBasicError {
+    error::RBasicError::new(error::BAD_FILE_MODE, format!("Bad file mode for {}", path))
+}
+
+fn input_past_end(path: &str) -> error::RBasicError {
+    error::RBasicError::new(error::INPUT_PAST_END, format!("Input past end of {}", path))
+}
@@ -0,0 +1,76 @@
+use crate::lexer;
+use crate::token;
+
+// Writes a line back out as canonical BASIC: keywords in upper case, a
+// single space between tokens except inside parentheses, after unary
+// operators and # and before commas, numbers as the lexer reads them, and
+// strings and comments as they were
+pub fn format_line(line: &lexer::LineOfCode) -> String {
+    let mut text = line.line_number.0.to_string();
+    let mut previous: Option<&token::Token> = None;
+
+    for lexer::TokenAndPos(_, token) in &line.tokens {
+        let token_text = match *token {
+            // A REM with nothing after it
+            token::Token::Comment(ref comment) if comment.is_empty() => continue,
+            token::Token::Comment(ref comment) => comment.to_string(),
+            token::Token::Variable(ref name, _) | token::Token::FunctionCall(ref name, _) => {
+                name.to_string()
+            }
+            token::Token::Number(number) => number.to_string(),
+            // Always with a decimal point or exponent, so that it is read back
+            // as a floating point number
+            token::Token::Float(float) => format!("{:?}", float),
+            token::Token::BString(ref string) => format!("\"{}\"", string),
+            ref token => token.keyword_text().unwrap_or_default().to_string(),
+        };
+
+        if previous.is_none_or(|previous| spaced(previous, token)) {
+            text.push(' ');
+        }
+        text.push_str(&token_text);
+        previous = Some(token);
+    }
+
+    text
+}
+
+// Whether a space goes between two tokens
+fn spaced(previous: &token::Token, next: &token::Token) -> bool {
+    !matches!(
+        (previous, next),
+        (_, token::Token::RParen)
+            | (_, token::Token::Comma)
+            | (token::Token::LParen, _)
+            | (token::Token::UMinus, _)
+            | (token::Token::Bang, _)
+            | (token::Token::Hash, _)
+            // An array element or function call, as in A(1)
+            | (token::Token::Variable(_, _), token::Token::LParen)
+    )
+}
+
+// Formats a line of source, checking that the result is read back as the same
+// tokens. Blank lines are left blank.
+pub fn format_source_line(text: &str) -> Result<String, String> {
+    if text.trim().is_empty() {
+        return Ok(String::new());
+    }
+
+    let line = lexer::tokenize_line(text)?;
+    let formatted = format_line(&line);
+    let reformatted = lexer::tokenize_line(&formatted)?;
+
+    if reformatted.line_number != line.line_number || tokens(&reformatted) != tokens(&line) {
+        return Err(format!(
+            "Formatting line {} changed how it is read:\n\t{}",
+            line.line_number.0, formatted
+        ));
+    }
+
+    Ok(formatted)
+}
+
+fn tokens(line: &lexer::LineOfCode) -> Vec<&token::Token> {
+    line.tokens.iter().map(|token| &token.1).collect()
+}
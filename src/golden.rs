@@ -0,0 +1,280 @@
+use crate::compiler;
+use crate::error;
+use crate::evaluator;
+use crate::host;
+use crate::lexer;
+use crate::limits;
+use crate::vm;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+// Stops programs that would otherwise run forever, such as a loop waiting
+// for a key that never comes
+const MAX_STATEMENTS: u64 = 1_000_000;
+
+// What a run of a program printed, as it would appear on stdout, and the
+// error it ended with, if any
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transcript {
+    pub output: String,
+    pub error: Option<String>,
+}
+
+// Runs a program, giving its INPUT statements the lines of input in turn.
+// The output holds the prompts of INPUT, as stdout does when input comes from
+// a file, and ends with how the program finished. Needing more input than
+// was given is an error.
+pub fn run_program(source: &str, input: &str) -> Transcript {
+    let mut transcript = Transcript::default();
+
+    let code_lines = match code_lines(source) {
+        Ok(code_lines) => code_lines,
+        Err(e) => {
+            transcript.error = Some(e);
+            return transcript;
+        }
+    };
+
+    let mut interpreter = match evaluator::Interpreter::new(code_lines) {
+        Ok(interpreter) => interpreter,
+        Err(e) => {
+            transcript.error = Some(e.to_string());
+            return transcript;
+        }
+    };
+    interpreter.set_limits(limits::Limits {
+        statements: Some(MAX_STATEMENTS),
+        ..limits::Limits::default()
+    });
+    interpreter.start();
+
+    let mut input_lines = input.lines();
+    loop {
+        match interpreter.resume() {
+            host::HostEvent::Output(text) => transcript.output.push_str(&text),
+            host::HostEvent::NeedsInput { prompt } => {
+                transcript.output.push_str(&prompt);
+                match input_lines.next() {
+                    Some(line) => interpreter.provide_input(line),
+                    None => {
+                        transcript.error = Some(host::input_past_end().to_string());
+                        return transcript;
+                    }
+                }
+            }
+            host::HostEvent::Finished(status) => {
+                transcript.output.push_str(&format!("{}\n", status));
+                return transcript;
+            }
+            host::HostEvent::Error(e) => {
+                transcript.error = Some(e.to_string());
+                return transcript;
+            }
+        }
+    }
+}
+
+// Runs a program as run_program does, but compiled and run on the VM, for
+// checking that the two behave the same. As with --vm, programs using
+// statements the compiler can't handle are interpreted instead.
+pub fn run_compiled(source: &str, input: &str) -> Transcript {
+    let mut transcript = Transcript::default();
+
+    let program = match code_lines(source) {
+        Ok(code_lines) => compiler::compile(code_lines),
+        Err(e) => {
+            transcript.error = Some(e);
+            return transcript;
+        }
+    };
+    let mut vm = match program {
+        Ok(program) => vm::Vm::new(program),
+        Err(ref e) if e.code == error::ADVANCED_FEATURE => return run_program(source, input),
+        Err(e) => {
+            transcript.error = Some(e.to_string());
+            return transcript;
+        }
+    };
+    vm.set_limits(limits::Limits {
+        statements: Some(MAX_STATEMENTS),
+        ..limits::Limits::default()
+    });
+    vm.script_console(input);
+
+    let result = vm.run();
+    transcript.output = vm.output().to_string();
+    match result {
+        Ok(status) => transcript.output.push_str(&format!("{}\n", status)),
+        Err(e) => transcript.error = Some(e.to_string()),
+    }
+    transcript
+}
+
+// The lines of a program, leaving out blank lines
+fn code_lines(source: &str) -> Result<Vec<lexer::LineOfCode>, String> {
+    let mut code_lines = Vec::new();
+    for (lineno, line) in source.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match lexer::tokenize_line(line) {
+            Ok(code_line) => code_lines.push(code_line),
+            Err(e) => return Err(format!("Error at line {}: {}", lineno + 1, e)),
+        }
+    }
+    Ok(code_lines)
+}
+
+// A program with the files beside it holding what it is given and what it
+// should do: foo.in for its input, foo.out for its output, and foo.err for
+// the error it should end with, when it should end with one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub program: PathBuf,
+}
+
+impl Case {
+    pub fn new(program: &Path) -> Case {
+        Case {
+            program: program.to_path_buf(),
+        }
+    }
+
+    pub fn input_path(&self) -> PathBuf {
+        self.program.with_extension("in")
+    }
+
+    pub fn output_path(&self) -> PathBuf {
+        self.program.with_extension("out")
+    }
+
+    pub fn error_path(&self) -> PathBuf {
+        self.program.with_extension("err")
+    }
+
+    // Runs the program with its input, or none if it has no .in file
+    pub fn run(&self) -> io::Result<Transcript> {
+        let source = fs::read_to_string(&self.program)?;
+        let input = read_if_present(&self.input_path())?.unwrap_or_default();
+        Ok(run_program(&source, &input))
+    }
+
+    // Runs the program and describes each way it differs from what is
+    // expected, giving none when it passes
+    pub fn check(&self) -> io::Result<Vec<String>> {
+        let transcript = self.run()?;
+        let mut problems = Vec::new();
+
+        match read_if_present(&self.output_path())? {
+            Some(expected) => {
+                if let Some(difference) =
+                    difference(&self.output_path(), &expected, &transcript.output)
+                {
+                    problems.push(difference);
+                }
+            }
+            None => problems.push(format!(
+                "{} is missing; run with --bless to create it",
+                self.output_path().display()
+            )),
+        }
+
+        let expected_error = read_if_present(&self.error_path())?;
+        match (expected_error, transcript.error) {
+            (Some(expected), Some(error)) => {
+                if let Some(difference) =
+                    difference(&self.error_path(), &expected, &format!("{}\n", error))
+                {
+                    problems.push(difference);
+                }
+            }
+            (Some(expected), None) => problems.push(format!(
+                "expected the error in {}, but the program finished:\n  {}",
+                self.error_path().display(),
+                expected.trim_end()
+            )),
+            (None, Some(error)) => problems.push(format!("program failed: {}", error)),
+            (None, None) => {}
+        }
+
+        Ok(problems)
+    }
+
+    // Runs the program and saves what it did as what is expected of it,
+    // removing the .err file when it finishes without an error
+    pub fn bless(&self) -> io::Result<()> {
+        let transcript = self.run()?;
+        fs::write(self.output_path(), &transcript.output)?;
+        match transcript.error {
+            Some(error) => fs::write(self.error_path(), format!("{}\n", error)),
+            None => match fs::remove_file(self.error_path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+// The cases to run for a path: the programs in a directory, in order of name,
+// or the program named
+pub fn find_cases(path: &Path) -> io::Result<Vec<Case>> {
+    if !path.is_dir() {
+        return Ok(vec![Case::new(path)]);
+    }
+
+    let mut programs = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let program = entry?.path();
+        if program
+            .extension()
+            .is_some_and(|extension| extension == "bas")
+        {
+            programs.push(program);
+        }
+    }
+    programs.sort();
+
+    Ok(programs.iter().map(|program| Case::new(program)).collect())
+}
+
+fn read_if_present(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Describes the first line where what happened differs from what was expected
+fn difference(path: &Path, expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut lineno = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(expected_line), Some(actual_line)) if expected_line == actual_line => lineno += 1,
+            (None, None) => {
+                return Some(format!(
+                    "{} differs in the newline at its end",
+                    path.display()
+                ))
+            }
+            (expected_line, actual_line) => {
+                return Some(format!(
+                    "{} differs at line {}:\n  expected: {}\n  actual:   {}",
+                    path.display(),
+                    lineno,
+                    expected_line.unwrap_or("(end of file)"),
+                    actual_line.unwrap_or("(end of output)")
+                ))
+            }
+        }
+    }
+}
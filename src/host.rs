@@ -0,0 +1,78 @@
+use crate::error;
+use crate::evaluator;
+
+use std::collections::VecDeque;
+
+// What a program run by Interpreter::resume needs from the program hosting
+// it next
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostEvent {
+    // INPUT is waiting for a line, to be given with Interpreter::provide_input
+    NeedsInput { prompt: String },
+    // Text written by PRINT, including its newline
+    Output(String),
+    // The program ended, or stopped at a STOP
+    Finished(evaluator::ExecutionStatus),
+    Error(error::RBasicError),
+}
+
+// The error when a program needs another line of input and the host has
+// none left to give it
+pub fn input_past_end() -> error::RBasicError {
+    error::RBasicError::new(
+        error::INPUT_PAST_END,
+        "INPUT past the end of the input".to_string(),
+    )
+}
+
+// Holds what PRINT writes and what INPUT reads when the program is driven by
+// its host rather than by the terminal
+#[derive(Debug, Default)]
+pub struct HostConsole {
+    output: VecDeque<String>,
+    input: Option<String>,
+    // The event to report once the output before it has been taken
+    finished: Option<HostEvent>,
+    // Whether the program ended with an error, after which it can't carry on
+    failed: bool,
+}
+
+impl HostConsole {
+    pub fn new() -> HostConsole {
+        HostConsole::default()
+    }
+
+    pub fn print(&mut self, text: String) {
+        self.output.push_back(text);
+    }
+
+    pub fn provide_input(&mut self, line: &str) {
+        self.input = Some(line.trim().to_string());
+    }
+
+    pub fn has_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn take_input(&mut self) -> Option<String> {
+        self.input.take()
+    }
+
+    pub fn finish(&mut self, event: HostEvent) {
+        self.failed = matches!(event, HostEvent::Error(_));
+        self.finished = Some(event);
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    // The next event to report, giving any output before the program
+    // finishing
+    pub fn next_event(&mut self) -> Option<HostEvent> {
+        self.output
+            .pop_front()
+            .map(HostEvent::Output)
+            .or_else(|| self.finished.take())
+    }
+}
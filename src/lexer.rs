@@ -1,4 +1,5 @@
 use crate::token;
+use crate::variables;
 
 use itertools::Itertools;
 
@@ -21,7 +22,7 @@ pub fn tokenize_line(line: &str) -> Result<LineOfCode, String> {
     let mut line_number = LineNumber(0);
     let mut tokens: Vec<TokenAndPos> = Vec::new();
 
-    while char_iter.peek() != None {
+    while char_iter.peek().is_some() {
         let (pos, ch) = char_iter.next().unwrap();
         let pos = pos as u32;
 
@@ -65,10 +66,13 @@ pub fn tokenize_line(line: &str) -> Result<LineOfCode, String> {
                         .map(|(_, x)| x)
                         .collect();
                     let bstring: String = str_chars.into_iter().collect();
-                    tokens.push(TokenAndPos(pos, token::Token::BString(bstring)))
+                    tokens.push(TokenAndPos(pos, token::Token::BString(bstring.into())))
                 }
                 '-' => {
-                    if !tokens.is_empty() && tokens.last().unwrap().1.is_value() {
+                    if !tokens.is_empty()
+                        && (tokens.last().unwrap().1.is_value()
+                            || tokens.last().unwrap().1 == token::Token::RParen)
+                    {
                         tokens.push(TokenAndPos(pos, token::Token::Minus))
                     } else {
                         tokens.push(TokenAndPos(pos, token::Token::UMinus))
@@ -77,11 +81,15 @@ pub fn tokenize_line(line: &str) -> Result<LineOfCode, String> {
                 '!' => tokens.push(TokenAndPos(pos, token::Token::Bang)),
                 '(' => tokens.push(TokenAndPos(pos, token::Token::LParen)),
                 ')' => tokens.push(TokenAndPos(pos, token::Token::RParen)),
+                ',' => tokens.push(TokenAndPos(pos, token::Token::Comma)),
+                '#' => tokens.push(TokenAndPos(pos, token::Token::Hash)),
                 _ => {
-                    // Otherwise, next token is until next whitespace or closing paren
+                    // Otherwise, next token is until next whitespace, paren or comma
                     let mut token_chars: Vec<char> = char_iter
                         .by_ref()
-                        .peeking_take_while(|&(_, x)| !(x.is_whitespace() || x == ')'))
+                        .peeking_take_while(|&(_, x)| {
+                            !(x.is_whitespace() || x == '(' || x == ')' || x == ',')
+                        })
                         .map(|(_, x)| x)
                         .collect();
                     token_chars.insert(0, ch);
@@ -92,6 +100,13 @@ pub fn tokenize_line(line: &str) -> Result<LineOfCode, String> {
                             pos,
                             token::Token::Number(i32::from_str(token_str.as_str()).unwrap()),
                         ));
+                    } else if token_str.starts_with(|x: char| x.is_ascii_digit() || x == '.')
+                        && f64::from_str(token_str.as_str()).is_ok()
+                    {
+                        tokens.push(TokenAndPos(
+                            pos,
+                            token::Token::Float(f64::from_str(token_str.as_str()).unwrap()),
+                        ));
                     } else {
                         let token = token::Token::token_for_string(token_str.as_str());
 
@@ -100,7 +115,10 @@ pub fn tokenize_line(line: &str) -> Result<LineOfCode, String> {
                                 if is_valid_identifier(&token_str) {
                                     tokens.push(TokenAndPos(
                                         pos,
-                                        token::Token::Variable(token_str.to_string()),
+                                        token::Token::Variable(
+                                            token_str.into(),
+                                            variables::UNRESOLVED,
+                                        ),
                                     ))
                                 } else {
                                     return Err(format!(
@@ -117,10 +135,8 @@ pub fn tokenize_line(line: &str) -> Result<LineOfCode, String> {
                                 // The rest of the line is a comment
                                 let comment_str: String =
                                     char_iter.by_ref().map(|(_, x)| x).collect();
-                                tokens.push(TokenAndPos(
-                                    (pos + 4) as u32,
-                                    token::Token::Comment(comment_str),
-                                ))
+                                tokens
+                                    .push(TokenAndPos(pos + 4, token::Token::Comment(comment_str)))
                             }
 
                             Some(token) => {
@@ -141,15 +157,13 @@ pub fn tokenize_line(line: &str) -> Result<LineOfCode, String> {
 
 // Starts with [a-zA-Z_]
 // Followed by any number of [a-zA-Z0-9_]
-fn is_valid_identifier(token_str: &str) -> bool {
-    let mut v = token_str.chars();
+// Optionally ending with $, as string variables do in other BASICs
+pub fn is_valid_identifier(token_str: &str) -> bool {
+    let mut v = token_str.strip_suffix('$').unwrap_or(token_str).chars();
     let c = v.next();
     match c {
-        Some(c) => match c {
-            'a'..='z' | 'A'..='Z' => (),
-            _ => return false,
-        },
-        None => return false,
+        Some('a'..='z') | Some('A'..='Z') => (),
+        _ => return false,
     }
     for c in v {
         match c {
@@ -1,6 +1,26 @@
-
-
+pub mod array;
+pub mod checker;
+pub mod compiler;
+pub mod coverage;
+pub mod debugger;
+pub mod error;
 pub mod evaluator;
+pub mod files;
+pub mod formatter;
+pub mod golden;
+pub mod host;
 pub mod lexer;
+pub mod limits;
+pub mod native;
+pub mod packing;
+pub mod profiler;
+pub mod random;
+pub mod renum;
+pub mod repl;
+pub mod snapshot;
 pub mod token;
+pub mod trace;
 pub mod value;
+pub mod variables;
+pub mod vm;
+pub mod xref;
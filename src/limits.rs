@@ -0,0 +1,93 @@
+use crate::error;
+use crate::lexer;
+
+use std::time::Duration;
+use std::time::Instant;
+
+// Limits on what a program may use, for running programs that can't be
+// trusted to finish. Each limit is unlimited when None. Exceeding a limit
+// ends the program even if it uses ON ERROR GOTO.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    // Statements executed
+    pub statements: Option<u64>,
+    // Time since the program started
+    pub time: Option<Duration>,
+    // Total length in bytes of the strings held in variables and arrays
+    pub string_bytes: Option<usize>,
+    // Total elements in all arrays
+    pub array_elements: Option<usize>,
+    // How deeply GOSUBs, or user-defined functions, may be nested
+    pub call_depth: Option<usize>,
+}
+
+// The checks below are shared by the interpreter and the VM, so that both
+// stop a program at the same point with the same error
+impl Limits {
+    // Checked before each line runs, with the number of statements run so
+    // far including that line
+    pub fn check_line(
+        &self,
+        line_number: &lexer::LineNumber,
+        statements: u64,
+        started: Instant,
+    ) -> Result<(), error::RBasicError> {
+        if let Some(limit) = self.statements {
+            if statements > limit {
+                return Err(error::RBasicError::new(
+                    error::STATEMENT_LIMIT,
+                    format!(
+                        "At {:?}, statement limit of {} exceeded",
+                        line_number, limit
+                    ),
+                ));
+            }
+        }
+
+        if let Some(limit) = self.time {
+            if started.elapsed() > limit {
+                return Err(error::RBasicError::new(
+                    error::TIME_LIMIT,
+                    format!(
+                        "At {:?}, time limit of {} seconds exceeded",
+                        line_number,
+                        limit.as_secs_f64()
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn check_string_bytes(&self, string_bytes: usize) -> Result<(), error::RBasicError> {
+        match self.string_bytes {
+            Some(limit) if string_bytes > limit => Err(error::RBasicError::new(
+                error::STRING_LIMIT,
+                format!("String space limit of {} bytes exceeded", limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_array_elements(&self, array_elements: usize) -> Result<(), error::RBasicError> {
+        match self.array_elements {
+            Some(limit) if array_elements > limit => Err(error::RBasicError::new(
+                error::ARRAY_LIMIT,
+                format!("Array size limit of {} elements exceeded", limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Checked with how deeply calls are nested before another is made
+    pub fn check_call_depth(&self, depth: usize) -> Result<(), error::RBasicError> {
+        match self.call_depth {
+            Some(limit) if depth >= limit => Err(error::RBasicError::new(
+                error::CALL_DEPTH_LIMIT,
+                format!("Call depth limit of {} exceeded", limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,12 +1,31 @@
 
 
 
+use std::io::BufWriter;
 use std::io::Read;
+use std::io::Write;
+use std::fs;
 use std::fs::File;
+use std::path::PathBuf;
 use std::env;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
 
+use rbasic::checker;
+use rbasic::compiler;
+use rbasic::debugger;
+use rbasic::error;
 use rbasic::lexer;
+use rbasic::limits;
 use rbasic::evaluator;
+use rbasic::formatter;
+use rbasic::golden;
+use rbasic::renum;
+use rbasic::repl;
+use rbasic::trace;
+use rbasic::vm;
+use rbasic::xref;
 
 fn read_file(path: &str) -> Result<String, std::io::Error> {
     let mut f = File::open(path)?;
@@ -15,11 +34,521 @@ fn read_file(path: &str) -> Result<String, std::io::Error> {
     Ok(s)
 }
 
+// Reads the number following a flag such as --max-statements
+fn limit_argument<T: FromStr>(flag: &str, value: Option<String>) -> Option<T> {
+    match value.map(|value| T::from_str(value.as_str())) {
+        Some(Ok(value)) => Some(value),
+        _ => {
+            println!("{} must be followed by a number", flag);
+            None
+        }
+    }
+}
+
+// Shows how the program finished, saving a snapshot of a program that stopped
+// so that it can be continued later with --restore
+fn report(
+    interpreter: &mut evaluator::Interpreter,
+    result: Result<evaluator::ExecutionStatus, error::RBasicError>,
+    save_snapshot: &Option<String>,
+) {
+    match result {
+        Ok(status @ evaluator::ExecutionStatus::Stopped(_)) => {
+            println!("{}", status);
+            if let Some(path) = save_snapshot {
+                match interpreter.save_snapshot(path) {
+                    Ok(()) => println!("Snapshot saved to {}", path),
+                    Err(e) => println!("{}", e),
+                }
+            }
+        }
+        Ok(status) => println!("{}", status),
+        Err(e) => println!("Execution failed: {}", e),
+    }
+}
+
+// Makes the tracer for --trace, logging to the trace file if there is one and
+// to stderr otherwise, with the text of each line when given the program's
+// lines
+fn make_tracer(
+    trace_file: &Option<String>,
+    statements: Option<&[&str]>,
+    trace_variables: bool,
+) -> Option<trace::Tracer> {
+    let mut tracer = match trace_file {
+        Some(ref path) => match File::create(path) {
+            Ok(file) => trace::Tracer::new(Box::new(BufWriter::new(file))),
+            Err(err) => {
+                println!("Creating trace file failed with error: {}", err);
+                return None;
+            }
+        },
+        None => trace::Tracer::to_stderr(),
+    };
+    if let Some(source_lines) = statements {
+        tracer.show_statements(source_lines);
+    }
+    if trace_variables {
+        tracer.show_variables();
+    }
+    Some(tracer)
+}
+
+// Shows the table of where the time went on stderr, away from the program's
+// output, and writes the folded stacks for a flame graph
+fn report_profile(
+    interpreter: &evaluator::Interpreter,
+    profile: bool,
+    profile_folded: &Option<String>,
+) {
+    let profiler = match interpreter.profiler() {
+        Some(profiler) => profiler,
+        None => return,
+    };
+
+    if profile {
+        eprint!("{}", profiler);
+    }
+    if let Some(path) = profile_folded {
+        let written = File::create(path).and_then(|file| {
+            let mut output = BufWriter::new(file);
+            profiler.write_folded(&mut output)?;
+            output.flush()
+        });
+        if let Err(err) = written {
+            println!("Writing profile failed with error: {}", err);
+        }
+    }
+}
+
+// Writes which lines of the program ran as an lcov tracefile and as an
+// annotated listing
+fn report_coverage(
+    interpreter: &evaluator::Interpreter,
+    program: &str,
+    source_lines: &[&str],
+    coverage_file: &Option<String>,
+    coverage_listing: &Option<String>,
+) {
+    let coverage = match interpreter.coverage() {
+        Some(coverage) => coverage,
+        None => return,
+    };
+
+    if let Some(path) = coverage_file {
+        let written = File::create(path).and_then(|file| {
+            let mut output = BufWriter::new(file);
+            coverage.write_lcov(program, source_lines, &mut output)?;
+            output.flush()
+        });
+        if let Err(err) = written {
+            println!("Writing coverage failed with error: {}", err);
+        }
+    }
+    if let Some(path) = coverage_listing {
+        if let Err(err) = fs::write(path, coverage.annotate(source_lines)) {
+            println!("Writing coverage listing failed with error: {}", err);
+        }
+    }
+}
+
+// Checks a program without running it, exiting with a nonzero status if it
+// has any errors
+fn check(path: Option<String>) {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            println!("check must be followed by a file name");
+            process::exit(2);
+        }
+    };
+
+    match read_file(path.as_str()) {
+        Ok(s) => {
+            let diagnostics = checker::check_source(&s);
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic);
+            }
+            if diagnostics.iter().any(checker::Diagnostic::is_error) {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            println!("Getting file contents failed with error: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+// Prints a program renumbered as RENUM would, given the file name followed by
+// the new starting line number, the old line number to start from and the
+// increment, each of which may be left out
+fn renum(mut arguments: impl Iterator<Item = String>) {
+    let path = match arguments.next() {
+        Some(path) => path,
+        None => {
+            println!("renum must be followed by a file name");
+            process::exit(2);
+        }
+    };
+
+    let renumbered = read_file(path.as_str())
+        .map_err(|err| format!("Getting file contents failed with error: {}", err))
+        .and_then(|s| {
+            let lines: Vec<&str> = s.lines().collect();
+            renum::Renumbering::parse(&arguments.collect::<Vec<_>>().join(","))
+                .and_then(|renumbering| renum::renumber(&lines, &renumbering))
+                .map_err(|e| format!("Renumbering failed: {}", e))
+        });
+
+    match renumbered {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Err(message) => {
+            println!("{}", message);
+            process::exit(1);
+        }
+    }
+}
+
+// Prints a program in its canonical form or, with --check, shows the lines
+// that aren't in it, exiting with a nonzero status if there are any or if a
+// line can't be formatted
+fn fmt(mut arguments: impl Iterator<Item = String>) {
+    let mut check_only = false;
+    let mut path: Option<String> = None;
+
+    for argument in arguments.by_ref() {
+        match argument.as_str() {
+            "--check" => check_only = true,
+            _ => path = Some(argument),
+        }
+    }
+
+    let s = match path.map(|path| read_file(path.as_str())) {
+        Some(Ok(s)) => s,
+        Some(Err(err)) => {
+            println!("Getting file contents failed with error: {}", err);
+            process::exit(2);
+        }
+        None => {
+            println!("fmt must be followed by a file name");
+            process::exit(2);
+        }
+    };
+
+    let mut failed = false;
+    let mut formatted_lines = Vec::new();
+
+    for (lineno, line) in s.lines().enumerate() {
+        match formatter::format_source_line(line) {
+            Ok(formatted) => {
+                if check_only && formatted != line {
+                    println!("Line {} is not formatted:\n-{}\n+{}", lineno + 1, line, formatted);
+                    failed = true;
+                }
+                formatted_lines.push(formatted);
+            }
+            Err(e) => {
+                println!("Error at line {}: {}", lineno + 1, e);
+                failed = true;
+            }
+        }
+    }
+
+    if !check_only && !failed {
+        for line in formatted_lines {
+            println!("{}", line);
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+// Prints which lines use each variable and branch to each line or, with
+// --dot, the flow of control between lines as a Graphviz graph
+fn xref(arguments: impl Iterator<Item = String>) {
+    let mut dot = false;
+    let mut path: Option<String> = None;
+
+    for argument in arguments {
+        match argument.as_str() {
+            "--dot" => dot = true,
+            _ => path = Some(argument),
+        }
+    }
+
+    let s = match path.map(|path| read_file(path.as_str())) {
+        Some(Ok(s)) => s,
+        Some(Err(err)) => {
+            println!("Getting file contents failed with error: {}", err);
+            process::exit(2);
+        }
+        None => {
+            println!("xref must be followed by a file name");
+            process::exit(2);
+        }
+    };
+
+    let mut code_lines: Vec<lexer::LineOfCode> = Vec::new();
+    for (lineno, line) in s.lines().enumerate() {
+        match lexer::tokenize_line(line) {
+            Ok(x) => code_lines.push(x),
+            Err(e) => {
+                println!("Error at line {}: {}", lineno, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if dot {
+        print!("{}", xref::control_flow_dot(code_lines));
+    } else {
+        print!("{}", xref::cross_reference(code_lines));
+    }
+}
+
+// Runs each program with its .in file as input and compares what it does
+// with its .out and .err files, or with --bless saves what it does as what is
+// expected. Runs the examples when no files or directories are given.
+fn test(arguments: impl Iterator<Item = String>) {
+    let mut bless = false;
+    let mut paths = Vec::new();
+
+    for argument in arguments {
+        match argument.as_str() {
+            "--bless" => bless = true,
+            _ => paths.push(PathBuf::from(argument)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("examples"));
+    }
+
+    let mut cases = Vec::new();
+    for path in &paths {
+        match golden::find_cases(path) {
+            Ok(found) => cases.extend(found),
+            Err(err) => {
+                println!("Finding tests in {} failed with error: {}", path.display(), err);
+                process::exit(2);
+            }
+        }
+    }
+
+    let mut failed = 0;
+    for case in &cases {
+        let program = case.program.display();
+        let result = if bless {
+            case.bless().map(|()| Vec::new())
+        } else {
+            case.check()
+        };
+        match result {
+            Ok(ref problems) if problems.is_empty() && bless => println!("{} ... blessed", program),
+            Ok(ref problems) if problems.is_empty() => println!("{} ... ok", program),
+            Ok(problems) => {
+                println!("{} ... FAILED", program);
+                for problem in problems {
+                    println!("  {}", problem.replace('\n', "\n  "));
+                }
+                failed += 1;
+            }
+            Err(err) => {
+                println!("{} ... FAILED", program);
+                println!("  Running it failed with error: {}", err);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} passed; {} failed", cases.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
 fn main() {
-    let mut argv = env::args();
+    match env::args().nth(1).as_deref() {
+        Some("check") => return check(env::args().nth(2)),
+        Some("fmt") => return fmt(env::args().skip(2)),
+        Some("renum") => return renum(env::args().skip(2)),
+        Some("xref") => return xref(env::args().skip(2)),
+        Some("test") => return test(env::args().skip(2)),
+        _ => {}
+    }
+
+    let mut program: Option<String> = None;
+    let mut seed: Option<u64> = None;
+    let mut debug = false;
+    let mut trace = false;
+    let mut trace_file: Option<String> = None;
+    let mut trace_statements = false;
+    let mut trace_variables = false;
+    let mut limits = limits::Limits::default();
+    let mut save_snapshot: Option<String> = None;
+    let mut restore_snapshot: Option<String> = None;
+    let mut use_vm = false;
+    let mut profile = false;
+    let mut profile_folded: Option<String> = None;
+    let mut coverage_file: Option<String> = None;
+    let mut coverage_listing: Option<String> = None;
+    let mut argv = env::args().skip(1);
 
-    if env::args().len() > 1 {
-        let program: String = argv.nth(1).unwrap();
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--seed" => match argv.next().map(|value| u64::from_str(value.as_str())) {
+                Some(Ok(value)) => seed = Some(value),
+                _ => {
+                    println!("--seed must be followed by a whole number");
+                    return;
+                }
+            },
+            "--debug" => debug = true,
+            "--trace" => trace = true,
+            "--trace-file" => match argv.next() {
+                Some(path) => trace_file = Some(path),
+                None => {
+                    println!("--trace-file must be followed by a file name");
+                    return;
+                }
+            },
+            "--trace-statements" => trace_statements = true,
+            "--trace-variables" => trace_variables = true,
+            "--max-statements" => match limit_argument(&arg, argv.next()) {
+                Some(value) => limits.statements = Some(value),
+                None => return,
+            },
+            "--max-time" => match limit_argument::<f64>(&arg, argv.next()) {
+                Some(value) if value >= 0.0 && value.is_finite() => {
+                    limits.time = Some(Duration::from_secs_f64(value))
+                }
+                Some(_) => {
+                    println!("--max-time must be followed by a number of seconds");
+                    return;
+                }
+                None => return,
+            },
+            "--max-string-bytes" => match limit_argument(&arg, argv.next()) {
+                Some(value) => limits.string_bytes = Some(value),
+                None => return,
+            },
+            "--max-array-elements" => match limit_argument(&arg, argv.next()) {
+                Some(value) => limits.array_elements = Some(value),
+                None => return,
+            },
+            "--max-call-depth" => match limit_argument(&arg, argv.next()) {
+                Some(value) => limits.call_depth = Some(value),
+                None => return,
+            },
+            "--save-snapshot" => match argv.next() {
+                Some(path) => save_snapshot = Some(path),
+                None => {
+                    println!("--save-snapshot must be followed by a file name");
+                    return;
+                }
+            },
+            "--restore" => match argv.next() {
+                Some(path) => restore_snapshot = Some(path),
+                None => {
+                    println!("--restore must be followed by a file name");
+                    return;
+                }
+            },
+            "--vm" => use_vm = true,
+            "--profile" => profile = true,
+            "--profile-folded" => match argv.next() {
+                Some(path) => profile_folded = Some(path),
+                None => {
+                    println!("--profile-folded must be followed by a file name");
+                    return;
+                }
+            },
+            "--coverage" => match argv.next() {
+                Some(path) => coverage_file = Some(path),
+                None => {
+                    println!("--coverage must be followed by a file name");
+                    return;
+                }
+            },
+            "--coverage-listing" => match argv.next() {
+                Some(path) => coverage_listing = Some(path),
+                None => {
+                    println!("--coverage-listing must be followed by a file name");
+                    return;
+                }
+            },
+            _ => program = Some(arg),
+        }
+    }
+
+    // The VM only runs programs, so the interpreter runs them instead when
+    // any of its other features are asked for
+    if use_vm
+        && (debug
+            || trace
+            || trace_file.is_some()
+            || trace_statements
+            || trace_variables
+            || save_snapshot.is_some()
+            || restore_snapshot.is_some()
+            || profile
+            || profile_folded.is_some()
+            || coverage_file.is_some()
+            || coverage_listing.is_some())
+    {
+        eprintln!("--vm doesn't support debugging, tracing, profiling, coverage or snapshots, so the program is interpreted");
+        use_vm = false;
+    }
+    // A snapshot carries on with the random numbers where they were
+    if restore_snapshot.is_some() && seed.is_some() {
+        println!("--seed can't be used with --restore, as the snapshot holds the random number generator");
+        return;
+    }
+    let profiling = profile || profile_folded.is_some();
+    let measuring_coverage = coverage_file.is_some() || coverage_listing.is_some();
+
+    // Coverage is reported against the lines of the program's file
+    if measuring_coverage && (restore_snapshot.is_some() || debug) {
+        println!("--coverage can't be used with --restore or --debug");
+        return;
+    }
+
+    if let Some(path) = restore_snapshot {
+        match evaluator::Interpreter::load_snapshot(&path) {
+            Ok(mut interpreter) => {
+                // Snapshots hold the tokens of the program rather than its
+                // text, so traced statements are shown formatted
+                let listing: Vec<String> = interpreter
+                    .program()
+                    .iter()
+                    .map(formatter::format_line)
+                    .collect();
+                let listing: Vec<&str> = listing.iter().map(String::as_str).collect();
+                let statements = if trace_statements { Some(&listing[..]) } else { None };
+                let tracer = match make_tracer(&trace_file, statements, trace_variables) {
+                    Some(tracer) => tracer,
+                    None => return,
+                };
+                interpreter.set_tracer(tracer);
+                // Tracing carries on as it was when the snapshot was saved,
+                // unless --trace turns it on
+                if trace {
+                    interpreter.set_tracing(true);
+                }
+                interpreter.set_limits(limits);
+                interpreter.set_profiling(profiling);
+                let result = interpreter.cont();
+                report(&mut interpreter, result, &save_snapshot);
+                report_profile(&interpreter, profile, &profile_folded);
+            }
+            Err(e) => println!("Restoring snapshot failed: {}", e),
+        }
+    } else if let Some(program) = program {
         match read_file(program.as_str()) {
             Ok(s) => {
                 let mut code_lines: Vec<lexer::LineOfCode> = Vec::new();
@@ -37,13 +566,77 @@ fn main() {
                     }
                 }
 
-                match evaluator::evaluate(code_lines) {
-                    Ok(msg) => println!("{}", msg),
-                    Err(msg) => println!("Execution failed: {}", msg),
+                if use_vm {
+                    match compiler::compile(code_lines.clone()) {
+                        Ok(program) => {
+                            let mut vm = vm::Vm::new(program);
+                            if let Some(seed) = seed {
+                                vm.set_seed(seed);
+                            }
+                            vm.set_limits(limits);
+                            match vm.run() {
+                                Ok(status) => println!("{}", status),
+                                Err(e) => println!("Execution failed: {}", e),
+                            }
+                            return;
+                        }
+                        // Statements the compiler can't handle, such as those
+                        // working with files, are left to the interpreter
+                        Err(ref e) if e.code == error::ADVANCED_FEATURE => {
+                            eprintln!("{}, so the program is interpreted", e)
+                        }
+                        Err(e) => {
+                            println!("Compiling failed: {}", e);
+                            return;
+                        }
+                    }
+                }
+
+                let source_lines: Vec<&str> = s.lines().collect();
+                let statements = if trace_statements { Some(&source_lines[..]) } else { None };
+                let tracer = match make_tracer(&trace_file, statements, trace_variables) {
+                    Some(tracer) => tracer,
+                    None => return,
+                };
+
+                let interpreter = evaluator::Interpreter::new(code_lines).map(|mut interpreter| {
+                    if let Some(seed) = seed {
+                        interpreter.set_seed(seed);
+                    }
+                    interpreter.set_tracer(tracer);
+                    interpreter.set_trace(trace);
+                    interpreter.set_limits(limits);
+                    interpreter.set_profiling(profiling);
+                    interpreter.set_coverage(measuring_coverage);
+                    interpreter
+                });
+
+                match interpreter {
+                    Ok(interpreter) if debug => {
+                        let mut debugger = debugger::Debugger::new(interpreter, &source_lines);
+                        if let Err(err) = debugger.run() {
+                            println!("Debugging session failed with error: {}", err);
+                        }
+                    }
+                    Ok(mut interpreter) => {
+                        let result = interpreter.run();
+                        report(&mut interpreter, result, &save_snapshot);
+                        report_profile(&interpreter, profile, &profile_folded);
+                        report_coverage(
+                            &interpreter,
+                            &program,
+                            &source_lines,
+                            &coverage_file,
+                            &coverage_listing,
+                        );
+                    }
+                    Err(e) => println!("Execution failed: {}", e),
                 }
 
             }
             Err(err) => println!("Getting file contents failed with error: {}", err),
         };
+    } else if let Err(err) = repl::run() {
+        println!("Interactive session failed with error: {}", err);
     }
 }
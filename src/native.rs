@@ -0,0 +1,111 @@
+use crate::error;
+use crate::value;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+// The type of value a native function takes for an argument. Arguments are
+// converted to the declared type before the function is called, so that a
+// Float argument is always given as value::RBasicValue::Float, for example.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgumentType {
+    Number,
+    Float,
+    String,
+    Bool,
+    // Any value, passed as it is
+    Any,
+}
+
+impl fmt::Display for ArgumentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            ArgumentType::Number => "integer",
+            ArgumentType::Float => "number",
+            ArgumentType::String => "string",
+            ArgumentType::Bool => "Boolean",
+            ArgumentType::Any => "value",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub type NativeFn = dyn Fn(&[value::RBasicValue]) -> Result<value::RBasicValue, error::RBasicError>;
+
+// A function written in Rust by a program embedding the interpreter, called
+// from BASIC in the same way as the built-in functions
+#[derive(Clone)]
+pub struct NativeFunction {
+    argument_types: Vec<ArgumentType>,
+    function: Rc<NativeFn>,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFunction({:?})", self.argument_types)
+    }
+}
+
+impl NativeFunction {
+    pub fn new<F>(argument_types: &[ArgumentType], function: F) -> NativeFunction
+    where
+        F: Fn(&[value::RBasicValue]) -> Result<value::RBasicValue, error::RBasicError> + 'static,
+    {
+        NativeFunction {
+            argument_types: argument_types.to_vec(),
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn argument_types(&self) -> &[ArgumentType] {
+        &self.argument_types
+    }
+
+    pub fn call(
+        &self,
+        name: &str,
+        args: Vec<value::RBasicValue>,
+    ) -> Result<value::RBasicValue, error::RBasicError> {
+        if args.len() != self.argument_types.len() {
+            return Err(error::RBasicError::new(
+                error::ILLEGAL_FUNCTION_CALL,
+                format!(
+                    "Function {} expects {} argument(s) but was given {}",
+                    name,
+                    self.argument_types.len(),
+                    args.len()
+                ),
+            ));
+        }
+
+        let args = args
+            .into_iter()
+            .zip(&self.argument_types)
+            .enumerate()
+            .map(|(index, (arg, argument_type))| {
+                convert(arg, *argument_type).map_err(|e| {
+                    error::RBasicError::new(
+                        e.code,
+                        format!("Argument {} of {}: {}", index + 1, name, e.message),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        (self.function)(&args)
+    }
+}
+
+fn convert(
+    arg: value::RBasicValue,
+    argument_type: ArgumentType,
+) -> Result<value::RBasicValue, error::RBasicError> {
+    match argument_type {
+        ArgumentType::Number => i32::try_from(arg).map(value::RBasicValue::from),
+        ArgumentType::Float => f64::try_from(arg).map(value::RBasicValue::from),
+        ArgumentType::String => String::try_from(arg).map(value::RBasicValue::from),
+        ArgumentType::Bool => bool::try_from(arg).map(value::RBasicValue::from),
+        ArgumentType::Any => Ok(arg),
+    }
+}
@@ -0,0 +1,140 @@
+use crate::error;
+
+// Conversions between numbers and the packed strings stored in random access
+// file records, byte compatible with GWBASIC's MKI$, MKS$ and MKD$. Singles
+// and doubles use the Microsoft Binary Format rather than IEEE 754.
+//
+// BASIC strings hold one byte per character, so bytes are mapped to the
+// characters U+0000 to U+00FF and back.
+
+pub fn string_from_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+// Characters that don't fit in a byte become '?'
+pub fn bytes_from_string(string: &str) -> Vec<u8> {
+    string
+        .chars()
+        .map(|ch| {
+            if (ch as u32) < 256 {
+                ch as u32 as u8
+            } else {
+                b'?'
+            }
+        })
+        .collect()
+}
+
+pub fn mki(number: i32) -> Result<String, error::RBasicError> {
+    if !(i16::MIN as i32..=i16::MAX as i32).contains(&number) {
+        return Err(overflow("MKI$", number as f64));
+    }
+
+    Ok(string_from_bytes(&(number as i16).to_le_bytes()))
+}
+
+pub fn cvi(string: &str) -> Result<i32, error::RBasicError> {
+    let bytes = packed_bytes("CVI", string, 2)?;
+    Ok(i16::from_le_bytes([bytes[0], bytes[1]]) as i32)
+}
+
+pub fn mks(number: f64) -> Result<String, error::RBasicError> {
+    let bits = (number as f32).to_bits();
+    let sign = (bits >> 31) as u8;
+    let exponent = (bits >> 23) & 0xff;
+    let mantissa = bits & 0x7f_ffff;
+
+    // Zero and numbers too small for MBF are stored as zero
+    if exponent == 0 {
+        return Ok(string_from_bytes(&[0; 4]));
+    }
+
+    // The MBF exponent is biased by 128 instead of 127 and the binary point
+    // sits before the implied leading 1 instead of after it
+    let exponent = exponent + 2;
+    if exponent > 0xff || !number.is_finite() {
+        return Err(overflow("MKS$", number));
+    }
+
+    Ok(string_from_bytes(&[
+        mantissa as u8,
+        (mantissa >> 8) as u8,
+        (sign << 7) | (mantissa >> 16) as u8,
+        exponent as u8,
+    ]))
+}
+
+pub fn cvs(string: &str) -> Result<f64, error::RBasicError> {
+    let bytes = packed_bytes("CVS", string, 4)?;
+
+    if bytes[3] < 3 {
+        return Ok(0.0);
+    }
+
+    let sign = (bytes[2] >> 7) as u32;
+    let exponent = bytes[3] as u32 - 2;
+    let mantissa = (((bytes[2] & 0x7f) as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[0] as u32;
+
+    Ok(f32::from_bits((sign << 31) | (exponent << 23) | mantissa) as f64)
+}
+
+pub fn mkd(number: f64) -> Result<String, error::RBasicError> {
+    let bits = number.to_bits();
+    let sign = (bits >> 63) as u8;
+    let exponent = ((bits >> 52) & 0x7ff) as i64;
+    // MBF doubles have 55 bits of mantissa to IEEE's 52
+    let mantissa = (bits & 0xf_ffff_ffff_ffff) << 3;
+
+    let exponent = exponent - 1023 + 129;
+    if exponent <= 0 || number == 0.0 {
+        return Ok(string_from_bytes(&[0; 8]));
+    }
+    if exponent > 0xff || !number.is_finite() {
+        return Err(overflow("MKD$", number));
+    }
+
+    let mut bytes = mantissa.to_le_bytes();
+    bytes[6] = (sign << 7) | (bytes[6] & 0x7f);
+    bytes[7] = exponent as u8;
+    Ok(string_from_bytes(&bytes))
+}
+
+pub fn cvd(string: &str) -> Result<f64, error::RBasicError> {
+    let bytes = packed_bytes("CVD", string, 8)?;
+
+    if bytes[7] == 0 {
+        return Ok(0.0);
+    }
+
+    let sign = (bytes[6] >> 7) as u64;
+    let exponent = bytes[7] as u64 + 1023 - 129;
+    let mut mantissa_bytes = [0; 8];
+    mantissa_bytes[..7].copy_from_slice(&bytes[..7]);
+    mantissa_bytes[6] &= 0x7f;
+    let mantissa = u64::from_le_bytes(mantissa_bytes) >> 3;
+
+    Ok(f64::from_bits((sign << 63) | (exponent << 52) | mantissa))
+}
+
+fn packed_bytes(name: &str, string: &str, length: usize) -> Result<Vec<u8>, error::RBasicError> {
+    let bytes = bytes_from_string(string);
+
+    if bytes.len() < length {
+        return Err(error::RBasicError::new(
+            error::ILLEGAL_FUNCTION_CALL,
+            format!(
+                "{} requires a string of at least {} characters",
+                name, length
+            ),
+        ));
+    }
+
+    Ok(bytes)
+}
+
+fn overflow(name: &str, number: f64) -> error::RBasicError {
+    error::RBasicError::new(
+        error::OVERFLOW,
+        format!("Overflow packing {} with {}", number, name),
+    )
+}
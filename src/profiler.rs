@@ -0,0 +1,179 @@
+use crate::lexer;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+// How often a line ran and the time spent running it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineProfile {
+    pub count: u64,
+    pub time: Duration,
+}
+
+// How often a subroutine was called by GOSUB and the time spent in it,
+// including in the subroutines it called
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoutineProfile {
+    pub calls: u64,
+    pub time: Duration,
+}
+
+// Counts the lines a program runs and the time taken by each, as --profile
+// shows. The time of each line is also kept for each chain of active GOSUBs
+// it ran under, for writing as folded stacks for flame graphs.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    lines: BTreeMap<lexer::LineNumber, LineProfile>,
+    // By the line number each subroutine starts at
+    routines: BTreeMap<lexer::LineNumber, RoutineProfile>,
+    // The line numbers of the active subroutines, outermost first
+    stack: Vec<lexer::LineNumber>,
+    // Each chain of subroutines seen, numbered so that the time of each line
+    // is kept by number rather than by a copy of the chain
+    stack_ids: BTreeMap<Vec<lexer::LineNumber>, usize>,
+    stack_id: usize,
+    folded: BTreeMap<(usize, lexer::LineNumber), Duration>,
+    total: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        let mut profiler = Profiler::default();
+        profiler.stack_ids.insert(Vec::new(), 0);
+        profiler
+    }
+
+    // Records a line having run, under the subroutines active when it started
+    pub fn record_line(&mut self, line_number: &lexer::LineNumber, time: Duration) {
+        let line = self.lines.entry(line_number.clone()).or_default();
+        line.count += 1;
+        line.time += time;
+        self.total += time;
+
+        // A subroutine calling itself counts the time once
+        for (depth, routine) in self.stack.iter().enumerate() {
+            if !self.stack[..depth].contains(routine) {
+                self.routines.entry(routine.clone()).or_default().time += time;
+            }
+        }
+
+        *self
+            .folded
+            .entry((self.stack_id, line_number.clone()))
+            .or_default() += time;
+    }
+
+    // Records a GOSUB to the subroutine starting at the given line
+    pub fn call(&mut self, routine: &lexer::LineNumber) {
+        self.routines.entry(routine.clone()).or_default().calls += 1;
+        self.stack.push(routine.clone());
+        self.update_stack_id();
+    }
+
+    // Records returns from subroutines until the given number are active
+    pub fn return_to(&mut self, depth: usize) {
+        self.stack.truncate(depth);
+        self.update_stack_id();
+    }
+
+    fn update_stack_id(&mut self) {
+        let next_id = self.stack_ids.len();
+        self.stack_id = *self.stack_ids.entry(self.stack.clone()).or_insert(next_id);
+    }
+
+    pub fn line(&self, line_number: &lexer::LineNumber) -> Option<&LineProfile> {
+        self.lines.get(line_number)
+    }
+
+    pub fn routine(&self, line_number: &lexer::LineNumber) -> Option<&RoutineProfile> {
+        self.routines.get(line_number)
+    }
+
+    // The total time spent running lines
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    // Writes the time of each line under each chain of subroutines in the
+    // folded stack format read by flamegraph.pl and inferno, in microseconds,
+    // as in:
+    //   main;GOSUB 100;line 120 2500
+    pub fn write_folded(&self, output: &mut dyn io::Write) -> io::Result<()> {
+        let stacks: BTreeMap<usize, &Vec<lexer::LineNumber>> = self
+            .stack_ids
+            .iter()
+            .map(|(stack, id)| (*id, stack))
+            .collect();
+
+        for ((stack_id, line_number), time) in &self.folded {
+            let mut frames = vec!["main".to_string()];
+            frames.extend(
+                stacks[stack_id]
+                    .iter()
+                    .map(|routine| format!("GOSUB {}", routine.0)),
+            );
+            frames.push(format!("line {}", line_number.0));
+            writeln!(output, "{} {}", frames.join(";"), time.as_micros())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn milliseconds(time: Duration) -> String {
+    format!("{:.3}ms", time.as_secs_f64() * 1000.0)
+}
+
+// Shown as a table of the lines that ran, taking the most time first, and
+// then one of the subroutines GOSUBs called
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |time: Duration| {
+            if self.total.is_zero() {
+                0.0
+            } else {
+                time.as_secs_f64() * 100.0 / self.total.as_secs_f64()
+            }
+        };
+
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by_key(|(_, line)| std::cmp::Reverse(line.time));
+        writeln!(f, "{:>8} {:>12} {:>14} {:>7}", "Line", "Count", "Time", "%")?;
+        for (line_number, line) in lines {
+            writeln!(
+                f,
+                "{:>8} {:>12} {:>14} {:>6.1}%",
+                line_number.0,
+                line.count,
+                milliseconds(line.time),
+                percent(line.time)
+            )?;
+        }
+
+        if !self.routines.is_empty() {
+            let mut routines: Vec<_> = self.routines.iter().collect();
+            routines.sort_by_key(|(_, routine)| std::cmp::Reverse(routine.time));
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{:>8} {:>12} {:>14} {:>7}",
+                "GOSUB", "Calls", "Time", "%"
+            )?;
+            for (line_number, routine) in routines {
+                writeln!(
+                    f,
+                    "{:>8} {:>12} {:>14} {:>6.1}%",
+                    line_number.0,
+                    routine.calls,
+                    milliseconds(routine.time),
+                    percent(routine.time)
+                )?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Total time: {}", milliseconds(self.total))
+    }
+}
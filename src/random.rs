@@ -0,0 +1,67 @@
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// The seed used until the program or its host chooses another, so that a
+// program gives the same numbers every time it runs, as in Microsoft BASIC
+pub const DEFAULT_SEED: u64 = 0;
+
+// A small pseudo-random number generator (SplitMix64) kept inside the crate
+// so that a given seed produces the same numbers on every platform
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    last: f64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        let mut rng = Rng {
+            state: 0,
+            last: 0.0,
+        };
+        rng.seed(seed);
+        rng
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+        self.last = 0.0;
+    }
+
+    // Seeds from the current time, for RANDOMIZE without a seed
+    pub fn seed_from_time(&mut self) {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or(DEFAULT_SEED);
+        self.seed(nanos);
+    }
+
+    // The next number, from 0 up to but not including 1
+    pub fn next_float(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        // Use the top 53 bits, which is all of the precision an f64 has
+        self.last = (z >> 11) as f64 / (1u64 << 53) as f64;
+        self.last
+    }
+
+    // The number last returned by next_float
+    pub fn last_float(&self) -> f64 {
+        self.last
+    }
+
+    // The generator's state and last number, for saving in a snapshot
+    pub fn state(&self) -> (u64, f64) {
+        (self.state, self.last)
+    }
+
+    // A generator that carries on from a saved state
+    pub fn from_state(state: u64, last: f64) -> Rng {
+        Rng { state, last }
+    }
+}
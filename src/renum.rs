@@ -0,0 +1,206 @@
+use crate::error;
+use crate::evaluator;
+use crate::lexer;
+use crate::token;
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+// How RENUM numbers a program: the lines from old_start on are numbered from
+// new_start, going up by increment, as in GWBASIC
+#[derive(Debug, Clone, PartialEq)]
+pub struct Renumbering {
+    pub new_start: u32,
+    pub old_start: u32,
+    pub increment: u32,
+}
+
+impl Default for Renumbering {
+    fn default() -> Renumbering {
+        Renumbering {
+            new_start: 10,
+            old_start: 0,
+            increment: 10,
+        }
+    }
+}
+
+impl Renumbering {
+    // Parses the [new_start][, [old_start][, [increment]]] arguments of
+    // RENUM, where any that are left out keep their defaults
+    pub fn parse(arguments: &str) -> Result<Renumbering, error::RBasicError> {
+        let mut renumbering = Renumbering::default();
+
+        if arguments.trim().is_empty() {
+            return Ok(renumbering);
+        }
+
+        let fields: Vec<&str> = arguments.split(',').map(str::trim).collect();
+        if fields.len() > 3 {
+            return Err(error::RBasicError::new(
+                error::SYNTAX_ERROR,
+                "RENUM takes at most three numbers".to_string(),
+            ));
+        }
+
+        let settings = [
+            &mut renumbering.new_start,
+            &mut renumbering.old_start,
+            &mut renumbering.increment,
+        ];
+        for (field, setting) in fields.into_iter().zip(settings) {
+            if field.is_empty() {
+                continue;
+            }
+            *setting = u32::from_str(field).map_err(|_| {
+                error::RBasicError::new(
+                    error::SYNTAX_ERROR,
+                    format!("RENUM expected a line number, not {}", field),
+                )
+            })?;
+        }
+
+        Ok(renumbering)
+    }
+}
+
+// Renumbers the lines of a program along with every line number they refer
+// to, returning its lines in their new order. Lines keep their spacing and
+// comments, and comparisons of ERL with a line number are renumbered too.
+// Nothing is changed if any line refers to a line that doesn't exist, or if
+// the new numbers would run into the lines before old_start.
+pub fn renumber<S: AsRef<str>>(
+    source_lines: &[S],
+    renumbering: &Renumbering,
+) -> Result<Vec<String>, error::RBasicError> {
+    if renumbering.increment == 0 {
+        return Err(error::RBasicError::new(
+            error::ILLEGAL_FUNCTION_CALL,
+            "RENUM increment must be at least 1".to_string(),
+        ));
+    }
+
+    let mut lines: BTreeMap<u32, (&str, lexer::LineOfCode)> = BTreeMap::new();
+    for line in source_lines {
+        let line = line.as_ref();
+        if line.trim().is_empty() {
+            continue;
+        }
+        let code_line = lexer::tokenize_line(line)
+            .map_err(|e| error::RBasicError::new(error::SYNTAX_ERROR, e))?;
+        lines.insert(code_line.line_number.0, (line, code_line));
+    }
+
+    if let Some(last_kept) = lines.range(..renumbering.old_start).next_back() {
+        if renumbering.new_start <= *last_kept.0 {
+            return Err(error::RBasicError::new(
+                error::ILLEGAL_FUNCTION_CALL,
+                format!(
+                    "RENUM can't number lines from {} after line {}",
+                    renumbering.new_start, last_kept.0
+                ),
+            ));
+        }
+    }
+
+    // The new number of each line that is renumbered
+    let mut new_numbers: BTreeMap<u32, u32> = BTreeMap::new();
+    let mut next_number = Some(renumbering.new_start);
+    for old_number in lines
+        .range(renumbering.old_start..)
+        .map(|(number, _)| *number)
+    {
+        let new_number = next_number.ok_or_else(|| {
+            error::RBasicError::new(
+                error::ILLEGAL_FUNCTION_CALL,
+                "RENUM would number lines past the largest line number".to_string(),
+            )
+        })?;
+        new_numbers.insert(old_number, new_number);
+        next_number = new_number.checked_add(renumbering.increment);
+    }
+
+    let mut undefined = Vec::new();
+    let mut renumbered = Vec::with_capacity(lines.len());
+
+    for (old_number, (text, code_line)) in &lines {
+        // Where each line number to change is, with its new number
+        let mut changes: Vec<(u32, u32)> = Vec::new();
+
+        if let Some(new_number) = new_numbers.get(old_number) {
+            changes.push((0, *new_number));
+        }
+        for reference in evaluator::line_references(&code_line.tokens) {
+            if !lines.contains_key(&reference.target.0) {
+                undefined.push(format!(
+                    "Undefined line {} in {}",
+                    reference.target.0, old_number
+                ));
+            } else if let Some(new_number) = new_numbers.get(&reference.target.0) {
+                changes.push((reference.pos, *new_number));
+            }
+        }
+        for (pos, target) in erl_comparisons(&code_line.tokens) {
+            if let Some(new_number) = new_numbers.get(&target) {
+                changes.push((pos, *new_number));
+            }
+        }
+
+        renumbered.push(replace_numbers(text, changes));
+    }
+
+    if !undefined.is_empty() {
+        return Err(error::RBasicError::new(
+            error::UNDEFINED_LINE_NUMBER,
+            undefined.join("\n"),
+        ));
+    }
+
+    Ok(renumbered)
+}
+
+// Finds the line numbers that ERL is compared with, as in IF ERL = 100 THEN,
+// with the position of each
+fn erl_comparisons(tokens: &[lexer::TokenAndPos]) -> Vec<(u32, u32)> {
+    let is_erl = |token: &token::Token| matches!(*token, token::Token::Variable(ref name, _) if &**name == "ERL");
+
+    tokens
+        .windows(3)
+        .filter_map(|window| match (&window[0].1, &window[1].1, &window[2]) {
+            (first, operator, lexer::TokenAndPos(pos, token::Token::Number(number)))
+                if is_erl(first) && operator.is_comparison_operator() && *number >= 0 =>
+            {
+                Some((*pos, *number as u32))
+            }
+            (token::Token::Number(number), operator, lexer::TokenAndPos(_, last))
+                if is_erl(last) && operator.is_comparison_operator() && *number >= 0 =>
+            {
+                Some((window[0].0, *number as u32))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Replaces the numbers starting at the given character positions of a line
+fn replace_numbers(text: &str, mut changes: Vec<(u32, u32)>) -> String {
+    let mut text = text.to_string();
+
+    // From the end of the line back, so that the positions before each
+    // change stay the same
+    changes.sort_unstable_by_key(|change| std::cmp::Reverse(change.0));
+    changes.dedup();
+
+    for (pos, number) in changes {
+        let start = match text.char_indices().nth(pos as usize) {
+            Some((start, _)) => start,
+            None => continue,
+        };
+        let end = text[start..]
+            .find(|ch: char| !ch.is_ascii_digit())
+            .map_or(text.len(), |length| start + length);
+        text.replace_range(start..end, &number.to_string());
+    }
+
+    text
+}
@@ -0,0 +1,124 @@
+use crate::error;
+use crate::evaluator;
+use crate::lexer;
+use crate::renum;
+
+use std::collections::BTreeMap;
+use std::io;
+
+// Runs an interactive session in the fashion of GWBASIC. Lines starting with
+// a line number are added to the program, replacing any line with the same
+// number, and a line number on its own deletes that line. Otherwise the
+// following commands are accepted:
+//   RUN     runs the program from the beginning
+//   CONT    resumes the program after a STOP
+//   LIST    shows the program
+//   NEW     clears the program
+//   RENUM [new_start][, [old_start][, [increment]]]
+//           renumbers the program, along with the line numbers it refers to
+//   SYSTEM  leaves the interpreter, as does the end of input
+pub fn run() -> Result<(), io::Error> {
+    let mut source: BTreeMap<lexer::LineNumber, String> = BTreeMap::new();
+    // The last program run, kept so that CONT can resume it. Changing the
+    // program means it can no longer be continued.
+    let mut interpreter: Option<evaluator::Interpreter> = None;
+
+    println!("Ok");
+
+    loop {
+        let mut input = String::new();
+
+        if io::stdin().read_line(&mut input)? == 0 {
+            break;
+        }
+
+        let input = input.trim();
+
+        if input.is_empty() {
+            continue;
+        }
+
+        if input.starts_with(|ch: char| ch.is_ascii_digit()) {
+            match lexer::tokenize_line(input) {
+                Ok(ref line) if line.tokens.is_empty() => {
+                    source.remove(&line.line_number);
+                }
+                Ok(line) => {
+                    source.insert(line.line_number, input.to_string());
+                }
+                Err(e) => println!("Syntax error: {}", e),
+            }
+            interpreter = None;
+            continue;
+        }
+
+        match input.to_uppercase().as_str() {
+            "RUN" => {
+                let code_lines = source
+                    .values()
+                    .map(|line| lexer::tokenize_line(line))
+                    .collect::<Result<Vec<_>, String>>()
+                    .and_then(|code_lines| {
+                        evaluator::Interpreter::new(code_lines).map_err(|e| e.message)
+                    });
+
+                match code_lines {
+                    Ok(mut program) => {
+                        report(program.run());
+                        interpreter = Some(program);
+                    }
+                    Err(e) => println!("Execution failed: {}", e),
+                }
+            }
+            "CONT" => match interpreter {
+                Some(ref mut program) => report(program.cont()),
+                None => println!("Execution failed: Can't continue"),
+            },
+            "LIST" => {
+                for line in source.values() {
+                    println!("{}", line);
+                }
+            }
+            "NEW" => {
+                source.clear();
+                interpreter = None;
+            }
+            "SYSTEM" => break,
+            command if command.starts_with("RENUM") => {
+                let renumbered = renum::Renumbering::parse(&command["RENUM".len()..]).and_then(
+                    |renumbering| {
+                        renum::renumber(&source.values().collect::<Vec<_>>(), &renumbering)
+                    },
+                );
+
+                match renumbered {
+                    Ok(lines) => {
+                        source = lines
+                            .into_iter()
+                            .filter_map(|line| {
+                                lexer::tokenize_line(&line)
+                                    .ok()
+                                    .map(|code_line| (code_line.line_number, line))
+                            })
+                            .collect();
+                        interpreter = None;
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            _ => println!("Unknown command: {}", input),
+        }
+
+        println!("Ok");
+    }
+
+    Ok(())
+}
+
+fn report(result: Result<evaluator::ExecutionStatus, error::RBasicError>) {
+    match result {
+        Ok(evaluator::ExecutionStatus::Ended) => {}
+        Ok(status) => println!("{}", status),
+        Err(e) => println!("Execution failed: {}", e),
+    }
+}
@@ -0,0 +1,265 @@
+use crate::error;
+use crate::lexer;
+use crate::token;
+use crate::value;
+use crate::variables;
+
+use std::fmt;
+use std::iter::Enumerate;
+use std::str::FromStr;
+use std::str::Lines;
+
+// The version written in the header of every snapshot. Snapshots with any
+// other version are refused rather than guessed at.
+pub const FORMAT_VERSION: u32 = 1;
+
+const HEADER: &str = "RBASIC SNAPSHOT";
+
+// Writes a snapshot as lines of text, each a key followed by its value, so
+// that a snapshot can be read, and compared, by eye
+pub struct Writer {
+    text: String,
+}
+
+impl Default for Writer {
+    fn default() -> Writer {
+        Writer::new()
+    }
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer {
+            text: format!("{} {}\n", HEADER, FORMAT_VERSION),
+        }
+    }
+
+    pub fn field<T: fmt::Display>(&mut self, key: &str, value: T) {
+        self.text.push_str(&format!("{} {}\n", key, value));
+    }
+
+    // Writes - when there is no value
+    pub fn optional<T: fmt::Display>(&mut self, key: &str, value: Option<T>) {
+        match value {
+            Some(value) => self.field(key, value),
+            None => self.field(key, "-"),
+        }
+    }
+
+    pub fn string(&mut self, key: &str, string: &str) {
+        self.field(key, escape(string));
+    }
+
+    // Writes bytes in hexadecimal
+    pub fn bytes(&mut self, key: &str, bytes: &[u8]) {
+        let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.field(key, hex);
+    }
+
+    pub fn value(&mut self, key: &str, value: &value::RBasicValue) {
+        match *value {
+            value::RBasicValue::Number(number) => self.field(key, format!("n {}", number)),
+            value::RBasicValue::Float(float) => self.field(key, format!("f {:?}", float)),
+            value::RBasicValue::String(ref string) => {
+                self.field(key, format!("s {}", escape(string)))
+            }
+            value::RBasicValue::Bool(boolean) => self.field(key, format!("b {}", boolean)),
+        }
+    }
+
+    // Writes the number of tokens, then each token with its position
+    pub fn tokens(&mut self, key: &str, tokens: &[lexer::TokenAndPos]) {
+        self.field(key, tokens.len());
+
+        for lexer::TokenAndPos(pos, token) in tokens {
+            let encoded = match *token {
+                token::Token::Comment(ref comment) => format!("c {}", escape(comment)),
+                token::Token::Variable(ref name, _) => format!("v {}", name),
+                token::Token::Number(number) => format!("n {}", number),
+                token::Token::Float(float) => format!("f {:?}", float),
+                token::Token::BString(ref string) => format!("s {}", escape(string)),
+                token::Token::FunctionCall(ref name, count) => format!("call {} {}", name, count),
+                token::Token::UMinus => "u".to_string(),
+                ref token => format!("k {}", token.keyword_text().unwrap_or_default()),
+            };
+            self.field("token", format!("{} {}", pos, encoded));
+        }
+    }
+
+    pub fn finish(mut self) -> String {
+        self.text.push_str("end\n");
+        self.text
+    }
+}
+
+// Reads the lines written by Writer back in the same order
+pub struct Reader<'a> {
+    lines: Enumerate<Lines<'a>>,
+    // The line last read, for error messages
+    line: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Result<Reader<'a>, error::RBasicError> {
+        let mut reader = Reader {
+            lines: text.lines().enumerate(),
+            line: 0,
+        };
+
+        match reader.next(HEADER).map(u32::from_str) {
+            Ok(Ok(FORMAT_VERSION)) => Ok(reader),
+            Ok(Ok(version)) => Err(error::RBasicError::new(
+                error::BAD_SNAPSHOT,
+                format!(
+                    "Snapshot version {} is not supported, only version {}",
+                    version, FORMAT_VERSION
+                ),
+            )),
+            _ => Err(reader.error("not an rbasic snapshot")),
+        }
+    }
+
+    pub fn field<T: FromStr>(&mut self, key: &str) -> Result<T, error::RBasicError> {
+        let text = self.next(key)?;
+        T::from_str(text).map_err(|_| self.error(&format!("invalid {} {}", key, text)))
+    }
+
+    pub fn optional<T: FromStr>(&mut self, key: &str) -> Result<Option<T>, error::RBasicError> {
+        match self.next(key)? {
+            "-" => Ok(None),
+            text => T::from_str(text)
+                .map(Some)
+                .map_err(|_| self.error(&format!("invalid {} {}", key, text))),
+        }
+    }
+
+    pub fn string(&mut self, key: &str) -> Result<String, error::RBasicError> {
+        let text = self.next(key)?;
+        unescape(text).ok_or_else(|| self.error(&format!("invalid {} {}", key, text)))
+    }
+
+    pub fn bytes(&mut self, key: &str) -> Result<Vec<u8>, error::RBasicError> {
+        let text = self.next(key)?;
+        let bytes = if text.len() % 2 == 0 && text.is_ascii() {
+            (0..text.len())
+                .step_by(2)
+                .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+                .collect()
+        } else {
+            None
+        };
+        bytes.ok_or_else(|| self.error(&format!("invalid {} {}", key, text)))
+    }
+
+    pub fn value(&mut self, key: &str) -> Result<value::RBasicValue, error::RBasicError> {
+        let text = self.next(key)?;
+        let (kind, rest) = text.split_at(text.find(' ').unwrap_or(text.len()));
+        let rest = rest.strip_prefix(' ').unwrap_or(rest);
+
+        let value = match kind {
+            "n" => i32::from_str(rest).ok().map(value::RBasicValue::Number),
+            "f" => f64::from_str(rest).ok().map(value::RBasicValue::Float),
+            "s" => unescape(rest).map(value::RBasicValue::from),
+            "b" => bool::from_str(rest).ok().map(value::RBasicValue::Bool),
+            _ => None,
+        };
+        value.ok_or_else(|| self.error(&format!("invalid {} {}", key, text)))
+    }
+
+    pub fn tokens(&mut self, key: &str) -> Result<Vec<lexer::TokenAndPos>, error::RBasicError> {
+        let count: usize = self.field(key)?;
+        let mut tokens = Vec::new();
+
+        for _ in 0..count {
+            let text = self.next("token")?;
+            let token =
+                parse_token(text).ok_or_else(|| self.error(&format!("invalid token {}", text)))?;
+            tokens.push(token);
+        }
+
+        Ok(tokens)
+    }
+
+    pub fn finish(mut self) -> Result<(), error::RBasicError> {
+        self.next("end")?;
+        match self.lines.next() {
+            None => Ok(()),
+            Some(_) => Err(self.error("unexpected text after the end")),
+        }
+    }
+
+    // The text after the key on the next line
+    fn next(&mut self, key: &str) -> Result<&'a str, error::RBasicError> {
+        let (index, line) = match self.lines.next() {
+            Some(next) => next,
+            None => return Err(self.error(&format!("expected {} but the snapshot ended", key))),
+        };
+        self.line = index + 1;
+
+        match line.strip_prefix(key) {
+            Some("") => Ok(""),
+            Some(rest) if rest.starts_with(' ') => Ok(&rest[1..]),
+            _ => Err(self.error(&format!("expected {}", key))),
+        }
+    }
+
+    fn error(&self, message: &str) -> error::RBasicError {
+        error::RBasicError::new(
+            error::BAD_SNAPSHOT,
+            format!("Bad snapshot at line {}: {}", self.line, message),
+        )
+    }
+}
+
+fn parse_token(text: &str) -> Option<lexer::TokenAndPos> {
+    let mut parts = text.splitn(3, ' ');
+    let pos = u32::from_str(parts.next()?).ok()?;
+    let kind = parts.next()?;
+    let rest = parts.next().unwrap_or_default();
+
+    let token = match kind {
+        "c" => token::Token::Comment(unescape(rest)?),
+        "v" => token::Token::Variable(rest.into(), variables::UNRESOLVED),
+        "n" => token::Token::Number(i32::from_str(rest).ok()?),
+        "f" => token::Token::Float(f64::from_str(rest).ok()?),
+        "s" => token::Token::BString(unescape(rest)?.into()),
+        "call" => {
+            let (name, count) = rest.split_once(' ')?;
+            token::Token::FunctionCall(name.into(), usize::from_str(count).ok()?)
+        }
+        "u" => token::Token::UMinus,
+        "k" => token::Token::token_for_string(rest)?,
+        _ => return None,
+    };
+
+    Some(lexer::TokenAndPos(pos, token))
+}
+
+// Strings are kept on one line by escaping backslashes and line breaks
+fn escape(string: &str) -> String {
+    string
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut string = String::new();
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            string.push(ch);
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => string.push('\\'),
+            'n' => string.push('\n'),
+            'r' => string.push('\r'),
+            _ => return None,
+        }
+    }
+
+    Some(string)
+}
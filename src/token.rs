@@ -1,11 +1,16 @@
+use std::rc::Rc;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Comment(String),
 
-    // Variables and Literals
-    Variable(String),
+    // Variables and Literals. A variable has the slot its value is kept in,
+    // which is variables::UNRESOLVED until the program is loaded and given
+    // its slots.
+    Variable(Rc<str>, usize),
     Number(i32),
-    BString(String),
+    Float(f64),
+    BString(Rc<str>),
 
     // Binary Operators
     Equals,
@@ -23,18 +28,66 @@ pub enum Token {
     LParen,
     RParen,
 
+    // Separators
+    Comma,
+    // Marks a file number, as in PRINT #1
+    Hash,
+
+    // Function call with its argument count, as produced by the expression
+    // parser from a Variable directly followed by a parenthesized list
+    FunctionCall(Rc<str>, usize),
+
     // Unary Operators
     Bang,
     UMinus,
 
     // Keywords
+    Append,
+    As,
+    Close,
+    Data,
+    Def,
+    Dim,
+    Do,
+    End,
+    Error,
+    Exit,
+    Field,
+    For,
+    Get,
+    Gosub,
     Goto,
     If,
     Input,
+    Len,
     Let,
+    Line,
+    Loop,
+    Lset,
+    Next,
+    On,
+    Open,
+    Output,
     Print,
+    Put,
+    Random,
+    Randomize,
+    Read,
     Rem,
+    Restore,
+    Resume,
+    Return,
+    Rset,
+    Step,
+    Stop,
     Then,
+    To,
+    Troff,
+    Tron,
+    Until,
+    Wend,
+    While,
+    Write,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -59,40 +112,162 @@ impl Token {
             "+" => Some(Token::Plus),
             "(" => Some(Token::LParen),
             ")" => Some(Token::RParen),
+            "," => Some(Token::Comma),
+            "#" => Some(Token::Hash),
             "!" => Some(Token::Bang),
+            "APPEND" => Some(Token::Append),
+            "AS" => Some(Token::As),
+            "CLOSE" => Some(Token::Close),
+            "DATA" => Some(Token::Data),
+            "DEF" => Some(Token::Def),
+            "DIM" => Some(Token::Dim),
+            "DO" => Some(Token::Do),
+            "END" => Some(Token::End),
+            "ERROR" => Some(Token::Error),
+            "EXIT" => Some(Token::Exit),
+            "FIELD" => Some(Token::Field),
+            "FOR" => Some(Token::For),
+            "GET" => Some(Token::Get),
+            "GOSUB" => Some(Token::Gosub),
             "GOTO" => Some(Token::Goto),
             "IF" => Some(Token::If),
             "INPUT" => Some(Token::Input),
+            "LEN" => Some(Token::Len),
             "LET" => Some(Token::Let),
+            "LINE" => Some(Token::Line),
+            "LOOP" => Some(Token::Loop),
+            "LSET" => Some(Token::Lset),
+            "NEXT" => Some(Token::Next),
+            "ON" => Some(Token::On),
+            "OPEN" => Some(Token::Open),
+            "OUTPUT" => Some(Token::Output),
             "PRINT" => Some(Token::Print),
+            "PUT" => Some(Token::Put),
+            "RANDOM" => Some(Token::Random),
+            "RANDOMIZE" => Some(Token::Randomize),
+            "READ" => Some(Token::Read),
             "REM" => Some(Token::Rem),
+            "RESTORE" => Some(Token::Restore),
+            "RESUME" => Some(Token::Resume),
+            "RETURN" => Some(Token::Return),
+            "RSET" => Some(Token::Rset),
+            "STEP" => Some(Token::Step),
+            "STOP" => Some(Token::Stop),
             "THEN" => Some(Token::Then),
+            "TO" => Some(Token::To),
+            "TROFF" => Some(Token::Troff),
+            "TRON" => Some(Token::Tron),
+            "UNTIL" => Some(Token::Until),
+            "WEND" => Some(Token::Wend),
+            "WHILE" => Some(Token::While),
+            "WRITE" => Some(Token::Write),
             _ => None,
         }
     }
 
-    pub fn is_operator(&self) -> bool {
+    // The text a keyword, operator or separator is written as, which
+    // token_for_string turns back into the token. Unary minus is written as
+    // "-" too, and told apart from Minus by what comes before it.
+    pub fn keyword_text(&self) -> Option<&'static str> {
         match *self {
-            Token::Equals | Token::LessThan | Token::GreaterThan | Token::LessThanEqual |
-            Token::GreaterThanEqual | Token::NotEqual | Token::Multiply | Token::Divide |
-            Token::Minus | Token::Plus | Token::UMinus | Token::Bang => true,
-            _ => false,
+            Token::Equals => Some("="),
+            Token::LessThan => Some("<"),
+            Token::GreaterThan => Some(">"),
+            Token::LessThanEqual => Some("<="),
+            Token::GreaterThanEqual => Some(">="),
+            Token::NotEqual => Some("<>"),
+            Token::Multiply => Some("*"),
+            Token::Divide => Some("/"),
+            Token::Minus => Some("-"),
+            Token::Plus => Some("+"),
+            Token::LParen => Some("("),
+            Token::RParen => Some(")"),
+            Token::Comma => Some(","),
+            Token::Hash => Some("#"),
+            Token::Bang => Some("!"),
+            Token::Append => Some("APPEND"),
+            Token::As => Some("AS"),
+            Token::Close => Some("CLOSE"),
+            Token::Data => Some("DATA"),
+            Token::Def => Some("DEF"),
+            Token::Dim => Some("DIM"),
+            Token::Do => Some("DO"),
+            Token::End => Some("END"),
+            Token::Error => Some("ERROR"),
+            Token::Exit => Some("EXIT"),
+            Token::Field => Some("FIELD"),
+            Token::For => Some("FOR"),
+            Token::Get => Some("GET"),
+            Token::Gosub => Some("GOSUB"),
+            Token::Goto => Some("GOTO"),
+            Token::If => Some("IF"),
+            Token::Input => Some("INPUT"),
+            Token::Len => Some("LEN"),
+            Token::Let => Some("LET"),
+            Token::Line => Some("LINE"),
+            Token::Loop => Some("LOOP"),
+            Token::Lset => Some("LSET"),
+            Token::Next => Some("NEXT"),
+            Token::On => Some("ON"),
+            Token::Open => Some("OPEN"),
+            Token::Output => Some("OUTPUT"),
+            Token::Print => Some("PRINT"),
+            Token::Put => Some("PUT"),
+            Token::Random => Some("RANDOM"),
+            Token::Randomize => Some("RANDOMIZE"),
+            Token::Read => Some("READ"),
+            Token::Rem => Some("REM"),
+            Token::Restore => Some("RESTORE"),
+            Token::Resume => Some("RESUME"),
+            Token::Return => Some("RETURN"),
+            Token::Rset => Some("RSET"),
+            Token::Step => Some("STEP"),
+            Token::Stop => Some("STOP"),
+            Token::Then => Some("THEN"),
+            Token::To => Some("TO"),
+            Token::Troff => Some("TROFF"),
+            Token::Tron => Some("TRON"),
+            Token::Until => Some("UNTIL"),
+            Token::Wend => Some("WEND"),
+            Token::While => Some("WHILE"),
+            Token::Write => Some("WRITE"),
+            Token::UMinus => Some("-"),
+            _ => None,
         }
     }
 
+    pub fn is_operator(&self) -> bool {
+        matches!(
+            *self,
+            Token::Equals
+                | Token::LessThan
+                | Token::GreaterThan
+                | Token::LessThanEqual
+                | Token::GreaterThanEqual
+                | Token::NotEqual
+                | Token::Multiply
+                | Token::Divide
+                | Token::Minus
+                | Token::Plus
+                | Token::UMinus
+                | Token::Bang
+        )
+    }
+
     pub fn is_comparison_operator(&self) -> bool {
-        match *self {
-            Token::Equals | Token::LessThan | Token::GreaterThan | Token::LessThanEqual |
-            Token::GreaterThanEqual | Token::NotEqual => true,
-            _ => false,
-        }
+        matches!(
+            *self,
+            Token::Equals
+                | Token::LessThan
+                | Token::GreaterThan
+                | Token::LessThanEqual
+                | Token::GreaterThanEqual
+                | Token::NotEqual
+        )
     }
 
     pub fn is_unary_operator(&self) -> bool {
-        match *self {
-            Token::UMinus | Token::Bang => true,
-            _ => false,
-        }
+        matches!(*self, Token::UMinus | Token::Bang)
     }
 
     pub fn is_binary_operator(&self) -> bool {
@@ -100,12 +275,10 @@ impl Token {
     }
 
     pub fn is_value(&self) -> bool {
-        match *self {
-            Token::Variable(_) |
-            Token::Number(_) |
-            Token::BString(_) => true,
-            _ => false,
-        }
+        matches!(
+            *self,
+            Token::Variable(..) | Token::Number(_) | Token::Float(_) | Token::BString(_)
+        )
     }
 
     pub fn operator_precedence(&self) -> Result<u8, String> {
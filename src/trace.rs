@@ -0,0 +1,104 @@
+use crate::error;
+use crate::lexer;
+use crate::value;
+use crate::variables;
+
+use std::collections::BTreeMap;
+use std::io;
+
+// Where TRON and --trace log each line as it runs. Each line is logged as
+// [line number], followed by its text when statements are shown, and
+// followed by the variables it changed when variables are shown.
+pub struct Tracer {
+    output: Box<dyn io::Write>,
+    // The text of each line, when statements are shown
+    source: Option<BTreeMap<lexer::LineNumber, String>>,
+    show_variables: bool,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn io::Write>) -> Tracer {
+        Tracer {
+            output,
+            source: None,
+            show_variables: false,
+        }
+    }
+
+    pub fn to_stderr() -> Tracer {
+        Tracer::new(Box::new(io::stderr()))
+    }
+
+    // Shows the text of each line after its line number
+    pub fn show_statements(&mut self, source_lines: &[&str]) {
+        self.source = Some(
+            source_lines
+                .iter()
+                .filter_map(|line| {
+                    let text = line
+                        .trim()
+                        .split_once(char::is_whitespace)
+                        .map_or("", |(_, text)| text.trim());
+                    lexer::tokenize_line(line)
+                        .ok()
+                        .map(|code| (code.line_number, text.to_string()))
+                })
+                .collect(),
+        );
+    }
+
+    // Shows the variables each line changed, with their new values
+    pub fn show_variables(&mut self) {
+        self.show_variables = true;
+    }
+
+    pub fn shows_variables(&self) -> bool {
+        self.show_variables
+    }
+
+    pub fn line(&mut self, line_number: &lexer::LineNumber) -> Result<(), error::RBasicError> {
+        let text = self
+            .source
+            .as_ref()
+            .and_then(|source| source.get(line_number))
+            .filter(|text| !text.is_empty());
+
+        match text {
+            Some(text) => writeln!(self.output, "[{}] {}", line_number.0, text),
+            None => writeln!(self.output, "[{}]", line_number.0),
+        }
+        .map_err(trace_error)
+    }
+
+    // Logs the variables whose values differ between before and after
+    pub fn changes(
+        &mut self,
+        before: &variables::Variables,
+        after: &variables::Variables,
+    ) -> Result<(), error::RBasicError> {
+        let mut changed: Vec<(&str, &value::RBasicValue)> = after
+            .iter()
+            .filter(|(name, value)| before.get_named(name) != Some(*value))
+            .collect();
+        changed.sort_by(|first, second| first.0.cmp(second.0));
+
+        for (name, value) in changed {
+            match *value {
+                value::RBasicValue::String(ref string) => {
+                    writeln!(self.output, "    {} = \"{}\"", name, string)
+                }
+                ref value => writeln!(self.output, "    {} = {}", name, value),
+            }
+            .map_err(trace_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn trace_error(e: io::Error) -> error::RBasicError {
+    error::RBasicError::new(
+        error::DEVICE_IO_ERROR,
+        format!("Device I/O Error writing trace: {}", e),
+    )
+}
@@ -1,38 +1,109 @@
+use crate::error;
+
+use std::convert::TryFrom;
+use std::fmt;
 use std::ops::Add;
 use std::ops::Div;
 use std::ops::Mul;
 use std::ops::Neg;
 use std::ops::Not;
 use std::ops::Sub;
+use std::rc::Rc;
 use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+// Strings are shared rather than copied as values are passed around, since a
+// string never changes once it is made
+#[derive(Debug, Clone, PartialEq)]
 pub enum RBasicValue {
-    String(String),
+    String(Rc<str>),
     Number(i32),
+    Float(f64),
     Bool(bool),
 }
 
+impl RBasicValue {
+    // The value as a floating point number, if it is numeric
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            RBasicValue::Number(number) => Some(number as f64),
+            RBasicValue::Float(float) => Some(float),
+            RBasicValue::String(ref string) => f64::from_str(string).ok(),
+            RBasicValue::Bool(_) => None,
+        }
+    }
+}
+
+// Values print the same way on the console and to files
+impl fmt::Display for RBasicValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RBasicValue::String(ref value) => write!(f, "{}", value),
+            RBasicValue::Number(value) => write!(f, "{}", value),
+            RBasicValue::Float(value) => write!(f, "{}", value),
+            RBasicValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// When either operand is a floating point number, returns both operands as
+// floating point numbers, so that mixed arithmetic is done in floating point
+fn float_operands(value1: &RBasicValue, value2: &RBasicValue) -> Option<(f64, f64)> {
+    match (value1, value2) {
+        (RBasicValue::Float(_), _) | (_, RBasicValue::Float(_)) => {
+            match (value1.as_float(), value2.as_float()) {
+                (Some(float1), Some(float2)) => Some((float1, float2)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// The result of arithmetic on whole numbers, which is an overflow error
+// rather than a panic when it is out of range, as with -2147483648 / -1
+pub fn whole_number(result: Option<i32>) -> Result<RBasicValue, error::RBasicError> {
+    result
+        .map(RBasicValue::Number)
+        .ok_or_else(|| error::RBasicError::new(error::OVERFLOW, "Overflow".to_string()))
+}
+
+// A float with no fraction as a whole number, or None when it is outside the
+// range of whole numbers, which a cast would clamp to the nearest end
+pub fn from_whole_float(number: f64) -> Option<i32> {
+    if number >= i32::MIN as f64 && number <= i32::MAX as f64 {
+        Some(number as i32)
+    } else {
+        None
+    }
+}
+
 // -----------------------------------------------
 // Implementations of unary operators
 impl Neg for RBasicValue {
-    type Output = Result<RBasicValue, String>;
+    type Output = Result<RBasicValue, error::RBasicError>;
 
     fn neg(self) -> Self::Output {
         match self {
-            RBasicValue::Number(ref number) => Ok(RBasicValue::Number(-*number)),
-            _ => Err("Cannot negate non-numeric values!".to_string()),
+            RBasicValue::Number(ref number) => whole_number(number.checked_neg()),
+            RBasicValue::Float(ref float) => Ok(RBasicValue::Float(-*float)),
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "Cannot negate non-numeric values!".to_string(),
+            )),
         }
     }
 }
 
 impl Not for RBasicValue {
-    type Output = Result<RBasicValue, String>;
+    type Output = Result<RBasicValue, error::RBasicError>;
 
     fn not(self) -> Self::Output {
         match self {
             RBasicValue::Bool(ref boolean) => Ok(RBasicValue::Bool(!boolean)),
-            _ => Err("Cannot apply unary not to non-Boolean values.".to_string()),
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "Cannot apply unary not to non-Boolean values.".to_string(),
+            )),
         }
     }
 }
@@ -40,152 +111,205 @@ impl Not for RBasicValue {
 // -----------------------------------------------
 // Implementations of binary operators
 impl Add for RBasicValue {
-    type Output = Result<RBasicValue, String>;
+    type Output = Result<RBasicValue, error::RBasicError>;
 
     fn add(self, other: RBasicValue) -> Self::Output {
+        if let Some((float1, float2)) = float_operands(&self, &other) {
+            return Ok(RBasicValue::Float(float1 + float2));
+        }
+
         match (self, other) {
             (RBasicValue::Number(number1), RBasicValue::Number(number2)) => {
-                Ok(RBasicValue::Number(number1 + number2))
-            }
-            (RBasicValue::String(string1), RBasicValue::String(string2)) => {
-                Ok(RBasicValue::String(format!("{}{}", string1, string2)))
+                whole_number(number1.checked_add(number2))
             }
+            (RBasicValue::String(string1), RBasicValue::String(string2)) => Ok(
+                RBasicValue::String(format!("{}{}", string1, string2).into()),
+            ),
             (RBasicValue::Number(number1), RBasicValue::String(string2)) => {
-                let number2 = i32::from_str(string2.as_str());
+                let number2 = i32::from_str(&string2);
 
                 if let Result::Ok(number2_value) = number2 {
-                    Ok(RBasicValue::Number(number1 + number2_value))
+                    whole_number(number1.checked_add(number2_value))
                 } else {
-                    Err(format!(
-                        "Cannot add integer {} and string {}",
-                        number1, string2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot add integer {} and string {}", number1, string2),
                     ))
                 }
             }
             (RBasicValue::String(string1), RBasicValue::Number(number2)) => {
-                let number1 = i32::from_str(string1.as_str());
+                let number1 = i32::from_str(&string1);
 
                 if let Result::Ok(number1_value) = number1 {
-                    Ok(RBasicValue::Number(number1_value + number2))
+                    whole_number(number1_value.checked_add(number2))
                 } else {
-                    Err(format!(
-                        "Cannot add string {} and integer {}",
-                        string1, number2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot add string {} and integer {}", string1, number2),
                     ))
                 }
             }
-            _ => Err("Can only add integers or concatenate strings.".to_string()),
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "Can only add integers or concatenate strings.".to_string(),
+            )),
         }
     }
 }
 
 impl Div for RBasicValue {
-    type Output = Result<RBasicValue, String>;
+    type Output = Result<RBasicValue, error::RBasicError>;
 
     fn div(self, other: RBasicValue) -> Self::Output {
+        if let Some((float1, float2)) = float_operands(&self, &other) {
+            if float2 == 0.0 {
+                return Err(error::RBasicError::new(
+                    error::DIVISION_BY_ZERO,
+                    "Division by zero".to_string(),
+                ));
+            }
+            return Ok(RBasicValue::Float(float1 / float2));
+        }
+
         match (self, other) {
             (RBasicValue::Number(number1), RBasicValue::Number(number2)) => {
-                Ok(RBasicValue::Number(number1 / number2))
+                divide(number1, number2)
             }
             (RBasicValue::Number(number1), RBasicValue::String(string2)) => {
-                let number2 = i32::from_str(string2.as_str());
+                let number2 = i32::from_str(&string2);
 
                 if let Result::Ok(number2_value) = number2 {
-                    Ok(RBasicValue::Number(number1 / number2_value))
+                    divide(number1, number2_value)
                 } else {
-                    Err(format!(
-                        "Cannot divide integer {} and string {}",
-                        number1, string2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot divide integer {} and string {}", number1, string2),
                     ))
                 }
             }
             (RBasicValue::String(string1), RBasicValue::Number(number2)) => {
-                let number1 = i32::from_str(string1.as_str());
+                let number1 = i32::from_str(&string1);
 
                 if let Result::Ok(number1_value) = number1 {
-                    Ok(RBasicValue::Number(number1_value / number2))
+                    divide(number1_value, number2)
                 } else {
-                    Err(format!(
-                        "Cannot divide string {} and integer {}",
-                        string1, number2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot divide string {} and integer {}", string1, number2),
                     ))
                 }
             }
-            _ => Err("Can only divide integers.".to_string()),
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "Can only divide integers.".to_string(),
+            )),
         }
     }
 }
 
+// Whole numbers divide to a whole number, dropping the fraction as integer
+// division in GWBASIC does, so 7 / 2 is 3 and -7 / 2 is -3
+fn divide(number1: i32, number2: i32) -> Result<RBasicValue, error::RBasicError> {
+    if number2 == 0 {
+        Err(error::RBasicError::new(
+            error::DIVISION_BY_ZERO,
+            "Division by zero".to_string(),
+        ))
+    } else {
+        whole_number(number1.checked_div(number2))
+    }
+}
+
 impl Mul for RBasicValue {
-    type Output = Result<RBasicValue, String>;
+    type Output = Result<RBasicValue, error::RBasicError>;
 
     fn mul(self, other: RBasicValue) -> Self::Output {
+        if let Some((float1, float2)) = float_operands(&self, &other) {
+            return Ok(RBasicValue::Float(float1 * float2));
+        }
+
         match (self, other) {
             (RBasicValue::Number(number1), RBasicValue::Number(number2)) => {
-                Ok(RBasicValue::Number(number1 * number2))
+                whole_number(number1.checked_mul(number2))
             }
             (RBasicValue::Number(number1), RBasicValue::String(string2)) => {
-                let number2 = i32::from_str(string2.as_str());
+                let number2 = i32::from_str(&string2);
 
                 if let Result::Ok(number2_value) = number2 {
-                    Ok(RBasicValue::Number(number1 * number2_value))
+                    whole_number(number1.checked_mul(number2_value))
                 } else {
-                    Err(format!(
-                        "Cannot multiply integer {} and string {}",
-                        number1, string2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot multiply integer {} and string {}", number1, string2),
                     ))
                 }
             }
             (RBasicValue::String(string1), RBasicValue::Number(number2)) => {
-                let number1 = i32::from_str(string1.as_str());
+                let number1 = i32::from_str(&string1);
 
                 if let Result::Ok(number1_value) = number1 {
-                    Ok(RBasicValue::Number(number1_value * number2))
+                    whole_number(number1_value.checked_mul(number2))
                 } else {
-                    Err(format!(
-                        "Cannot multiply string {} and integer {}",
-                        string1, number2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot multiply string {} and integer {}", string1, number2),
                     ))
                 }
             }
-            _ => Err("Can only multiply integers.".to_string()),
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "Can only multiply integers.".to_string(),
+            )),
         }
     }
 }
 
 impl Sub for RBasicValue {
-    type Output = Result<RBasicValue, String>;
+    type Output = Result<RBasicValue, error::RBasicError>;
 
     fn sub(self, other: RBasicValue) -> Self::Output {
+        if let Some((float1, float2)) = float_operands(&self, &other) {
+            return Ok(RBasicValue::Float(float1 - float2));
+        }
+
         match (self, other) {
             (RBasicValue::Number(number1), RBasicValue::Number(number2)) => {
-                Ok(RBasicValue::Number(number1 - number2))
+                whole_number(number1.checked_sub(number2))
             }
             (RBasicValue::Number(number1), RBasicValue::String(string2)) => {
-                let number2 = i32::from_str(string2.as_str());
+                let number2 = i32::from_str(&string2);
 
                 if let Result::Ok(number2_value) = number2 {
-                    Ok(RBasicValue::Number(number1 - number2_value))
+                    whole_number(number1.checked_sub(number2_value))
                 } else {
-                    Err(format!(
-                        "Cannot subtract integer {} from string {}",
-                        number1, string2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!(
+                            "Cannot subtract integer {} from string {}",
+                            number1, string2
+                        ),
                     ))
                 }
             }
             (RBasicValue::String(string1), RBasicValue::Number(number2)) => {
-                let number1 = i32::from_str(string1.as_str());
+                let number1 = i32::from_str(&string1);
 
                 if let Result::Ok(number1_value) = number1 {
-                    Ok(RBasicValue::Number(number1_value - number2))
+                    whole_number(number1_value.checked_sub(number2))
                 } else {
-                    Err(format!(
-                        "Cannot subtract string {} from integer {}",
-                        string1, number2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!(
+                            "Cannot subtract string {} from integer {}",
+                            string1, number2
+                        ),
                     ))
                 }
             }
-            _ => Err("Can only subtract integers.".to_string()),
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                "Can only subtract integers.".to_string(),
+            )),
         }
     }
 }
@@ -193,131 +317,231 @@ impl Sub for RBasicValue {
 // -----------------------------------------------
 // Implementations of binary comparison operators
 impl RBasicValue {
-    pub fn eq(&self, other: &RBasicValue) -> Result<bool, String> {
+    pub fn eq(&self, other: &RBasicValue) -> Result<bool, error::RBasicError> {
+        if let Some((float1, float2)) = float_operands(self, other) {
+            return Ok(float1 == float2);
+        }
+
         match (self, other) {
-            (&RBasicValue::Number(number1), &RBasicValue::Number(number2)) => {
-                Ok(number1 == number2)
-            }
-            (&RBasicValue::String(ref string1), &RBasicValue::String(ref string2)) => {
-                Ok(string1 == string2)
-            }
-            (&RBasicValue::Bool(bool1), &RBasicValue::Bool(bool2)) => Ok(bool1 == bool2),
-            (&RBasicValue::Number(number1), &RBasicValue::String(ref string2)) => {
-                let number2 = i32::from_str(string2.as_str());
+            (RBasicValue::Number(number1), RBasicValue::Number(number2)) => Ok(number1 == number2),
+            (RBasicValue::String(string1), RBasicValue::String(string2)) => Ok(string1 == string2),
+            (RBasicValue::Bool(bool1), RBasicValue::Bool(bool2)) => Ok(bool1 == bool2),
+            (RBasicValue::Number(number1), RBasicValue::String(string2)) => {
+                let number2 = i32::from_str(string2);
 
                 if let Result::Ok(number2_value) = number2 {
-                    Ok(number1 == number2_value)
+                    Ok(*number1 == number2_value)
                 } else {
-                    Err(format!(
-                        "Cannot compare integer {} from string {}",
-                        number1, string2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot compare integer {} from string {}", number1, string2),
                     ))
                 }
             }
-            (&RBasicValue::String(ref string1), &RBasicValue::Number(number2)) => {
-                let number1 = i32::from_str(string1.as_str());
+            (RBasicValue::String(string1), RBasicValue::Number(number2)) => {
+                let number1 = i32::from_str(string1);
 
                 if let Result::Ok(number1_value) = number1 {
-                    Ok(number1_value == number2)
+                    Ok(number1_value == *number2)
                 } else {
-                    Err(format!(
-                        "Cannot compare string {} and integer {}",
-                        string1, number2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot compare string {} and integer {}", string1, number2),
                     ))
                 }
             }
-            _ => Err(format!(
-                "Cannot compare values of different types {:?} and {:?}",
-                *self, *other
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                format!(
+                    "Cannot compare values of different types {:?} and {:?}",
+                    *self, *other
+                ),
             )),
         }
     }
 
-    pub fn neq(&self, other: &RBasicValue) -> Result<bool, String> {
+    pub fn neq(&self, other: &RBasicValue) -> Result<bool, error::RBasicError> {
         self.eq(other).map(|value| !value)
     }
 
-    pub fn lt(&self, other: &RBasicValue) -> Result<bool, String> {
+    pub fn lt(&self, other: &RBasicValue) -> Result<bool, error::RBasicError> {
+        if let Some((float1, float2)) = float_operands(self, other) {
+            return Ok(float1 < float2);
+        }
+
         match (self, other) {
-            (&RBasicValue::Number(number1), &RBasicValue::Number(number2)) => Ok(number1 < number2),
-            (&RBasicValue::String(ref string1), &RBasicValue::String(ref string2)) => {
-                Ok(string1 < string2)
-            }
-            (&RBasicValue::Bool(bool1), &RBasicValue::Bool(bool2)) => Ok(bool1 == bool2),
-            (&RBasicValue::Number(number1), &RBasicValue::String(ref string2)) => {
-                let number2 = i32::from_str(string2.as_str());
+            (RBasicValue::Number(number1), RBasicValue::Number(number2)) => Ok(number1 < number2),
+            (RBasicValue::String(string1), RBasicValue::String(string2)) => Ok(string1 < string2),
+            (RBasicValue::Bool(bool1), RBasicValue::Bool(bool2)) => Ok(bool1 == bool2),
+            (RBasicValue::Number(number1), RBasicValue::String(string2)) => {
+                let number2 = i32::from_str(string2);
 
                 if let Result::Ok(number2_value) = number2 {
-                    Ok(number1 < number2_value)
+                    Ok(*number1 < number2_value)
                 } else {
-                    Err(format!(
-                        "Cannot compare integer {} from string {}",
-                        number1, string2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot compare integer {} from string {}", number1, string2),
                     ))
                 }
             }
-            (&RBasicValue::String(ref string1), &RBasicValue::Number(number2)) => {
-                let number1 = i32::from_str(string1.as_str());
+            (RBasicValue::String(string1), RBasicValue::Number(number2)) => {
+                let number1 = i32::from_str(string1);
 
                 if let Result::Ok(number1_value) = number1 {
-                    Ok(number1_value < number2)
+                    Ok(number1_value < *number2)
                 } else {
-                    Err(format!(
-                        "Cannot compare string {} and integer {}",
-                        string1, number2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot compare string {} and integer {}", string1, number2),
                     ))
                 }
             }
-            _ => Err(format!(
-                "Cannot compare values of different types {:?} and {:?}",
-                *self, *other
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                format!(
+                    "Cannot compare values of different types {:?} and {:?}",
+                    *self, *other
+                ),
             )),
         }
     }
 
-    pub fn gt(&self, other: &RBasicValue) -> Result<bool, String> {
+    pub fn gt(&self, other: &RBasicValue) -> Result<bool, error::RBasicError> {
+        if let Some((float1, float2)) = float_operands(self, other) {
+            return Ok(float1 > float2);
+        }
+
         match (self, other) {
-            (&RBasicValue::Number(number1), &RBasicValue::Number(number2)) => Ok(number1 > number2),
-            (&RBasicValue::String(ref string1), &RBasicValue::String(ref string2)) => {
-                Ok(string1 > string2)
-            }
-            (&RBasicValue::Bool(bool1), &RBasicValue::Bool(bool2)) => Ok(bool1 && !bool2),
-            (&RBasicValue::Number(number1), &RBasicValue::String(ref string2)) => {
-                let number2 = i32::from_str(string2.as_str());
+            (RBasicValue::Number(number1), RBasicValue::Number(number2)) => Ok(number1 > number2),
+            (RBasicValue::String(string1), RBasicValue::String(string2)) => Ok(string1 > string2),
+            (RBasicValue::Bool(bool1), RBasicValue::Bool(bool2)) => Ok(*bool1 && !bool2),
+            (RBasicValue::Number(number1), RBasicValue::String(string2)) => {
+                let number2 = i32::from_str(string2);
 
                 if let Result::Ok(number2_value) = number2 {
-                    Ok(number1 > number2_value)
+                    Ok(*number1 > number2_value)
                 } else {
-                    Err(format!(
-                        "Cannot compare integer {} from string {}",
-                        number1, string2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot compare integer {} from string {}", number1, string2),
                     ))
                 }
             }
-            (&RBasicValue::String(ref string1), &RBasicValue::Number(number2)) => {
-                let number1 = i32::from_str(string1.as_str());
+            (RBasicValue::String(string1), RBasicValue::Number(number2)) => {
+                let number1 = i32::from_str(string1);
 
-                if let Result::Ok(number1_value) = number1 { 
-                    Ok(number1_value > number2)
+                if let Result::Ok(number1_value) = number1 {
+                    Ok(number1_value > *number2)
                 } else {
-                    Err(format!(
-                        "Cannot compare string {} and integer {}",
-                        string1, number2
+                    Err(error::RBasicError::new(
+                        error::TYPE_MISMATCH,
+                        format!("Cannot compare string {} and integer {}", string1, number2),
                     ))
                 }
             }
-            _ => Err(format!(
-                "Cannot compare values of different types {:?} and {:?}",
-                *self, *other
+            _ => Err(error::RBasicError::new(
+                error::TYPE_MISMATCH,
+                format!(
+                    "Cannot compare values of different types {:?} and {:?}",
+                    *self, *other
+                ),
             )),
         }
     }
 
-    pub fn lteq(&self, other: &RBasicValue) -> Result<bool, String> {
+    pub fn lteq(&self, other: &RBasicValue) -> Result<bool, error::RBasicError> {
         self.gt(other).map(|value| !value)
     }
 
-    pub fn gteq(&self, other: &RBasicValue) -> Result<bool, String> {
+    pub fn gteq(&self, other: &RBasicValue) -> Result<bool, error::RBasicError> {
         self.lt(other).map(|value| !value)
     }
 }
+
+// -----------------------------------------------
+// Conversions to and from Rust values, for functions registered by programs
+// embedding the interpreter. As elsewhere, strings holding numbers, such as
+// those read by INPUT, convert to numbers.
+impl From<i32> for RBasicValue {
+    fn from(number: i32) -> RBasicValue {
+        RBasicValue::Number(number)
+    }
+}
+
+impl From<f64> for RBasicValue {
+    fn from(float: f64) -> RBasicValue {
+        RBasicValue::Float(float)
+    }
+}
+
+impl From<String> for RBasicValue {
+    fn from(string: String) -> RBasicValue {
+        RBasicValue::String(string.into())
+    }
+}
+
+impl From<&str> for RBasicValue {
+    fn from(string: &str) -> RBasicValue {
+        RBasicValue::String(string.into())
+    }
+}
+
+impl From<bool> for RBasicValue {
+    fn from(boolean: bool) -> RBasicValue {
+        RBasicValue::Bool(boolean)
+    }
+}
+
+impl TryFrom<RBasicValue> for i32 {
+    type Error = error::RBasicError;
+
+    fn try_from(value: RBasicValue) -> Result<i32, Self::Error> {
+        match value {
+            RBasicValue::Number(number) => Ok(number),
+            RBasicValue::String(ref string) => {
+                i32::from_str(string).map_err(|_| conversion_error(&value, "an integer"))
+            }
+            _ => Err(conversion_error(&value, "an integer")),
+        }
+    }
+}
+
+impl TryFrom<RBasicValue> for f64 {
+    type Error = error::RBasicError;
+
+    fn try_from(value: RBasicValue) -> Result<f64, Self::Error> {
+        value
+            .as_float()
+            .ok_or_else(|| conversion_error(&value, "a number"))
+    }
+}
+
+impl TryFrom<RBasicValue> for String {
+    type Error = error::RBasicError;
+
+    fn try_from(value: RBasicValue) -> Result<String, Self::Error> {
+        match value {
+            RBasicValue::String(string) => Ok(string.to_string()),
+            _ => Err(conversion_error(&value, "a string")),
+        }
+    }
+}
+
+impl TryFrom<RBasicValue> for bool {
+    type Error = error::RBasicError;
+
+    fn try_from(value: RBasicValue) -> Result<bool, Self::Error> {
+        match value {
+            RBasicValue::Bool(boolean) => Ok(boolean),
+            _ => Err(conversion_error(&value, "a Boolean")),
+        }
+    }
+}
+
+fn conversion_error(value: &RBasicValue, expected: &str) -> error::RBasicError {
+    error::RBasicError::new(
+        error::TYPE_MISMATCH,
+        format!("Expected {} but found {:?}", expected, value),
+    )
+}
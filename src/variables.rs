@@ -0,0 +1,129 @@
+use crate::error;
+use crate::value;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// The slots of ERR and ERL, which every program has, so that they can be told
+// apart from other variables without comparing names
+pub const ERR: usize = 0;
+pub const ERL: usize = 1;
+
+// The slot of a variable the program hasn't been loaded to resolve yet. It is
+// never a real slot, so using one that was missed is an internal error rather
+// than a read of ERR.
+pub const UNRESOLVED: usize = usize::MAX;
+
+// The variables of a program, each kept in a numbered slot. Slots are given to
+// every name in the program when it is loaded, so that running it doesn't
+// need to look names up, and to any other name when it is first used.
+#[derive(Debug, Clone)]
+pub struct Variables {
+    names: Vec<Rc<str>>,
+    slots: HashMap<Rc<str>, usize>,
+    // The value in each slot, or None if the variable hasn't been set
+    values: Vec<Option<value::RBasicValue>>,
+}
+
+impl Variables {
+    pub fn new() -> Variables {
+        let mut variables = Variables {
+            names: Vec::new(),
+            slots: HashMap::new(),
+            values: Vec::new(),
+        };
+        variables.slot("ERR");
+        variables.slot("ERL");
+        variables
+    }
+
+    // The slot for a name, giving it one if it doesn't have one yet
+    pub fn slot(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.slots.get(name) {
+            return *slot;
+        }
+
+        let name: Rc<str> = name.into();
+        self.names.push(name.clone());
+        self.values.push(None);
+        self.slots.insert(name, self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    pub fn name(&self, slot: usize) -> Option<&str> {
+        self.names.get(slot).map(|name| &**name)
+    }
+
+    // The value in a slot, or None if the variable hasn't been set
+    pub fn get(&self, slot: usize) -> Result<Option<&value::RBasicValue>, error::RBasicError> {
+        match self.values.get(slot) {
+            Some(value) => Ok(value.as_ref()),
+            None => Err(unresolved(slot)),
+        }
+    }
+
+    pub fn set(
+        &mut self,
+        slot: usize,
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        match self.values.get_mut(slot) {
+            Some(current) => {
+                *current = Some(value);
+                Ok(())
+            }
+            None => Err(unresolved(slot)),
+        }
+    }
+
+    // The value of the variable with a name, or None if it hasn't been set
+    pub fn get_named(&self, name: &str) -> Option<&value::RBasicValue> {
+        self.find(name)
+            .and_then(|slot| self.values.get(slot))
+            .and_then(Option::as_ref)
+    }
+
+    // Sets the variable with a name, giving it a slot if it doesn't have one
+    pub fn set_named(&mut self, name: &str, value: value::RBasicValue) {
+        let slot = self.slot(name);
+        self.values[slot] = Some(value);
+    }
+
+    // The same slots with none of the variables set, for running again
+    pub fn cleared(&self) -> Variables {
+        Variables {
+            names: self.names.clone(),
+            slots: self.slots.clone(),
+            values: vec![None; self.values.len()],
+        }
+    }
+
+    // The variables that have been set, by name, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &value::RBasicValue)> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| value.as_ref().map(|value| (&**name, value)))
+    }
+}
+
+impl Default for Variables {
+    fn default() -> Variables {
+        Variables::new()
+    }
+}
+
+// The error for a slot these variables don't have, as with a token whose
+// slot wasn't resolved because its program was never loaded
+fn unresolved(slot: usize) -> error::RBasicError {
+    let message = if slot == UNRESOLVED {
+        "Internal error: variable used before its program was loaded".to_string()
+    } else {
+        format!("Internal error: no variable in slot {}", slot)
+    };
+    error::RBasicError::new(error::INTERNAL_ERROR, message)
+}
@@ -0,0 +1,808 @@
+use crate::array;
+use crate::compiler;
+use crate::error;
+use crate::evaluator;
+use crate::files;
+use crate::host;
+use crate::limits;
+use crate::random;
+use crate::value;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+// An active FOR loop, as the VM keeps it
+#[derive(Debug, Clone)]
+struct ActiveLoop {
+    variable: usize,
+    end: value::RBasicValue,
+    step: value::RBasicValue,
+    // Where NEXT continues from when it loops again
+    body: usize,
+}
+
+// Where PRINT and INPUT go instead of the terminal once script_console is
+// called: the lines INPUT is still to be given, and what has been written
+#[derive(Debug, Default)]
+struct ScriptedConsole {
+    input: VecDeque<String>,
+    output: String,
+}
+
+// Runs programs compiled by compiler::compile, which behave just as they do
+// when interpreted but without the interpreter parsing each statement every
+// time it runs and looking up variables by name
+pub struct Vm {
+    program: compiler::Program,
+    // The next instruction
+    pc: usize,
+    stack: Vec<value::RBasicValue>,
+    // Subscripts of the array element the current statement sets
+    subscripts: Vec<i32>,
+    variables: Vec<Option<value::RBasicValue>>,
+    arrays: Vec<Option<array::BasicArray>>,
+    // The entry of bodies each function has been defined with
+    functions: Vec<Option<usize>>,
+    // Where each user function being called returns to, and where its
+    // arguments start on the stack
+    frames: Vec<(usize, usize)>,
+    gosub_stack: Vec<usize>,
+    for_stack: Vec<ActiveLoop>,
+    // The current entry of statements, for reporting errors
+    statement: usize,
+    data_index: usize,
+    // Where ON ERROR GOTO sends errors, and the entry of statements with
+    // the error being handled along with the error
+    error_handler: Option<usize>,
+    trapped_error: Option<(usize, error::RBasicError)>,
+    // Values of ERR and ERL
+    error_code: i32,
+    error_line: u32,
+    limits: limits::Limits,
+    // What the program has used, to check against the limits
+    statements: u64,
+    started: Instant,
+    string_bytes: usize,
+    array_elements: usize,
+    rng: random::Rng,
+    seed: u64,
+    // Always empty, since OPEN can't be compiled, but EOF and LOF look in it
+    files: HashMap<i32, files::BasicFile>,
+    can_continue: bool,
+    console: Option<ScriptedConsole>,
+}
+
+impl Vm {
+    pub fn new(program: compiler::Program) -> Vm {
+        Vm {
+            pc: 0,
+            stack: Vec::new(),
+            subscripts: Vec::new(),
+            variables: vec![None; program.variables.len()],
+            arrays: vec![None; program.arrays.len()],
+            functions: vec![None; program.functions.len()],
+            frames: Vec::new(),
+            gosub_stack: Vec::new(),
+            for_stack: Vec::new(),
+            statement: 0,
+            data_index: 0,
+            error_handler: None,
+            trapped_error: None,
+            error_code: 0,
+            error_line: 0,
+            limits: limits::Limits::default(),
+            statements: 0,
+            started: Instant::now(),
+            string_bytes: 0,
+            array_elements: 0,
+            rng: random::Rng::new(random::DEFAULT_SEED),
+            seed: random::DEFAULT_SEED,
+            files: HashMap::new(),
+            can_continue: false,
+            console: None,
+            program,
+        }
+    }
+
+    // Runs the program from the beginning with no variables set
+    pub fn run(&mut self) -> Result<evaluator::ExecutionStatus, error::RBasicError> {
+        self.pc = 0;
+        self.stack.clear();
+        self.subscripts.clear();
+        self.variables = vec![None; self.program.variables.len()];
+        self.arrays = vec![None; self.program.arrays.len()];
+        self.functions = vec![None; self.program.functions.len()];
+        self.frames.clear();
+        self.gosub_stack.clear();
+        self.for_stack.clear();
+        self.data_index = 0;
+        self.error_handler = None;
+        self.trapped_error = None;
+        self.error_code = 0;
+        self.error_line = 0;
+        self.statements = 0;
+        self.started = Instant::now();
+        self.string_bytes = 0;
+        self.array_elements = 0;
+        self.rng = random::Rng::new(self.seed);
+        self.execute()
+    }
+
+    // Resumes the program from the line after the STOP that halted it
+    pub fn cont(&mut self) -> Result<evaluator::ExecutionStatus, error::RBasicError> {
+        if !self.can_continue {
+            return Err(error::RBasicError::new(
+                error::CANT_CONTINUE,
+                "Can't continue".to_string(),
+            ));
+        }
+        self.execute()
+    }
+
+    // Fixes the seed the random number generator starts from when the program
+    // runs, as Interpreter::set_seed does
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Sets the limits the program is held to, which are checked as the
+    // interpreter checks them
+    pub fn set_limits(&mut self, limits: limits::Limits) {
+        self.limits = limits;
+    }
+
+    // Gives INPUT the lines of input in turn instead of reading the terminal,
+    // keeping what PRINT and the prompts of INPUT write to be taken with
+    // output. Needing more lines than were given is an error.
+    pub fn script_console(&mut self, input: &str) {
+        self.console = Some(ScriptedConsole {
+            input: input.lines().map(|line| line.trim().to_string()).collect(),
+            output: String::new(),
+        });
+    }
+
+    // What has been written since script_console was called
+    pub fn output(&self) -> &str {
+        self.console
+            .as_ref()
+            .map_or("", |console| console.output.as_str())
+    }
+
+    pub fn variable(&self, name: &str) -> Option<value::RBasicValue> {
+        let slot = self.program.variables.iter().position(|n| n == name)?;
+        self.variables[slot].clone()
+    }
+
+    // Every variable that has been set, by name
+    pub fn variables(&self) -> BTreeMap<&str, &value::RBasicValue> {
+        self.program
+            .variables
+            .iter()
+            .zip(&self.variables)
+            .filter_map(|(name, value)| value.as_ref().map(|value| (name.as_str(), value)))
+            .collect()
+    }
+
+    pub fn array(&self, name: &str) -> Option<&array::BasicArray> {
+        let slot = self.program.arrays.iter().position(|n| n == name)?;
+        self.arrays[slot].as_ref()
+    }
+
+    fn execute(&mut self) -> Result<evaluator::ExecutionStatus, error::RBasicError> {
+        self.can_continue = false;
+
+        loop {
+            match self.execute_until_error() {
+                Err(e) => self.trap_error(e)?,
+                result => return result,
+            }
+        }
+    }
+
+    // Jumps to the ON ERROR GOTO handler as the interpreter does, leaving any
+    // user functions and expressions the error came from
+    fn trap_error(&mut self, e: error::RBasicError) -> Result<(), error::RBasicError> {
+        // Running out of scripted input ends the program, just as it does for
+        // a host with no more input to give
+        match self.error_handler {
+            Some(handler)
+                if self.trapped_error.is_none()
+                    && !error::is_limit_error(e.code)
+                    && e.code != error::INPUT_PAST_END =>
+            {
+                self.error_code = e.code;
+                self.error_line = self.program.statements[self.statement].line_number.0;
+                self.trapped_error = Some((self.statement, e));
+                self.pc = handler;
+                self.stack.clear();
+                self.subscripts.clear();
+                self.frames.clear();
+                Ok(())
+            }
+            _ => Err(e),
+        }
+    }
+
+    fn execute_until_error(&mut self) -> Result<evaluator::ExecutionStatus, error::RBasicError> {
+        loop {
+            let op = self.program.code[self.pc];
+            self.pc += 1;
+
+            match op {
+                compiler::Op::Statement(index) => {
+                    self.statement = index;
+                    self.statements += 1;
+                    self.limits.check_line(
+                        &self.program.statements[index].line_number,
+                        self.statements,
+                        self.started,
+                    )?;
+                }
+                compiler::Op::Constant(index) => {
+                    self.stack.push(self.program.constants[index].clone())
+                }
+                compiler::Op::Load(slot) => match self.variables[slot] {
+                    Some(ref value) => self.stack.push(value.clone()),
+                    None => {
+                        return Err(self.wrap(error::RBasicError::new(
+                            error::UNDEFINED_VARIABLE,
+                            format!(
+                                "Invalid variable reference {} in expression",
+                                self.program.variables[slot]
+                            ),
+                        )))
+                    }
+                },
+                compiler::Op::LoadParameter(index) => {
+                    let base = self
+                        .frames
+                        .last()
+                        .expect("parameters are used in functions")
+                        .1;
+                    self.stack.push(self.stack[base + index].clone());
+                }
+                compiler::Op::Negate => {
+                    let value = self.pop();
+                    self.push(-value)?;
+                }
+                compiler::Op::Not => {
+                    let value = self.pop();
+                    self.push(!value)?;
+                }
+                compiler::Op::Add
+                | compiler::Op::Subtract
+                | compiler::Op::Multiply
+                | compiler::Op::Divide => {
+                    let operand2 = self.pop();
+                    let operand1 = self.pop();
+                    self.push(match op {
+                        compiler::Op::Add => operand1 + operand2,
+                        compiler::Op::Subtract => operand1 - operand2,
+                        compiler::Op::Multiply => operand1 * operand2,
+                        _ => operand1 / operand2,
+                    })?;
+                }
+                compiler::Op::Equal
+                | compiler::Op::NotEqual
+                | compiler::Op::LessThan
+                | compiler::Op::GreaterThan
+                | compiler::Op::LessThanEqual
+                | compiler::Op::GreaterThanEqual => {
+                    let operand2 = &self.pop();
+                    let operand1 = &self.pop();
+                    let result = match op {
+                        compiler::Op::Equal => operand1.eq(operand2),
+                        compiler::Op::NotEqual => operand1.neq(operand2),
+                        compiler::Op::LessThan => operand1.lt(operand2),
+                        compiler::Op::GreaterThan => operand1.gt(operand2),
+                        compiler::Op::LessThanEqual => operand1.lteq(operand2),
+                        _ => operand1.gteq(operand2),
+                    };
+                    self.push(result.map(value::RBasicValue::Bool))?;
+                }
+                compiler::Op::Call(index, arg_count) => self.call(index, arg_count)?,
+                compiler::Op::ReturnValue => {
+                    let result = self.pop();
+                    let (pc, base) = self.frames.pop().expect("functions return once");
+                    self.stack.truncate(base);
+                    self.stack.push(result);
+                    self.pc = pc;
+                }
+                compiler::Op::Pop => {
+                    self.pop();
+                }
+                compiler::Op::Store(slot) => {
+                    let value = self.pop();
+                    self.set_variable(slot, value)?;
+                }
+                compiler::Op::Subscripts(count) => {
+                    let start = self.stack.len() - count;
+                    let subscripts =
+                        evaluator::subscripts(&self.stack[start..]).map_err(|e| self.wrap(e))?;
+                    self.stack.truncate(start);
+                    self.subscripts.extend(subscripts);
+                }
+                compiler::Op::StoreElement(slot, count) => {
+                    let value = self.pop();
+                    self.set_element(slot, count, value)?;
+                }
+                compiler::Op::Dim(slot, count) => self.dim(slot, count)?,
+                compiler::Op::ReadVariable(slot) => {
+                    let item = self.read()?;
+                    let item = evaluator::data_item(&item, &self.program.variables[slot])
+                        .map_err(|e| self.wrap(e))?;
+                    self.set_variable(slot, item)?;
+                }
+                compiler::Op::ReadElement(slot, count) => {
+                    let item = self.read()?;
+                    let item = evaluator::data_item(&item, &self.program.arrays[slot])
+                        .map_err(|e| self.wrap(e))?;
+                    self.set_element(slot, count, item)?;
+                }
+                compiler::Op::Restore(data_index) => self.data_index = data_index,
+                compiler::Op::Print => {
+                    let value = self.pop();
+                    match self.console {
+                        Some(ref mut console) => console.output.push_str(&format!("{}\n", value)),
+                        None => println!("{}", value),
+                    }
+                }
+                compiler::Op::Input(slot, prompt) => {
+                    let prompt = &self.program.prompts[prompt];
+                    let input = match self.console {
+                        Some(ref mut console) => {
+                            console.output.push_str(prompt);
+                            console.input.pop_front().ok_or_else(host::input_past_end)?
+                        }
+                        None => evaluator::read_console_line(prompt).map_err(|e| {
+                            self.error_at(
+                                error::DEVICE_IO_ERROR,
+                                &format!("INPUT failed to read a line: {}", e),
+                            )
+                        })?,
+                    };
+                    self.set_variable(slot, value::RBasicValue::from(input))?;
+                }
+                compiler::Op::Jump(target) => self.pc = target,
+                compiler::Op::Branch {
+                    when,
+                    target,
+                    error,
+                } => match self.pop() {
+                    value::RBasicValue::Bool(value) => {
+                        if value == when {
+                            self.pc = target;
+                        }
+                    }
+                    _ => return Err(self.program.errors[error].clone()),
+                },
+                compiler::Op::Gosub(target) => {
+                    self.limits
+                        .check_call_depth(self.gosub_stack.len())
+                        .map_err(|e| self.error_at(e.code, &e.message))?;
+                    self.gosub_stack.push(self.pc);
+                    self.pc = target;
+                }
+                compiler::Op::Return => match self.gosub_stack.pop() {
+                    Some(pc) => self.pc = pc,
+                    None => {
+                        return Err(
+                            self.error_at(error::RETURN_WITHOUT_GOSUB, "RETURN without GOSUB")
+                        )
+                    }
+                },
+                compiler::Op::On(index) => self.on(index)?,
+                compiler::Op::For { variable, exit } => self.for_loop(variable, exit)?,
+                compiler::Op::Next(index) => self.next(index)?,
+                compiler::Op::Define(slot, body) => self.functions[slot] = Some(body),
+                compiler::Op::Randomize => match self.pop().as_float() {
+                    Some(seed) => self.rng.seed(seed.to_bits()),
+                    None => {
+                        return Err(
+                            self.error_at(error::TYPE_MISMATCH, "RANDOMIZE seed must be a number")
+                        )
+                    }
+                },
+                compiler::Op::SeedFromTime => self.rng.seed_from_time(),
+                compiler::Op::Error => match self.pop() {
+                    value::RBasicValue::Number(code) if (1..=255).contains(&code) => {
+                        return Err(self.error_at(code, error::message_for_code(code)))
+                    }
+                    _ => {
+                        return Err(self.error_at(
+                            error::ILLEGAL_FUNCTION_CALL,
+                            "ERROR must be given a code from 1 to 255",
+                        ))
+                    }
+                },
+                compiler::Op::OnError(None) => {
+                    self.error_handler = None;
+
+                    // Turning off trapping within the handler ends the
+                    // program with the error being handled
+                    if let Some((_, e)) = self.trapped_error.take() {
+                        return Err(e);
+                    }
+                }
+                compiler::Op::OnError(handler) => self.error_handler = handler,
+                compiler::Op::Resume(resume) => self.resume(resume)?,
+                compiler::Op::ErrorCode => {
+                    self.stack.push(value::RBasicValue::Number(self.error_code))
+                }
+                compiler::Op::ErrorLine => self
+                    .stack
+                    .push(value::RBasicValue::Number(self.error_line as i32)),
+                compiler::Op::Raise(error) => return Err(self.program.errors[error].clone()),
+                compiler::Op::Fail(error) => {
+                    return Err(self.wrap(self.program.errors[error].clone()))
+                }
+                compiler::Op::Stop => {
+                    self.can_continue = true;
+                    let statement = &self.program.statements[self.statement];
+                    return Ok(evaluator::ExecutionStatus::Stopped(
+                        statement.line_number.clone(),
+                    ));
+                }
+                compiler::Op::End => {
+                    self.trapped_error = None;
+                    return Ok(evaluator::ExecutionStatus::Ended);
+                }
+                compiler::Op::EndOfProgram => {
+                    // The error stays trapped, so that this one isn't
+                    if let Some((statement, _)) = self.trapped_error {
+                        return Err(error::RBasicError::new(
+                            error::NO_RESUME,
+                            format!(
+                                "At {:?}, error handler ended without RESUME",
+                                self.program.statements[statement].line_number
+                            ),
+                        ));
+                    }
+                    return Ok(evaluator::ExecutionStatus::Ended);
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> value::RBasicValue {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn push(
+        &mut self,
+        result: Result<value::RBasicValue, error::RBasicError>,
+    ) -> Result<(), error::RBasicError> {
+        let value = result.map_err(|e| self.wrap(e))?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // Reports an error as coming from within the current statement
+    fn wrap(&self, e: error::RBasicError) -> error::RBasicError {
+        let statement = &self.program.statements[self.statement];
+        error::RBasicError::new(e.code, format!("{}{}", statement.prefix, e))
+    }
+
+    // An error in the current statement itself
+    fn error_at(&self, code: i32, message: &str) -> error::RBasicError {
+        let statement = &self.program.statements[self.statement];
+        error::RBasicError::new(code, format!("{}{}", statement.at, message))
+    }
+
+    // As in GWBASIC, an array used without DIM is created with subscripts up
+    // to 10 in each dimension
+    fn array_in_slot(
+        &mut self,
+        slot: usize,
+        dimensions: usize,
+    ) -> Result<&mut array::BasicArray, error::RBasicError> {
+        if self.arrays[slot].is_none() {
+            self.dim_array(slot, vec![array::DEFAULT_BOUND; dimensions])?;
+        }
+        Ok(self.arrays[slot].as_mut().unwrap())
+    }
+
+    fn dim_array(&mut self, slot: usize, bounds: Vec<usize>) -> Result<(), error::RBasicError> {
+        let name = &self.program.arrays[slot];
+        if self.arrays[slot].is_some() {
+            return Err(error::RBasicError::new(
+                error::DUPLICATE_DEFINITION,
+                format!("Array {} is already dimensioned", name),
+            ));
+        }
+
+        let array_elements = self
+            .array_elements
+            .saturating_add(array::element_count(&bounds));
+        self.limits.check_array_elements(array_elements)?;
+
+        self.arrays[slot] = Some(array::BasicArray::new(name, bounds));
+        self.array_elements = array_elements;
+        Ok(())
+    }
+
+    // Errors setting a variable are reported against the statement itself
+    // rather than its expression, as when interpreted
+    fn set_variable(
+        &mut self,
+        slot: usize,
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        let old_length = evaluator::string_length(self.variables[slot].as_ref());
+        self.use_string_bytes(old_length, &value)
+            .map_err(|e| self.error_at(e.code, &e.message))?;
+        self.variables[slot] = Some(value);
+        Ok(())
+    }
+
+    // Keeps count of the bytes used by strings as a value is replaced
+    fn use_string_bytes(
+        &mut self,
+        old_length: usize,
+        new_value: &value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        let string_bytes =
+            self.string_bytes - old_length + evaluator::string_length(Some(new_value));
+        self.limits.check_string_bytes(string_bytes)?;
+        self.string_bytes = string_bytes;
+        Ok(())
+    }
+
+    fn set_element(
+        &mut self,
+        slot: usize,
+        count: usize,
+        value: value::RBasicValue,
+    ) -> Result<(), error::RBasicError> {
+        let subscripts = self.subscripts.split_off(self.subscripts.len() - count);
+        let old_length = self
+            .array_in_slot(slot, count)
+            .and_then(|array| array.get(&subscripts))
+            .map(|old_value| evaluator::string_length(Some(old_value)))
+            .map_err(|e| self.wrap(e))?;
+        self.use_string_bytes(old_length, &value)
+            .map_err(|e| self.wrap(e))?;
+        self.array_in_slot(slot, count)
+            .and_then(|array| array.set(&subscripts, value))
+            .map_err(|e| self.wrap(e))?;
+        Ok(())
+    }
+
+    fn dim(&mut self, slot: usize, count: usize) -> Result<(), error::RBasicError> {
+        let name = &self.program.arrays[slot];
+        let subscripts = self.subscripts.split_off(self.subscripts.len() - count);
+        let mut bounds: Vec<usize> = Vec::new();
+
+        for bound in subscripts {
+            if bound < 0 {
+                return Err(self.wrap(error::RBasicError::new(
+                    error::ILLEGAL_FUNCTION_CALL,
+                    format!("illegal array size {} for {}", bound, name),
+                )));
+            }
+            bounds.push(bound as usize);
+        }
+
+        self.dim_array(slot, bounds).map_err(|e| self.wrap(e))
+    }
+
+    // Takes the next DATA item, as it was written
+    fn read(&mut self) -> Result<value::RBasicValue, error::RBasicError> {
+        let item = match self.program.data.get(self.data_index) {
+            Some(item) => item.clone(),
+            None => {
+                return Err(self.wrap(error::RBasicError::new(
+                    error::OUT_OF_DATA,
+                    "Out of DATA".to_string(),
+                )))
+            }
+        };
+        self.data_index += 1;
+        Ok(item)
+    }
+
+    // Calls a function, or gets an array element, in the same order of
+    // precedence as the interpreter
+    fn call(&mut self, index: usize, arg_count: usize) -> Result<(), error::RBasicError> {
+        let args = self.stack.split_off(self.stack.len() - arg_count);
+        let callee = &self.program.callees[index];
+        let (slot, function) = (callee.array, callee.function);
+
+        if let Some(ref array) = self.arrays[slot] {
+            let subscripts = evaluator::subscripts(&args).map_err(|e| self.wrap(e))?;
+            let value = array.get(&subscripts).map_err(|e| self.wrap(e))?.clone();
+            self.stack.push(value);
+            return Ok(());
+        }
+
+        if let Some(result) =
+            evaluator::call_builtin_function(&callee.name, &args, &mut self.rng, &mut self.files)
+        {
+            return self.push(result);
+        }
+
+        let function = match function {
+            Some(function) => function,
+            None => {
+                let subscripts = evaluator::subscripts(&args).map_err(|e| self.wrap(e))?;
+                let result = self
+                    .array_in_slot(slot, subscripts.len())
+                    .and_then(|array| array.get(&subscripts).cloned());
+                return self.push(result);
+            }
+        };
+
+        let body = match self.functions[function] {
+            Some(body) => &self.program.bodies[body],
+            None => {
+                return Err(self.wrap(error::RBasicError::new(
+                    error::UNDEFINED_USER_FUNCTION,
+                    format!("Undefined user function {}", callee.name),
+                )))
+            }
+        };
+
+        if body.parameters != args.len() {
+            return Err(self.wrap(error::RBasicError::new(
+                error::ILLEGAL_FUNCTION_CALL,
+                format!(
+                    "Function {} expects {} argument(s) but was given {}",
+                    callee.name,
+                    body.parameters,
+                    args.len()
+                ),
+            )));
+        }
+
+        self.limits
+            .check_call_depth(self.frames.len())
+            .map_err(|e| self.wrap(e))?;
+
+        // As deep as the interpreter allows, failing the same way
+        if self.frames.len() >= evaluator::MAX_FUNCTION_DEPTH {
+            return Err(self.wrap(error::RBasicError::new(
+                error::OUT_OF_MEMORY,
+                format!(
+                    "Out of memory calling {}: user functions nested more than {} deep",
+                    callee.name,
+                    evaluator::MAX_FUNCTION_DEPTH
+                ),
+            )));
+        }
+
+        self.frames.push((self.pc, self.stack.len()));
+        self.pc = body.start;
+        self.stack.extend(args);
+        Ok(())
+    }
+
+    fn resume(&mut self, resume: compiler::Resume) -> Result<(), error::RBasicError> {
+        let statement = match self.trapped_error.take() {
+            Some((statement, _)) => statement,
+            None => return Err(self.error_at(error::RESUME_WITHOUT_ERROR, "RESUME without error")),
+        };
+
+        let line = self.program.statements[statement].line;
+        self.pc = match resume {
+            compiler::Resume::Error => self.program.line_starts[line],
+            compiler::Resume::Next => self.program.line_starts[line + 1],
+            compiler::Resume::Line(target) => target,
+            compiler::Resume::Invalid(error) => return Err(self.program.errors[error].clone()),
+        };
+        Ok(())
+    }
+
+    fn on(&mut self, index: usize) -> Result<(), error::RBasicError> {
+        let selector = match self.pop() {
+            value::RBasicValue::Number(number) => number,
+            _ => return Err(self.error_at(error::TYPE_MISMATCH, "ON expression must be a number")),
+        };
+
+        // As in Applesoft, values past the end of the target list fall
+        // through to the next line
+        if !(0..=255).contains(&selector) {
+            return Err(self.error_at(
+                error::ILLEGAL_FUNCTION_CALL,
+                &format!("illegal quantity {} for ON", selector),
+            ));
+        }
+
+        let on_targets = &self.program.on_targets[index];
+        if selector >= 1 && selector as usize <= on_targets.targets.len() {
+            if on_targets.is_gosub {
+                self.limits
+                    .check_call_depth(self.gosub_stack.len())
+                    .map_err(|e| self.error_at(e.code, &e.message))?;
+                self.gosub_stack.push(self.pc);
+            }
+            self.pc = on_targets.targets[selector as usize - 1];
+        }
+        Ok(())
+    }
+
+    fn for_loop(&mut self, variable: usize, exit: usize) -> Result<(), error::RBasicError> {
+        let step = self.pop();
+        let end = self.pop();
+        let start = self.pop();
+
+        for value in &[&start, &end, &step] {
+            if !matches!(
+                value,
+                value::RBasicValue::Number(_) | value::RBasicValue::Float(_)
+            ) {
+                return Err(self.wrap(error::RBasicError::new(
+                    error::TYPE_MISMATCH,
+                    "FOR values must be numbers".to_string(),
+                )));
+            }
+        }
+
+        self.set_variable(variable, start.clone())?;
+
+        // A FOR using the variable of an active loop replaces that loop and
+        // any inside of it
+        if let Some(index) = self
+            .for_stack
+            .iter()
+            .position(|active| active.variable == variable)
+        {
+            self.for_stack.truncate(index);
+        }
+
+        if evaluator::loop_finished(&start, &end, &step).map_err(|e| self.wrap(e))? {
+            self.pc = exit;
+        } else {
+            self.for_stack.push(ActiveLoop {
+                variable,
+                end,
+                step,
+                body: self.pc,
+            });
+        }
+        Ok(())
+    }
+
+    fn next(&mut self, index: usize) -> Result<(), error::RBasicError> {
+        for position in 0..self.program.next_variables[index].len() {
+            // NEXT without a variable applies to the innermost loop
+            let loop_index = match self.program.next_variables[index][position] {
+                Some(variable) => self
+                    .for_stack
+                    .iter()
+                    .rposition(|active| active.variable == variable),
+                None => self.for_stack.len().checked_sub(1),
+            };
+            let loop_index = match loop_index {
+                Some(loop_index) => loop_index,
+                None => return Err(self.error_at(error::NEXT_WITHOUT_FOR, "NEXT without FOR")),
+            };
+
+            // Loops inside this one are left unfinished
+            self.for_stack.truncate(loop_index + 1);
+            let active = &self.for_stack[loop_index];
+            let (variable, body) = (active.variable, active.body);
+            let value = self.variables[variable]
+                .clone()
+                .unwrap_or(value::RBasicValue::Number(0));
+            let value = (value + active.step.clone())
+                .and_then(|value| {
+                    evaluator::loop_finished(&value, &active.end, &active.step)
+                        .map(|finished| (value, finished))
+                })
+                .map_err(|e| self.wrap(e))?;
+            self.set_variable(variable, value.0)?;
+
+            if value.1 {
+                self.for_stack.pop();
+            } else {
+                self.pc = body;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
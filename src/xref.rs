@@ -0,0 +1,269 @@
+use crate::checker;
+use crate::evaluator;
+use crate::formatter;
+use crate::lexer;
+use crate::token;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+
+// The lines that write and read a variable or array
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VariableUses {
+    pub written: BTreeSet<lexer::LineNumber>,
+    pub read: BTreeSet<lexer::LineNumber>,
+}
+
+// Who uses each variable and who branches to each line of a program
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CrossReference {
+    pub variables: BTreeMap<String, VariableUses>,
+    // The statements referring to each line, such as GOTO or RESTORE, and the
+    // lines they are on
+    pub lines: BTreeMap<lexer::LineNumber, Vec<(&'static str, lexer::LineNumber)>>,
+}
+
+// Lists the variables a program uses and the line numbers it refers to
+pub fn cross_reference(code_lines: Vec<lexer::LineOfCode>) -> CrossReference {
+    let program = in_line_order(code_lines);
+    let mut cross_reference = CrossReference::default();
+
+    for line in &program {
+        let (written, read) = variable_uses(&line.tokens);
+        for name in written {
+            let uses = cross_reference
+                .variables
+                .entry(name.to_string())
+                .or_default();
+            uses.written.insert(line.line_number.clone());
+        }
+        for name in read {
+            let uses = cross_reference
+                .variables
+                .entry(name.to_string())
+                .or_default();
+            uses.read.insert(line.line_number.clone());
+        }
+
+        for reference in evaluator::line_references(&line.tokens) {
+            cross_reference
+                .lines
+                .entry(reference.target)
+                .or_default()
+                .push((reference.statement, line.line_number.clone()));
+        }
+    }
+
+    cross_reference
+}
+
+// Shown as one line for each variable and then one for each line number
+// referred to, as in:
+//   A: written in 10, 60; read in 20
+//   40: GOTO from 20, IF from 70
+impl fmt::Display for CrossReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let numbers = |lines: &BTreeSet<lexer::LineNumber>| {
+            lines
+                .iter()
+                .map(|line| line.0.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        writeln!(f, "Variables:")?;
+        for (name, uses) in &self.variables {
+            let mut described = Vec::new();
+            if !uses.written.is_empty() {
+                described.push(format!("written in {}", numbers(&uses.written)));
+            }
+            if !uses.read.is_empty() {
+                described.push(format!("read in {}", numbers(&uses.read)));
+            }
+            writeln!(f, "  {}: {}", name, described.join("; "))?;
+        }
+
+        writeln!(f, "Line numbers:")?;
+        for (target, references) in &self.lines {
+            let described: Vec<String> = references
+                .iter()
+                .map(|(statement, line)| format!("{} from {}", statement, line.0))
+                .collect();
+            writeln!(f, "  {}: {}", target.0, described.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Writes the flow of control between the lines of a program as a Graphviz
+// DOT graph. Each line is a node showing its text, and the lines GOSUBs go to
+// are drawn with a double border, with the calls to them dashed.
+pub fn control_flow_dot(code_lines: Vec<lexer::LineOfCode>) -> String {
+    let program = in_line_order(code_lines);
+    let successors = checker::flow_within_subroutines(&program);
+    let line_map: BTreeMap<&lexer::LineNumber, usize> = program
+        .iter()
+        .enumerate()
+        .map(|(index, line)| (&line.line_number, index))
+        .collect();
+    let references: Vec<Vec<evaluator::LineReference>> = program
+        .iter()
+        .map(|line| evaluator::line_references(&line.tokens))
+        .collect();
+    let subroutines: BTreeSet<&lexer::LineNumber> = references
+        .iter()
+        .flatten()
+        .filter(|reference| reference.statement.ends_with("GOSUB"))
+        .map(|reference| &reference.target)
+        .collect();
+
+    let mut dot = String::from("digraph program {\n    node [shape=box];\n");
+
+    for line in &program {
+        let peripheries = if subroutines.contains(&line.line_number) {
+            ", peripheries=2"
+        } else {
+            ""
+        };
+        dot.push_str(&format!(
+            "    {} [label=\"{}\"{}];\n",
+            line.line_number.0,
+            escape(&formatter::format_line(line)),
+            peripheries
+        ));
+    }
+
+    for (line_index, line) in program.iter().enumerate() {
+        let from = line.line_number.0;
+
+        for successor in &successors[line_index] {
+            let to = &program[*successor].line_number;
+            let jump = references[line_index].iter().find(|reference| {
+                &reference.target == to && !reference.statement.ends_with("GOSUB")
+            });
+            let label = match jump {
+                Some(reference) => Some(reference.statement),
+                // Loops going back, or past their end
+                None if *successor != line_index + 1 => {
+                    line.tokens.first().and_then(|first| first.1.keyword_text())
+                }
+                None => None,
+            };
+            match label {
+                Some(label) => dot.push_str(&format!(
+                    "    {} -> {} [label=\"{}\"];\n",
+                    from, to.0, label
+                )),
+                None => dot.push_str(&format!("    {} -> {};\n", from, to.0)),
+            }
+        }
+
+        for reference in &references[line_index] {
+            if reference.statement.ends_with("GOSUB") && line_map.contains_key(&reference.target) {
+                dot.push_str(&format!(
+                    "    {} -> {} [label=\"{}\", style=dashed];\n",
+                    from, reference.target.0, reference.statement
+                ));
+            }
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+fn in_line_order(code_lines: Vec<lexer::LineOfCode>) -> Vec<lexer::LineOfCode> {
+    let mut lineno_to_code = BTreeMap::new();
+
+    for line in code_lines {
+        lineno_to_code.insert(line.line_number.clone(), line);
+    }
+
+    lineno_to_code.into_values().collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// The variables and arrays a line writes and reads. NEXT both reads and
+// writes its variables, and the body of DEF FN reads the variables other than
+// its parameters.
+fn variable_uses(tokens: &[lexer::TokenAndPos]) -> (Vec<&str>, Vec<&str>) {
+    let statement = tokens.first().map(|first| &first.1);
+    let name = |index: usize| match tokens[index].1 {
+        token::Token::Variable(ref name, _) => Some(&**name),
+        _ => None,
+    };
+
+    match statement {
+        Some(token::Token::Next) => {
+            let names: Vec<&str> = (0..tokens.len()).filter_map(name).collect();
+            return (names.clone(), names);
+        }
+        Some(token::Token::Def) => {
+            let equals =
+                checker::top_level_position(tokens, &token::Token::Equals).unwrap_or(tokens.len());
+            let parameters: Vec<&str> = (2..equals).filter_map(name).collect();
+            let read = (equals..tokens.len())
+                .filter(|index| !is_call(tokens, *index))
+                .filter_map(name)
+                .filter(|name| !parameters.contains(name))
+                .collect();
+            return (Vec::new(), read);
+        }
+        _ => {}
+    }
+
+    let (mut written, mut read): (Vec<&str>, Vec<&str>) = {
+        let (written, read) = checker::variable_uses(tokens);
+        (written, read.into_iter().map(|(_, name)| name).collect())
+    };
+
+    // Arrays are written by DIM, by LET and by READ and INPUT outside of
+    // parentheses, and read everywhere else
+    let mut depth = 0;
+    for (index, lexer::TokenAndPos(_, token)) in tokens.iter().enumerate() {
+        match *token {
+            token::Token::LParen => depth += 1,
+            token::Token::RParen => depth -= 1,
+            token::Token::Variable(ref array, _) if is_array(tokens, index) => {
+                let writes = match statement {
+                    Some(token::Token::Dim) => true,
+                    Some(token::Token::Let) => index == 1,
+                    Some(token::Token::Read) | Some(token::Token::Input) => depth == 0,
+                    _ => false,
+                };
+                if writes {
+                    written.push(array);
+                } else {
+                    read.push(array);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (written, read)
+}
+
+// Whether the token is a name followed by (
+fn is_call(tokens: &[lexer::TokenAndPos], index: usize) -> bool {
+    matches!(tokens[index].1, token::Token::Variable(_, _))
+        && tokens.get(index + 1).map(|next| &next.1) == Some(&token::Token::LParen)
+}
+
+// Whether the token is an array named with its subscripts, rather than a
+// call of a built-in or user-defined function
+fn is_array(tokens: &[lexer::TokenAndPos], index: usize) -> bool {
+    match tokens[index].1 {
+        token::Token::Variable(ref name, _) => {
+            is_call(tokens, index)
+                && !name.starts_with("FN")
+                && !evaluator::is_builtin_function(name)
+        }
+        _ => false,
+    }
+}
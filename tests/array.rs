@@ -0,0 +1,29 @@
+use rbasic::array::*;
+use rbasic::error;
+use rbasic::value::RBasicValue;
+
+#[test]
+fn array_initial_values() {
+    let numbers = BasicArray::new("A", vec![2, 3]);
+    assert_eq!(numbers.values().len(), 12);
+    assert_eq!(numbers.get(&[2, 3]), Ok(&RBasicValue::Number(0)));
+
+    let strings = BasicArray::new("A$", vec![1]);
+    assert_eq!(strings.get(&[1]), Ok(&RBasicValue::String("".into())));
+}
+
+#[test]
+fn array_set_and_get() {
+    let mut array = BasicArray::new("A", vec![2, 3]);
+    assert_eq!(array.set(&[1, 2], RBasicValue::Number(5)), Ok(RBasicValue::Number(0)));
+    assert_eq!(array.get(&[1, 2]), Ok(&RBasicValue::Number(5)));
+    assert_eq!(array.values()[6], RBasicValue::Number(5));
+}
+
+#[test]
+fn array_subscript_out_of_range() {
+    let array = BasicArray::new("A", vec![2, 3]);
+    assert_eq!(array.get(&[3, 0]).map_err(|e| e.code), Err(error::SUBSCRIPT_OUT_OF_RANGE));
+    assert_eq!(array.get(&[0, -1]).map_err(|e| e.code), Err(error::SUBSCRIPT_OUT_OF_RANGE));
+    assert_eq!(array.get(&[0]).map_err(|e| e.code), Err(error::SUBSCRIPT_OUT_OF_RANGE));
+}
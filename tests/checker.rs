@@ -0,0 +1,154 @@
+use rbasic::checker::*;
+use rbasic::lexer::*;
+
+fn check_lines(lines: &[&str]) -> Vec<String> {
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    check(code_lines).iter().map(|diagnostic| diagnostic.to_string()).collect()
+}
+
+#[test]
+fn check_clean_program() {
+    let lines = [
+        "10 INPUT \"Name\", N$",
+        "20 FOR I = 1 TO 3",
+        "30 GOSUB 100",
+        "40 NEXT I",
+        "50 END",
+        "100 PRINT N$ + \"!\"",
+        "110 RETURN",
+    ];
+    assert_eq!(check_lines(&lines), Vec::<String>::new());
+}
+
+#[test]
+fn check_read_before_assignment() {
+    let lines = ["10 IF A > 1 THEN 40", "20 LET B = 1", "30 GOTO 10", "40 PRINT B + C"];
+    assert_eq!(
+        check_lines(&lines),
+        vec![
+            "10:7: warning: A is read before anything is assigned to it",
+            "40:14: warning: C is read before anything is assigned to it",
+        ]
+    );
+}
+
+#[test]
+fn check_error_handler_sees_assignments() {
+    let lines = [
+        "10 ON ERROR GOTO 100",
+        "20 LET A = 1",
+        "30 ERROR 5",
+        "100 PRINT A",
+        "110 RESUME NEXT",
+    ];
+    assert_eq!(check_lines(&lines), Vec::<String>::new());
+}
+
+#[test]
+fn check_unreachable_lines() {
+    let lines = [
+        "10 GOTO 50",
+        "20 PRINT 1",
+        "30 REM Not counted",
+        "40 PRINT 2",
+        "50 END",
+        "60 PRINT 3",
+    ];
+    assert_eq!(
+        check_lines(&lines),
+        vec![
+            "20:4: warning: lines 20 to 40 are never reached",
+            "60:4: warning: line 60 is never reached",
+        ]
+    );
+}
+
+#[test]
+fn check_gosub_without_return() {
+    let lines = ["10 GOSUB 100", "20 GOSUB 200", "30 END", "100 GOTO 100", "200 RETURN"];
+    assert_eq!(
+        check_lines(&lines),
+        vec!["10:10: warning: the subroutine at line 100 never RETURNs"]
+    );
+}
+
+#[test]
+fn check_unmatched_loops() {
+    let lines = ["10 FOR I = 1 TO 2", "20 WHILE 1 = 1", "30 LOOP", "40 NEXT J"];
+    assert_eq!(
+        check_lines(&lines),
+        vec![
+            "20:4: error: WHILE without WEND",
+            "30:4: error: LOOP without DO",
+        ]
+    );
+
+    let lines = ["10 NEXT", "20 FOR I = 1 TO 2"];
+    assert_eq!(
+        check_lines(&lines),
+        vec!["10:4: error: NEXT without FOR", "20:4: error: FOR without NEXT"]
+    );
+}
+
+#[test]
+fn check_jump_into_loop() {
+    let lines = [
+        "10 FOR I = 1 TO 2",
+        "20 PRINT I",
+        "30 NEXT I",
+        "40 IF I < 5 THEN 20",
+        "50 WHILE I < 5",
+        "60 LET I = I + 1",
+        "70 IF I = 4 THEN 60",
+        "80 WEND",
+    ];
+    assert_eq!(
+        check_lines(&lines),
+        vec!["40:18: warning: IF jumps into the middle of the FOR loop at line 10"]
+    );
+}
+
+#[test]
+fn check_type_mismatches() {
+    let lines = [
+        "10 LET A$ = 1 + 2",
+        "20 LET B = \"x\"",
+        "30 PRINT A$ * 2",
+        "40 IF A$ = 1 THEN 60",
+        "50 PRINT -\"x\" + (B > 1)",
+        "60 LET C$ = MKI$(3) + A$",
+    ];
+    assert_eq!(
+        check_lines(&lines),
+        vec![
+            "10:8: warning: type mismatch: a number is assigned to string variable A$",
+            "20:8: warning: type mismatch: a string is assigned to numeric variable B",
+            "30:10: warning: type mismatch: * can't be given a string and a number",
+            "40:7: warning: type mismatch: = can't be given a string and a number",
+            "50:10: warning: type mismatch: - can't be given a string",
+            "50:10: warning: type mismatch: + can't be given a number and a Boolean value",
+        ]
+    );
+}
+
+#[test]
+fn check_unused_data() {
+    let lines = ["10 DATA 1, 2", "20 PRINT 1"];
+    assert_eq!(check_lines(&lines), vec!["10:4: warning: DATA is never READ"]);
+
+    let lines = ["10 DATA 1, 2", "20 READ A, B"];
+    assert_eq!(check_lines(&lines), Vec::<String>::new());
+}
+
+#[test]
+fn check_source_reports_errors() {
+    let diagnostics = check_source("10 GOTO 30\n20 PRINT 1 @\n");
+    assert!(diagnostics.iter().all(Diagnostic::is_error));
+    assert_eq!(
+        diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<_>>(),
+        vec![
+            "10:9: error: GOTO refers to line 30, which doesn't exist",
+            "20:1: error: Unimplemented token at 11:\t@",
+        ]
+    );
+}
@@ -0,0 +1,139 @@
+use rbasic::coverage::*;
+use rbasic::evaluator::*;
+use rbasic::lexer::*;
+
+const PROGRAM: [&str; 10] = [
+    "10 REM COUNT THE BIG ONES",
+    "20 LET B = 0",
+    "30 FOR I = 1 TO 4",
+    "40 IF I < 3 THEN 60",
+    "50 LET B = B + 1",
+    "60 NEXT I",
+    "70 IF B > 5 THEN 90",
+    "80 END",
+    "90 PRINT \"TOO MANY\"",
+    "100 DATA 1, 2",
+];
+
+fn coverage(lines: &[&str]) -> Coverage {
+    let code_lines = lines
+        .iter()
+        .map(|line| tokenize_line(line).unwrap())
+        .collect();
+    let mut interpreter = Interpreter::new(code_lines).unwrap();
+    interpreter.set_coverage(true);
+    interpreter.run().unwrap();
+    interpreter.coverage().unwrap().clone()
+}
+
+#[test]
+fn coverage_counts_lines_and_branches() {
+    let coverage = coverage(&PROGRAM);
+
+    assert_eq!(coverage.line(&LineNumber(20)), 1);
+    assert_eq!(coverage.line(&LineNumber(40)), 4);
+    assert_eq!(coverage.line(&LineNumber(50)), 2);
+    assert_eq!(coverage.line(&LineNumber(90)), 0);
+    assert_eq!(
+        coverage.branch(&LineNumber(40)),
+        Some(&BranchCounts {
+            taken: 2,
+            not_taken: 2
+        })
+    );
+    assert_eq!(
+        coverage.branch(&LineNumber(70)),
+        Some(&BranchCounts {
+            taken: 0,
+            not_taken: 1
+        })
+    );
+    assert_eq!(coverage.branch(&LineNumber(20)), None);
+}
+
+#[test]
+fn coverage_lcov_uses_file_lines() {
+    let mut lines = vec![""];
+    lines.extend(&PROGRAM);
+    let coverage = coverage(&PROGRAM);
+
+    let mut output = Vec::new();
+    coverage
+        .write_lcov("count.bas", &lines, &mut output)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "TN:
+SF:count.bas
+DA:3,1
+DA:4,1
+DA:5,4
+BRDA:5,0,0,2
+BRDA:5,0,1,2
+DA:6,2
+DA:7,4
+DA:8,1
+BRDA:8,0,0,0
+BRDA:8,0,1,1
+DA:9,1
+DA:10,0
+BRF:4
+BRH:3
+LF:8
+LH:7
+end_of_record
+"
+    );
+}
+
+#[test]
+fn coverage_lcov_if_never_run() {
+    let lines = ["10 END", "20 IF 1 = 1 THEN 10"];
+    let coverage = coverage(&lines);
+
+    let mut output = Vec::new();
+    coverage.write_lcov("end.bas", &lines, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("DA:2,0\nBRDA:2,0,0,-\nBRDA:2,0,1,-\n"));
+    assert!(output.contains("BRF:2\nBRH:0\n"));
+}
+
+#[test]
+fn coverage_annotated_listing() {
+    assert_eq!(
+        coverage(&PROGRAM).annotate(&PROGRAM),
+        "        -: 10 REM COUNT THE BIG ONES
+        1: 20 LET B = 0
+        1: 30 FOR I = 1 TO 4
+        4: 40 IF I < 3 THEN 60   [taken 2, not taken 2]
+        2: 50 LET B = B + 1
+        4: 60 NEXT I
+        1: 70 IF B > 5 THEN 90   [taken 0, not taken 1]
+        1: 80 END
+    #####: 90 PRINT \"TOO MANY\"
+        -: 100 DATA 1, 2
+"
+    );
+}
+
+#[test]
+fn coverage_merges_runs() {
+    let mut first = Coverage::new();
+    first.record_line(&LineNumber(10));
+    first.record_branch(&LineNumber(10), true);
+    let mut second = Coverage::new();
+    second.record_line(&LineNumber(10));
+    second.record_line(&LineNumber(20));
+    second.record_branch(&LineNumber(10), false);
+
+    first.merge(&second);
+    assert_eq!(first.line(&LineNumber(10)), 2);
+    assert_eq!(first.line(&LineNumber(20)), 1);
+    assert_eq!(
+        first.branch(&LineNumber(10)),
+        Some(&BranchCounts {
+            taken: 1,
+            not_taken: 1
+        })
+    );
+}
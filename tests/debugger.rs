@@ -0,0 +1,53 @@
+use rbasic::debugger::*;
+use rbasic::evaluator::*;
+use rbasic::lexer::*;
+
+fn new_debugger(lines: &[&str]) -> Debugger {
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    Debugger::new(Interpreter::new(code_lines).unwrap(), lines)
+}
+
+const PROGRAM: [&str; 7] = ["10 LET T = 0",
+                            "20 FOR I = 1 TO 3",
+                            "30 GOSUB 100",
+                            "40 NEXT I",
+                            "50 END",
+                            "100 LET T = T + I",
+                            "110 RETURN"];
+
+#[test]
+fn debug_step() {
+    let mut debugger = new_debugger(&PROGRAM);
+    assert_eq!(debugger.command("list"), "Next: 10 LET T = 0");
+    assert_eq!(debugger.command("step"), "Next: 20 FOR I = 1 TO 3");
+    assert_eq!(debugger.command("p T"), "T = 0");
+    assert_eq!(debugger.command("p X"), "X is not set");
+}
+
+#[test]
+fn debug_breakpoint_and_stack() {
+    let mut debugger = new_debugger(&PROGRAM);
+    assert_eq!(debugger.command("break 100"), "Breakpoint at line 100");
+    assert_eq!(debugger.command("break 99"), "There is no line 99");
+    assert_eq!(debugger.command("c"), "Breakpoint at line 100\nNext: 100 LET T = T + I");
+    assert_eq!(debugger.command("stack"),
+               "GOSUB at line 30\nFOR I TO 3 STEP 1 at line 20, with I = 1");
+    assert_eq!(debugger.command("delete 100"), "Deleted breakpoint at line 100");
+    assert_eq!(debugger.command("c"), "Program ended");
+    assert_eq!(debugger.command("p T"), "T = 6");
+    assert_eq!(debugger.command("c"), "The program has finished, use restart to run it again");
+}
+
+#[test]
+fn debug_watch_and_set() {
+    let mut debugger = new_debugger(&PROGRAM);
+    assert_eq!(debugger.command("watch T"), "Watching T");
+    assert_eq!(debugger.command("c"), "T changed from unset to 0\nNext: 20 FOR I = 1 TO 3");
+    assert_eq!(debugger.command("c"), "T changed from 0 to 1\nNext: 110 RETURN");
+    assert_eq!(debugger.command("set T = 10"), "T = 10");
+    assert_eq!(debugger.command("unwatch T"), "Stopped watching T");
+    assert_eq!(debugger.command("c"), "Program ended");
+    assert_eq!(debugger.command("p T"), "T = 15");
+    assert_eq!(debugger.command("restart"), "Next: 10 LET T = 0");
+    assert_eq!(debugger.command("p T"), "T is not set");
+}
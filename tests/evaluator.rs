@@ -1,7 +1,13 @@
 
 
+use rbasic::array::BasicArray;
+use rbasic::error;
 use rbasic::lexer::*;
 use rbasic::evaluator::*;
+use rbasic::limits::Limits;
+use rbasic::value::RBasicValue;
+
+use std::time::Duration;
 
 fn eval_line(line: &str) -> Result<String, String> {
     let result = tokenize_line(line);
@@ -10,11 +16,38 @@ fn eval_line(line: &str) -> Result<String, String> {
     evaluate(vec![code_line])
 }
 
+fn eval_lines(lines: &[&str]) -> Result<String, String> {
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    evaluate(code_lines)
+}
+
+// Runs a program whose checks go to line 9999 when they fail, which stops it
+// there instead of letting it complete
+fn eval_check(lines: &[&str]) -> Result<String, String> {
+    let mut lines = lines.to_vec();
+    lines.extend(&["9998 END", "9999 STOP"]);
+    eval_lines(&lines)
+}
+
 #[test]
 fn eval_goto_invalid_target_line_number() {
     let eval_result = eval_line("10 GOTO 5").err();
     assert_eq!(eval_result,
-               Some("At LineNumber(10), 8 invalid target line for GOTO".to_string()));
+               Some("At LineNumber(10), 8 invalid target line 5 for GOTO".to_string()));
+}
+
+#[test]
+fn eval_invalid_target_lines_reported_together() {
+    let eval_result = eval_lines(&["10 IF 1 < 2 THEN 40",
+                                   "20 GOSUB 55",
+                                   "30 ON 2 GOTO 10, 60, 20",
+                                   "40 RESUME 0",
+                                   "50 RESTORE 70"]);
+    assert_eq!(eval_result,
+               Err("At LineNumber(20), 9 invalid target line 55 for GOSUB\n\
+                    At LineNumber(30), 17 invalid target line 60 for ON GOTO\n\
+                    At LineNumber(50), 11 invalid target line 70 for RESTORE"
+                   .to_string()));
 }
 
 #[test]
@@ -37,3 +70,793 @@ fn eval_input_no_variable() {
     assert_eq!(eval_result,
                Some("At LineNumber(10), 8 INPUT must be followed by a variable name".to_string()));
 }
+
+#[test]
+fn eval_def_fn() {
+    let eval_result = eval_check(&["10 DEF FNAREA(W, H) = W * H",
+                                   "20 LET W = 100",
+                                   "30 IF FNAREA(3, 4) + FNAREA(1, 2) <> 14 THEN 9999",
+                                   "40 IF W <> 100 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_def_fn_undefined_function() {
+    let eval_result = eval_line("10 LET A = FNX(1)").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 3 error in LET expression: Undefined user function FNX"
+                   .to_string()));
+}
+
+#[test]
+fn eval_def_fn_arity_mismatch() {
+    let eval_result = eval_lines(&["10 DEF FNSQ(X) = X * X", "20 LET A = FNSQ(1, 2)"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 error in LET expression: Function FNSQ expects 1 \
+                     argument(s) but was given 2"
+                   .to_string()));
+}
+
+#[test]
+fn eval_def_fn_recursion_out_of_memory() {
+    let mut interpreter = new_interpreter(&["10 DEF FNR(X) = FNR(X)", "20 PRINT FNR(1)"]);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::OUT_OF_MEMORY));
+
+    let eval_result = eval_check(&["10 ON ERROR GOTO 100",
+                                   "20 DEF FNR(X) = FNR(X + 1)",
+                                   "30 LET A = FNR(1)",
+                                   "40 IF E <> 7 THEN 9999",
+                                   "50 END",
+                                   "100 LET E = ERR",
+                                   "110 RESUME NEXT"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_print_comma_in_parentheses() {
+    let eval_result = eval_line("10 PRINT (1, 2)").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10). 3 PRINT must be followed by valid expression: Invalid \
+                     expression!"
+                   .to_string()));
+}
+
+#[test]
+fn eval_print_more_than_one_expression() {
+    let eval_result = eval_line("10 PRINT 1, 2").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 10 PRINT takes a single expression, with nothing after \
+                     it"
+                   .to_string()));
+}
+
+#[test]
+fn eval_def_fn_invalid_name() {
+    let eval_result = eval_line("10 DEF SQ(X) = X * X").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 3 invalid syntax for DEF: function name must start \
+                     with FN"
+                   .to_string()));
+}
+
+#[test]
+fn eval_while_wend() {
+    let eval_result = eval_check(&["10 LET I = 0",
+                                   "20 LET T = 0",
+                                   "30 WHILE I < 5",
+                                   "40 LET I = I + 1",
+                                   "50 LET T = T + I",
+                                   "60 WEND",
+                                   "70 IF T <> 15 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_do_loop_with_exit() {
+    let eval_result = eval_check(&["10 LET I = 0",
+                                   "20 DO",
+                                   "30 LET I = I + 1",
+                                   "40 DO UNTIL I > 100",
+                                   "50 EXIT DO",
+                                   "60 LOOP",
+                                   "70 LOOP WHILE I < 3",
+                                   "80 IF I <> 3 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_wend_without_while() {
+    let eval_result = eval_lines(&["10 PRINT \"NEVER PRINTED\"", "20 WEND"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(20), 3 WEND without WHILE".to_string()));
+}
+
+#[test]
+fn eval_do_without_loop() {
+    let eval_result = eval_lines(&["10 DO", "20 WHILE 1 = 1", "30 WEND"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(10), 3 DO without LOOP".to_string()));
+}
+
+#[test]
+fn eval_exit_do_outside_loop() {
+    let eval_result = eval_line("10 EXIT DO").err();
+    assert_eq!(eval_result, Some("At LineNumber(10), 3 EXIT DO outside of DO loop".to_string()));
+}
+
+#[test]
+fn eval_on_gosub_and_fall_through() {
+    let eval_result = eval_check(&["10 LET T = 0",
+                                   "20 LET C = 1",
+                                   "30 ON C GOSUB 100, 200",
+                                   "40 LET C = C + 1",
+                                   "50 IF C < 4 THEN 30",
+                                   "60 ON 0 GOTO 80",
+                                   "70 ON 2 GOTO 80, 90",
+                                   "80 GOTO 9999",
+                                   "90 IF T <> 11 THEN 9999",
+                                   "95 GOTO 300",
+                                   "100 LET T = T + 1",
+                                   "110 RETURN",
+                                   "200 LET T = T + 10",
+                                   "210 RETURN",
+                                   "300 REM DONE"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_on_goto_invalid_target_line_number() {
+    let eval_result = eval_lines(&["10 ON 1 GOTO 20, 30", "20 PRINT \"NEVER PRINTED\""]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 17 invalid target line 30 for ON GOTO".to_string()));
+}
+
+#[test]
+fn eval_on_goto_illegal_quantity() {
+    let eval_result = eval_lines(&["10 ON -1 GOTO 20", "20 REM"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 3 illegal quantity -1 for ON".to_string()));
+}
+
+#[test]
+fn eval_return_without_gosub() {
+    let eval_result = eval_line("10 RETURN").err();
+    assert_eq!(eval_result, Some("At LineNumber(10), 3 RETURN without GOSUB".to_string()));
+}
+
+#[test]
+fn eval_end() {
+    let eval_result = eval_check(&["10 END", "20 GOTO 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_stop() {
+    let eval_result = eval_check(&["10 LET A = 1", "20 STOP", "30 GOTO 9999"]);
+    assert_eq!(eval_result, Ok("BREAK IN 20".to_string()));
+}
+
+fn new_interpreter(lines: &[&str]) -> Interpreter {
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    Interpreter::new(code_lines).unwrap()
+}
+
+#[test]
+fn eval_stop_and_cont() {
+    let mut interpreter = new_interpreter(&["10 LET A = 1",
+                                            "20 STOP",
+                                            "30 IF A <> 1 THEN 9999",
+                                            "40 STOP",
+                                            "50 END",
+                                            "9999 STOP"]);
+    assert_eq!(interpreter.cont().map_err(|e| e.code), Err(error::CANT_CONTINUE));
+    assert_eq!(interpreter.run(), Ok(ExecutionStatus::Stopped(LineNumber(20))));
+    assert_eq!(interpreter.cont(), Ok(ExecutionStatus::Stopped(LineNumber(40))));
+    assert_eq!(interpreter.cont(), Ok(ExecutionStatus::Ended));
+    assert_eq!(interpreter.cont().map_err(|e| e.code), Err(error::CANT_CONTINUE));
+}
+
+#[test]
+fn eval_on_error_resume_next() {
+    let eval_result = eval_check(&["10 ON ERROR GOTO 100",
+                                   "20 LET A = 10 / 0",
+                                   "30 ON ERROR GOTO 0",
+                                   "40 IF E <> 11 THEN 9999",
+                                   "50 IF L <> 20 THEN 9999",
+                                   "60 END",
+                                   "100 LET E = ERR",
+                                   "110 LET L = ERL",
+                                   "120 RESUME NEXT"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_on_error_resume_retries_statement() {
+    let eval_result = eval_check(&["10 ON ERROR GOTO 100",
+                                   "20 LET A = 10 / D",
+                                   "30 ON ERROR GOTO 0",
+                                   "40 IF A <> 5 THEN 9999",
+                                   "50 END",
+                                   "100 LET D = 2",
+                                   "110 RESUME"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_on_error_traps_overflow() {
+    let eval_result = eval_check(&["5 LET N = 0",
+                                   "10 ON ERROR GOTO 100",
+                                   "20 LET A = 2147483647 + 1",
+                                   "30 LET B = -2147483647 - 1",
+                                   "40 LET C = B / -1",
+                                   "50 IF N <> 2 THEN 9999",
+                                   "60 END",
+                                   "100 IF ERR <> 6 THEN 9999",
+                                   "110 LET N = N + 1",
+                                   "120 RESUME NEXT"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_error_statement_code() {
+    let mut interpreter = new_interpreter(&["10 ERROR 11"]);
+    assert_eq!(interpreter.run(),
+               Err(error::RBasicError::new(error::DIVISION_BY_ZERO,
+                                           "At LineNumber(10), 3 Division by zero".to_string())));
+}
+
+#[test]
+fn eval_error_in_handler_is_not_trapped() {
+    let mut interpreter = new_interpreter(&["10 ON ERROR GOTO 30",
+                                            "20 ERROR 200",
+                                            "30 RETURN"]);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::RETURN_WITHOUT_GOSUB));
+}
+
+#[test]
+fn eval_error_handler_without_resume() {
+    let eval_result = eval_lines(&["10 ON ERROR GOTO 30", "20 ERROR 200", "30 REM"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), error handler ended without RESUME".to_string()));
+}
+
+#[test]
+fn eval_resume_without_error() {
+    let eval_result = eval_line("10 RESUME NEXT").err();
+    assert_eq!(eval_result, Some("At LineNumber(10), 3 RESUME without error".to_string()));
+}
+
+#[test]
+fn eval_on_error_invalid_target_line_number() {
+    let eval_result = eval_line("10 ON ERROR GOTO 20").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 17 invalid target line 20 for ON ERROR GOTO".to_string()));
+}
+
+#[test]
+fn eval_rnd_negative_reseeds_and_zero_repeats() {
+    let eval_result = eval_check(&["10 LET A = RND(-5)",
+                                   "20 LET B = RND(1)",
+                                   "30 IF RND(0) <> B THEN 9999",
+                                   "40 IF RND(-5) <> A THEN 9999",
+                                   "50 IF RND(1) <> B THEN 9999",
+                                   "60 IF A = B THEN 9999",
+                                   "70 IF A < 0 THEN 9999",
+                                   "80 IF A >= 1 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_randomize_with_seed() {
+    let eval_result = eval_check(&["10 RANDOMIZE 1234",
+                                   "20 LET A = RND(1)",
+                                   "30 RANDOMIZE 1234",
+                                   "40 IF RND(1) <> A THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_rand_range() {
+    let eval_result = eval_check(&["10 LET I = 0",
+                                   "20 WHILE I < 200",
+                                   "30 LET R = RAND(6)",
+                                   "40 IF R < 1 THEN 9999",
+                                   "50 IF R > 6 THEN 9999",
+                                   "60 IF INT(R) <> R THEN 9999",
+                                   "70 LET I = I + 1",
+                                   "80 WEND"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_rand_illegal_maximum() {
+    let eval_result = eval_line("10 LET A = RAND(0)").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 3 error in LET expression: RAND requires a maximum \
+                     of at least 1, not 0"
+                   .to_string()));
+
+    let eval_result = eval_line("10 LET A = RAND(3000000000)").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 3 error in LET expression: RAND requires a maximum \
+                     of at most 2147483647, not 3000000000"
+                   .to_string()));
+
+    let eval_result = eval_check(&["10 LET A = RAND(2147483647)",
+                                   "20 IF A < 1 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_int_overflow() {
+    let eval_result = eval_check(&["10 IF INT(2147483647.5) <> 2147483647 THEN 9999",
+                                   "20 IF INT(-2147483648) <> -2147483647 - 1 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+
+    for line in ["10 LET A = INT(3000000000.5)", "10 LET A = INT(-2147483648.5)"].iter() {
+        let eval_result = eval_line(line).err();
+        assert_eq!(eval_result,
+                   Some("At LineNumber(10), 3 error in LET expression: Overflow".to_string()));
+    }
+}
+
+#[test]
+fn eval_seeded_runs_repeat() {
+    let lines = ["10 LET A = RND(1)",
+                 "20 STOP",
+                 "30 IF RND(1) = A THEN 9999",
+                 "40 END",
+                 "9999 STOP"];
+    let mut interpreter = new_interpreter(&lines);
+    interpreter.set_seed(99);
+    assert_eq!(interpreter.run(), Ok(ExecutionStatus::Stopped(LineNumber(20))));
+    assert_eq!(interpreter.cont(), Ok(ExecutionStatus::Ended));
+}
+
+#[test]
+fn eval_float_arithmetic() {
+    let eval_result = eval_check(&["10 LET A = 1.5 * 2 + 1",
+                                   "20 IF A <> 4 THEN 9999",
+                                   "30 IF INT(-0.5) <> -1 THEN 9999",
+                                   "40 IF 7 / 2 <> 3 THEN 9999",
+                                   "50 IF 7 / 2.0 <> 3.5 THEN 9999",
+                                   "60 IF -7 / 2 <> -3 THEN 9999",
+                                   "70 IF 1 <> 1.0 THEN 9999",
+                                   "80 IF 2 <= 1.5 THEN 9999",
+                                   "90 IF 1.5 >= A / 2 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+fn temp_file(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rbasic-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn eval_write_and_input_file() {
+    let path = temp_file("write.dat");
+    let open_output = format!("10 OPEN \"{}\" FOR OUTPUT AS #1", path);
+    let open_input = format!("40 OPEN \"{}\" FOR INPUT AS #2", path);
+    let eval_result = eval_check(&[open_output.as_str(),
+                                   "20 WRITE #1, \"Smith, J\", 42, 1.5",
+                                   "30 CLOSE #1",
+                                   open_input.as_str(),
+                                   "50 INPUT #2, N, A, F",
+                                   "60 IF N <> \"Smith, J\" THEN 9999",
+                                   "70 IF A <> 42 THEN 9999",
+                                   "80 IF F <> 1.5 THEN 9999",
+                                   "90 IF !EOF(2) THEN 9999",
+                                   "100 CLOSE"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "\"Smith, J\",42,1.5\n");
+}
+
+#[test]
+fn eval_print_append_and_line_input_file() {
+    let path = temp_file("lines.txt");
+    let open_output = format!("10 OPEN \"{}\" FOR OUTPUT AS #1", path);
+    let open_append = format!("40 OPEN \"{}\" FOR APPEND AS 1", path);
+    let open_input = format!("70 OPEN \"{}\" FOR INPUT AS #1", path);
+    let eval_result = eval_check(&[open_output.as_str(),
+                                   "20 PRINT #1, \"one, two\"",
+                                   "30 CLOSE 1",
+                                   open_append.as_str(),
+                                   "50 PRINT #1, 3 * 4",
+                                   "60 CLOSE",
+                                   open_input.as_str(),
+                                   "80 IF LOF(1) <> 12 THEN 9999",
+                                   "90 LINE INPUT #1, A",
+                                   "100 IF A <> \"one, two\" THEN 9999",
+                                   "110 LINE INPUT #1, B",
+                                   "120 IF B <> \"12\" THEN 9999",
+                                   "130 IF !EOF(1) THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_file_not_found_trapped() {
+    let path = temp_file("missing.txt");
+    let open_input = format!("20 OPEN \"{}\" FOR INPUT AS #1", path);
+    let eval_result = eval_check(&["10 ON ERROR GOTO 40",
+                                   open_input.as_str(),
+                                   "30 END",
+                                   "40 IF ERR <> 53 THEN 9999",
+                                   "50 IF ERL <> 20 THEN 9999",
+                                   "60 RESUME 30"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_file_errors() {
+    let path = temp_file("errors.txt");
+    let open_output = format!("10 OPEN \"{}\" FOR OUTPUT AS #1", path);
+    let open_input = format!("40 OPEN \"{}\" FOR INPUT AS #1", path);
+
+    let eval_result = eval_lines(&[open_output.as_str(), "20 INPUT #1, A"]).err();
+    assert_eq!(eval_result, Some(format!("At LineNumber(20), 3 Bad file mode for {}", path)));
+
+    let eval_result = eval_lines(&[open_output.as_str(),
+                                   "20 PRINT #2, 1"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(20), 3 Bad file number 2".to_string()));
+
+    let eval_result = eval_lines(&[open_output.as_str(),
+                                   "20 CLOSE",
+                                   "30 LET A = 0",
+                                   open_input.as_str(),
+                                   "50 LINE INPUT #1, A"]).err();
+    assert_eq!(eval_result, Some(format!("At LineNumber(50), 3 Input past end of {}", path)));
+}
+
+#[test]
+fn eval_random_access_file() {
+    let path = temp_file("records.dat");
+    let open_file = format!("10 OPEN \"{}\" FOR RANDOM AS #1 LEN = 8", path);
+    let reopen_file = format!("110 OPEN \"{}\" AS 1 LEN = 8", path);
+    let eval_result = eval_check(&[open_file.as_str(),
+                                   "20 FIELD #1, 6 AS NAME$, 2 AS AGE$",
+                                   "30 LSET NAME$ = \"Ann\"",
+                                   "40 LSET AGE$ = MKI$(42)",
+                                   "50 PUT #1, 2",
+                                   "60 RSET NAME$ = \"Robert Smith\"",
+                                   "70 LSET AGE$ = MKI$(-7)",
+                                   "80 PUT #1, 1",
+                                   "90 IF LOF(1) <> 16 THEN 9999",
+                                   "100 CLOSE #1",
+                                   reopen_file.as_str(),
+                                   "120 FIELD 1, 6 AS N$, 2 AS A$",
+                                   "130 GET #1, 2",
+                                   "140 IF N$ <> \"Ann   \" THEN 9999",
+                                   "150 IF CVI(A$) <> 42 THEN 9999",
+                                   "160 GET #1",
+                                   "170 IF !EOF(1) THEN 9999",
+                                   "180 GET #1, 1",
+                                   "190 IF N$ <> \"Robert\" THEN 9999",
+                                   "200 IF CVI(A$) <> -7 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+
+    let mut record = b"Ann   ".to_vec();
+    record.extend_from_slice(&[42, 0]);
+    assert_eq!(std::fs::read(&path).unwrap()[8..], record[..]);
+}
+
+#[test]
+fn eval_random_access_errors() {
+    let path = temp_file("field.dat");
+    let open_file = format!("10 OPEN \"{}\" AS #1 LEN = 4", path);
+
+    let eval_result = eval_lines(&[open_file.as_str(),
+                                   "20 FIELD #1, 3 AS A$, 2 AS B$"]).err();
+    assert_eq!(eval_result,
+               Some(format!("At LineNumber(20), 3 Fields are longer than the record length 4 \
+                             of {}", path)));
+
+    let eval_result = eval_lines(&[open_file.as_str(),
+                                   "20 GET #1, 0"]).err();
+    assert_eq!(eval_result,
+               Some(format!("At LineNumber(20), 3 Bad record number 0 for {}", path)));
+}
+
+#[test]
+fn eval_for_next() {
+    let eval_result = eval_check(&["10 LET T = 0",
+                                   "20 FOR I = 1 TO 4",
+                                   "30 FOR J = 10 TO 1 STEP -3",
+                                   "40 LET T = T + J",
+                                   "50 NEXT J, I",
+                                   "60 IF T <> 88 THEN 9999",
+                                   "70 IF I <> 5 THEN 9999",
+                                   "80 FOR K = 1 TO 0",
+                                   "90 LET T = 0",
+                                   "100 NEXT",
+                                   "110 IF T <> 88 THEN 9999",
+                                   "120 FOR F = 0 TO 1 STEP 0.5",
+                                   "130 LET T = T + F",
+                                   "140 NEXT F",
+                                   "150 IF T <> 89.5 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_for_step() {
+    let eval_result = eval_check(&["10 LET T = 0",
+                                   "20 FOR I = 1 TO 10 STEP 3",
+                                   "30 LET T = T * 100 + I",
+                                   "40 NEXT I",
+                                   "50 IF T <> 1040710 THEN 9999",
+                                   "60 IF I <> 13 THEN 9999",
+                                   "70 FOR I = 5 TO -5 STEP -5",
+                                   "80 LET T = T + I",
+                                   "90 NEXT I",
+                                   "100 IF T <> 1040710 THEN 9999",
+                                   "110 IF I <> -10 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_for_zero_trip() {
+    // Loops that wouldn't run at all go past their own NEXT, skipping any
+    // loops inside them
+    let eval_result = eval_check(&["10 LET T = 0",
+                                   "20 FOR I = 3 TO 1",
+                                   "30 FOR J = 1 TO 2",
+                                   "40 LET T = T + 1",
+                                   "50 NEXT J",
+                                   "60 NEXT I",
+                                   "70 IF T <> 0 THEN 9999",
+                                   "80 IF I <> 3 THEN 9999",
+                                   "90 FOR K = 1 TO 3 STEP -1",
+                                   "100 LET T = T + 1",
+                                   "110 NEXT K",
+                                   "120 IF T <> 0 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_next_without_for() {
+    let eval_result = eval_lines(&["10 FOR I = 1 TO 2", "20 NEXT J"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(20), 3 NEXT without FOR".to_string()));
+
+    let eval_result = eval_lines(&["10 NEXT"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(10), 3 NEXT without FOR".to_string()));
+
+    // The loop is over once it has finished, so a second NEXT has no FOR
+    let eval_result = eval_lines(&["10 FOR I = 1 TO 2", "20 NEXT I", "30 NEXT I"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(30), 3 NEXT without FOR".to_string()));
+
+    let eval_result = eval_check(&["10 ON ERROR GOTO 30",
+                                   "20 NEXT I",
+                                   "30 IF ERR <> 1 THEN 9999",
+                                   "40 RESUME 50",
+                                   "50 END"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_for_without_next() {
+    let eval_result = eval_line("10 FOR I = 2 TO 1").err();
+    assert_eq!(eval_result, Some("At LineNumber(10), 3 FOR without NEXT".to_string()));
+}
+
+#[test]
+fn eval_arrays() {
+    let eval_result = eval_check(&["10 DIM A(5), B$(2, 3)",
+                                   "20 FOR I = 0 TO 5",
+                                   "30 LET A(I) = I * I",
+                                   "40 NEXT I",
+                                   "50 LET B$(1, A(1) + 1) = \"HI\"",
+                                   "60 IF A(3) + A(5) <> 34 THEN 9999",
+                                   "70 IF B$(1, 2) <> \"HI\" THEN 9999",
+                                   "80 IF B$(2, 3) <> \"\" THEN 9999",
+                                   "90 LET C(10) = 7",
+                                   "100 IF C(10) + C(0) <> 7 THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_array_errors() {
+    let eval_result = eval_lines(&["10 DIM A(5)", "20 LET A(6) = 1"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 Subscript 6 is out of range 0 to 5".to_string()));
+
+    let eval_result = eval_lines(&["10 DIM A(2)", "20 LET X = A(-1)"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 error in LET expression: Subscript -1 is out of range \
+                     0 to 2".to_string()));
+
+    let eval_result = eval_lines(&["10 DIM A(2)", "20 LET X = A(1, 1)"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 error in LET expression: Array has 1 dimension(s) but \
+                     was given 2 subscript(s)".to_string()));
+
+    // Arrays used without DIM go up to 10
+    let eval_result = eval_lines(&["10 LET A(10) = 1", "20 LET A(11) = 1"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 Subscript 11 is out of range 0 to 10".to_string()));
+
+    let eval_result = eval_lines(&["10 DIM A(-1)"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(10), 3 illegal array size -1 for A".to_string()));
+}
+
+#[test]
+fn eval_array_redimensioned() {
+    let eval_result = eval_lines(&["10 DIM A(5)", "20 DIM A(5)"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 Array A is already dimensioned".to_string()));
+
+    // Using an array dimensions it, so it can't be given a DIM afterwards
+    let eval_result = eval_lines(&["10 LET A(1) = 1", "20 DIM A(20)"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 Array A is already dimensioned".to_string()));
+
+    // Arrays and variables of the same name are separate
+    let eval_result = eval_lines(&["10 LET A = 1", "20 DIM A(3)", "30 DIM A$(3)"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_on_error_traps_array_errors() {
+    let eval_result = eval_check(&["10 ON ERROR GOTO 100",
+                                   "20 DIM A(2)",
+                                   "30 LET A(3) = 1",
+                                   "40 IF E <> 9 THEN 9999",
+                                   "50 DIM A(4)",
+                                   "60 IF E <> 10 THEN 9999",
+                                   "70 END",
+                                   "100 LET E = ERR",
+                                   "110 RESUME NEXT"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+fn limited_interpreter(lines: &[&str], limits: Limits) -> Interpreter {
+    let mut interpreter = new_interpreter(lines);
+    interpreter.set_limits(limits);
+    interpreter
+}
+
+#[test]
+fn eval_statement_limit() {
+    let limits = Limits { statements: Some(100), ..Limits::default() };
+    let mut interpreter = limited_interpreter(&["10 GOTO 10"], limits);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::STATEMENT_LIMIT));
+}
+
+#[test]
+fn eval_time_limit() {
+    let limits = Limits { time: Some(Duration::from_millis(10)), ..Limits::default() };
+    let mut interpreter = limited_interpreter(&["10 GOTO 10"], limits);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::TIME_LIMIT));
+}
+
+#[test]
+fn eval_string_limit() {
+    let limits = Limits { string_bytes: Some(10), ..Limits::default() };
+    let mut interpreter = limited_interpreter(&["10 LET A$ = \"HELLO\"",
+                                                "20 LET B$ = A$",
+                                                "30 LET A$ = \"\"",
+                                                "40 LET C$ = B$ + \"!\""],
+                                              limits);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::STRING_LIMIT));
+    assert_eq!(interpreter.current_line(), Some(LineNumber(40)));
+}
+
+#[test]
+fn eval_array_limit() {
+    let limits = Limits { array_elements: Some(20), ..Limits::default() };
+    let mut interpreter = limited_interpreter(&["10 DIM A(9)", "20 LET B(1) = 1"], limits);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::ARRAY_LIMIT));
+}
+
+#[test]
+fn eval_call_depth_limit() {
+    let limits = Limits { call_depth: Some(10), ..Limits::default() };
+    let mut interpreter = limited_interpreter(&["10 GOSUB 10"], limits.clone());
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::CALL_DEPTH_LIMIT));
+
+    let mut interpreter = limited_interpreter(&["10 DEF FNF(X) = FNF(X)", "20 LET A = FNF(1)"],
+                                              limits);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::CALL_DEPTH_LIMIT));
+}
+
+#[test]
+fn eval_limits_not_trapped() {
+    let limits = Limits { statements: Some(100), ..Limits::default() };
+    let mut interpreter = limited_interpreter(&["10 ON ERROR GOTO 30",
+                                                "20 GOTO 20",
+                                                "30 RESUME 20"],
+                                              limits);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::STATEMENT_LIMIT));
+}
+
+#[test]
+fn eval_preset_variables_and_arrays() {
+    let mut rates = BasicArray::new("RATE", vec![2]);
+    rates.set(&[1], RBasicValue::Float(0.25)).unwrap();
+
+    let mut interpreter = new_interpreter(&["10 LET TAX = TOTAL * RATE(BAND)",
+                                            "20 LET BAND = 2",
+                                            "30 LET RATE(2) = TAX"]);
+    interpreter.preset_variable("TOTAL", RBasicValue::Number(80)).unwrap();
+    interpreter.preset_variable("BAND", RBasicValue::Number(1)).unwrap();
+    interpreter.preset_array("RATE", rates).unwrap();
+
+    // Each run starts again from the preset values
+    for _ in 0..2 {
+        assert_eq!(interpreter.run(), Ok(ExecutionStatus::Ended));
+
+        let variables = interpreter.variables();
+        assert_eq!(variables.keys().cloned().collect::<Vec<_>>(), vec!["BAND", "TAX", "TOTAL"]);
+        assert_eq!(variables["TAX"], &RBasicValue::Float(20.0));
+        assert_eq!(variables["BAND"], &RBasicValue::Number(2));
+
+        let rate = interpreter.array("RATE").unwrap();
+        assert_eq!(rate.get(&[2]), Ok(&RBasicValue::Float(20.0)));
+        assert_eq!(interpreter.arrays().len(), 1);
+    }
+}
+
+#[test]
+fn eval_preset_invalid_names() {
+    let mut interpreter = new_interpreter(&["10 END"]);
+    assert_eq!(interpreter.preset_variable("PRINT", RBasicValue::Number(1)).map_err(|e| e.code),
+               Err(error::SYNTAX_ERROR));
+    assert_eq!(interpreter.preset_array("A B", BasicArray::new("A", vec![1])).map_err(|e| e.code),
+               Err(error::SYNTAX_ERROR));
+}
+
+#[test]
+fn eval_read_data() {
+    let eval_result = eval_check(&["10 DATA 1, -2.5, \"HI THERE\", WORD",
+                                   "20 READ A, B, C$, D$",
+                                   "30 IF A + B <> -1.5 THEN 9999",
+                                   "40 IF C$ + D$ <> \"HI THEREWORD\" THEN 9999",
+                                   "50 RESTORE 70",
+                                   "60 READ E, F(2)",
+                                   "70 DATA 7, 8",
+                                   "80 IF E + F(2) <> 15 THEN 9999",
+                                   "90 RESTORE",
+                                   "100 READ G$",
+                                   "110 IF G$ <> \"1\" THEN 9999"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_on_error_traps_data_errors() {
+    // The item that didn't fit is skipped, so reading carries on after it
+    let eval_result = eval_check(&["10 ON ERROR GOTO 100",
+                                   "20 DATA 1, WORD, 3",
+                                   "30 READ A, B",
+                                   "40 IF E <> 13 THEN 9999",
+                                   "50 READ C",
+                                   "60 IF A + C <> 4 THEN 9999",
+                                   "70 READ D",
+                                   "80 IF E <> 4 THEN 9999",
+                                   "90 END",
+                                   "100 LET E = ERR",
+                                   "110 RESUME NEXT"]);
+    assert_eq!(eval_result, Ok("Completed Successfully".to_string()));
+}
+
+#[test]
+fn eval_data_errors() {
+    let eval_result = eval_lines(&["10 DATA 1", "20 READ A, B"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(20), 3 Out of DATA".to_string()));
+
+    let eval_result = eval_lines(&["10 DATA 1, 2", "20 READ A", "30 READ B, C"]).err();
+    assert_eq!(eval_result, Some("At LineNumber(30), 3 Out of DATA".to_string()));
+
+    let eval_result = eval_lines(&["10 DATA HELLO", "20 READ A"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 3 Type mismatch reading HELLO into A".to_string()));
+
+    let eval_result = eval_lines(&["10 DIM A(2)", "20 DATA 1, \"X\"", "30 READ A(1), A(2)"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(30), 3 Type mismatch reading X into A".to_string()));
+
+    let eval_result = eval_lines(&["10 DATA 1", "20 RESTORE 30"]).err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(20), 11 invalid target line 30 for RESTORE".to_string()));
+
+    let eval_result = eval_line("10 DATA 1 + 2").err();
+    assert_eq!(eval_result,
+               Some("At LineNumber(10), 3 DATA must be followed by constants separated by commas"
+                   .to_string()));
+}
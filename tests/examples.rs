@@ -0,0 +1,22 @@
+// Runs each program in the examples directory with its .in file as input,
+// checking what it does against its .out and .err files. After changing what
+// an example does, update them with cargo run -- test --bless.
+
+use rbasic::golden;
+
+use std::path::Path;
+
+#[test]
+fn examples_match_expected_output() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let cases = golden::find_cases(&examples).unwrap();
+    assert!(!cases.is_empty());
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        for problem in case.check().unwrap() {
+            failures.push(format!("{}: {}", case.program.display(), problem));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
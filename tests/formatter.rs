@@ -0,0 +1,55 @@
+use rbasic::formatter::*;
+use rbasic::lexer::*;
+
+fn format(line: &str) -> String {
+    format_line(&tokenize_line(line).unwrap())
+}
+
+#[test]
+fn format_spacing() {
+    assert_eq!(
+        format("010   PRINT ( 3 + 4 ) * ( 5 - 1  )"),
+        "10 PRINT (3 + 4) * (5 - 1)"
+    );
+    assert_eq!(format("20 LET A( I , J ) = - B"), "20 LET A(I, J) = -B");
+    assert_eq!(format("30 PRINT # 1 , ! (X > Y)"), "30 PRINT #1, !(X > Y)");
+    assert_eq!(format("40 DEF FNA (X) = INT (X)"), "40 DEF FNA(X) = INT(X)");
+    assert_eq!(format("50 ON  ERROR  GOTO  100"), "50 ON ERROR GOTO 100");
+}
+
+#[test]
+fn format_numbers() {
+    assert_eq!(format("10 PRINT +007 - 2.50"), "10 PRINT 7 - 2.5");
+    assert_eq!(format("20 LET A = 1.0 * 1e20"), "20 LET A = 1.0 * 1e20");
+}
+
+#[test]
+fn format_keeps_strings_and_comments() {
+    assert_eq!(
+        format("10 PRINT \"  spaced  , (out) \""),
+        "10 PRINT \"  spaced  , (out) \""
+    );
+    assert_eq!(format("20    REM   as  it was "), "20 REM   as  it was ");
+    assert_eq!(format("30 REM"), "30 REM");
+}
+
+#[test]
+fn format_source_lines_round_trip() {
+    assert_eq!(
+        format_source_line("10  IF A<>1 THEN 20"),
+        Err("Unimplemented token at 7:\tA<>1".to_string())
+    );
+    assert_eq!(format_source_line(""), Ok(String::new()));
+
+    let lines = [
+        "10 OPEN \"out.txt\" FOR RANDOM AS #1 LEN = 20",
+        "20 FIELD #1, 10 AS A$, 10 AS B$",
+        "30 FOR I = 10 TO 1 STEP -1",
+        "40 IF (A - -1) > -(2) THEN 60",
+        "50 LINE INPUT #1, L$",
+        "60 DATA 1, 2.5, \"x, y\", WORD",
+    ];
+    for line in lines.iter() {
+        assert_eq!(format_source_line(line), Ok(line.to_string()));
+    }
+}
@@ -0,0 +1,107 @@
+use rbasic::golden::*;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// A directory of its own for each test, as the tests run at the same time
+fn temp_dir(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("rbasic-golden-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+fn run_program_with_input() {
+    let transcript = run_program(
+        "10 INPUT \"NAME? \", N\n\n20 PRINT \"HELLO \" + N\n",
+        "ADA\n",
+    );
+    assert_eq!(
+        transcript,
+        Transcript {
+            output: "NAME? HELLO ADA\nCompleted Successfully\n".to_string(),
+            error: None,
+        }
+    );
+}
+
+#[test]
+fn run_program_past_end_of_input() {
+    let transcript = run_program("10 INPUT A\n20 GOTO 10\n", "1\n");
+    assert_eq!(transcript.output, "? ? ");
+    assert_eq!(
+        transcript.error,
+        Some("INPUT past the end of the input".to_string())
+    );
+}
+
+#[test]
+fn run_program_error() {
+    let transcript = run_program("10 PRINT 1\n20 GOTO 5\n", "");
+    assert_eq!(transcript.output, "");
+    assert!(transcript.error.unwrap().contains("invalid target line 5"));
+
+    let transcript = run_program("10 PRINT 1\n20 LET X = 1 / 0\n", "");
+    assert_eq!(transcript.output, "1\n");
+    assert!(transcript.error.is_some());
+}
+
+#[test]
+fn run_program_stops_endless_loop() {
+    let transcript = run_program("10 GOTO 10\n", "");
+    assert!(transcript.error.is_some());
+}
+
+#[test]
+fn case_bless_then_check() {
+    let dir = temp_dir("bless");
+    let program = dir.join("double.bas");
+    fs::write(&program, "10 INPUT A\n20 PRINT A * 2\n").unwrap();
+    fs::write(dir.join("double.in"), "21\n").unwrap();
+    fs::write(dir.join("double.err"), "stale\n").unwrap();
+    fs::write(dir.join("notes.txt"), "not a program").unwrap();
+
+    let cases = find_cases(&dir).unwrap();
+    assert_eq!(cases, vec![Case::new(&program)]);
+    let case = &cases[0];
+
+    let problems = case.check().unwrap();
+    assert_eq!(problems.len(), 2);
+    assert!(problems[0].ends_with("double.out is missing; run with --bless to create it"));
+
+    case.bless().unwrap();
+    assert_eq!(
+        fs::read_to_string(case.output_path()).unwrap(),
+        "? 42\nCompleted Successfully\n"
+    );
+    assert!(!case.error_path().exists());
+    assert_eq!(case.check().unwrap(), Vec::<String>::new());
+
+    fs::write(&program, "10 INPUT A\n20 PRINT A * 3\n").unwrap();
+    let problems = case.check().unwrap();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].ends_with("differs at line 1:\n  expected: ? 42\n  actual:   ? 63"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn case_expected_error() {
+    let dir = temp_dir("error");
+    let program = dir.join("divide.bas");
+    fs::write(&program, "10 PRINT \"START\"\n20 LET X = 1 / 0\n").unwrap();
+
+    let case = Case::new(&program);
+    case.bless().unwrap();
+    assert_eq!(fs::read_to_string(case.output_path()).unwrap(), "START\n");
+    assert!(case.error_path().exists());
+    assert_eq!(case.check().unwrap(), Vec::<String>::new());
+
+    fs::write(&program, "10 PRINT \"START\"\n").unwrap();
+    let problems = case.check().unwrap();
+    assert_eq!(problems.len(), 2);
+    assert!(problems[1].starts_with("expected the error in"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
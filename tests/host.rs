@@ -0,0 +1,82 @@
+use rbasic::error;
+use rbasic::evaluator::*;
+use rbasic::host::HostEvent;
+use rbasic::lexer::*;
+
+fn new_interpreter(lines: &[&str]) -> Interpreter {
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    let mut interpreter = Interpreter::new(code_lines).unwrap();
+    interpreter.start();
+    interpreter
+}
+
+fn output(text: &str) -> HostEvent {
+    HostEvent::Output(text.to_string())
+}
+
+#[test]
+fn host_output_and_input() {
+    let mut interpreter = new_interpreter(&["10 PRINT \"NAME\"",
+                                            "20 INPUT N$",
+                                            "30 INPUT \"AGE: \", A",
+                                            "40 PRINT N$ + \" IS \" + A",
+                                            "50 PRINT \"DONE\""]);
+    assert_eq!(interpreter.resume(), output("NAME\n"));
+    assert_eq!(interpreter.resume(), HostEvent::NeedsInput { prompt: "? ".to_string() });
+    assert_eq!(interpreter.resume(), HostEvent::NeedsInput { prompt: "? ".to_string() });
+
+    interpreter.provide_input("ADA\n");
+    assert_eq!(interpreter.resume(), HostEvent::NeedsInput { prompt: "AGE: ".to_string() });
+
+    interpreter.provide_input("36");
+    assert_eq!(interpreter.resume(), output("ADA IS 36\n"));
+    assert_eq!(interpreter.resume(), output("DONE\n"));
+    assert_eq!(interpreter.resume(), HostEvent::Finished(ExecutionStatus::Ended));
+}
+
+#[test]
+fn host_stop_and_resume() {
+    let mut interpreter = new_interpreter(&["10 PRINT 1", "20 STOP", "30 PRINT 2"]);
+    assert_eq!(interpreter.resume(), output("1\n"));
+    assert_eq!(interpreter.resume(), HostEvent::Finished(ExecutionStatus::Stopped(LineNumber(20))));
+    assert_eq!(interpreter.resume(), output("2\n"));
+    assert_eq!(interpreter.resume(), HostEvent::Finished(ExecutionStatus::Ended));
+}
+
+#[test]
+fn host_error() {
+    let mut interpreter = new_interpreter(&["10 PRINT 1", "20 ERROR 5"]);
+    assert_eq!(interpreter.resume(), output("1\n"));
+    match interpreter.resume() {
+        HostEvent::Error(e) => assert_eq!(e.code, error::ILLEGAL_FUNCTION_CALL),
+        event => panic!("expected an error, not {:?}", event),
+    }
+}
+
+#[test]
+fn host_resume_after_error() {
+    let mut interpreter = new_interpreter(&["10 PRINT 1", "20 LET A = 1 / 0", "30 PRINT 2"]);
+    assert_eq!(interpreter.resume(), output("1\n"));
+    match interpreter.resume() {
+        HostEvent::Error(e) => assert_eq!(e.code, error::DIVISION_BY_ZERO),
+        event => panic!("expected an error, not {:?}", event),
+    }
+    for _ in 0..2 {
+        match interpreter.resume() {
+            HostEvent::Error(e) => assert_eq!(e.code, error::CANT_CONTINUE),
+            event => panic!("expected an error, not {:?}", event),
+        }
+    }
+
+    interpreter.start();
+    assert_eq!(interpreter.resume(), output("1\n"));
+}
+
+#[test]
+fn host_restart_clears_state() {
+    let mut interpreter = new_interpreter(&["10 PRINT 1", "20 INPUT A"]);
+    assert_eq!(interpreter.resume(), output("1\n"));
+    interpreter.start();
+    assert_eq!(interpreter.resume(), output("1\n"));
+    assert_eq!(interpreter.resume(), HostEvent::NeedsInput { prompt: "? ".to_string() });
+}
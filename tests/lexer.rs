@@ -2,6 +2,7 @@
 
 use rbasic::lexer::*;
 use rbasic::token::*;
+use rbasic::variables::UNRESOLVED;
 
 #[test]
 fn tokenize_no_line_number() {
@@ -29,7 +30,7 @@ fn tokenize_line_with_string() {
     let line_of_code = tokenize_line("10 PRINT \"FOO BAR BAZ\"").unwrap();
     assert_eq!(LineNumber(10), line_of_code.line_number);
     let tokens: Vec<TokenAndPos> = vec![TokenAndPos(3, Token::Print),
-                                        TokenAndPos(9, Token::BString("FOO BAR BAZ".to_string()))];
+                                        TokenAndPos(9, Token::BString("FOO BAR BAZ".into()))];
     assert_eq!(tokens, line_of_code.tokens)
 }
 
@@ -38,7 +39,7 @@ fn tokenize_line_with_identifier() {
     let line_of_code = tokenize_line("10 INPUT A").unwrap();
     assert_eq!(LineNumber(10), line_of_code.line_number);
     let tokens: Vec<TokenAndPos> = vec![TokenAndPos(3, Token::Input),
-                                        TokenAndPos(9, Token::Variable("A".to_string()))];
+                                        TokenAndPos(9, Token::Variable("A".into(), UNRESOLVED))];
     assert_eq!(tokens, line_of_code.tokens)
 }
 
@@ -57,3 +58,56 @@ fn tokenize_line_with_comment() {
              TokenAndPos(7, Token::Comment("THIS IS A COMMENT 123".to_string()))];
     assert_eq!(tokens, line_of_code.tokens)
 }
+
+#[test]
+fn tokenize_line_with_function_call() {
+    let line_of_code = tokenize_line("10 PRINT FNA(X, 2) - 1").unwrap();
+    assert_eq!(LineNumber(10), line_of_code.line_number);
+    let tokens: Vec<TokenAndPos> = vec![TokenAndPos(3, Token::Print),
+                                        TokenAndPos(9, Token::Variable("FNA".into(), UNRESOLVED)),
+                                        TokenAndPos(12, Token::LParen),
+                                        TokenAndPos(13, Token::Variable("X".into(), UNRESOLVED)),
+                                        TokenAndPos(14, Token::Comma),
+                                        TokenAndPos(16, Token::Number(2)),
+                                        TokenAndPos(17, Token::RParen),
+                                        TokenAndPos(19, Token::Minus),
+                                        TokenAndPos(21, Token::Number(1))];
+    assert_eq!(tokens, line_of_code.tokens)
+}
+
+#[test]
+fn tokenize_line_with_float() {
+    let line_of_code = tokenize_line("10 PRINT 2.5 * .5").unwrap();
+    assert_eq!(LineNumber(10), line_of_code.line_number);
+    let tokens: Vec<TokenAndPos> = vec![TokenAndPos(3, Token::Print),
+                                        TokenAndPos(9, Token::Float(2.5)),
+                                        TokenAndPos(13, Token::Multiply),
+                                        TokenAndPos(15, Token::Float(0.5))];
+    assert_eq!(tokens, line_of_code.tokens)
+}
+
+#[test]
+fn tokenize_line_with_file_number() {
+    let line_of_code = tokenize_line("10 PRINT #1, A").unwrap();
+    assert_eq!(LineNumber(10), line_of_code.line_number);
+    let tokens: Vec<TokenAndPos> = vec![TokenAndPos(3, Token::Print),
+                                        TokenAndPos(9, Token::Hash),
+                                        TokenAndPos(10, Token::Number(1)),
+                                        TokenAndPos(11, Token::Comma),
+                                        TokenAndPos(13, Token::Variable("A".into(), UNRESOLVED))];
+    assert_eq!(tokens, line_of_code.tokens)
+}
+
+#[test]
+fn tokenize_line_with_string_variable() {
+    let line_of_code = tokenize_line("10 LSET NAME$ = MKI$(1)").unwrap();
+    assert_eq!(LineNumber(10), line_of_code.line_number);
+    let tokens: Vec<TokenAndPos> = vec![TokenAndPos(3, Token::Lset),
+                                        TokenAndPos(8, Token::Variable("NAME$".into(), UNRESOLVED)),
+                                        TokenAndPos(14, Token::Equals),
+                                        TokenAndPos(16, Token::Variable("MKI$".into(), UNRESOLVED)),
+                                        TokenAndPos(20, Token::LParen),
+                                        TokenAndPos(21, Token::Number(1)),
+                                        TokenAndPos(22, Token::RParen)];
+    assert_eq!(tokens, line_of_code.tokens)
+}
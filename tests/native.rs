@@ -0,0 +1,61 @@
+use rbasic::error;
+use rbasic::evaluator::*;
+use rbasic::lexer::*;
+use rbasic::native::ArgumentType;
+use rbasic::value::RBasicValue;
+
+use std::convert::TryFrom;
+
+fn new_interpreter(lines: &[&str]) -> Interpreter {
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    let mut interpreter = Interpreter::new(code_lines).unwrap();
+    interpreter.register_function("PRICE", &[ArgumentType::String], |args| {
+        match String::try_from(args[0].clone())?.as_str() {
+            "APPLE" => Ok(RBasicValue::from(0.5)),
+            sku => Err(error::RBasicError::new(error::ILLEGAL_FUNCTION_CALL,
+                                               format!("No price for {}", sku))),
+        }
+    }).unwrap();
+    interpreter.register_function("SCALE", &[ArgumentType::Float, ArgumentType::Number], |args| {
+        Ok(RBasicValue::from(f64::try_from(args[0].clone())? * i32::try_from(args[1].clone())? as f64))
+    }).unwrap();
+    interpreter
+}
+
+#[test]
+fn native_functions_in_expressions() {
+    let mut interpreter = new_interpreter(&["10 LET P = PRICE(\"APPLE\") * 4",
+                                            "20 IF P <> 2 THEN 9999",
+                                            "30 IF SCALE(3, 2) + 1 <> 7 THEN 9999",
+                                            "40 END",
+                                            "9999 STOP"]);
+    assert_eq!(interpreter.run(), Ok(ExecutionStatus::Ended));
+    assert_eq!(interpreter.variable("P"), Some(RBasicValue::Float(2.0)));
+
+    // Registered functions are kept when the program is run again
+    assert_eq!(interpreter.run(), Ok(ExecutionStatus::Ended));
+}
+
+#[test]
+fn native_function_errors() {
+    let mut interpreter = new_interpreter(&["10 LET P = PRICE(\"PEAR\")"]);
+    assert_eq!(interpreter.run().map_err(|e| e.message),
+               Err("At LineNumber(10), 3 error in LET expression: No price for PEAR".to_string()));
+
+    let mut interpreter = new_interpreter(&["10 LET P = PRICE(1)"]);
+    assert_eq!(interpreter.run().map_err(|e| e.message),
+               Err("At LineNumber(10), 3 error in LET expression: Argument 1 of PRICE: \
+                    Expected a string but found Number(1)".to_string()));
+
+    let mut interpreter = new_interpreter(&["10 LET P = SCALE(1)"]);
+    assert_eq!(interpreter.run().map_err(|e| e.code), Err(error::ILLEGAL_FUNCTION_CALL));
+}
+
+#[test]
+fn native_function_names() {
+    let mut interpreter = new_interpreter(&["10 END"]);
+    for name in &["PRINT", "FNX", "1X", ""] {
+        let result = interpreter.register_function(name, &[], |_| Ok(RBasicValue::from(true)));
+        assert_eq!(result.map_err(|e| e.code), Err(error::SYNTAX_ERROR));
+    }
+}
@@ -0,0 +1,43 @@
+use rbasic::error;
+use rbasic::packing::*;
+
+fn packed(bytes: &[u8]) -> String {
+    string_from_bytes(bytes)
+}
+
+#[test]
+fn mki_little_endian() {
+    assert_eq!(mki(258), Ok(packed(&[0x02, 0x01])));
+    assert_eq!(mki(-1), Ok(packed(&[0xff, 0xff])));
+    assert_eq!(cvi(&packed(&[0x02, 0x01])), Ok(258));
+    assert_eq!(mki(32768).map_err(|e| e.code), Err(error::OVERFLOW));
+}
+
+#[test]
+fn mks_microsoft_binary_format() {
+    assert_eq!(mks(0.0), Ok(packed(&[0x00, 0x00, 0x00, 0x00])));
+    assert_eq!(mks(1.0), Ok(packed(&[0x00, 0x00, 0x00, 0x81])));
+    assert_eq!(mks(-1.0), Ok(packed(&[0x00, 0x00, 0x80, 0x81])));
+    assert_eq!(mks(0.5), Ok(packed(&[0x00, 0x00, 0x00, 0x80])));
+    assert_eq!(mks(10.0), Ok(packed(&[0x00, 0x00, 0x20, 0x84])));
+    assert_eq!(mks(-2.5), Ok(packed(&[0x00, 0x00, 0xa0, 0x82])));
+}
+
+#[test]
+fn mkd_microsoft_binary_format() {
+    assert_eq!(mkd(1.0), Ok(packed(&[0, 0, 0, 0, 0, 0, 0x00, 0x81])));
+    assert_eq!(mkd(-2.5), Ok(packed(&[0, 0, 0, 0, 0, 0, 0xa0, 0x82])));
+}
+
+#[test]
+fn packed_round_trips() {
+    for number in &[0.0, 1.0, -1.0, 0.1, 3.25, -1234.5678, 1.0e30] {
+        assert_eq!(cvd(&mkd(*number).unwrap()), Ok(*number));
+        assert_eq!(cvs(&mks(*number).unwrap()), Ok(*number as f32 as f64));
+    }
+}
+
+#[test]
+fn unpack_short_string() {
+    assert_eq!(cvs("ab").map_err(|e| e.code), Err(error::ILLEGAL_FUNCTION_CALL));
+}
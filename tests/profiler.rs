@@ -0,0 +1,101 @@
+use rbasic::evaluator::*;
+use rbasic::lexer::*;
+use rbasic::profiler::*;
+
+use std::time::Duration;
+
+fn profile(lines: &[&str]) -> Profiler {
+    let code_lines = lines
+        .iter()
+        .map(|line| tokenize_line(line).unwrap())
+        .collect();
+    let mut interpreter = Interpreter::new(code_lines).unwrap();
+    interpreter.set_profiling(true);
+    interpreter.run().unwrap();
+    interpreter.profiler().unwrap().clone()
+}
+
+fn folded_stacks(profiler: &Profiler) -> Vec<String> {
+    let mut output = Vec::new();
+    profiler.write_folded(&mut output).unwrap();
+    String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0.to_string())
+        .collect()
+}
+
+#[test]
+fn profile_counts_lines_and_gosubs() {
+    let profiler = profile(&[
+        "5 LET X = 0",
+        "10 FOR I = 1 TO 3",
+        "20 GOSUB 100",
+        "30 NEXT I",
+        "40 END",
+        "100 GOSUB 200",
+        "110 RETURN",
+        "200 LET X = X + I",
+        "210 RETURN",
+    ]);
+
+    assert_eq!(profiler.line(&LineNumber(10)).unwrap().count, 1);
+    assert_eq!(profiler.line(&LineNumber(30)).unwrap().count, 3);
+    assert_eq!(profiler.line(&LineNumber(200)).unwrap().count, 3);
+    assert_eq!(profiler.line(&LineNumber(40)).unwrap().count, 1);
+    assert_eq!(profiler.routine(&LineNumber(100)).unwrap().calls, 3);
+    assert_eq!(profiler.routine(&LineNumber(200)).unwrap().calls, 3);
+    assert!(profiler.routine(&LineNumber(10)).is_none());
+
+    // The outer subroutine's time includes the inner one's
+    let outer = profiler.routine(&LineNumber(100)).unwrap().time;
+    let inner = profiler.routine(&LineNumber(200)).unwrap().time;
+    assert!(outer >= inner);
+    assert!(profiler.total() >= outer);
+}
+
+#[test]
+fn profile_folded_stacks() {
+    let profiler = profile(&[
+        "10 GOSUB 100",
+        "20 END",
+        "100 GOSUB 200",
+        "110 RETURN",
+        "200 RETURN",
+    ]);
+
+    assert_eq!(
+        folded_stacks(&profiler),
+        vec![
+            "main;line 10",
+            "main;line 20",
+            "main;GOSUB 100;line 100",
+            "main;GOSUB 100;line 110",
+            "main;GOSUB 100;GOSUB 200;line 200",
+        ]
+    );
+}
+
+#[test]
+fn profile_recursive_gosub_counts_time_once() {
+    let mut profiler = Profiler::new();
+    profiler.call(&LineNumber(100));
+    profiler.call(&LineNumber(100));
+    profiler.record_line(&LineNumber(100), Duration::from_millis(2));
+    profiler.return_to(0);
+    profiler.record_line(&LineNumber(10), Duration::from_millis(1));
+
+    let routine = profiler.routine(&LineNumber(100)).unwrap();
+    assert_eq!(routine.calls, 2);
+    assert_eq!(routine.time, Duration::from_millis(2));
+    assert_eq!(profiler.total(), Duration::from_millis(3));
+    assert!(profiler.to_string().ends_with("Total time: 3.000ms\n"));
+}
+
+#[test]
+fn profile_off_by_default() {
+    let code_lines = vec![tokenize_line("10 END").unwrap()];
+    let mut interpreter = Interpreter::new(code_lines).unwrap();
+    interpreter.run().unwrap();
+    assert!(interpreter.profiler().is_none());
+}
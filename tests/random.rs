@@ -0,0 +1,36 @@
+use rbasic::random::*;
+
+#[test]
+fn same_seed_same_numbers() {
+    let mut rng1 = Rng::new(42);
+    let mut rng2 = Rng::new(42);
+    for _ in 0..100 {
+        assert_eq!(rng1.next_float(), rng2.next_float());
+    }
+}
+
+#[test]
+fn different_seeds_different_numbers() {
+    let mut rng1 = Rng::new(1);
+    let mut rng2 = Rng::new(2);
+    assert_ne!(rng1.next_float(), rng2.next_float());
+}
+
+#[test]
+fn numbers_in_range() {
+    let mut rng = Rng::new(DEFAULT_SEED);
+    for _ in 0..1000 {
+        let number = rng.next_float();
+        assert!((0.0..1.0).contains(&number));
+        assert_eq!(number, rng.last_float());
+    }
+}
+
+#[test]
+fn reseeding_restarts_sequence() {
+    let mut rng = Rng::new(7);
+    let first = rng.next_float();
+    rng.next_float();
+    rng.seed(7);
+    assert_eq!(rng.next_float(), first);
+}
@@ -0,0 +1,90 @@
+use rbasic::error;
+use rbasic::renum::*;
+
+#[test]
+fn renumber_lines_and_references() {
+    let lines = [
+        "5 ON ERROR GOTO 500",
+        "20 IF A > 0 THEN 45",
+        "30   GOSUB 300",
+        "45 ON A GOTO 5, 30",
+        "50 RESTORE 600",
+        "300 RETURN",
+        "500 IF ERL = 45 THEN 510",
+        "510 RESUME 50",
+        "600 DATA 1",
+    ];
+    assert_eq!(
+        renumber(&lines, &Renumbering::default()),
+        Ok(vec![
+            "10 ON ERROR GOTO 70".to_string(),
+            "20 IF A > 0 THEN 40".to_string(),
+            "30   GOSUB 60".to_string(),
+            "40 ON A GOTO 10, 30".to_string(),
+            "50 RESTORE 90".to_string(),
+            "60 RETURN".to_string(),
+            "70 IF ERL = 40 THEN 80".to_string(),
+            "80 RESUME 50".to_string(),
+            "90 DATA 1".to_string(),
+        ])
+    );
+}
+
+#[test]
+fn renumber_from_old_start() {
+    let lines = ["30 GOTO 40", "10 GOTO 30", "40 GOTO 10", "41 RESUME 0"];
+    let renumbering = Renumbering {
+        new_start: 100,
+        old_start: 40,
+        increment: 5,
+    };
+    assert_eq!(
+        renumber(&lines, &renumbering),
+        Ok(vec![
+            "10 GOTO 30".to_string(),
+            "30 GOTO 100".to_string(),
+            "100 GOTO 10".to_string(),
+            "105 RESUME 0".to_string(),
+        ])
+    );
+}
+
+#[test]
+fn renumber_refuses_undefined_lines() {
+    let lines = ["10 GOTO 15", "20 GOSUB 25"];
+    let result = renumber(&lines, &Renumbering::default());
+    assert_eq!(result.as_ref().map_err(|e| e.code), Err(error::UNDEFINED_LINE_NUMBER));
+    assert_eq!(
+        result.map_err(|e| e.message),
+        Err("Undefined line 15 in 10\nUndefined line 25 in 20".to_string())
+    );
+}
+
+#[test]
+fn renumber_refuses_overlapping_lines() {
+    let lines = ["10 PRINT 1", "20 PRINT 2", "30 PRINT 3"];
+    let renumbering = Renumbering {
+        new_start: 15,
+        old_start: 30,
+        increment: 10,
+    };
+    assert_eq!(
+        renumber(&lines, &renumbering).map_err(|e| e.code),
+        Err(error::ILLEGAL_FUNCTION_CALL)
+    );
+}
+
+#[test]
+fn parse_renumbering() {
+    assert_eq!(Renumbering::parse(""), Ok(Renumbering::default()));
+    assert_eq!(
+        Renumbering::parse(" 100, , 5"),
+        Ok(Renumbering {
+            new_start: 100,
+            old_start: 0,
+            increment: 5,
+        })
+    );
+    assert!(Renumbering::parse("1, 2, 3, 4").is_err());
+    assert!(Renumbering::parse("X").is_err());
+}
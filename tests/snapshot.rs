@@ -0,0 +1,89 @@
+use rbasic::error;
+use rbasic::evaluator::*;
+use rbasic::lexer::*;
+use rbasic::snapshot;
+use rbasic::value::RBasicValue;
+
+use std::env;
+
+fn new_interpreter(lines: &[&str]) -> Interpreter {
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    Interpreter::new(code_lines).unwrap()
+}
+
+const PROGRAM: [&str; 15] = ["10 DIM A$(2)",
+                             "20 DATA \"X\", 2.5, -3",
+                             "30 DEF FNSQ(X) = X * X",
+                             "40 ON ERROR GOTO 110",
+                             "50 FOR I = 1 TO 2",
+                             "60 READ A$(I)",
+                             "70 GOSUB 150",
+                             "80 NEXT I",
+                             "90 READ N",
+                             "100 LET R = RND(1)",
+                             "110 END",
+                             "150 LET T = FNSQ(I) + RND(1)",
+                             "160 IF I = 2 THEN 180",
+                             "170 STOP",
+                             "180 RETURN"];
+
+#[test]
+fn snapshot_restores_everything() {
+    let mut original = new_interpreter(&PROGRAM);
+    original.set_seed(7);
+    assert_eq!(original.run(), Ok(ExecutionStatus::Stopped(LineNumber(170))));
+
+    let text = original.snapshot().unwrap();
+    let mut restored = Interpreter::from_snapshot(&text).unwrap();
+    assert_eq!(restored.snapshot().map(|_| ()), Ok(()));
+    assert_eq!(restored.current_line(), Some(LineNumber(180)));
+    assert_eq!(restored.gosub_stack(), original.gosub_stack());
+    assert_eq!(restored.for_stack().len(), 1);
+
+    // Both carry on the same way, down to the random numbers
+    for interpreter in [&mut original, &mut restored].iter_mut() {
+        assert_eq!(interpreter.cont(), Ok(ExecutionStatus::Ended));
+    }
+    assert_eq!(restored.variables(), original.variables());
+    assert_eq!(restored.variable("N"), Some(RBasicValue::Number(-3)));
+    assert_eq!(restored.array("A$").unwrap().values()[2],
+               RBasicValue::String("2.5".into()));
+}
+
+#[test]
+fn snapshot_of_open_files() {
+    let path = env::temp_dir().join(format!("rbasic-snapshot-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let open_file = format!("10 OPEN \"{}\" FOR OUTPUT AS #1", path);
+    let mut interpreter = new_interpreter(&[open_file.as_str(),
+                                            "20 PRINT #1, \"BEFORE\"",
+                                            "30 STOP",
+                                            "40 PRINT #1, \"AFTER\""]);
+    assert_eq!(interpreter.run(), Ok(ExecutionStatus::Stopped(LineNumber(30))));
+
+    let mut restored = Interpreter::from_snapshot(&interpreter.snapshot().unwrap()).unwrap();
+    assert_eq!(restored.cont(), Ok(ExecutionStatus::Ended));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "BEFORE\nAFTER\n");
+}
+
+#[test]
+fn snapshot_version_mismatch() {
+    let mut interpreter = new_interpreter(&["10 END"]);
+    let text = interpreter.snapshot().unwrap();
+    let newer = text.replacen(&format!("SNAPSHOT {}", snapshot::FORMAT_VERSION), "SNAPSHOT 99", 1);
+
+    assert_eq!(Interpreter::from_snapshot(&newer).map(|_| ()).map_err(|e| e.message),
+               Err(format!("Snapshot version 99 is not supported, only version {}",
+                           snapshot::FORMAT_VERSION)));
+}
+
+#[test]
+fn snapshot_bad_text() {
+    assert_eq!(Interpreter::from_snapshot("10 PRINT 1").map(|_| ()).map_err(|e| e.code),
+               Err(error::BAD_SNAPSHOT));
+
+    let mut interpreter = new_interpreter(&["10 END"]);
+    let text = interpreter.snapshot().unwrap().replace("line_index 0", "line_index 9");
+    assert_eq!(Interpreter::from_snapshot(&text).map(|_| ()).map_err(|e| e.message),
+               Err("Bad snapshot: index 9 is past the end".to_string()));
+}
@@ -0,0 +1,85 @@
+use rbasic::evaluator::*;
+use rbasic::formatter::format_line;
+use rbasic::lexer::*;
+use rbasic::trace::*;
+
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+// Collects what the tracer writes so that tests can check it
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedOutput {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+fn trace_lines(lines: &[&str], trace: bool, configure: fn(&mut Tracer, &[&str])) -> String {
+    let output = SharedOutput::default();
+    let mut tracer = Tracer::new(Box::new(output.clone()));
+    configure(&mut tracer, lines);
+
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    let mut interpreter = Interpreter::new(code_lines).unwrap();
+    interpreter.set_tracer(tracer);
+    interpreter.set_trace(trace);
+    assert_eq!(interpreter.run(), Ok(ExecutionStatus::Ended));
+    output.contents()
+}
+
+#[test]
+fn tron_and_troff() {
+    let lines = ["10 LET A = 1", "20 TRON", "30 GOSUB 60", "40 TROFF", "50 END", "60 RETURN"];
+    assert_eq!(trace_lines(&lines, false, |_, _| {}), "[30]\n[60]\n[40]\n");
+}
+
+#[test]
+fn trace_from_start() {
+    let lines = ["10 LET A = 1", "20 IF A = 1 THEN 40", "30 LET A = 2", "40 END"];
+    assert_eq!(trace_lines(&lines, true, |_, _| {}), "[10]\n[20]\n[40]\n");
+}
+
+#[test]
+fn trace_statements_and_variables() {
+    let lines = ["10 LET A = 1", "20 LET B = \"x\"", "30 LET A = 1"];
+    let trace = trace_lines(&lines, true, |tracer, lines| {
+        tracer.show_statements(lines);
+        tracer.show_variables();
+    });
+    assert_eq!(trace,
+               "[10] LET A = 1\n    A = 1\n[20] LET B = \"x\"\n    B = \"x\"\n[30] LET A = 1\n");
+}
+
+#[test]
+fn trace_restored_snapshot() {
+    let lines = ["10 LET A = 1", "20 STOP", "30 LET A = A + 1", "40 END"];
+    let code_lines = lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    let mut original = Interpreter::new(code_lines).unwrap();
+    assert_eq!(original.run(), Ok(ExecutionStatus::Stopped(LineNumber(20))));
+
+    let output = SharedOutput::default();
+    let mut tracer = Tracer::new(Box::new(output.clone()));
+    let listing: Vec<String> = original.program().iter().map(format_line).collect();
+    let listing: Vec<&str> = listing.iter().map(String::as_str).collect();
+    tracer.show_statements(&listing);
+
+    let mut restored = Interpreter::from_snapshot(&original.snapshot().unwrap()).unwrap();
+    restored.set_tracer(tracer);
+    restored.set_tracing(true);
+    assert_eq!(restored.cont(), Ok(ExecutionStatus::Ended));
+    assert_eq!(output.contents(), "[30] LET A = A + 1\n[40] END\n");
+}
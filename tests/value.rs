@@ -0,0 +1,77 @@
+use rbasic::error;
+use rbasic::value::RBasicValue;
+
+use std::convert::TryFrom;
+
+#[test]
+fn value_from_rust() {
+    assert_eq!(RBasicValue::from(3), RBasicValue::Number(3));
+    assert_eq!(RBasicValue::from(1.5), RBasicValue::Float(1.5));
+    assert_eq!(RBasicValue::from("A"), RBasicValue::String("A".into()));
+    assert_eq!(RBasicValue::from(true), RBasicValue::Bool(true));
+}
+
+#[test]
+fn value_to_rust() {
+    assert_eq!(i32::try_from(RBasicValue::Number(3)), Ok(3));
+    assert_eq!(i32::try_from(RBasicValue::String("42".into())), Ok(42));
+    assert_eq!(f64::try_from(RBasicValue::Number(3)), Ok(3.0));
+    assert_eq!(String::try_from(RBasicValue::String("A".into())), Ok("A".to_string()));
+    assert_eq!(bool::try_from(RBasicValue::Bool(false)), Ok(false));
+}
+
+#[test]
+fn value_to_rust_type_mismatch() {
+    assert_eq!(i32::try_from(RBasicValue::Float(1.5)).map_err(|e| e.code),
+               Err(error::TYPE_MISMATCH));
+    assert_eq!(f64::try_from(RBasicValue::Bool(true)).map_err(|e| e.code),
+               Err(error::TYPE_MISMATCH));
+    assert_eq!(String::try_from(RBasicValue::Number(1)).map_err(|e| e.code),
+               Err(error::TYPE_MISMATCH));
+    assert_eq!(bool::try_from(RBasicValue::Number(1)).map_err(|e| e.code),
+               Err(error::TYPE_MISMATCH));
+}
+
+#[test]
+fn value_whole_number_overflow() {
+    let max = || RBasicValue::Number(i32::MAX);
+    let min = || RBasicValue::Number(i32::MIN);
+    let one = || RBasicValue::Number(1);
+
+    assert_eq!((max() + one()).map_err(|e| e.code).err(), Some(error::OVERFLOW));
+    assert_eq!((min() - one()).map_err(|e| e.code).err(), Some(error::OVERFLOW));
+    assert_eq!((max() * RBasicValue::Number(2)).map_err(|e| e.code).err(), Some(error::OVERFLOW));
+    assert_eq!((min() / RBasicValue::Number(-1)).map_err(|e| e.code).err(), Some(error::OVERFLOW));
+    assert_eq!((-min()).map_err(|e| e.code).err(), Some(error::OVERFLOW));
+    assert_eq!((max() + RBasicValue::String("1".into())).map_err(|e| e.code).err(),
+               Some(error::OVERFLOW));
+    assert!(matches!(max() - one(), Ok(RBasicValue::Number(number)) if number == i32::MAX - 1));
+}
+
+#[test]
+fn value_whole_number_division() {
+    let divide = |number1, number2| RBasicValue::Number(number1) / RBasicValue::Number(number2);
+    assert!(matches!(divide(7, 2), Ok(RBasicValue::Number(3))));
+    assert!(matches!(divide(-7, 2), Ok(RBasicValue::Number(-3))));
+    assert!(matches!(divide(7, -2), Ok(RBasicValue::Number(-3))));
+    assert_eq!(divide(1, 0).map_err(|e| e.code).err(), Some(error::DIVISION_BY_ZERO));
+    assert!(matches!(RBasicValue::Number(7) / RBasicValue::Float(2.0),
+                     Ok(RBasicValue::Float(float)) if float == 3.5));
+    assert!(matches!(RBasicValue::Float(-7.0) / RBasicValue::Number(2),
+                     Ok(RBasicValue::Float(float)) if float == -3.5));
+}
+
+#[test]
+fn value_mixed_comparison() {
+    let two = RBasicValue::Number(2);
+    assert_eq!(two.eq(&RBasicValue::Float(2.0)), Ok(true));
+    assert_eq!(RBasicValue::Float(2.0).eq(&two), Ok(true));
+    assert_eq!(two.neq(&RBasicValue::Float(2.5)), Ok(true));
+    assert_eq!(two.lt(&RBasicValue::Float(2.5)), Ok(true));
+    assert_eq!(two.gt(&RBasicValue::Float(1.5)), Ok(true));
+    assert_eq!(RBasicValue::Float(1.5).gt(&two), Ok(false));
+    assert_eq!(two.lteq(&RBasicValue::Float(2.0)), Ok(true));
+    assert_eq!(two.gteq(&RBasicValue::Float(2.5)), Ok(false));
+    assert_eq!(RBasicValue::Float(1.5).lt(&RBasicValue::String("2".into())), Ok(true));
+    assert_eq!(two.eq(&RBasicValue::Bool(true)).map_err(|e| e.code), Err(error::TYPE_MISMATCH));
+}
@@ -0,0 +1,74 @@
+use rbasic::error;
+use rbasic::evaluator::Interpreter;
+use rbasic::lexer::*;
+use rbasic::token::Token;
+use rbasic::value::RBasicValue;
+use rbasic::variables::*;
+
+#[test]
+fn variables_slots() {
+    let mut variables = Variables::new();
+    assert_eq!(variables.find("ERR"), Some(ERR));
+    assert_eq!(variables.find("ERL"), Some(ERL));
+
+    let a = variables.slot("A");
+    let b = variables.slot("B$");
+    assert_eq!(variables.slot("A"), a);
+    assert_ne!(a, b);
+    assert_eq!(variables.find("C"), None);
+    assert_eq!(variables.name(b), Some("B$"));
+}
+
+#[test]
+fn variables_set_and_clear() {
+    let mut variables = Variables::new();
+    let a = variables.slot("A");
+    let b = variables.slot("B$");
+    assert_eq!(variables.get(a), Ok(None));
+
+    variables.set(b, RBasicValue::from("HI")).unwrap();
+    variables.set(a, RBasicValue::Number(1)).unwrap();
+    assert_eq!(variables.get(a), Ok(Some(&RBasicValue::Number(1))));
+    assert_eq!(variables.iter().collect::<Vec<_>>(),
+               vec![("A", &RBasicValue::Number(1)), ("B$", &RBasicValue::from("HI"))]);
+
+    let cleared = variables.cleared();
+    assert_eq!(cleared.find("B$"), Some(b));
+    assert_eq!(cleared.get(b), Ok(None));
+}
+
+#[test]
+fn variables_unresolved_slot() {
+    let mut variables = Variables::new();
+    assert_eq!(variables.get(UNRESOLVED).map_err(|e| e.code), Err(error::INTERNAL_ERROR));
+    assert_eq!(variables.set(UNRESOLVED, RBasicValue::Number(1)),
+               Err(error::RBasicError::new(error::INTERNAL_ERROR,
+                                           "Internal error: variable used before its program \
+                                            was loaded"
+                                               .to_string())));
+    assert_eq!(variables.get(2).map_err(|e| e.code), Err(error::INTERNAL_ERROR));
+    assert_eq!(variables.name(UNRESOLVED), None);
+}
+
+// Every variable in a loaded program has a slot, whether it was loaded from
+// source or from a snapshot
+#[test]
+fn variables_resolved_when_loaded() {
+    let lines = ["10 DEF FNA(X) = X + Y", "20 LET Y = ERR", "30 STOP", "40 PRINT FNA(Y)"];
+    let code_lines: Vec<LineOfCode> =
+        lines.iter().map(|line| tokenize_line(line).unwrap()).collect();
+    assert_eq!(code_lines[1].tokens[1], TokenAndPos(7, Token::Variable("Y".into(), UNRESOLVED)));
+
+    let mut interpreter = Interpreter::new(code_lines).unwrap();
+    interpreter.run().unwrap();
+    let restored = Interpreter::from_snapshot(&interpreter.snapshot().unwrap()).unwrap();
+    for program in [interpreter.program(), restored.program()].iter() {
+        for TokenAndPos(_, token) in program.iter().flat_map(|line| &line.tokens) {
+            if let Token::Variable(ref name, slot) = *token {
+                assert_ne!(slot, UNRESOLVED, "{} has no slot", name);
+            }
+        }
+    }
+    assert_eq!(restored.program()[1].tokens[3],
+               TokenAndPos(11, Token::Variable("ERR".into(), ERR)));
+}
@@ -0,0 +1,264 @@
+use rbasic::compiler;
+use rbasic::error;
+use rbasic::evaluator::*;
+use rbasic::golden;
+use rbasic::lexer::*;
+use rbasic::limits::Limits;
+use rbasic::value::RBasicValue;
+use rbasic::vm::Vm;
+
+use std::fs;
+use std::path::Path;
+
+fn tokenize(lines: &[&str]) -> Vec<LineOfCode> {
+    lines.iter().map(|line| tokenize_line(line).unwrap()).collect()
+}
+
+// Runs the program both interpreted and compiled, checking that they finish
+// the same way with the same variables
+fn run_both(lines: &[&str]) -> Result<ExecutionStatus, String> {
+    run_both_limited(lines, Limits::default())
+}
+
+fn run_both_limited(lines: &[&str], limits: Limits) -> Result<ExecutionStatus, String> {
+    let mut interpreter = Interpreter::new(tokenize(lines)).map_err(|e| e.message)?;
+    let mut vm = Vm::new(compiler::compile(tokenize(lines)).unwrap());
+    interpreter.set_limits(limits.clone());
+    vm.set_limits(limits);
+
+    let interpreted = interpreter.run();
+    let compiled = vm.run();
+    assert_eq!(compiled, interpreted);
+    assert_eq!(vm.variables(), interpreter.variables());
+
+    compiled.map_err(|e| e.message)
+}
+
+#[test]
+fn vm_runs_loops_and_subroutines() {
+    let result = run_both(&["10 DEF FNSQ(X) = X * X",
+                            "20 LET T = 0",
+                            "30 FOR I = 1 TO 10",
+                            "40 GOSUB 200",
+                            "50 NEXT I",
+                            "60 LET J = 0",
+                            "70 WHILE J < 3",
+                            "80 LET J = J + 1",
+                            "90 WEND",
+                            "100 DO UNTIL J > 5",
+                            "110 LET J = J + 2",
+                            "120 IF J = 7 THEN 140",
+                            "130 LOOP",
+                            "140 ON J - 5 GOTO 150, 160",
+                            "150 END",
+                            "160 LET T = T + FNSQ(-J) / 2",
+                            "170 END",
+                            "200 LET T = T + FNSQ(I)",
+                            "210 RETURN"]);
+    assert_eq!(result, Ok(ExecutionStatus::Ended));
+}
+
+#[test]
+fn vm_runs_for_loops_as_interpreted() {
+    let result = run_both(&["10 LET T = 0",
+                            "20 FOR I = 1 TO 10 STEP 3",
+                            "30 FOR J = 5 TO -5 STEP -5",
+                            "40 LET T = T + I * J",
+                            "50 NEXT J, I",
+                            "60 FOR K = 3 TO 1",
+                            "70 FOR L = 1 TO 2",
+                            "80 LET T = T + 1",
+                            "90 NEXT L",
+                            "100 NEXT K",
+                            "110 FOR M = 1 TO 3 STEP -1",
+                            "120 NEXT"]);
+    assert_eq!(result, Ok(ExecutionStatus::Ended));
+    assert_eq!(run_both(&["10 FOR I = 1 TO 2", "20 NEXT I", "30 NEXT I"]),
+               Err("At LineNumber(30), 3 NEXT without FOR".to_string()));
+}
+
+#[test]
+fn vm_runs_arrays_and_data() {
+    let result = run_both(&["10 DIM A(3, 2)",
+                            "20 FOR I = 0 TO 3",
+                            "30 READ A(I, 1), N$",
+                            "40 NEXT",
+                            "50 LET B(2) = A(1, 1) + A(3, 1)",
+                            "60 RESTORE 100",
+                            "70 READ X",
+                            "80 LET R = RAND(6) + INT(2.5) + B(2)",
+                            "100 DATA 1, 2, 3, 4, 5, 6, 7, 8"]);
+    assert_eq!(result, Ok(ExecutionStatus::Ended));
+}
+
+#[test]
+fn vm_reports_errors_as_interpreted() {
+    assert_eq!(run_both(&["10 LET A = B + 1"]),
+               Err("At LineNumber(10), 3 error in LET expression: Invalid variable reference B \
+                    in expression"
+                   .to_string()));
+    assert_eq!(run_both(&["10 IF 1 = 2 THEN 30", "20 IF 1 = 1 THEN 5"]),
+               Err("At LineNumber(10), 17 invalid target line 30 for IF\n\
+                    At LineNumber(20), 17 invalid target line 5 for IF"
+                   .to_string()));
+    assert_eq!(run_both(&["10 IF 1 THEN 20", "20 END"]),
+               Err("At LineNumber(10), 3, invalid syntax for IF.".to_string()));
+    assert_eq!(run_both(&["10 NEXT I"]),
+               Err("At LineNumber(10), 3 NEXT without FOR".to_string()));
+    assert_eq!(run_both(&["10 FOR I = 2 TO 1"]),
+               Err("At LineNumber(10), 3 FOR without NEXT".to_string()));
+    assert_eq!(run_both(&["10 DIM A(2)", "20 LET A(3) = 1"]),
+               Err("At LineNumber(20), 3 Subscript 3 is out of range 0 to 2".to_string()));
+    assert_eq!(run_both(&["10 LET A = RAND(3000000000)"]),
+               Err("At LineNumber(10), 3 error in LET expression: RAND requires a maximum of \
+                    at most 2147483647, not 3000000000"
+                   .to_string()));
+    assert_eq!(run_both(&["10 LET A = INT(-3000000000.5)"]),
+               Err("At LineNumber(10), 3 error in LET expression: Overflow".to_string()));
+    assert_eq!(run_both(&["10 DATA 1", "20 READ A, B"]),
+               Err("At LineNumber(20), 3 Out of DATA".to_string()));
+    assert_eq!(run_both(&["10 DATA 1, \"X\"", "20 READ A(1), A(2)"]),
+               Err("At LineNumber(20), 3 Type mismatch reading X into A".to_string()));
+    assert_eq!(run_both(&["10 LET A(11) = 1"]),
+               Err("At LineNumber(10), 3 Subscript 11 is out of range 0 to 10".to_string()));
+    assert_eq!(run_both(&["10 LET A(1) = 1", "20 DIM A(20)"]),
+               Err("At LineNumber(20), 3 Array A is already dimensioned".to_string()));
+    assert_eq!(run_both(&["10 LET X = 1 2"]),
+               Err("At LineNumber(10), 3 error in LET expression: Invalid expression!"
+                   .to_string()));
+    assert_eq!(run_both(&["10 GOTO"]),
+               Err("At LineNumber(10), 7 GOTO must be followed by a line number".to_string()));
+    assert_eq!(run_both(&["10 PRINT 1, 2"]),
+               Err("At LineNumber(10), 10 PRINT takes a single expression, with nothing after \
+                    it"
+                   .to_string()));
+    assert_eq!(run_both(&["10 DEF FNR(X) = FNR(X)", "20 LET A = FNR(1)"]),
+               Err("At LineNumber(20), 3 error in LET expression: Out of memory calling FNR: \
+                    user functions nested more than 200 deep"
+                   .to_string()));
+}
+
+#[test]
+fn vm_traps_errors_as_interpreted() {
+    let result = run_both(&["10 ON ERROR GOTO 100",
+                            "15 LET N = 0",
+                            "20 DEF FNQ(X) = 10 / X",
+                            "30 LET A = FNQ(0)",
+                            "40 LET B = ERR * 1000 + ERL",
+                            "50 GOSUB 200",
+                            "60 ERROR 42",
+                            "70 LET C = ERR",
+                            "80 LET D(20) = 1",
+                            "90 END",
+                            "100 LET N = N + 1",
+                            "110 IF ERR = 9 THEN 130",
+                            "120 RESUME NEXT",
+                            "130 RESUME 90",
+                            "200 FOR I = 1 TO 3",
+                            "210 IF I = 2 THEN 230",
+                            "220 LET Z = Y",
+                            "230 NEXT I",
+                            "240 RETURN"]);
+    assert_eq!(result, Ok(ExecutionStatus::Ended));
+
+    // RESUME on its own runs the line with the error again
+    let result = run_both(&["10 ON ERROR GOTO 100",
+                            "20 LET A = 10 / X",
+                            "30 END",
+                            "100 LET X = 2",
+                            "110 RESUME"]);
+    assert_eq!(result, Ok(ExecutionStatus::Ended));
+
+    assert_eq!(run_both(&["10 ON ERROR GOTO 100", "20 ERROR 5", "100 ERROR 6"]),
+               Err("At LineNumber(100), 4 Overflow".to_string()));
+    assert_eq!(run_both(&["10 ON ERROR GOTO 100", "20 ERROR 5", "100 ON ERROR GOTO 0"]),
+               Err("At LineNumber(20), 3 Illegal function call".to_string()));
+    assert_eq!(run_both(&["10 ON ERROR GOTO 100", "20 ERROR 5", "100 LET A = ERR"]),
+               Err("At LineNumber(20), error handler ended without RESUME".to_string()));
+    assert_eq!(run_both(&["10 RESUME NEXT"]),
+               Err("At LineNumber(10), 3 RESUME without error".to_string()));
+
+    // A badly formed RESUME gives a syntax error, which is trapped in turn
+    let result = run_both(&["10 ON ERROR GOTO 100",
+                            "20 ERROR 5",
+                            "100 IF ERR = 2 THEN 200",
+                            "110 RESUME X",
+                            "200 RESUME 300",
+                            "300 LET E = ERL"]);
+    assert_eq!(result, Ok(ExecutionStatus::Ended));
+}
+
+#[test]
+fn vm_enforces_limits_as_interpreted() {
+    let statements = Limits { statements: Some(10), ..Limits::default() };
+    assert_eq!(run_both_limited(&["10 ON ERROR GOTO 100", "20 GOTO 20", "100 RESUME NEXT"],
+                                statements),
+               Err("At LineNumber(20), statement limit of 10 exceeded".to_string()));
+
+    let string_bytes = Limits { string_bytes: Some(8), ..Limits::default() };
+    assert_eq!(run_both_limited(&["10 LET A$ = \"ABCD\"",
+                                  "20 LET A$ = \"ABCDEFGH\"",
+                                  "30 LET B$(1) = \"X\""],
+                                string_bytes),
+               Err("At LineNumber(30), 3 String space limit of 8 bytes exceeded".to_string()));
+
+    let array_elements = Limits { array_elements: Some(15), ..Limits::default() };
+    assert_eq!(run_both_limited(&["10 DIM A(5)", "20 LET B(1) = 1"], array_elements),
+               Err("At LineNumber(20), 3 Array size limit of 15 elements exceeded".to_string()));
+
+    let call_depth = Limits { call_depth: Some(3), ..Limits::default() };
+    assert_eq!(run_both_limited(&["10 GOSUB 10"], call_depth.clone()),
+               Err("At LineNumber(10), 3 Call depth limit of 3 exceeded".to_string()));
+    assert_eq!(run_both_limited(&["10 DEF FNR(X) = FNR(X)", "20 LET A = FNR(1)"], call_depth),
+               Err("At LineNumber(20), 3 error in LET expression: Call depth limit of 3 \
+                    exceeded"
+                   .to_string()));
+}
+
+#[test]
+fn vm_stops_and_continues() {
+    let mut vm = Vm::new(compiler::compile(tokenize(&["10 LET A = 1",
+                                                      "20 STOP",
+                                                      "30 LET A = 2"])).unwrap());
+    assert_eq!(vm.run(), Ok(ExecutionStatus::Stopped(LineNumber(20))));
+    assert_eq!(vm.variable("A"), Some(RBasicValue::Number(1)));
+    assert_eq!(vm.cont(), Ok(ExecutionStatus::Ended));
+    assert_eq!(vm.variable("A"), Some(RBasicValue::Number(2)));
+    assert_eq!(vm.cont().map_err(|e| e.code), Err(error::CANT_CONTINUE));
+}
+
+#[test]
+fn vm_rejects_statements_it_cant_compile() {
+    let result = compiler::compile(tokenize(&["10 OPEN \"F\" FOR INPUT AS 1"]));
+    assert_eq!(result.map(|_| ()).map_err(|e| (e.code, e.message)),
+               Err((error::ADVANCED_FEATURE,
+                    "At LineNumber(10), 3 OPEN can't be compiled".to_string())));
+}
+
+#[test]
+fn vm_scripted_console() {
+    let mut vm = Vm::new(compiler::compile(tokenize(&["10 INPUT \"N? \", N",
+                                                      "20 PRINT N + \"!\"",
+                                                      "30 GOTO 10"])).unwrap());
+    vm.script_console("A\nB\n");
+    assert_eq!(vm.run().map_err(|e| e.code), Err(error::INPUT_PAST_END));
+    assert_eq!(vm.output(), "N? A!\nN? B!\nN? ");
+}
+
+// Every example, with the input it is tested with, prints the same and ends
+// the same way on the VM as when interpreted
+#[test]
+fn vm_runs_examples_as_interpreted() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let cases = golden::find_cases(&examples).unwrap();
+    assert!(!cases.is_empty());
+
+    for case in cases {
+        let source = fs::read_to_string(&case.program).unwrap();
+        let input = fs::read_to_string(case.input_path()).unwrap_or_default();
+        assert_eq!(golden::run_compiled(&source, &input),
+                   golden::run_program(&source, &input),
+                   "{}",
+                   case.program.display());
+    }
+}
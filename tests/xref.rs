@@ -0,0 +1,68 @@
+use rbasic::lexer::*;
+use rbasic::xref::*;
+
+fn code_lines(lines: &[&str]) -> Vec<LineOfCode> {
+    lines
+        .iter()
+        .map(|line| tokenize_line(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn cross_reference_variables_and_lines() {
+    let lines = [
+        "10 DIM A(5)",
+        "20 FOR I = 1 TO 5",
+        "30 READ A(I)",
+        "40 NEXT I",
+        "50 GOSUB 100",
+        "60 IF T > 10 THEN 90",
+        "70 DEF FNS(X) = X * S",
+        "80 RESTORE 150",
+        "90 END",
+        "100 LET T = T + A(1)",
+        "110 RETURN",
+        "150 DATA 1, 2, 3, 4, 5",
+    ];
+    assert_eq!(
+        cross_reference(code_lines(&lines)).to_string(),
+        "Variables:
+  A: written in 10, 30; read in 100
+  I: written in 20, 40; read in 30, 40
+  S: read in 70
+  T: written in 100; read in 60, 100
+Line numbers:
+  90: IF from 60
+  100: GOSUB from 50
+  150: RESTORE from 80
+"
+    );
+}
+
+#[test]
+fn control_flow_graph() {
+    let lines = [
+        "10 GOSUB 40",
+        "20 IF A = 1 THEN 10",
+        "30 END",
+        "40 PRINT \"\\\"",
+        "50 RETURN",
+    ];
+    assert_eq!(
+        control_flow_dot(code_lines(&lines)),
+        "digraph program {
+    node [shape=box];
+    10 [label=\"10 GOSUB 40\"];
+    20 [label=\"20 IF A = 1 THEN 10\"];
+    30 [label=\"30 END\"];
+    40 [label=\"40 PRINT \\\"\\\\\\\"\", peripheries=2];
+    50 [label=\"50 RETURN\"];
+    10 -> 20;
+    10 -> 40 [label=\"GOSUB\", style=dashed];
+    20 -> 10 [label=\"IF\"];
+    20 -> 30;
+    40 -> 50;
+}
+"
+    );
+}